@@ -160,6 +160,11 @@ HitInfo ray_cast(Ray ray) {
 void main() {
     ivec2 location = ivec2(int(gl_GlobalInvocationID.x), int(gl_GlobalInvocationID.y));
 
+    // The last work groups overhang the image when its extent isn't a multiple of 8
+    if (any(greaterThanEqual(location, imageSize(image)))) {
+        return;
+    }
+
     uint64_t start = clockARB();
 
     Ray ray = get_primary_ray(location);
@@ -0,0 +1,12 @@
+// Push constants shared by the egui vertex and fragment shaders
+
+#include <vulkano.glsl>
+
+layout(push_constant) uniform PushConstants {
+    // The size of the window in points
+    vec2 screen_size;
+    SampledImageId texture_id;
+    SamplerId sampler_id;
+    // Non zero when the swapchain encodes to sRGB itself
+    uint srgb_target;
+};
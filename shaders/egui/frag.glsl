@@ -0,0 +1,25 @@
+#version 460
+
+#extension GL_GOOGLE_include_directive : enable
+
+#include "egui.glsl"
+
+layout(location = 0) in vec2 vertex_uv;
+layout(location = 1) in vec4 vertex_color;
+
+layout(location = 0) out vec4 out_color;
+
+vec3 srgb_to_linear(vec3 color) {
+    return mix(color / 12.92, pow((color + 0.055) / 1.055, vec3(2.4)), step(0.04045, color));
+}
+
+void main() {
+    // egui blends in sRGB space, the conversion is left to the attachment when it's sRGB
+    vec4 color = vertex_color * texture(vko_sampler2D(texture_id, sampler_id), vertex_uv);
+
+    if (srgb_target != 0) {
+        color.rgb = srgb_to_linear(color.rgb);
+    }
+
+    out_color = color;
+}
@@ -0,0 +1,21 @@
+#version 460
+
+#extension GL_GOOGLE_include_directive : enable
+
+#include "egui.glsl"
+
+// egui meshes are in points, with the origin at the top left like Vulkan
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 uv;
+// Premultiplied sRGB
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec2 vertex_uv;
+layout(location = 1) out vec4 vertex_color;
+
+void main() {
+    vertex_uv = uv;
+    vertex_color = color;
+
+    gl_Position = vec4(position / screen_size * 2.0 - 1.0, 0.0, 1.0);
+}
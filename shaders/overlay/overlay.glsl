@@ -0,0 +1,119 @@
+#version 460
+
+#extension GL_GOOGLE_include_directive : enable
+
+#include <vulkano.glsl>
+
+layout(local_size_x = 8, local_size_y = 8, local_size_z = 1) in;
+
+layout(push_constant) uniform PushConstants {
+    StorageImageId image_id;
+    uvec2 extent;
+    uint fps;
+    uint speed;
+    // The counters are only drawn when non-zero
+    uint show_stats;
+    // The `OUTPUT_*` encoding of the ray traced image, see output.glsl
+    uint output_encoding;
+};
+
+const uint OUTPUT_SCRGB = 1u;
+const uint OUTPUT_HDR10 = 2u;
+
+const int CROSSHAIR_SIZE = 8;
+const int CROSSHAIR_THICKNESS = 1;
+
+const uint GLYPH_SCALE = 3;
+const uint DIGIT_COUNT = 5;
+
+// 3x5 digit glyphs, the top row is stored in the highest bits
+const uint DIGITS[10] = uint[](
+    31599, 11415, 29671, 29647, 23497, 31183, 31215, 29257, 31727, 31695
+);
+
+bool crosshair(ivec2 pixel) {
+    const ivec2 offset = abs(pixel - ivec2(extent / 2));
+
+    return (offset.x <= CROSSHAIR_THICKNESS && offset.y <= CROSSHAIR_SIZE)
+        || (offset.y <= CROSSHAIR_THICKNESS && offset.x <= CROSSHAIR_SIZE);
+}
+
+bool glyph(uint digit, uvec2 cell) {
+    const uint bit = (4 - cell.y) * 3 + (2 - cell.x);
+
+    return ((DIGITS[digit] >> bit) & 1) != 0;
+}
+
+// Draws `value` right aligned on `DIGIT_COUNT` digits, starting at `origin`
+bool number(uint value, uvec2 origin, uvec2 pixel) {
+    if (any(lessThan(pixel, origin))) {
+        return false;
+    }
+
+    const uvec2 local = (pixel - origin) / GLYPH_SCALE;
+
+    // Each glyph is followed by a one cell gap
+    const uint digit_index = local.x / 4;
+    const uvec2 cell = uvec2(local.x % 4, local.y);
+
+    if (digit_index >= DIGIT_COUNT || cell.x >= 3 || cell.y >= 5) {
+        return false;
+    }
+
+    const uint power = DIGIT_COUNT - 1 - digit_index;
+
+    uint divisor = 1;
+    for (uint i = 0; i < power; i++) {
+        divisor *= 10;
+    }
+
+    // Skip the leading zeroes
+    if (power > 0 && value < divisor) {
+        return false;
+    }
+
+    return glyph((value / divisor) % 10, cell);
+}
+
+void main() {
+    const uvec2 pixel = gl_GlobalInvocationID.xy;
+
+    if (any(greaterThanEqual(pixel, extent))) {
+        return;
+    }
+
+    bool covered = crosshair(ivec2(pixel));
+
+    if (show_stats != 0) {
+        const uint line_height = 7 * GLYPH_SCALE;
+
+        covered = covered
+            || number(fps, uvec2(8, 8), pixel)
+            || number(speed, uvec2(8, 8 + line_height), pixel);
+    }
+
+    if (!covered) {
+        return;
+    }
+
+    const ivec2 position = ivec2(pixel);
+
+    // Inverting the color keeps the overlay readable on both bright and dark areas. HDR10 is
+    // inverted in its encoded range, scRGB colors brighter than 1 become black
+    switch (output_encoding) {
+    case OUTPUT_SCRGB: {
+        const vec4 color = imageLoad(vko_image2D_rgba16f(image_id), position);
+        imageStore(vko_image2D_rgba16f(image_id), position, vec4(max(1.0 - color.rgb, 0.0), 1.0));
+        break;
+    }
+    case OUTPUT_HDR10: {
+        const vec4 color = imageLoad(vko_image2D_rgb10_a2(image_id), position);
+        imageStore(vko_image2D_rgb10_a2(image_id), position, vec4(1.0 - color.rgb, 1.0));
+        break;
+    }
+    default: {
+        const vec4 color = imageLoad(vko_image2D_rgba8(image_id), position);
+        imageStore(vko_image2D_rgba8(image_id), position, vec4(1.0 - color.rgb, 1.0));
+    }
+    }
+}
@@ -1,6 +1,11 @@
 struct MainPassPayload {
     vec4 color;
     float t;
+    // The world space normal and the instance custom index of the hit, zero for misses
+    vec3 normal;
+    uint custom_index;
+    // The amount of reflections that led to this ray, 0 for primary rays
+    uint depth;
 };
 
 struct TPayload {
@@ -9,5 +14,17 @@ struct TPayload {
 
 #define FLT_MAX 3.402823466e+38
 #define EPSILON 0.0001
-const uint AO_SPP = 1;
 const float PI = 3.14159265358979323;
+
+// Instance custom index layout, see `instance_custom_index` in chunk.rs
+uint instance_material(uint custom_index) {
+    return custom_index & 0xFFu;
+}
+
+uint instance_lod(uint custom_index) {
+    return (custom_index >> 8) & 0xFu;
+}
+
+uint instance_chunk(uint custom_index) {
+    return (custom_index >> 12) & 0xFFFu;
+}
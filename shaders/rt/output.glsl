@@ -0,0 +1,77 @@
+// Tonemapping and encoding of the output image, shared by the scene shaders and the denoiser.
+// The includer declares `sunlight` and `image_id`
+
+// The operators of `sunlight.tonemap`, clamping keeps the radiance as is up to the brightest
+// color of the output
+const uint TONEMAP_CLAMP = 0u;
+const uint TONEMAP_REINHARD = 1u;
+const uint TONEMAP_ACES = 2u;
+
+// The encodings of `sunlight.output_encoding`, see `OutputEncoding` in app.rs
+const uint OUTPUT_SDR = 0u;
+const uint OUTPUT_SCRGB = 1u;
+const uint OUTPUT_HDR10 = 2u;
+
+// scRGB maps 1.0 to 80 nits
+const float SCRGB_WHITE_NITS = 80.0;
+
+// Column major, from the primaries of sRGB and scRGB to the ones of HDR10
+const mat3 BT709_TO_BT2020 = mat3(
+    0.6274, 0.0691, 0.0164,
+    0.3293, 0.9195, 0.0880,
+    0.0433, 0.0114, 0.8956
+);
+
+// Narkowicz's fit of the ACES filmic curve
+vec3 aces_film(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+// The brightest tonemapped color, relative to the SDR white
+float output_peak() {
+    return sunlight.output_encoding == OUTPUT_SDR ? 1.0 : sunlight.hdr_max_nits / sunlight.hdr_white_nits;
+}
+
+// HDR outputs stretch the operators up to their peak instead of 1
+vec3 tonemap(vec3 radiance) {
+    radiance *= sunlight.exposure;
+
+    const float peak = output_peak();
+
+    switch (sunlight.tonemap) {
+    case TONEMAP_REINHARD:
+        return radiance / (1.0 + radiance / peak);
+    case TONEMAP_ACES:
+        return aces_film(radiance / peak) * peak;
+    default:
+        return min(radiance, vec3(peak));
+    }
+}
+
+// SMPTE ST 2084, the inverse EOTF of HDR10 from nits to [0, 1]
+vec3 pq_encode(vec3 nits) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+
+    const vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(m1));
+
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+// Writes a color relative to the SDR white in the encoding of the output image, SDR colors are
+// stored as is
+void store_output(ivec2 pixel, vec4 color) {
+    switch (sunlight.output_encoding) {
+    case OUTPUT_SCRGB:
+        imageStore(vko_image2D_rgba16f(image_id), pixel, vec4(color.rgb * sunlight.hdr_white_nits / SCRGB_WHITE_NITS, color.a));
+        break;
+    case OUTPUT_HDR10:
+        imageStore(vko_image2D_rgb10_a2(image_id), pixel, vec4(pq_encode(BT709_TO_BT2020 * color.rgb * sunlight.hdr_white_nits), color.a));
+        break;
+    default:
+        imageStore(vko_image2D_rgba8(image_id), pixel, color);
+    }
+}
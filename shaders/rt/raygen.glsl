@@ -0,0 +1,85 @@
+// The ray generation shader, shared by the normal and heatmap variants
+
+layout(location = 0) rayPayloadEXT MainPassPayload payload;
+layout(location = 1) rayPayloadEXT TPayload shadow_payload;
+
+// layout(set = 0, binding = 0) uniform accelerationStructureEXT static_as;
+// layout(set = 0, binding = 1) uniform accelerationStructureEXT bodies_as;
+
+// layout(set = 1, binding = 0) uniform _Camera { Camera camera; };
+
+// layout(set = 2, binding = 0, rgba32f) uniform image2D image;
+
+float saturate(float x) {
+    return clamp(x, 0.0, 1.0);
+}
+
+vec3 viridis_quintic(float x) {
+    x = saturate(x);
+    vec4 x1 = vec4(1.0, x, x * x, x * x * x); // 1 x x2 x3
+    vec4 x2 = x1 * x1.w * x; // x4 x5 x6 x7
+    return vec3(
+        dot(x1.xyzw, vec4(+0.280268003, -0.143510503, +2.225793877, -14.815088879)) + dot(x2.xy, vec2(+25.212752309, -11.772589584)),
+        dot(x1.xyzw, vec4(-0.002117546, +1.617109353, -1.909305070, +2.701152864)) + dot(x2.xy, vec2(-1.685288385, +0.178738871)),
+        dot(x1.xyzw, vec4(+0.300805501, +2.614650302, -12.019139090, +28.933559110)) + dot(x2.xy, vec2(-33.491294770, +13.762053843)));
+}
+
+float random(vec2 st) {
+    return fract(sin(dot(st.xy, vec2(12.9898, 78.233))) * 43758.5453123);
+}
+
+#ifdef HEATMAP
+// Cycles mapped to red, cheaper pixels go through green and blue
+const float HEATMAP_MAX_CYCLES = 100000.0;
+
+vec3 heatmap_color(float t) {
+    return clamp(1.5 - abs(4.0 * t - vec3(3.0, 2.0, 1.0)), 0.0, 1.0);
+}
+#endif
+
+void main() {
+#ifdef HEATMAP
+    const uint64_t start = clockARB();
+#endif
+
+    vec3 origin;
+    vec3 direction;
+    camera_ray(gl_LaunchIDEXT.xy, gl_LaunchSizeEXT.xy, origin, direction);
+
+    // The geometry flags decide which voxels go through the any-hit shader
+    const uint ray_flags = gl_RayFlagsNoneEXT;
+    // const uint ray_flags = gl_RayFlagsTerminateOnFirstHitEXT;
+
+    vec4 final_color = vec4(0.0);
+
+    payload.color = vec4(0.0);
+    payload.t = -1.0;
+    payload.normal = vec3(0.0);
+    payload.custom_index = 0;
+    payload.depth = 0;
+
+    traceRayEXT(vko_accelerationStructureEXT(acceleration_structure_id), ray_flags, 0xFFu, 0u, 0u, 0u, origin, EPSILON, direction, camera.max_ray_distance, 0);
+
+    final_color += apply_fog(payload.color, payload.t);
+
+    // vec3 primary_hit_position = origin + (payload.t - EPSILON) * direction;
+
+    {
+        // shadow ray
+        shadow_payload.t = -1.0;
+
+        // traceRayEXT(vko_accelerationStructureEXT(acceleration_structure_id), gl_RayFlagsTerminateOnFirstHitEXT, 0xFFu, 0u, 0u, 0u, primary_hit_position, EPSILON, -sunlight.direction, FLT_MAX, 1);
+    }
+
+    // if (shadow_payload.t > EPSILON) {
+    // final_color *= 0.25;
+    // }
+
+#ifdef HEATMAP
+    const float cycles = float(clockARB() - start);
+    final_color = vec4(heatmap_color(cycles / HEATMAP_MAX_CYCLES), 1.0);
+#endif
+
+    store_pixel(ivec2(gl_LaunchIDEXT.xy), final_color);
+    store_g_buffer(ivec2(gl_LaunchIDEXT.xy), payload.normal, payload.t, payload.custom_index);
+}
@@ -0,0 +1,68 @@
+// Scene buffers and push constants, shared by the ray tracing pipeline and the ray query renderer
+
+#define VKO_ACCELERATION_STRUCTURE_ENABLED 1
+
+#include <vulkano.glsl>
+#include "sunlight.glsl"
+
+VKO_DECLARE_STORAGE_BUFFER(camera, Camera{
+    // Camera view * projection
+    mat4 view_proj;
+    // Camera inverse view matrix
+    mat4 view_inverse;
+    // Camera inverse projection matrix
+    mat4 proj_inverse;
+    // Radius of the lens, 0 for a pinhole camera without depth of field
+    float aperture;
+    // Distance from the camera to the plane in focus
+    float focus_distance;
+    // The tmax of the primary rays and of the reflection rays, in voxels. Ambient occlusion
+    // rays stop at `ao_radius` instead
+    float max_ray_distance;
+    float max_reflection_distance;
+})
+
+VKO_DECLARE_STORAGE_BUFFER(palette, Palette{
+    vec4[256] colors;
+    // Metallic and roughness of each palette entry
+    vec2[256] materials;
+    // Emitted color of each palette entry in rgb and its strength in a
+    vec4[256] emission;
+})
+
+#define camera vko_buffer(camera, camera_buffer_id)
+#define palette vko_buffer(palette, palette_buffer_id)
+
+layout(push_constant) uniform PushConstants {
+    // Flat color output by the miss shader instead of the sky, unless its alpha is 0
+    vec4 background_color;
+    // Offset of the primary rays within their pixel, in [-0.5, 0.5)
+    vec2 jitter;
+    StorageImageId image_id;
+    StorageImageId accumulation_image_id;
+    // The amount of frames already blended in the accumulation image
+    uint accumulated_frames;
+    // The amount of reflection rays that can follow a primary ray
+    uint max_bounces;
+    // Non zero to color voxels by their chunk instead of their material
+    uint chunk_colors;
+    // Non zero to output the normals of the primary hits as colors instead of shading them, to
+    // check the intersection shader
+    uint debug_normals;
+    // Non zero to output the palette colors of the hits without any lighting, to check the
+    // geometry and the instance transforms
+    uint flat_colors;
+    // Non zero to darken creases with ambient occlusion
+    uint ao_enabled;
+    // The G-buffer, the normal and distance then the instance custom index of the primary hits.
+    // Only written when `g_buffer_enabled` is non zero
+    StorageImageId normal_image_id;
+    StorageImageId instance_image_id;
+    uint g_buffer_enabled;
+    // Non zero when the denoiser writes the output image instead
+    uint denoise_enabled;
+    AccelerationStructureId acceleration_structure_id;
+    StorageBufferId camera_buffer_id;
+    StorageBufferId palette_buffer_id;
+    StorageBufferId sunlight_buffer_id;
+};
@@ -0,0 +1,264 @@
+// Shading shared by the ray tracing pipeline and the ray query renderer, so that both produce
+// the same image. Needs `common.glsl` and `scene.glsl`
+
+float random(vec3 seed) {
+    return fract(sin(dot(seed, vec3(12.9898, 78.233, 37.719))) * 43758.5453123);
+}
+
+// A color that differs between neighbouring chunks
+vec3 chunk_color(uint chunk) {
+    const float hue = float(chunk) / 4096.0;
+    return clamp(abs(fract(hue + vec3(0.0, 2.0, 1.0) / 3.0) * 6.0 - 3.0) - 1.0, 0.0, 1.0) * 0.8 + 0.2;
+}
+
+// The color of a voxel before lighting, from its material or from its chunk
+vec4 voxel_color(uint custom_index) {
+    return chunk_colors != 0
+        ? vec4(chunk_color(instance_chunk(custom_index)), 1.0)
+        : vec4(palette.colors[instance_material(custom_index)].rgb, 1.0);
+}
+
+// Whether a ray stops at the voxel of `instance`, with the probability of the alpha of its
+// palette entry. Once the frames are accumulated, translucent voxels are blended with what is
+// behind them. The seed only depends on the instance, so both faces of a box agree
+bool alpha_hit(uint custom_index, uint instance, uvec2 pixel) {
+    if (chunk_colors != 0) {
+        return true;
+    }
+
+    const float alpha = palette.colors[instance_material(custom_index)].a;
+    const vec3 seed = vec3(pixel, float(accumulated_frames) + fract(float(instance) * 0.618034));
+
+    return random(seed) < alpha;
+}
+
+// Each axis of the normal from [-1, 1] to [0, 1]
+vec4 normal_color(vec3 normal) {
+    return vec4(normal * 0.5 + 0.5, 1.0);
+}
+
+// The face normal of the voxel box hit at `local_position`, the box is centered on the instance
+// origin
+vec3 box_normal(vec3 local_position, mat4x3 object_to_world) {
+    const vec3 distance = abs(local_position);
+
+    vec3 normal = vec3(0.0);
+
+    if (distance.x >= distance.y && distance.x >= distance.z) {
+        normal.x = sign(local_position.x);
+    } else if (distance.y >= distance.z) {
+        normal.y = sign(local_position.y);
+    } else {
+        normal.z = sign(local_position.z);
+    }
+
+    return normalize(object_to_world * vec4(normal, 0.0));
+}
+
+// The world space normal of an object space normal, the inverse transpose keeps it
+// perpendicular to the face once the instance is rotated or scaled
+vec3 object_normal_to_world(vec3 normal, mat4x3 world_to_object) {
+    return normalize(normal * mat3(world_to_object));
+}
+
+// Share of the light that reaches every face, whatever its orientation
+const float AMBIENT_LIGHT = 0.35;
+
+// Lambertian N·L lighting by the sun, scaled by the visible fraction of the sun disk. Faces
+// turned away and shadowed faces keep the ambient light
+float diffuse_light(vec3 normal, float sun_visibility) {
+    const float n_dot_l = max(dot(normal, -normalize(sunlight.direction)), 0.0);
+    return AMBIENT_LIGHT + (1.0 - AMBIENT_LIGHT) * n_dot_l * sun_visibility;
+}
+
+// Whether the face receives sunlight at all, shadow rays are only cast from those
+bool sunlit(vec3 normal) {
+    return sunlight.shadow_sample_count != 0 && dot(normal, sunlight.direction) < 0.0;
+}
+
+// The tangent, bitangent and normal as columns, it maps directions around the Z axis to
+// directions around the normal
+mat3 tangent_frame(vec3 normal) {
+    const vec3 tangent = normalize(cross(normal, abs(normal.x) > 0.5 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0)));
+    return mat3(tangent, cross(normal, tangent), normal);
+}
+
+// A cosine weighted direction in the hemisphere around the normal, from two uniform numbers
+vec3 cosine_sample_hemisphere(vec3 normal, vec2 u) {
+    const float radius = sqrt(u.x);
+    const float phi = 2.0 * PI * u.y;
+
+    return normalize(tangent_frame(normal) * vec3(radius * cos(phi), radius * sin(phi), sqrt(max(0.0, 1.0 - u.x))));
+}
+
+// A microfacet normal around the normal, importance sampled from the GGX distribution of width
+// `alpha` with two uniform numbers
+vec3 ggx_sample_normal(vec3 normal, float alpha, vec2 u) {
+    const float cos_theta = sqrt((1.0 - u.x) / (1.0 + (alpha * alpha - 1.0) * u.x));
+    const float sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+    const float phi = 2.0 * PI * u.y;
+
+    return normalize(tangent_frame(normal) * vec3(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta));
+}
+
+// The least amount of rings the shadow samples are stratified in
+const uint SHADOW_RINGS = 8u;
+
+// The direction of the shadow ray `i` toward a point of the sun disk. The samples are stratified
+// in rings of equal area with their angles a golden angle apart. With fewer samples than
+// `SHADOW_RINGS`, the next frames go on with the next rings, so the noise still converges in
+// strata with the accumulation. A radius of 0 aims every ray at the center of the sun
+vec3 shadow_direction(uvec2 pixel, uint i) {
+    const vec3 to_sun = -normalize(sunlight.direction);
+    const uint sample_index = accumulated_frames * sunlight.shadow_sample_count + i;
+    const vec3 seed = vec3(pixel, float(sample_index) + 0.25);
+
+    const uint ring_count = max(sunlight.shadow_sample_count, SHADOW_RINGS);
+    const float radius = sqrt((float(sample_index % ring_count) + random(seed)) / float(ring_count));
+    const float phi = 2.0 * PI * fract(random(seed.zxy) + float(sample_index) * 0.618034);
+    const vec2 offset = radius * tan(sunlight.sun_angular_radius) * vec2(cos(phi), sin(phi));
+
+    return normalize(tangent_frame(to_sun) * vec3(offset, 1.0));
+}
+
+// The direction of the ambient occlusion ray `i`, the noise converges with the accumulation
+vec3 ao_direction(vec3 normal, uvec2 pixel, uint i) {
+    const vec3 seed = vec3(pixel, float(accumulated_frames * sunlight.ao_sample_count + i));
+    return cosine_sample_hemisphere(normal, vec2(random(seed), random(seed.zxy)));
+}
+
+// The ray mirrored by a microfacet of the GGX lobe with alpha = roughness², so a roughness of 0
+// is a sharp mirror and 1 spreads the reflections over the hemisphere like a diffuse surface.
+// The noise converges with the accumulation
+vec3 reflection_direction(vec3 direction, vec3 normal, float roughness, uvec2 pixel) {
+    const vec3 seed = vec3(pixel, float(accumulated_frames));
+    const vec3 microfacet = ggx_sample_normal(normal, roughness * roughness, vec2(random(seed), random(seed.yzx)));
+
+    // Reflections going under the surface are folded back above it
+    vec3 reflected = reflect(direction, microfacet);
+    reflected -= 2.0 * min(dot(reflected, normal), 0.0) * normal;
+
+    return normalize(reflected);
+}
+
+// Light emitted by the material, non emissive materials skip the scaling
+vec4 emitted_light(uint material_index) {
+    const vec4 emission = palette.emission[material_index];
+
+    if (emission.a <= 0.0) {
+        return vec4(0.0);
+    }
+
+    return vec4(emission.rgb * emission.a, 0.0);
+}
+
+// Angular radius of the sun disk, in radians
+const float SUN_RADIUS = 0.02;
+const float SUN_INTENSITY = 8.0;
+
+vec3 sky_color(vec3 direction) {
+    // The world is Y down
+    const float height = -direction.y;
+
+    if (height < 0.0) {
+        // The ground fades from the horizon to a darker tint
+        return mix(sunlight.horizon_color, sunlight.horizon_color * 0.25, sqrt(-height));
+    }
+
+    vec3 color = mix(sunlight.horizon_color, sunlight.zenith_color, sqrt(height));
+
+    const vec3 to_sun = -normalize(sunlight.direction);
+    const float sun = smoothstep(cos(SUN_RADIUS * 1.2), cos(SUN_RADIUS), dot(direction, to_sun));
+
+    return mix(color, vec3(SUN_INTENSITY), sun);
+}
+
+// Fades a primary hit `distance` voxels away toward the horizon, so distant chunks blend into
+// the sky instead of ending at a hard edge. Misses already have the sky color
+vec4 apply_fog(vec4 color, float distance) {
+    if (sunlight.fog_density <= 0.0 || distance <= 0.0 || debug_normals != 0 || flat_colors != 0) {
+        return color;
+    }
+
+    const float fog = 1.0 - exp(-sunlight.fog_density * max(distance - sunlight.fog_start, 0.0));
+    const vec3 fog_color = background_color.a > 0.0 ? background_color.rgb : sunlight.horizon_color;
+
+    return vec4(mix(color.rgb, fog_color, fog), color.a);
+}
+
+// The color of rays leaving the scene, the flat background replaces the sky when set
+vec4 miss_color(vec3 direction) {
+    if (background_color.a > 0.0) {
+        return vec4(background_color.rgb, 1.0);
+    }
+
+    return vec4(sky_color(direction), 1.0);
+}
+
+// The world space direction of the primary ray through a point of the screen
+vec3 eye_direction(vec2 ndc) {
+    vec4 eye_pos = camera.proj_inverse * vec4(ndc, -1.0, 1.0);
+    eye_pos /= eye_pos.w;
+
+    return normalize((camera.view_inverse * vec4(eye_pos.xyz, 0.0)).xyz);
+}
+
+// The primary ray through the center of the pixel. A non zero aperture moves the origin across
+// the lens, the rays of a pixel still meet on the focus plane so only it stays sharp once the
+// frames are accumulated
+void camera_ray(uvec2 pixel, uvec2 size, out vec3 origin, out vec3 direction) {
+    const vec2 pixel_center = vec2(pixel) + vec2(0.5) + jitter;
+    const vec2 in_uv = pixel_center / vec2(size);
+    const vec2 ndc = in_uv * 2.0 - 1.0;
+
+    origin = (camera.view_inverse * vec4(0.0, 0.0, 0.0, 1.0)).xyz;
+    direction = eye_direction(ndc);
+
+    if (camera.aperture > 0.0) {
+        const vec3 forward = eye_direction(vec2(0.0));
+        const vec3 focus_point = origin + direction * (camera.focus_distance / dot(direction, forward));
+
+        const vec3 seed = vec3(pixel, float(accumulated_frames) + 0.5);
+        const float radius = camera.aperture * sqrt(random(seed.zxy));
+        const float phi = 2.0 * PI * random(seed.yzx);
+
+        const vec3 right = normalize((camera.view_inverse * vec4(1.0, 0.0, 0.0, 0.0)).xyz);
+        const vec3 up = normalize((camera.view_inverse * vec4(0.0, 1.0, 0.0, 0.0)).xyz);
+
+        origin += (right * cos(phi) + up * sin(phi)) * radius;
+        direction = normalize(focus_point - origin);
+    }
+}
+
+#include "output.glsl"
+
+// Blends the color into the accumulated history and writes the result to the output image. The
+// accumulation image keeps the radiance, so the exposure and tonemap apply to the whole history
+void store_pixel(ivec2 pixel, vec4 color) {
+    // A zero frame count overwrites the history, which also clears stale data after a resize
+    if (accumulated_frames > 0) {
+        const vec4 history = imageLoad(vko_image2D_rgba32f(accumulation_image_id), pixel);
+        color = mix(history, color, 1.0 / float(accumulated_frames + 1));
+    }
+
+    imageStore(vko_image2D_rgba32f(accumulation_image_id), pixel, color);
+#ifdef HEATMAP
+    store_output(pixel, color);
+#else
+    if (denoise_enabled == 0) {
+        // The normals and the flat colors are shown as they are
+        const bool raw = debug_normals != 0 || flat_colors != 0;
+
+        store_output(pixel, vec4(raw ? color.rgb : tonemap(color.rgb), color.a));
+    }
+#endif
+}
+
+// Writes the primary hit of the pixel to the G-buffer for the passes after the ray tracing.
+// Misses have a zero normal and distance
+void store_g_buffer(ivec2 pixel, vec3 normal, float t, uint custom_index) {
+    if (g_buffer_enabled != 0) {
+        imageStore(vko_image2D_rgba32f(normal_image_id), pixel, vec4(normal, max(t, 0.0)));
+        imageStore(vko_uimage2D_r32ui(instance_image_id), pixel, uvec4(custom_index));
+    }
+}
@@ -0,0 +1,36 @@
+// The lighting parameters, shared by the scene shaders and the denoiser. The includer declares
+// `sunlight_buffer_id`
+
+VKO_DECLARE_STORAGE_BUFFER(sunlight, Sunlight{
+    // The direction light travels in, shadow rays and the sun disk use its opposite
+    vec3 direction;
+    vec3 horizon_color;
+    vec3 zenith_color;
+    // Angular radius of the sun disk in radians, 0 casts hard shadows
+    float sun_angular_radius;
+    // Shadow rays cast toward the sun disk from each primary hit, 0 disables the shadows
+    uint shadow_sample_count;
+    // Ambient occlusion rays cast from each primary hit
+    uint ao_sample_count;
+    // Length of the ambient occlusion rays, in voxels
+    float ao_radius;
+    // Offset of the shadow, ambient occlusion and reflection ray origins along the normal, in
+    // voxels, so they don't hit the surface they start from
+    float surface_bias;
+    // Distance from the camera at which the fog starts, in voxels
+    float fog_start;
+    // Exponential falloff of the fog past its start, 0 disables it
+    float fog_density;
+    // Scales the radiance before it is tonemapped
+    float exposure;
+    // One of the `TONEMAP_*` operators
+    uint tonemap;
+    // One of the `OUTPUT_*` encodings, depending on the swapchain color space
+    uint output_encoding;
+    // The luminance of a tonemapped 1.0 and the brightest tonemapped color on HDR outputs, in
+    // nits
+    float hdr_white_nits;
+    float hdr_max_nits;
+})
+
+#define sunlight vko_buffer(sunlight, sunlight_buffer_id)
@@ -1,29 +1,48 @@
-use glam::{Mat4, vec3};
+use glam::{IVec3, Mat4, Vec3, Vec4, vec3};
 use std::{
+    error::Error,
     f32::consts::PI,
-    sync::{Arc, mpsc},
+    fmt::Display,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+        mpsc,
+    },
     time::{Duration, Instant},
 };
 use vulkano::{
-    VulkanError, VulkanLibrary,
+    DeviceSize, Version, VulkanError, VulkanLibrary,
+    buffer::{Buffer, BufferCreateInfo, BufferUsage},
     device::{
         Device, DeviceCreateInfo, DeviceExtensions, DeviceFeatures, Queue, QueueCreateInfo,
-        QueueFlags, physical::PhysicalDeviceType,
+        QueueFlags,
+        physical::{PhysicalDevice, PhysicalDeviceType},
+    },
+    format::Format,
+    image::{
+        Image, ImageCreateInfo, ImageFormatInfo, ImageLayout, ImageType, ImageUsage,
+        view::ImageView,
     },
-    image::{ImageFormatInfo, ImageLayout, ImageUsage, view::ImageView},
     instance::{Instance, InstanceCreateFlags, InstanceCreateInfo, InstanceExtensions},
-    memory::allocator::{MemoryAllocator, StandardMemoryAllocator},
-    swapchain::{PresentMode, Surface, Swapchain, SwapchainCreateInfo},
+    memory::allocator::{
+        AllocationCreateInfo, DeviceLayout, MemoryAllocator, MemoryTypeFilter,
+        StandardMemoryAllocator,
+    },
+    padded::Padded,
+    swapchain::{ColorSpace, PresentMode, Surface, Swapchain, SwapchainCreateInfo},
 };
 use vulkano_taskgraph::{
     Id, QueueFamilyType,
     descriptor_set::{BindlessContext, StorageImageId},
-    graph::{CompileInfo, ExecutableTaskGraph, ExecuteError, TaskGraph},
-    resource::{AccessTypes, Flight, ImageLayoutType, Resources, ResourcesCreateInfo},
+    graph::{AttachmentInfo, CompileInfo, ExecutableTaskGraph, ExecuteError, NodeId, TaskGraph},
+    resource::{
+        AccessTypes, Flight, HostAccessType, ImageLayoutType, Resources, ResourcesCreateInfo,
+    },
     resource_map,
 };
 
-#[cfg(debug_assertions)]
 use vulkano::pipeline::graphics::viewport::Viewport;
 
 use winit::{
@@ -31,51 +50,633 @@ use winit::{
     dpi::PhysicalSize,
     event::{DeviceEvent, ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
     event_loop::{ActiveEventLoop, EventLoop},
-    keyboard::{Key, NamedKey},
-    window::{Window, WindowAttributes},
+    keyboard::{Key, ModifiersState, NamedKey},
+    window::{Fullscreen, Window, WindowAttributes},
 };
 
 use crate::{
-    async_worker::run_worker,
-    physics::PhysicsController,
+    async_worker::{WorkerRequest, run_worker},
+    benchmark::{self, BenchmarkFrame, CameraKeyframe},
+    frame_stats::FrameStats,
+    gpu_timer::{GpuTimer, GpuTimings},
+    physics::{self, PhysicsController},
     player_controller::PlayerController,
-    rt::raygen,
-    tasks::{debug, render::RayTracingRenderTask, update_as::UpdateAccelerationStructureTask},
-    world::{chunk::Chunks, voxel::open_file},
+    recording::{self, CameraRecorder, CameraRecording, CameraReplay},
+    rt::{raygen, scratch::ScratchBufferPool},
+    screenshot,
+    tasks::{
+        denoise::DenoiseTask,
+        overlay::{OverlayStats, OverlayTask},
+        render::{DebugScene, GeometryKind, RayTracingRenderTask, RenderPath},
+        screenshot::ScreenshotTask,
+        update_as::{DEFAULT_UPDATES_PER_FRAME, UpdateAccelerationStructureTask},
+        upscale::UpscaleTask,
+    },
+    world::{
+        chunk::{
+            Chunks, MAX_CHUNK_WIDTH, VOXEL_PHYSICAL_LENGTH, WorldSize, sort_instances_spatially,
+        },
+        commands::{WorldCommand, WorldCommandQueue},
+        frustum::Frustum,
+        loader::VoxelSource,
+        materials::{MaterialOverrides, OverriddenSource},
+        occlusion::OcclusionCuller,
+        voxel::{LoadError, Material, list_assets, open_file},
+    },
+};
+
+#[cfg(debug_assertions)]
+use crate::tasks::debug::DrawDebugTask;
+use crate::tasks::debug::{self, HighlightTask};
+
+#[cfg(feature = "gamepad")]
+use crate::player_controller::{GamepadAxis, GamepadState};
+#[cfg(feature = "egui")]
+use crate::{
+    tasks::egui::EguiTask,
+    tuning::{TuningParams, TuningUi},
 };
+#[cfg(feature = "gamepad")]
+use gilrs::{Axis, Button, EventType};
 
 pub const MAX_FRAMES_IN_FLIGHT: u32 = 2;
 pub const MIN_SWAPCHAIN_IMAGES: u32 = MAX_FRAMES_IN_FLIGHT + 1;
-pub const TICKS_PER_SECOND: u32 = 1;
+// Instances requested by default, lowered to the device limit
+pub const DEFAULT_MAX_INSTANCE_COUNT: u64 = 1_000;
+pub const TICKS_PER_SECOND: u32 = 60;
+// Scratch buffers kept between acceleration structure builds by default
+pub const DEFAULT_SCRATCH_BUFFER_POOL_SIZE: usize = 4;
+// Seeds the random stress-test instances, the same seed places them the same way every run
+pub const DEFAULT_SEED: u64 = 0;
+// World commands applied per frame at most, the rest wait for the next frames
+pub const MAX_WORLD_COMMANDS_PER_FRAME: usize = 256;
+// The maximum distance from the camera at which voxels can be edited
+pub const VOXEL_EDIT_RANGE: f32 = 128.0;
+pub const WORLD_SAVE_PATH: &str = "world.bin";
+pub const WORLD_EXPORT_PATH: &str = "world.obj";
+pub const BENCHMARK_CSV_PATH: &str = "benchmark.csv";
+pub const CAMERA_RECORDING_PATH: &str = "camera.bin";
+pub const CAMERA_DUMP_PATH: &str = "camera.txt";
+// Upper bound of the pipeline recursion depth, deeper recursion needs a larger ray stack
+pub const MAX_RAY_RECURSION_DEPTH: u32 = 4;
+pub const DEFAULT_MAX_BOUNCES: u32 = 1;
+pub const DEFAULT_AO_SAMPLE_COUNT: u32 = 2;
+pub const DEFAULT_AO_RADIUS: f32 = 4.0;
+// About twice the sun seen from the earth, so the penumbrae are visible at voxel scale
+pub const DEFAULT_SUN_ANGULAR_RADIUS: f32 = 0.01;
+pub const DEFAULT_SHADOW_SAMPLE_COUNT: u32 = 1;
+// A few float steps at the edge of the default world, where positions are 4096 voxels from the
+// origin, and still invisible on a unit voxel
+pub const DEFAULT_SURFACE_BIAS: f32 = 0.002;
+// Lens radii cycled through for depth of field, in voxels. 0 is a pinhole camera
+pub const APERTURE_PRESETS: [f32; 4] = [0.0, 0.25, 0.5, 1.0];
+pub const DEFAULT_FOCUS_DISTANCE: f32 = 64.0;
+pub const DEFAULT_EXPOSURE: f32 = 1.0;
+// The tonemap operators, in the order of the `TONEMAP_*` constants of the shaders
+pub const TONEMAP_OPERATORS: [&str; 3] = ["clamp", "Reinhard", "ACES"];
+// The luminance of the SDR white and the brightest tonemapped color on HDR swapchains, in nits
+pub const HDR_WHITE_NITS: f32 = 200.0;
+pub const HDR_MAX_NITS: f32 = 1000.0;
+// The furthest voxel the camera can focus on
+pub const FOCUS_RANGE: f32 = 1024.0;
+// Flat colors replacing the sky when debugging, `None` draws the sky
+pub const BACKGROUND_PRESETS: [Option<[f32; 3]>; 4] = [
+    None,
+    Some([1.0, 0.0, 1.0]),
+    Some([0.0, 0.0, 0.0]),
+    Some([1.0, 1.0, 1.0]),
+];
+// Number of frames between two occlusion culling passes
+pub const OCCLUSION_INTERVAL: u64 = 8;
+// The instances are culled with a frustum wider than the view and starting behind the camera,
+// so small camera moves don't rebuild them
+pub const CULLING_FOV: f32 = PI * 2.0 / 3.0;
+pub const CULLING_MARGIN: f32 = 32.0;
+
+// The selected GPU, logged at startup to help with bug reports
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub device_type: PhysicalDeviceType,
+    // Encoded differently by each vendor
+    pub driver_version: u32,
+    pub driver_name: Option<String>,
+    pub driver_info: Option<String>,
+    pub api_version: Version,
+    pub render_path: RenderPath,
+}
+
+impl DeviceInfo {
+    fn new(physical_device: &PhysicalDevice, render_path: RenderPath) -> Self {
+        let properties = physical_device.properties();
+
+        Self {
+            name: properties.device_name.clone(),
+            device_type: properties.device_type,
+            driver_version: properties.driver_version,
+            driver_name: properties.driver_name.clone(),
+            driver_info: properties.driver_info.clone(),
+            api_version: physical_device.api_version(),
+            render_path,
+        }
+    }
+}
+
+impl Display for DeviceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({:?}), driver {} {} ({:#x}), Vulkan {}, render path {:?}",
+            self.name,
+            self.device_type,
+            self.driver_name.as_deref().unwrap_or("unknown"),
+            self.driver_info.as_deref().unwrap_or(""),
+            self.driver_version,
+            self.api_version,
+            self.render_path,
+        )
+    }
+}
+
+// A device that lacks something the renderer needs
+#[derive(Debug)]
+pub struct UnsuitableDevice {
+    pub name: String,
+    pub missing_extensions: DeviceExtensions,
+    pub missing_features: DeviceFeatures,
+    // No queue family supports both graphics and presenting to the window
+    pub missing_present_queue: bool,
+}
+
+impl Display for UnsuitableDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:", self.name)?;
+
+        if !self.missing_extensions.is_empty() {
+            write!(f, " missing extensions {:?}", self.missing_extensions)?;
+        }
+
+        if !self.missing_features.is_empty() {
+            write!(f, " missing features {:?}", self.missing_features)?;
+        }
+
+        if self.missing_present_queue {
+            write!(f, " no graphics queue can present")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum AppInitError {
+    // Vulkan couldn't be loaded or initialized, `stage` is what was being done
+    Vulkan {
+        stage: &'static str,
+        source: Box<dyn Error>,
+    },
+    // Every device lacks something, or there are none
+    NoSuitableDevice(Vec<UnsuitableDevice>),
+    Config(AppConfigError),
+    // The world file couldn't be loaded
+    Asset(Box<dyn Error>),
+}
+
+impl AppInitError {
+    // For `map_err`, wraps a Vulkan error with the initialization stage it happened in
+    fn vulkan<E: Into<Box<dyn Error>>>(stage: &'static str) -> impl FnOnce(E) -> Self {
+        move |e| AppInitError::Vulkan {
+            stage,
+            source: e.into(),
+        }
+    }
+}
+
+impl Display for AppInitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppInitError::Vulkan { stage, source } => write!(f, "failed to {stage}: {source}"),
+            AppInitError::NoSuitableDevice(devices) if devices.is_empty() => {
+                write!(f, "no Vulkan device found")
+            }
+            AppInitError::NoSuitableDevice(devices) => {
+                write!(f, "no device supports ray tracing:")?;
+
+                for device in devices {
+                    write!(f, "\n  {device}")?;
+                }
+
+                Ok(())
+            }
+            AppInitError::Config(e) => write!(f, "invalid config: {e}"),
+            AppInitError::Asset(e) => write!(f, "failed to load the world: {e}"),
+        }
+    }
+}
+
+impl Error for AppInitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AppInitError::Vulkan { source, .. } => Some(source.as_ref()),
+            AppInitError::NoSuitableDevice(_) => None,
+            AppInitError::Config(e) => Some(e),
+            AppInitError::Asset(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    // The inner size of the window, in physical pixels
+    pub window_size: [u32; 2],
+    // Borderless fullscreen on the current monitor
+    pub fullscreen: bool,
+    // The .vox file the world is built from
+    pub asset_path: PathBuf,
+    // Falls back to another mode when the surface doesn't support it
+    pub present_mode: PresentMode,
+    pub max_frames_in_flight: u32,
+    // Clamped to the surface capabilities when the swapchain is created
+    pub min_swapchain_images: u32,
+    // Runs the benchmark over this amount of frames then exits, instead of the interactive loop
+    pub benchmark_frames: Option<u32>,
+    pub world_size: WorldSize,
+    // Initial capacity of the instance buffer and TLAS, clamped to the device
+    // `max_instance_count`. They grow up to that limit when the world needs more instances
+    pub max_instance_count: u64,
+    // Chunks farther than this amount of chunks from the camera are streamed out, every chunk
+    // stays resident when `None`
+    pub stream_radius: Option<u32>,
+    // Chunks farther than this amount of chunks from the camera are hidden but stay resident,
+    // every chunk is shown when `None`
+    pub visibility_radius: Option<u32>,
+    // Distance from the camera at which distant voxels start fading into the sky, in meters
+    pub fog_start: f32,
+    // Exponential falloff of the fog per meter, 0 disables it
+    pub fog_density: f32,
+    // Voxels farther than this from the camera aren't traced, in meters. Infinite by default
+    pub max_ray_distance: f32,
+    // The same limit for reflection rays, measured from the reflecting surface
+    pub max_reflection_distance: f32,
+    // Ratio of the ray traced resolution to the window size, in (0, 1]. Below 1 the rays are
+    // traced into a smaller image upscaled to the swapchain
+    pub render_scale: f32,
+    // The most scratch buffers reused across acceleration structure builds, 0 allocates one
+    // for every build
+    pub scratch_buffer_pool_size: usize,
+    // Presents in an HDR10 or scRGB color space when the surface supports one, SDR otherwise
+    pub hdr: bool,
+    // Builds the denoise pass, toggled at runtime. Without it the G-buffer isn't allocated
+    pub denoiser: bool,
+    // Seeds the random sphere scene and the TLAS update scrambling
+    pub seed: u64,
+    // Orders the world instances along a Z-order curve before the TLAS builds, toggled at
+    // runtime
+    pub sort_instances: bool,
+    // Leaves the chunks outside of a widened view frustum out of the world TLAS, toggled at
+    // runtime. The shadow, AO and reflection rays trace the same TLAS, so they lose the geometry
+    // out of view while it's on
+    pub frustum_culling: bool,
+    // The eye and target of the starting camera in voxels, it frames the non-empty chunks of the
+    // loaded world when `None`
+    pub camera: Option<(Vec3, Vec3)>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            window_size: [1920, 1080],
+            fullscreen: false,
+            asset_path: PathBuf::from("assets/custom.vox"),
+            present_mode: PresentMode::Mailbox,
+            max_frames_in_flight: MAX_FRAMES_IN_FLIGHT,
+            min_swapchain_images: MIN_SWAPCHAIN_IMAGES,
+            benchmark_frames: None,
+            world_size: WorldSize::default(),
+            max_instance_count: DEFAULT_MAX_INSTANCE_COUNT,
+            stream_radius: None,
+            visibility_radius: None,
+            fog_start: 0.0,
+            fog_density: 0.0,
+            max_ray_distance: f32::INFINITY,
+            max_reflection_distance: f32::INFINITY,
+            render_scale: 1.0,
+            scratch_buffer_pool_size: DEFAULT_SCRATCH_BUFFER_POOL_SIZE,
+            hdr: false,
+            denoiser: true,
+            seed: DEFAULT_SEED,
+            sort_instances: false,
+            frustum_culling: false,
+            camera: None,
+        }
+    }
+}
+
+impl AppConfig {
+    pub fn builder() -> AppConfigBuilder {
+        AppConfigBuilder::default()
+    }
+
+    pub fn validate(&self) -> Result<(), AppConfigError> {
+        if self.window_size.contains(&0) {
+            return Err(AppConfigError::EmptyWindow);
+        }
+
+        if self.max_frames_in_flight == 0 {
+            return Err(AppConfigError::NoFramesInFlight);
+        }
+
+        if self.asset_path.as_os_str().is_empty() {
+            return Err(AppConfigError::EmptyAssetPath);
+        }
+
+        if !(1..=MAX_CHUNK_WIDTH).contains(&self.world_size.chunk_width) {
+            return Err(AppConfigError::InvalidChunkWidth);
+        }
+
+        if !self.world_size.is_valid() {
+            return Err(AppConfigError::EmptyWorld);
+        }
+
+        if self.max_instance_count == 0 {
+            return Err(AppConfigError::NoInstances);
+        }
+
+        if ![self.fog_start, self.fog_density]
+            .iter()
+            .all(|value| value.is_finite() && *value >= 0.0)
+        {
+            return Err(AppConfigError::InvalidFog);
+        }
+
+        if !(self.max_ray_distance > 0.0 && self.max_reflection_distance > 0.0) {
+            return Err(AppConfigError::InvalidRayDistance);
+        }
+
+        if !(self.render_scale > 0.0 && self.render_scale <= 1.0) {
+            return Err(AppConfigError::InvalidRenderScale);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppConfigError {
+    EmptyWindow,
+    NoFramesInFlight,
+    EmptyAssetPath,
+    InvalidChunkWidth,
+    EmptyWorld,
+    NoInstances,
+    InvalidFog,
+    InvalidRayDistance,
+    InvalidRenderScale,
+}
+
+impl Display for AppConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppConfigError::EmptyWindow => write!(f, "the window size can't be zero"),
+            AppConfigError::NoFramesInFlight => write!(f, "at least one frame has to be in flight"),
+            AppConfigError::EmptyAssetPath => write!(f, "the asset path is empty"),
+            AppConfigError::InvalidChunkWidth => {
+                write!(f, "chunks must be 1 to {MAX_CHUNK_WIDTH} voxels wide")
+            }
+            AppConfigError::EmptyWorld => write!(f, "the world has no chunks"),
+            AppConfigError::NoInstances => {
+                write!(f, "at least one instance has to fit in the TLAS")
+            }
+            AppConfigError::InvalidFog => {
+                write!(
+                    f,
+                    "the fog start and density must be finite and non-negative"
+                )
+            }
+            AppConfigError::InvalidRayDistance => {
+                write!(f, "the max ray distances must be greater than 0")
+            }
+            AppConfigError::InvalidRenderScale => {
+                write!(f, "the render scale must be greater than 0 and at most 1")
+            }
+        }
+    }
+}
+
+impl Error for AppConfigError {}
+
+// Builds an `AppConfig` starting from the defaults, `build` rejects invalid combinations
+#[derive(Debug, Clone, Default)]
+pub struct AppConfigBuilder {
+    config: AppConfig,
+}
+
+impl AppConfigBuilder {
+    pub fn window_size(mut self, width: u32, height: u32) -> Self {
+        self.config.window_size = [width, height];
+        self
+    }
+
+    pub fn fullscreen(mut self, fullscreen: bool) -> Self {
+        self.config.fullscreen = fullscreen;
+        self
+    }
+
+    pub fn asset_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.asset_path = path.into();
+        self
+    }
+
+    pub fn present_mode(mut self, present_mode: PresentMode) -> Self {
+        self.config.present_mode = present_mode;
+        self
+    }
+
+    pub fn max_frames_in_flight(mut self, max_frames_in_flight: u32) -> Self {
+        self.config.max_frames_in_flight = max_frames_in_flight;
+        self
+    }
+
+    pub fn min_swapchain_images(mut self, min_swapchain_images: u32) -> Self {
+        self.config.min_swapchain_images = min_swapchain_images;
+        self
+    }
+
+    pub fn benchmark_frames(mut self, benchmark_frames: Option<u32>) -> Self {
+        self.config.benchmark_frames = benchmark_frames;
+        self
+    }
+
+    pub fn world_size(mut self, world_size: WorldSize) -> Self {
+        self.config.world_size = world_size;
+        self
+    }
+
+    pub fn max_instance_count(mut self, max_instance_count: u64) -> Self {
+        self.config.max_instance_count = max_instance_count;
+        self
+    }
+
+    pub fn scratch_buffer_pool_size(mut self, scratch_buffer_pool_size: usize) -> Self {
+        self.config.scratch_buffer_pool_size = scratch_buffer_pool_size;
+        self
+    }
+
+    pub fn hdr(mut self, hdr: bool) -> Self {
+        self.config.hdr = hdr;
+        self
+    }
+
+    pub fn denoiser(mut self, denoiser: bool) -> Self {
+        self.config.denoiser = denoiser;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.config.seed = seed;
+        self
+    }
+
+    pub fn sort_instances(mut self, sort_instances: bool) -> Self {
+        self.config.sort_instances = sort_instances;
+        self
+    }
+
+    pub fn frustum_culling(mut self, frustum_culling: bool) -> Self {
+        self.config.frustum_culling = frustum_culling;
+        self
+    }
+
+    pub fn camera(mut self, eye: Vec3, target: Vec3) -> Self {
+        self.config.camera = Some((eye, target));
+        self
+    }
+
+    pub fn stream_radius(mut self, stream_radius: Option<u32>) -> Self {
+        self.config.stream_radius = stream_radius;
+        self
+    }
+
+    pub fn visibility_radius(mut self, visibility_radius: Option<u32>) -> Self {
+        self.config.visibility_radius = visibility_radius;
+        self
+    }
+
+    pub fn fog(mut self, start: f32, density: f32) -> Self {
+        self.config.fog_start = start;
+        self.config.fog_density = density;
+        self
+    }
+
+    pub fn max_ray_distance(mut self, primary: f32, reflection: f32) -> Self {
+        self.config.max_ray_distance = primary;
+        self.config.max_reflection_distance = reflection;
+        self
+    }
+
+    pub fn render_scale(mut self, render_scale: f32) -> Self {
+        self.config.render_scale = render_scale;
+        self
+    }
+
+    pub fn build(self) -> Result<AppConfig, AppConfigError> {
+        self.config.validate()?;
+
+        Ok(self.config)
+    }
+}
 
 pub struct App {
     close_requested: bool,
 
     instance: Arc<Instance>,
     pub device: Arc<Device>,
+    device_info: DeviceInfo,
 
     pub graphics_queue: Arc<Queue>,
     pub compute_queue: Arc<Queue>,
-    #[cfg(debug_assertions)]
+    // Used for the one off uploads of the scene data
     pub transfer_queue: Arc<Queue>,
 
     pub memory_allocator: Arc<dyn MemoryAllocator>,
+    // Shared by every acceleration structure build
+    pub(crate) scratch_buffers: Mutex<ScratchBufferPool>,
 
     pub resources: Arc<Resources>,
     pub graphics_flight_id: Id<Flight>,
     pub compute_flight_id: Id<Flight>,
+    pub max_frames_in_flight: u32,
+    pub min_swapchain_images: u32,
+    benchmark_frames: Option<u32>,
+    // Requests a TLAS update from the worker every frame
+    pub tlas_updates: bool,
+    // Requests a single TLAS update after the next frame while the updates are paused
+    pub tlas_step_requested: bool,
+    // Shared with the update task, read by the worker on every update
+    pub tlas_updates_per_frame: Arc<AtomicU32>,
+    occlusion_culler: OcclusionCuller,
+    frame_index: u64,
+    // The chunk of the camera when the world instances were last built, their LODs depend on it
+    lod_origin: IVec3,
+    // The volume the world instances were last culled with, `None` when they weren't culled
+    culling_frustum: Option<Frustum>,
+    stream_radius: Option<u32>,
+    visibility_radius: Option<u32>,
+    // The camera chunk the visibility radius was last applied around, `None` when it needs to
+    // be applied again
+    visibility_origin: Option<IVec3>,
+    // Start and density in meters, applied to the scene parameters once the render context
+    // exists
+    fog: [f32; 2],
+    // The max distance of the primary and reflection rays in meters
+    ray_distances: [f32; 2],
+    render_scale: f32,
+    hdr: bool,
+    denoiser: bool,
 
     last_frame_update: Instant,
     next_log_update: Instant,
     delta_time: Duration,
+    frame_stats: FrameStats,
+    gpu_timings: GpuTimings,
     focused: bool,
+    modifiers: ModifiersState,
 
+    // The current capacity of the instance buffer and TLAS
     pub max_instance_count: u64,
-    pub voxel_data: dot_vox::DotVoxData,
-    world: Chunks,
+    // What the instance buffer can grow to, the device limit clamped to 32 bits
+    pub device_max_instance_count: u64,
+    // The device limit clamped to `MAX_RAY_RECURSION_DEPTH`
+    pub max_ray_recursion_depth: u32,
+    pub render_path: RenderPath,
+    // The device has shader clocks and traces with the ray tracing pipeline
+    pub heatmap_supported: bool,
+    // The loaded model with the material overrides saved next to it
+    pub voxel_data: OverriddenSource,
+    // The models next to `AppConfig::asset_path`, `cycle_model` goes through them
+    pub assets: Vec<PathBuf>,
+    pub asset_index: usize,
+    pub world: Chunks,
+    world_commands: WorldCommandQueue,
+    pub debug_scene: DebugScene,
+    // Seeds the random instances of the stress-test tasks
+    pub seed: u64,
+    // Sorts the world instances with `sort_instances_spatially` before they are uploaded
+    pub sort_instances: bool,
+    // Culls the world instances with `culling_frustum`
+    pub frustum_culling: bool,
+    pub geometry_kind: GeometryKind,
+    pub preferred_present_mode: PresentMode,
+    window_size: [u32; 2],
+    fullscreen: bool,
 
     player_controller: PlayerController,
     physics_controller: PhysicsController,
+    camera_recorder: Option<CameraRecorder>,
+    // Drives the camera instead of the inputs while set
+    camera_replay: Option<CameraReplay>,
+    #[cfg(feature = "gamepad")]
+    gilrs: Option<gilrs::Gilrs>,
 
     rcx: Option<RenderContext>,
 }
@@ -84,24 +685,356 @@ pub struct RenderContext {
     window: Arc<Window>,
     swapchain_id: Id<Swapchain>,
     virtual_swapchain_id: Id<Swapchain>,
-    pub swapchain_storage_image_ids: Vec<StorageImageId>,
+    supported_present_modes: Vec<PresentMode>,
+    present_mode: PresentMode,
+    swapchain_storage_image_ids: Vec<StorageImageId>,
+    accumulation_image_id: Id<Image>,
+    virtual_accumulation_image_id: Id<Image>,
+    pub accumulation_storage_image_id: StorageImageId,
+    // The normal and distance, then the instance custom index of the primary hits. Only written
+    // while a pass reads them
+    g_buffer: Option<PassImages>,
+    // The images the denoiser iterations ping-pong between
+    denoise_images: Option<PassImages>,
+    // Filters the noise of the ray traced image, the denoise task skips its dispatches when
+    // unset
+    pub denoise: bool,
+    render_scale: f32,
+    // The resolution rays are traced at, the window size scaled by `render_scale`
+    pub render_extent: [u32; 2],
+    // Only created when the render scale isn't 1, the rays are traced directly into the
+    // swapchain otherwise
+    render_image: Option<RenderImage>,
+    // The amount of frames blended in the accumulation image, 0 discards the history
+    pub accumulated_frames: u32,
+    // Sub-pixel offset of the primary rays, follows `accumulated_frames`
+    pub jitter: [f32; 2],
+    pub max_bounces: u32,
+    // Colors voxels by their chunk instead of their material
+    pub chunk_colors: bool,
+    // Shows the normals of the primary hits instead of the shaded image
+    pub debug_normals: bool,
+    // Shows the palette colors of the hits without lighting
+    pub flat_colors: bool,
+    // Ambient occlusion rays need a recursion depth of 2
+    pub ambient_occlusion: bool,
+    // Index in `BACKGROUND_PRESETS`
+    pub background_preset: usize,
+    // Index in `APERTURE_PRESETS`
+    pub aperture_preset: usize,
+    // Shows the cost of each pixel instead of its color
+    pub heatmap: bool,
+    pub focus_distance: f32,
     // scene_params: tree64::SceneParams,
     pub rt_camera_data: raygen::Camera,
     pub rt_sunlight_data: raygen::Sunlight,
-    #[cfg(debug_assertions)]
+    // The view and projection of the line pipeline
     pub debug_constant_data: debug::shader::vert::PushConstants,
-    #[cfg(debug_assertions)]
+    // The voxel the crosshair points at within the edit range, outlined by the highlight task
+    pub highlighted_voxel: Option<IVec3>,
     pub viewport: Viewport,
+    pub overlay_stats: OverlayStats,
     recreate_swapchain: bool,
+    // Set by `take_screenshot` until a frame is captured
+    screenshot_requested: bool,
+    // The host buffer the screenshot task copies the swapchain image to, only set while the
+    // captured frame executes
+    pub screenshot_buffer_id: Option<Id<Buffer>>,
     task_graph: ExecutableTaskGraph<Self>,
-    channel: mpsc::Sender<()>,
+    render_node_id: NodeId,
+    // Draws only the chunk wireframes instead of ray tracing
+    #[cfg(debug_assertions)]
+    pub wireframe: bool,
+    #[cfg(debug_assertions)]
+    wireframe_task_graph: ExecutableTaskGraph<Self>,
+    #[cfg(debug_assertions)]
+    wireframe_swapchain_id: Id<Swapchain>,
+    #[cfg(debug_assertions)]
+    wireframe_node_id: NodeId,
+    #[cfg(feature = "egui")]
+    pub tuning_ui: TuningUi,
+    // The palette index shown in the material editor of the tuning window
+    #[cfg(feature = "egui")]
+    pub edited_material: u8,
+    #[cfg(feature = "egui")]
+    egui_node_id: NodeId,
+    // Shared with the TLAS update task running on the worker
+    tlas_timer: Arc<GpuTimer>,
+    channel: mpsc::Sender<WorkerRequest>,
+}
+
+// The image rays are traced into before being upscaled to the swapchain
+struct RenderImage {
+    image_id: Id<Image>,
+    virtual_image_id: Id<Image>,
+    storage_image_id: StorageImageId,
+}
+
+// Images sized like the accumulation image, only part of the task graph when the pass reading
+// them is. The task graph needs an image bound to each of its virtual images, so single texel
+// stand-ins are bound until the pass is first enabled
+struct PassImages {
+    formats: [Format; 2],
+    image_ids: [Id<Image>; 2],
+    virtual_image_ids: [Id<Image>; 2],
+    storage_image_ids: [StorageImageId; 2],
+    allocated: bool,
+}
+
+impl PassImages {
+    fn new(
+        resources: &Resources,
+        task_graph: &mut TaskGraph<RenderContext>,
+        formats: [Format; 2],
+        extent: [u32; 2],
+        allocate: bool,
+    ) -> Self {
+        let image_extent = if allocate { extent } else { [1, 1] };
+        let images = formats.map(|format| storage_image_setup(resources, format, image_extent));
+
+        Self {
+            formats,
+            image_ids: images.map(|(image_id, _)| image_id),
+            virtual_image_ids: formats
+                .map(|format| task_graph.add_image(&storage_image_create_info(format, extent))),
+            storage_image_ids: images.map(|(_, storage_image_id)| storage_image_id),
+            allocated: allocate,
+        }
+    }
+
+    fn recreate(&mut self, resources: &Resources, extent: [u32; 2]) {
+        let images = self
+            .formats
+            .map(|format| storage_image_setup(resources, format, extent));
+
+        self.image_ids = images.map(|(image_id, _)| image_id);
+        self.storage_image_ids = images.map(|(_, storage_image_id)| storage_image_id);
+    }
+
+    // Replaces the stand-ins with images of the extent, the frames in flight may still bind them
+    fn allocate(&mut self, resources: &Resources, extent: [u32; 2]) {
+        if self.allocated {
+            return;
+        }
+
+        let mut batch = resources.create_deferred_batch();
+
+        for (&image_id, &storage_image_id) in self.image_ids.iter().zip(&self.storage_image_ids) {
+            batch.destroy_storage_image(storage_image_id);
+            batch.destroy_image(image_id);
+        }
+
+        batch.enqueue();
+
+        self.recreate(resources, extent);
+        self.allocated = true;
+    }
+}
+
+impl RenderContext {
+    // Recreates the swapchain and every resource sized after it. Resize events only flag the
+    // recreation, so dragging the window edge recreates them at most once per frame. The tasks
+    // only know the virtual resources, the new ones are bound through the resource map
+    fn on_resize(&mut self, resources: &Resources, new_size: PhysicalSize<u32>) {
+        self.swapchain_id = resources
+            .recreate_swapchain(self.swapchain_id, |create_info| SwapchainCreateInfo {
+                image_extent: new_size.into(),
+                present_mode: self.present_mode,
+                ..create_info.clone()
+            })
+            .expect("failed to recreate swapchain");
+
+        self.viewport = Viewport {
+            offset: [0.0, 0.0],
+            extent: new_size.into(),
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+
+        // The old ids are replaced right after, so each image is only destroyed once
+        let mut batch = resources.create_deferred_batch();
+
+        for &id in &self.swapchain_storage_image_ids {
+            batch.destroy_storage_image(id);
+        }
+
+        batch.destroy_storage_image(self.accumulation_storage_image_id);
+        batch.destroy_image(self.accumulation_image_id);
+
+        // The stand-ins are kept until the images are allocated
+        for images in [&self.g_buffer, &self.denoise_images]
+            .into_iter()
+            .flatten()
+            .filter(|images| images.allocated)
+        {
+            for (&image_id, &storage_image_id) in
+                images.image_ids.iter().zip(&images.storage_image_ids)
+            {
+                batch.destroy_storage_image(storage_image_id);
+                batch.destroy_image(image_id);
+            }
+        }
+
+        if let Some(render_image) = &self.render_image {
+            batch.destroy_storage_image(render_image.storage_image_id);
+            batch.destroy_image(render_image.image_id);
+        }
+
+        batch.enqueue();
+
+        // The surface may give a different number of images to the new swapchain, the storage
+        // images are rebuilt from its images so every image index stays in bounds
+        let image_count = self.swapchain_storage_image_ids.len();
+        self.swapchain_storage_image_ids =
+            window_size_dependent_setup(resources, self.swapchain_id);
+
+        if self.swapchain_storage_image_ids.len() != image_count {
+            println!(
+                "The swapchain now has {} images instead of {image_count}",
+                self.swapchain_storage_image_ids.len()
+            );
+        }
+
+        self.render_extent = scaled_extent(new_size.into(), self.render_scale);
+
+        (
+            self.accumulation_image_id,
+            self.accumulation_storage_image_id,
+        ) = storage_image_setup(resources, Format::R32G32B32A32_SFLOAT, self.render_extent);
+
+        for images in [&mut self.g_buffer, &mut self.denoise_images]
+            .into_iter()
+            .flatten()
+            .filter(|images| images.allocated)
+        {
+            images.recreate(resources, self.render_extent);
+        }
+
+        if let Some(render_image) = &mut self.render_image {
+            (render_image.image_id, render_image.storage_image_id) =
+                render_image_setup(resources, self.swapchain_id, self.render_extent);
+        }
+        self.accumulated_frames = 0;
+
+        self.recreate_swapchain = false;
+    }
+
+    // Records and submits the task graph of the current render mode
+    fn execute(&self) -> Result<(), ExecuteError> {
+        #[cfg(debug_assertions)]
+        if self.wireframe {
+            let resource_map = resource_map!(
+                &self.wireframe_task_graph,
+                self.wireframe_swapchain_id => self.swapchain_id,
+            )
+            .unwrap();
+
+            return unsafe {
+                self.wireframe_task_graph
+                    .execute(resource_map, self, || self.window.pre_present_notify())
+            };
+        }
+
+        let mut resource_map = resource_map!(
+            &self.task_graph,
+            self.virtual_swapchain_id => self.swapchain_id,
+            self.virtual_accumulation_image_id => self.accumulation_image_id,
+        )
+        .unwrap();
+
+        // The optional images are only part of the task graph when they were allocated
+        if let Some(render_image) = &self.render_image {
+            resource_map
+                .insert(render_image.virtual_image_id, render_image.image_id)
+                .unwrap();
+        }
+
+        for images in [&self.g_buffer, &self.denoise_images].into_iter().flatten() {
+            for (&virtual_image_id, &image_id) in
+                images.virtual_image_ids.iter().zip(&images.image_ids)
+            {
+                resource_map.insert(virtual_image_id, image_id).unwrap();
+            }
+        }
+
+        unsafe {
+            self.task_graph
+                .execute(resource_map, self, || self.window.pre_present_notify())
+        }
+    }
+
+    // The storage image bound to the swapchain image acquired for the frame
+    pub fn swapchain_storage_image_id(&self, swapchain_image_index: u32) -> StorageImageId {
+        let image_count = self.swapchain_storage_image_ids.len();
+
+        *self
+            .swapchain_storage_image_ids
+            .get(swapchain_image_index as usize)
+            .unwrap_or_else(|| {
+                panic!(
+                    "swapchain image {swapchain_image_index} is out of the {image_count} storage \
+                    images, the swapchain was recreated without them"
+                )
+            })
+    }
+
+    // The storage image the rays of the frame are written to
+    pub fn render_target(&self, swapchain_image_index: u32) -> StorageImageId {
+        match &self.render_image {
+            Some(render_image) => render_image.storage_image_id,
+            None => self.swapchain_storage_image_id(swapchain_image_index),
+        }
+    }
+
+    pub fn g_buffer_storage_image_ids(&self) -> Option<[StorageImageId; 2]> {
+        self.g_buffer
+            .as_ref()
+            .map(|images| images.storage_image_ids)
+    }
+
+    pub fn denoise_storage_image_ids(&self) -> Option<[StorageImageId; 2]> {
+        self.denoise_images
+            .as_ref()
+            .map(|images| images.storage_image_ids)
+    }
+
+    // The heatmap shows the cost of the rays, the debug normals and the flat colors are exact,
+    // none of them is denoised
+    pub fn denoising(&self) -> bool {
+        self.denoise && !self.heatmap && !self.debug_normals && !self.flat_colors
+    }
+
+    // The G-buffer is only written for the passes reading it, the denoiser is the only one
+    pub fn writes_g_buffer(&self) -> bool {
+        self.g_buffer.is_some() && self.denoising()
+    }
+
+    // The background color push constant, a zero alpha keeps the sky
+    pub fn background_color(&self) -> [f32; 4] {
+        match BACKGROUND_PRESETS[self.background_preset] {
+            Some([r, g, b]) => [r, g, b, 1.0],
+            None => [0.0; 4],
+        }
+    }
 }
 
 impl App {
-    pub fn new(event_loop: &EventLoop<()>) -> Self {
-        let required_extensions = Surface::required_extensions(event_loop).unwrap();
+    pub fn new(event_loop: &EventLoop<()>) -> Result<Self, AppInitError> {
+        Self::with_config(event_loop, AppConfig::default())
+    }
+
+    // The config is validated again, since its fields can be set without the builder
+    pub fn with_config(
+        event_loop: &EventLoop<()>,
+        config: AppConfig,
+    ) -> Result<Self, AppInitError> {
+        config.validate().map_err(AppInitError::Config)?;
 
-        let library = unsafe { VulkanLibrary::new() }.unwrap();
+        let required_extensions = Surface::required_extensions(event_loop)
+            .map_err(AppInitError::vulkan("query the surface extensions"))?;
+
+        let library = unsafe { VulkanLibrary::new() }
+            .map_err(AppInitError::vulkan("load the Vulkan library"))?;
         let instance = Instance::new(
             &library,
             &InstanceCreateInfo {
@@ -113,51 +1046,79 @@ impl App {
                 ..Default::default()
             },
         )
-        .unwrap();
+        .map_err(AppInitError::vulkan("create the Vulkan instance"))?;
 
         let device_extensions = DeviceExtensions {
             khr_acceleration_structure: true,
             khr_deferred_host_operations: true,
-            khr_ray_tracing_maintenance1: true,
-            khr_ray_tracing_pipeline: true,
             khr_synchronization2: true,
-            khr_shader_clock: true,
             khr_swapchain: true,
             ..BindlessContext::required_extensions(&instance)
         };
         let device_features = DeviceFeatures {
             acceleration_structure: true,
             descriptor_binding_acceleration_structure_update_after_bind: true,
-            ray_tracing_pipeline: true,
             buffer_device_address: true,
             storage_push_constant8: true,
             synchronization2: true,
             shader_float64: true,
             shader_int64: true,
             shader_int8: true,
-            shader_subgroup_clock: true,
             storage_buffer8_bit_access: true,
             ..BindlessContext::required_features(&instance)
         };
 
-        let (physical_device, graphics_family_index) = instance
+        let mut unsuitable_devices = Vec::new();
+
+        let (physical_device, graphics_family_index, render_path) = instance
             .enumerate_physical_devices()
-            .unwrap()
-            .filter(|p| {
-                p.supported_extensions().contains(&device_extensions)
-                    && p.supported_features().contains(&device_features)
-            })
+            .map_err(AppInitError::vulkan("enumerate the devices"))?
             .filter_map(|p| {
-                p.queue_family_properties()
+                let graphics_family_index = p
+                    .queue_family_properties()
                     .iter()
                     .enumerate()
                     .position(|(i, q)| {
                         q.queue_flags.intersects(QueueFlags::GRAPHICS)
-                            && p.presentation_support(i as u32, event_loop).unwrap()
-                    })
-                    .map(|i| (p, i as u32))
+                            && p.presentation_support(i as u32, event_loop)
+                                .unwrap_or(false)
+                    });
+
+                // The ray tracing pipeline is preferred, the ray query path is the fallback
+                let render_path = [RenderPath::Pipeline, RenderPath::RayQuery]
+                    .into_iter()
+                    .find(|path| {
+                        p.supported_extensions()
+                            .contains(&path.required_extensions())
+                            && p.supported_features().contains(&path.required_features())
+                    });
+                let required_path = render_path.unwrap_or_default();
+
+                let unsuitable = UnsuitableDevice {
+                    name: p.properties().device_name.clone(),
+                    missing_extensions: device_extensions
+                        .union(&required_path.required_extensions())
+                        .difference(p.supported_extensions()),
+                    missing_features: device_features
+                        .union(&required_path.required_features())
+                        .difference(p.supported_features()),
+                    missing_present_queue: graphics_family_index.is_none(),
+                };
+
+                match (graphics_family_index, render_path) {
+                    (Some(i), Some(render_path))
+                        if unsuitable.missing_extensions.is_empty()
+                            && unsuitable.missing_features.is_empty() =>
+                    {
+                        Some((p, i as u32, render_path))
+                    }
+                    _ => {
+                        unsuitable_devices.push(unsuitable);
+                        None
+                    }
+                }
             })
-            .min_by_key(|(p, _)| match p.properties().device_type {
+            .min_by_key(|(p, _, _)| match p.properties().device_type {
                 PhysicalDeviceType::DiscreteGpu => 0,
                 PhysicalDeviceType::IntegratedGpu => 1,
                 PhysicalDeviceType::VirtualGpu => 2,
@@ -165,16 +1126,45 @@ impl App {
                 PhysicalDeviceType::Other => 4,
                 _ => 5,
             })
-            .unwrap();
+            .ok_or(AppInitError::NoSuitableDevice(unsuitable_devices))?;
 
-        let compute_family_index = physical_device
+        let device_info = DeviceInfo::new(&physical_device, render_path);
+        println!("Device: {device_info}");
+
+        // Only the heatmap reads the shader clock, it's left out on devices without one
+        let heatmap_extensions = DeviceExtensions {
+            khr_shader_clock: true,
+            ..DeviceExtensions::empty()
+        };
+        let heatmap_features = DeviceFeatures {
+            shader_subgroup_clock: true,
+            ..DeviceFeatures::empty()
+        };
+        let heatmap_supported = render_path == RenderPath::Pipeline
+            && physical_device
+                .supported_extensions()
+                .contains(&heatmap_extensions)
+            && physical_device
+                .supported_features()
+                .contains(&heatmap_features);
+        let (device_extensions, device_features) = if heatmap_supported {
+            (
+                device_extensions.union(&heatmap_extensions),
+                device_features.union(&heatmap_features),
+            )
+        } else {
+            (device_extensions, device_features)
+        };
+
+        // The graphics family can always run compute and transfer work, even when it doesn't
+        // report the transfer flag, so it's the fallback
+        let compute_family_index = physical_device
             .queue_family_properties()
             .iter()
             .enumerate()
             .filter(|(_, q)| q.queue_flags.intersects(QueueFlags::COMPUTE))
             .min_by_key(|(_, q)| q.queue_flags.count())
-            .unwrap()
-            .0 as u32;
+            .map_or(graphics_family_index, |(i, _)| i as u32);
 
         let transfer_family_index = physical_device
             .queue_family_properties()
@@ -182,44 +1172,79 @@ impl App {
             .enumerate()
             .filter(|(_, q)| q.queue_flags.intersects(QueueFlags::TRANSFER))
             .min_by_key(|(_, q)| q.queue_flags.count())
-            .unwrap()
-            .0 as u32;
-
-        let (device, mut queues) = {
-            let mut queue_create_infos = vec![QueueCreateInfo {
-                queue_family_index: graphics_family_index,
-                ..Default::default()
-            }];
-
-            queue_create_infos.push(QueueCreateInfo {
-                queue_family_index: compute_family_index,
-                ..Default::default()
-            });
+            .map_or(graphics_family_index, |(i, _)| i as u32);
+
+        // A family may serve several roles but can only be requested once, with a queue per role
+        // as far as it has queues. Each role gets the family index in `families` and the index of
+        // its queue within the family
+        const QUEUE_PRIORITIES: [f32; 3] = [0.5; 3];
+
+        let mut families: Vec<(u32, usize)> = vec![];
+        let roles = [
+            graphics_family_index,
+            compute_family_index,
+            transfer_family_index,
+        ]
+        .map(|family| {
+            let queue_count =
+                physical_device.queue_family_properties()[family as usize].queue_count as usize;
+            let position = families
+                .iter()
+                .position(|&(index, _)| index == family)
+                .unwrap_or_else(|| {
+                    families.push((family, 0));
+                    families.len() - 1
+                });
+
+            let requested = &mut families[position].1;
+            let queue_index = (*requested).min(queue_count - 1);
+            *requested = (*requested + 1).min(queue_count);
+
+            (position, queue_index)
+        });
 
-            queue_create_infos.push(QueueCreateInfo {
-                queue_family_index: transfer_family_index,
-                ..Default::default()
-            });
+        let (device, queues) = {
+            let queue_create_infos = families
+                .iter()
+                .map(|&(queue_family_index, queue_count)| QueueCreateInfo {
+                    queue_family_index,
+                    queues: &QUEUE_PRIORITIES[..queue_count],
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>();
 
             Device::new(
                 &physical_device,
                 &DeviceCreateInfo {
-                    enabled_extensions: &device_extensions,
-                    enabled_features: &device_features,
+                    enabled_extensions: &device_extensions
+                        .union(&render_path.required_extensions()),
+                    enabled_features: &device_features.union(&render_path.required_features()),
                     queue_create_infos: &queue_create_infos,
                     ..Default::default()
                 },
             )
-            .unwrap()
+            .map_err(AppInitError::vulkan("create the device"))?
         };
 
-        let graphics_queue = queues.next().unwrap();
-        let compute_queue = queues.next().unwrap();
-        #[cfg(debug_assertions)]
-        let transfer_queue = queues.next().unwrap();
+        // The queues come family after family, in the order they were requested
+        let queues = queues.collect::<Vec<_>>();
+        let [graphics_queue, compute_queue, transfer_queue] =
+            roles.map(|(position, queue_index)| {
+                let first_queue = families[..position]
+                    .iter()
+                    .map(|&(_, queue_count)| queue_count)
+                    .sum::<usize>();
+
+                queues[first_queue + queue_index].clone()
+            });
 
         let memory_allocator = Arc::new(StandardMemoryAllocator::new(&device, &Default::default()));
 
+        let scratch_buffers = Mutex::new(ScratchBufferPool::new(
+            &device,
+            config.scratch_buffer_pool_size,
+        ));
+
         let resources = Resources::new(
             &device,
             &ResourcesCreateInfo {
@@ -227,55 +1252,125 @@ impl App {
                 ..Default::default()
             },
         )
-        .unwrap();
+        .map_err(AppInitError::vulkan("create the resources"))?;
 
-        let graphics_flight_id = resources.create_flight(MAX_FRAMES_IN_FLIGHT).unwrap();
-        let compute_flight_id = resources.create_flight(1).unwrap();
+        let graphics_flight_id = resources
+            .create_flight(config.max_frames_in_flight)
+            .map_err(AppInitError::vulkan("create the graphics flight"))?;
+        let compute_flight_id = resources
+            .create_flight(1)
+            .map_err(AppInitError::vulkan("create the compute flight"))?;
 
-        let max_instance_count = device
+        let device_max_instance_count = device
             .physical_device()
             .properties()
             .max_instance_count
-            .expect("Max instance count not found");
+            .ok_or("the max instance count is missing")
+            .map_err(AppInitError::vulkan("read the device limits"))?
+            .min(u32::MAX as u64);
+        let max_instance_count =
+            instance_limit(config.max_instance_count, device_max_instance_count);
+
+        // Ray queries are traced in a loop, so only the pipeline has a recursion limit
+        let max_ray_recursion_depth = match render_path {
+            RenderPath::Pipeline => device
+                .physical_device()
+                .properties()
+                .max_ray_recursion_depth
+                .ok_or("the max ray recursion depth is missing")
+                .map_err(AppInitError::vulkan("read the device limits"))?
+                .min(MAX_RAY_RECURSION_DEPTH),
+            RenderPath::RayQuery => MAX_RAY_RECURSION_DEPTH,
+        };
 
-        let max_instance_count = 1_000;
+        let voxel_data =
+            open_asset(&config.asset_path).map_err(|e| AppInitError::Asset(e.into()))?;
+        let world = Chunks::new(&voxel_data, config.world_size);
+        // The startup instances are picked around the origin
+        println!("World: {}", world.stats(&IVec3::ZERO));
 
-        dbg!(max_instance_count);
+        let (assets, asset_index) = asset_list(&config.asset_path);
 
-        let voxel_data = open_file("assets/custom.vox");
-        let world = Chunks::new(&voxel_data);
+        let player_controller = match (config.camera, world.bounds()) {
+            (Some((eye, target)), _) => PlayerController::looking_at(eye, target),
+            (None, Some((min, max))) => PlayerController::framing(min, max),
+            (None, None) => PlayerController::default(),
+        };
 
-        App {
+        Ok(App {
             close_requested: false,
 
             instance,
             device,
+            device_info,
 
             graphics_queue,
             compute_queue,
-            #[cfg(debug_assertions)]
             transfer_queue,
 
             memory_allocator,
+            scratch_buffers,
 
             resources,
             graphics_flight_id,
             compute_flight_id,
+            max_frames_in_flight: config.max_frames_in_flight,
+            min_swapchain_images: config.min_swapchain_images,
+            benchmark_frames: config.benchmark_frames,
+            tlas_updates: true,
+            tlas_step_requested: false,
+            tlas_updates_per_frame: Arc::new(AtomicU32::new(DEFAULT_UPDATES_PER_FRAME)),
+            occlusion_culler: OcclusionCuller::default(),
+            frame_index: 0,
+            lod_origin: IVec3::ZERO,
+            culling_frustum: None,
+            stream_radius: config.stream_radius,
+            visibility_radius: config.visibility_radius,
+            visibility_origin: None,
+            fog: [config.fog_start, config.fog_density],
+            ray_distances: [config.max_ray_distance, config.max_reflection_distance],
+            render_scale: config.render_scale,
+            hdr: config.hdr,
+            denoiser: config.denoiser,
 
             last_frame_update: Instant::now(),
             next_log_update: Instant::now().checked_add(Duration::from_secs(1)).unwrap(),
             delta_time: Duration::ZERO,
+            frame_stats: FrameStats::default(),
+            gpu_timings: GpuTimings::default(),
             focused: false,
+            modifiers: ModifiersState::empty(),
 
-            player_controller: PlayerController::default(),
+            player_controller,
             physics_controller: PhysicsController::new(),
+            camera_recorder: None,
+            camera_replay: None,
+            #[cfg(feature = "gamepad")]
+            gilrs: gilrs::Gilrs::new()
+                .inspect_err(|e| eprintln!("Gamepads are unavailable: {e}"))
+                .ok(),
 
             max_instance_count,
+            device_max_instance_count,
+            max_ray_recursion_depth,
+            render_path,
+            heatmap_supported,
             voxel_data,
+            assets,
+            asset_index,
             world,
+            world_commands: WorldCommandQueue::default(),
+            debug_scene: DebugScene::default(),
+            seed: config.seed,
+            sort_instances: config.sort_instances,
+            frustum_culling: config.frustum_culling,
+            geometry_kind: GeometryKind::default(),
+            preferred_present_mode: config.present_mode,
+            window_size: config.window_size,
+            fullscreen: config.fullscreen,
 
             rcx: None,
-        }
+        })
     }
 
     pub fn toggle_capture_mouse(&mut self) {
@@ -312,36 +1407,1432 @@ impl App {
         }
         self.last_frame_update = now;
         self.delta_time = delta;
+        self.frame_stats.push(delta);
+    }
+
+    pub fn device_info(&self) -> &DeviceInfo {
+        &self.device_info
+    }
+
+    pub fn frame_stats(&self) -> &FrameStats {
+        &self.frame_stats
+    }
+
+    // Read back one frame late, when the previous frame is waited on before the next one
+    pub fn gpu_timings(&self) -> GpuTimings {
+        self.gpu_timings
+    }
+
+    // The TLAS updates run on another queue, their timing is kept until a newer one is available
+    fn read_gpu_timings(&mut self) {
+        let tlas_update = self.rcx.as_ref().unwrap().tlas_timer.elapsed();
+
+        self.gpu_timings = GpuTimings {
+            ray_trace: self.render_task().timer.elapsed(),
+            tlas_update: tlas_update.or(self.gpu_timings.tlas_update),
+        };
+    }
+
+    pub fn update_camera(&mut self) {
+        #[cfg(feature = "gamepad")]
+        self.poll_gamepad();
+
+        if let Some(replay) = self.camera_replay.as_mut() {
+            match replay.advance(self.delta_time) {
+                Some(keyframe) => self.player_controller.set_keyframe(&keyframe),
+                None => {
+                    self.camera_replay = None;
+                    println!("Camera replay finished");
+                }
+            }
+        } else if self.physics_controller.walk_mode {
+            let previous = self.player_controller.translation;
+            self.player_controller.walk_movement(self.delta_time);
+
+            let (translation, blocked) = physics::move_and_slide(
+                &self.world,
+                previous,
+                self.player_controller.translation - previous,
+                physics::player_radius(),
+            );
+
+            self.player_controller
+                .resolve_collision(translation, blocked);
+        } else {
+            self.player_controller.fly_movement(self.delta_time);
+        }
+
+        if let Some(recorder) = self.camera_recorder.as_mut() {
+            recorder.record(self.delta_time, self.player_controller.keyframe());
+        }
+
+        let view = self.player_controller.view();
+
+        self.set_camera_view(view);
+
+        let rcx = self.rcx.as_mut().unwrap();
+        rcx.jitter = pixel_jitter(rcx.accumulated_frames);
+    }
+
+    // Starts recording the camera, or stops and saves the recording
+    pub fn toggle_camera_recording(&mut self) {
+        let Some(recorder) = self.camera_recorder.take() else {
+            self.camera_replay = None;
+            self.camera_recorder = Some(CameraRecorder::default());
+            println!("Recording the camera");
+            return;
+        };
+
+        let recording = recorder.finish();
+
+        match recording.save(Path::new(CAMERA_RECORDING_PATH)) {
+            Ok(()) => println!(
+                "Saved {:.1}s of camera recording to {CAMERA_RECORDING_PATH}",
+                recording.duration()
+            ),
+            Err(e) => eprintln!("Failed to save the camera recording: {e}"),
+        }
+    }
+
+    // Prints the camera pose and matrices of the current frame and saves them, to restore the
+    // view with `load_camera_dump`
+    pub fn dump_camera(&self) {
+        let camera = &self.rcx.as_ref().unwrap().rt_camera_data;
+        let dump = recording::camera_dump(
+            &self.player_controller.keyframe(),
+            &[
+                ("view_proj", camera.view_proj),
+                ("view_inverse", camera.view_inverse),
+                ("proj_inverse", camera.proj_inverse),
+            ],
+        );
+
+        print!("{dump}");
+
+        match std::fs::write(CAMERA_DUMP_PATH, dump) {
+            Ok(()) => println!("Saved the camera to {CAMERA_DUMP_PATH}"),
+            Err(e) => eprintln!("Failed to save the camera: {e}"),
+        }
+    }
+
+    pub fn load_camera_dump(&mut self) {
+        match recording::load_camera_dump(Path::new(CAMERA_DUMP_PATH)) {
+            Ok(keyframe) => {
+                self.camera_replay = None;
+                self.player_controller.set_keyframe(&keyframe);
+                println!("Loaded the camera from {CAMERA_DUMP_PATH}");
+            }
+            Err(e) => eprintln!("Failed to load the camera: {e}"),
+        }
+    }
+
+    // Replays the saved recording, or stops the current replay
+    pub fn toggle_camera_replay(&mut self, looping: bool) {
+        if self.camera_replay.take().is_some() {
+            return;
+        }
+
+        let recording = match CameraRecording::load(Path::new(CAMERA_RECORDING_PATH)) {
+            Ok(recording) => recording,
+            Err(e) => {
+                eprintln!("Failed to load the camera recording: {e}");
+                return;
+            }
+        };
+
+        self.camera_recorder = None;
+        self.camera_replay = CameraReplay::new(recording, looping);
+
+        if self.camera_replay.is_none() {
+            println!("The camera recording is empty");
+        }
+    }
+
+    // Feeds the sticks and triggers of every gamepad to the player controller, a disconnected
+    // gamepad stops moving the camera
+    #[cfg(feature = "gamepad")]
+    fn poll_gamepad(&mut self) {
+        let Some(gilrs) = self.gilrs.as_mut() else {
+            return;
+        };
+
+        while let Some(event) = gilrs.next_event() {
+            let (axis, value) = match event.event {
+                EventType::AxisChanged(Axis::LeftStickX, value, _) => {
+                    (GamepadAxis::LeftStickX, value)
+                }
+                EventType::AxisChanged(Axis::LeftStickY, value, _) => {
+                    (GamepadAxis::LeftStickY, value)
+                }
+                EventType::AxisChanged(Axis::RightStickX, value, _) => {
+                    (GamepadAxis::RightStickX, value)
+                }
+                EventType::AxisChanged(Axis::RightStickY, value, _) => {
+                    (GamepadAxis::RightStickY, value)
+                }
+                EventType::ButtonChanged(Button::LeftTrigger2, value, _) => {
+                    (GamepadAxis::LeftTrigger, value)
+                }
+                EventType::ButtonChanged(Button::RightTrigger2, value, _) => {
+                    (GamepadAxis::RightTrigger, value)
+                }
+                EventType::Disconnected => {
+                    self.player_controller.gamepad = GamepadState::default();
+                    continue;
+                }
+                _ => continue,
+            };
+
+            self.player_controller.handle_gamepad_axis(axis, value);
+        }
+    }
+
+    fn projection(&self) -> Mat4 {
+        let size = self.rcx.as_ref().unwrap().window.inner_size();
+
+        Mat4::perspective_lh(
+            PI / 2.0,
+            (size.width as f32) / (size.height as f32),
+            0.01,
+            10000.0,
+        )
+    }
+
+    fn set_camera_view(&mut self, view: Mat4) {
+        let proj = self.projection();
+        let rcx = self.rcx.as_mut().unwrap();
+
+        let [max_ray_distance, max_reflection_distance] =
+            self.ray_distances.map(distance_in_voxels);
+
+        let camera_data = raygen::Camera {
+            proj_inverse: proj.inverse().to_cols_array_2d(),
+            view_inverse: view.inverse().to_cols_array_2d(),
+            view_proj: (view * proj).to_cols_array_2d(),
+            aperture: APERTURE_PRESETS[rcx.aperture_preset],
+            focus_distance: rcx.focus_distance,
+            max_ray_distance,
+            max_reflection_distance,
+        };
+
+        if camera_data.view_proj != rcx.rt_camera_data.view_proj
+            || camera_data.aperture != rcx.rt_camera_data.aperture
+            || camera_data.focus_distance != rcx.rt_camera_data.focus_distance
+            || camera_data.max_ray_distance != rcx.rt_camera_data.max_ray_distance
+            || camera_data.max_reflection_distance != rcx.rt_camera_data.max_reflection_distance
+        {
+            rcx.accumulated_frames = 0;
+        }
+
+        rcx.rt_camera_data = camera_data;
+
+        rcx.debug_constant_data = debug::shader::vert::PushConstants {
+            world: Mat4::default().to_cols_array_2d(),
+            view: view.to_cols_array_2d(),
+            proj: proj.to_cols_array_2d(),
+        };
+    }
+
+    // Removes the voxel under the crosshair, or places one against it
+    pub fn edit_voxel(&mut self, place: bool) {
+        let origin = self.player_controller.translation;
+        let direction = self.player_controller.forward();
+
+        let Some(hit) = self.world.raycast(origin, direction, VOXEL_EDIT_RANGE) else {
+            return;
+        };
+
+        // Nothing is placed from inside of a voxel, there's no face to place against
+        let edited = if place {
+            let voxel = self.world.get_voxel(&hit.position);
+
+            (hit.normal != IVec3::ZERO)
+                .then_some(hit.position + hit.normal)
+                .zip(voxel)
+                .and_then(|(position, voxel)| self.world.insert(position, voxel))
+        } else {
+            self.world.remove_voxel(&hit.position).map(|_| hit.position)
+        };
+
+        if edited.is_some() {
+            self.occlusion_culler.invalidate();
+            self.rebuild_world_instances();
+        }
+    }
+
+    // Follows the crosshair every frame, the world may change under it without the camera moving
+    fn update_voxel_highlight(&mut self) {
+        let origin = self.player_controller.translation;
+        let direction = self.player_controller.forward();
+
+        self.rcx.as_mut().unwrap().highlighted_voxel = self
+            .world
+            .raycast(origin, direction, VOXEL_EDIT_RANGE)
+            .map(|hit| hit.position);
+    }
+
+    // Other threads edit the world through this sender, the commands are applied at the start
+    // of the next frames
+    pub fn world_command_sender(&self) -> mpsc::Sender<WorldCommand> {
+        self.world_commands.sender()
+    }
+
+    fn apply_world_commands(&mut self) {
+        if self
+            .world_commands
+            .drain(&mut self.world, MAX_WORLD_COMMANDS_PER_FRAME)
+        {
+            self.occlusion_culler.invalidate();
+            self.rebuild_world_instances();
+        }
+    }
+
+    pub fn rebuild_world_instances(&mut self) {
+        // Waits for a running TLAS update, the worker can't start another one until the rebuild
+        // is done
+        let tlas_lock = self.render_task().tlas_lock.clone();
+        let _tlas_guard = tlas_lock.lock().unwrap();
+
+        self.resources
+            .flight(self.graphics_flight_id)
+            .unwrap()
+            .wait_idle()
+            .unwrap();
+
+        self.resources
+            .flight(self.compute_flight_id)
+            .unwrap()
+            .wait_idle()
+            .unwrap();
+
+        self.lod_origin = self.camera_chunk();
+
+        self.culling_frustum = self.frustum_culling.then(|| self.wide_frustum());
+
+        let mut instances = self.world.to_instances(
+            &self.player_controller.translation.as_ivec3(),
+            &self.render_task().blas_references(),
+            self.device_max_instance_count,
+            self.culling_frustum.as_ref(),
+        );
+
+        if self.sort_instances {
+            sort_instances_spatially(&mut instances);
+        }
+
+        if instances.len() as u64 > self.max_instance_count {
+            self.grow_instance_buffer(instances.len() as u64);
+        }
+
+        self.render_task().rebuild_instances(self, instances);
+
+        // The accumulated history no longer matches the scene
+        self.rcx.as_mut().unwrap().accumulated_frames = 0;
+
+        #[cfg(debug_assertions)]
+        if self.rcx.as_ref().unwrap().wireframe {
+            self.update_wireframe();
+        }
+    }
+
+    // Hides the chunks behind large solid boxes, every `OCCLUSION_INTERVAL` frames
+    pub fn cull_occluded_chunks(&mut self) {
+        self.frame_index += 1;
+
+        if !self.frame_index.is_multiple_of(OCCLUSION_INTERVAL) {
+            return;
+        }
+
+        let view_proj = self.projection() * self.player_controller.view();
+
+        if self.occlusion_culler.cull(&mut self.world, view_proj) {
+            self.rebuild_world_instances();
+        }
+    }
+
+    // The view frustum widened by `CULLING_FOV` and `CULLING_MARGIN`, twice as deep
+    fn wide_frustum(&mut self) -> Frustum {
+        let size = self.rcx.as_ref().unwrap().window.inner_size();
+        let proj = Mat4::perspective_lh(
+            CULLING_FOV,
+            (size.width as f32) / (size.height as f32),
+            0.01,
+            20000.0,
+        );
+
+        Frustum::from_view_proj(
+            proj * Mat4::from_translation(Vec3::Z * CULLING_MARGIN) * self.player_controller.view(),
+        )
+    }
+
+    // Culls the world instances again once the view leaves the volume they were culled with
+    pub fn update_frustum_culling(&mut self) {
+        if !self.frustum_culling || self.debug_scene != DebugScene::World {
+            return;
+        }
+
+        let view_proj = self.projection() * self.player_controller.view();
+
+        if self.culling_frustum.is_none_or(|frustum| {
+            !Frustum::corners(view_proj)
+                .iter()
+                .all(|corner| frustum.contains_point(*corner))
+        }) {
+            self.rebuild_world_instances();
+        }
+    }
+
+    fn camera_chunk(&self) -> IVec3 {
+        self.world
+            .chunk_position(&self.player_controller.translation.as_ivec3())
+    }
+
+    // Hides the chunks past the visibility radius once the camera enters another chunk
+    pub fn update_chunk_visibility(&mut self) {
+        let camera_chunk = self.camera_chunk();
+
+        if self.visibility_origin == Some(camera_chunk) {
+            return;
+        }
+
+        self.visibility_origin = Some(camera_chunk);
+
+        if self
+            .world
+            .update_visibility_radius(camera_chunk, self.visibility_radius)
+            && self.debug_scene == DebugScene::World
+        {
+            self.rebuild_world_instances();
+        }
+    }
+
+    // Grows or shrinks the visibility radius by `delta` chunks, growing it past the corners of
+    // the world shows every chunk again
+    pub fn adjust_visibility_radius(&mut self, delta: i32) {
+        let max_radius = self.world.size().extent().as_vec3().length().ceil() as u32;
+        let radius = self
+            .visibility_radius
+            .unwrap_or(max_radius)
+            .saturating_add_signed(delta);
+
+        self.visibility_radius = (radius < max_radius).then_some(radius);
+        self.visibility_origin = None;
+
+        match self.visibility_radius {
+            Some(radius) => println!("Visibility radius: {radius} chunks"),
+            None => println!("Visibility radius: unlimited"),
+        }
+    }
+
+    // Picks new chunk LODs once the camera enters another chunk
+    pub fn update_lods(&mut self) {
+        if self.debug_scene == DebugScene::World && self.camera_chunk() != self.lod_origin {
+            self.rebuild_world_instances();
+        }
+    }
+
+    // Streams the chunks around the camera in and out, a few per frame
+    pub fn stream_chunks(&mut self) {
+        let Some(radius) = self.stream_radius else {
+            return;
+        };
+
+        let origin = self.player_controller.translation.as_ivec3();
+
+        if self.debug_scene == DebugScene::World && self.world.stream(&origin, radius) {
+            self.occlusion_culler.invalidate();
+            self.visibility_origin = None;
+            self.rebuild_world_instances();
+        }
+    }
+
+    // Recolors a palette entry without reloading the model
+    pub fn set_palette_color(&mut self, index: u8, color: Vec4) {
+        self.render_task()
+            .rt_resources
+            .set_palette_color(self, index, color);
+        let changed = self.world.set_material_translucent(index, color.w < 1.0);
+        self.update_translucent_materials(changed);
+        self.rcx.as_mut().unwrap().accumulated_frames = 0;
+    }
+
+    pub fn set_palette(&mut self, colors: &[Vec4; 256]) {
+        self.render_task().rt_resources.set_palette(self, colors);
+        let changed = self.world.set_translucent_materials(colors);
+        self.update_translucent_materials(changed);
+        self.rcx.as_mut().unwrap().accumulated_frames = 0;
+    }
+
+    // Overrides the color and material of a palette entry of the loaded model, saved by
+    // `save_material_overrides`
+    pub fn set_material(&mut self, index: u8, color: Vec4, material: Material) {
+        self.voxel_data.overrides.set(index, color, material);
+        self.render_task().rt_resources.reload_palette(self);
+        let changed = self.world.set_material_translucent(index, color.w < 1.0);
+        self.update_translucent_materials(changed);
+        self.rcx.as_mut().unwrap().accumulated_frames = 0;
+    }
+
+    // Writes the material overrides next to the loaded model, they are applied when it is
+    // loaded again
+    pub fn save_material_overrides(&self) {
+        let path = MaterialOverrides::sidecar_path(&self.assets[self.asset_index]);
+
+        match self.voxel_data.overrides.save(&path) {
+            Ok(()) => println!("Saved the material overrides to {}", path.display()),
+            Err(e) => eprintln!("Failed to save the material overrides: {e}"),
+        }
+    }
+
+    // Moves the voxels whose palette alpha crossed 1 between the opaque and translucent BLAS
+    fn update_translucent_materials(&mut self, changed: bool) {
+        if changed && self.debug_scene == DebugScene::World {
+            self.rebuild_world_instances();
+        }
+    }
+
+    // Reallocates the instance buffer, both TLAS and the scratch buffer of the TLAS updates for
+    // at least `instance_count` instances. No frame or TLAS update may be in flight
+    fn grow_instance_buffer(&mut self, instance_count: u64) {
+        let capacity = instance_count
+            .next_power_of_two()
+            .min(self.device_max_instance_count);
+
+        println!(
+            "Growing the instance buffer from {} to {capacity} instances",
+            self.max_instance_count
+        );
+
+        // The new tasks are created from the app, which holds the render context
+        let mut rcx = self.rcx.take().unwrap();
+        self.max_instance_count = capacity;
+
+        let rt_pass = rcx
+            .task_graph
+            .task_node_mut(rcx.render_node_id)
+            .unwrap()
+            .task_mut()
+            .downcast_mut::<RayTracingRenderTask>()
+            .unwrap();
+
+        rt_pass.grow(self, capacity);
+
+        let update_as_task = UpdateAccelerationStructureTask::new(
+            self,
+            rt_pass.instance_buffer_id,
+            rt_pass.blas[0].device_address().into(),
+            rt_pass.instance_count.clone(),
+            self.tlas_updates_per_frame.clone(),
+            rt_pass.dirty_instances.clone(),
+            rcx.tlas_timer.clone(),
+        );
+
+        rcx.channel
+            .send(WorkerRequest::Resize(
+                update_as_task,
+                rt_pass.acceleration_structures.clone(),
+            ))
+            .unwrap();
+
+        self.rcx = Some(rcx);
+    }
+
+    fn render_task(&self) -> &RayTracingRenderTask {
+        let rcx = self.rcx.as_ref().unwrap();
+
+        rcx.task_graph
+            .task_node(rcx.render_node_id)
+            .unwrap()
+            .task()
+            .downcast_ref::<RayTracingRenderTask>()
+            .unwrap()
+    }
+
+    // Switches between ray tracing and drawing only the chunk wireframes
+    #[cfg(debug_assertions)]
+    pub fn toggle_wireframe(&mut self) {
+        let wireframe = !self.rcx.as_ref().unwrap().wireframe;
+
+        if wireframe {
+            self.update_wireframe();
+        }
+
+        let rcx = self.rcx.as_mut().unwrap();
+        rcx.wireframe = wireframe;
+        rcx.accumulated_frames = 0;
     }
 
-    pub fn update_camera(&mut self) {
+    // Uploads the wireframes of the current chunks
+    #[cfg(debug_assertions)]
+    fn update_wireframe(&mut self) {
+        self.resources
+            .flight(self.graphics_flight_id)
+            .unwrap()
+            .wait_idle()
+            .unwrap();
+
+        let (vertex_buffer_id, vertex_count) = debug::line_buffer(self, &self.world.debug_lines());
+
+        let rcx = self.rcx.as_mut().unwrap();
+        let task = rcx
+            .wireframe_task_graph
+            .task_node_mut(rcx.wireframe_node_id)
+            .unwrap()
+            .task_mut()
+            .downcast_mut::<DrawDebugTask>()
+            .unwrap();
+
+        let mut batch = self.resources.create_deferred_batch();
+        batch.destroy_buffer(task.vertex_buffer_id);
+        batch.enqueue();
+
+        task.vertex_buffer_id = vertex_buffer_id;
+        task.vertex_count = vertex_count;
+    }
+
+    // A graph with the wireframe pass as its only pass, next to the ray tracing one
+    #[cfg(debug_assertions)]
+    fn create_wireframe_task_graph(
+        &self,
+    ) -> (ExecutableTaskGraph<RenderContext>, Id<Swapchain>, NodeId) {
+        let mut task_graph = TaskGraph::new(&self.resources);
+
+        let virtual_swapchain_id = task_graph.add_swapchain(&SwapchainCreateInfo::default());
+
+        let wireframe_node_id = task_graph
+            .create_task_node(
+                "Wireframe",
+                QueueFamilyType::Graphics,
+                DrawDebugTask::new(self, virtual_swapchain_id),
+            )
+            .color_attachment(
+                virtual_swapchain_id.current_image_id(),
+                AccessTypes::COLOR_ATTACHMENT_WRITE,
+                ImageLayoutType::Optimal,
+                &AttachmentInfo {
+                    clear: true,
+                    ..Default::default()
+                },
+            )
+            .build();
+
+        let screenshot_node_id = task_graph
+            .create_task_node(
+                "Screenshot",
+                QueueFamilyType::Graphics,
+                ScreenshotTask::new(virtual_swapchain_id),
+            )
+            .image_access(
+                virtual_swapchain_id.current_image_id(),
+                AccessTypes::COPY_TRANSFER_READ,
+                ImageLayoutType::General,
+            )
+            .build();
+
+        task_graph
+            .add_edge(wireframe_node_id, screenshot_node_id)
+            .unwrap();
+
+        let mut task_graph = unsafe {
+            task_graph.compile(&CompileInfo {
+                queues: &[&self.graphics_queue],
+                present_queue: Some(&self.graphics_queue),
+                flight_id: self.graphics_flight_id,
+                ..Default::default()
+            })
+        }
+        .unwrap();
+
+        let node = task_graph.task_node_mut(wireframe_node_id).unwrap();
+        let pipeline = debug::line_pipeline(self, node.subpass().unwrap());
+
+        node.task_mut()
+            .downcast_mut::<DrawDebugTask>()
+            .unwrap()
+            .pipeline = Some(pipeline);
+
+        (task_graph, virtual_swapchain_id, wireframe_node_id)
+    }
+
+    pub fn cycle_aperture(&mut self) {
+        let rcx = self.rcx.as_mut().unwrap();
+
+        rcx.aperture_preset = (rcx.aperture_preset + 1) % APERTURE_PRESETS.len();
+
+        println!("Aperture: {}", APERTURE_PRESETS[rcx.aperture_preset]);
+    }
+
+    // The accumulated frames are kept, they hold the radiance before the exposure
+    pub fn scale_exposure(&mut self, factor: f32) {
+        let sunlight = &mut self.rcx.as_mut().unwrap().rt_sunlight_data;
+
+        sunlight.exposure = (sunlight.exposure * factor).clamp(1.0 / 64.0, 64.0);
+
+        println!("Exposure: {}", sunlight.exposure);
+    }
+
+    pub fn cycle_tonemap(&mut self) {
+        let sunlight = &mut self.rcx.as_mut().unwrap().rt_sunlight_data;
+
+        sunlight.tonemap = (sunlight.tonemap + 1) % TONEMAP_OPERATORS.len() as u32;
+
+        println!("Tonemap: {}", TONEMAP_OPERATORS[sunlight.tonemap as usize]);
+    }
+
+    // Focuses on the voxel under the crosshair
+    pub fn focus_on_center(&mut self) {
+        let origin = self.player_controller.translation;
+        let direction = self.player_controller.forward();
+
+        let Some(hit) = self.world.raycast(origin, direction, FOCUS_RANGE) else {
+            println!("Nothing to focus on");
+            return;
+        };
+
+        let focus_distance = (hit.position.as_vec3() - origin).dot(direction);
+        self.rcx.as_mut().unwrap().focus_distance = focus_distance;
+
+        println!("Focus distance: {focus_distance:.1}");
+    }
+
+    pub fn toggle_heatmap(&mut self) {
+        if !self.heatmap_supported {
+            println!("The heatmap is not supported by the device");
+            return;
+        }
+
+        let rcx = self.rcx.as_mut().unwrap();
+
+        rcx.heatmap = !rcx.heatmap;
+        rcx.accumulated_frames = 0;
+
+        println!("Heatmap: {}", rcx.heatmap);
+    }
+
+    pub fn toggle_denoiser(&mut self) {
+        if !self.denoiser {
+            println!("The denoiser is disabled in the config");
+            return;
+        }
+
+        let rcx = self.rcx.as_mut().unwrap();
+
+        rcx.denoise = !rcx.denoise;
+
+        // The denoiser is the only pass reading the G-buffer
+        if rcx.denoise {
+            for images in [&mut rcx.g_buffer, &mut rcx.denoise_images]
+                .into_iter()
+                .flatten()
+            {
+                images.allocate(&self.resources, rcx.render_extent);
+            }
+        }
+
+        println!("Denoiser: {}", rcx.denoise);
+    }
+
+    pub fn toggle_chunk_colors(&mut self) {
+        let rcx = self.rcx.as_mut().unwrap();
+
+        rcx.chunk_colors = !rcx.chunk_colors;
+        rcx.accumulated_frames = 0;
+    }
+
+    pub fn toggle_debug_normals(&mut self) {
+        let rcx = self.rcx.as_mut().unwrap();
+
+        rcx.debug_normals = !rcx.debug_normals;
+        rcx.accumulated_frames = 0;
+        println!("Debug normals: {}", rcx.debug_normals);
+    }
+
+    pub fn toggle_flat_colors(&mut self) {
+        let rcx = self.rcx.as_mut().unwrap();
+
+        rcx.flat_colors = !rcx.flat_colors;
+        rcx.accumulated_frames = 0;
+        println!("Flat colors: {}", rcx.flat_colors);
+    }
+
+    pub fn cycle_background(&mut self) {
+        let rcx = self.rcx.as_mut().unwrap();
+
+        rcx.background_preset = (rcx.background_preset + 1) % BACKGROUND_PRESETS.len();
+        rcx.accumulated_frames = 0;
+
+        println!(
+            "Background: {:?}",
+            BACKGROUND_PRESETS[rcx.background_preset]
+        );
+    }
+
+    pub fn toggle_ambient_occlusion(&mut self) {
+        if self.max_ray_recursion_depth < 2 {
+            println!("Ambient occlusion is not supported by the device");
+            return;
+        }
+
+        let rcx = self.rcx.as_mut().unwrap();
+
+        rcx.ambient_occlusion = !rcx.ambient_occlusion;
+        rcx.accumulated_frames = 0;
+
+        println!("Ambient occlusion: {}", rcx.ambient_occlusion);
+    }
+
+    // Fades the voxels past `start` meters toward the horizon color, a density of 0 disables the
+    // fog
+    pub fn set_fog(&mut self, start: f32, density: f32) {
+        self.fog = [start.max(0.0), density.max(0.0)];
+
+        if let Some(rcx) = self.rcx.as_mut() {
+            [
+                rcx.rt_sunlight_data.fog_start,
+                rcx.rt_sunlight_data.fog_density,
+            ] = fog_in_voxels(self.fog);
+            rcx.accumulated_frames = 0;
+        }
+    }
+
+    // Bounds the traversal of the primary and reflection rays, in meters, from the next frame.
+    // Voxels past the distance show the sky, pairing it with the fog hides the cutoff
+    pub fn set_max_ray_distance(&mut self, primary: f32, reflection: f32) {
+        self.ray_distances = [primary, reflection];
+    }
+
+    pub fn next_animation_frame(&mut self) {
+        let frame = self.world.animation_frame() + 1;
+
+        if self.world.set_animation_frame(frame) {
+            self.occlusion_culler.invalidate();
+            self.rebuild_world_instances();
+        }
+    }
+
+    pub fn save_world(&self) {
+        match self.world.save(Path::new(WORLD_SAVE_PATH)) {
+            Ok(()) => println!("Saved world to {WORLD_SAVE_PATH}"),
+            Err(e) => eprintln!("Failed to save world: {e}"),
+        }
+    }
+
+    // Colors come from the palette of the loaded model, edits made with `set_palette` are lost
+    pub fn export_world(&self) {
+        match self
+            .world
+            .export_obj(Path::new(WORLD_EXPORT_PATH), &self.voxel_data.palette())
+        {
+            Ok(()) => println!("Exported world to {WORLD_EXPORT_PATH}"),
+            Err(e) => eprintln!("Failed to export world: {e}"),
+        }
+    }
+
+    // Rebuilds the world and the palette from another model, the world size is kept
+    pub fn select_model(&mut self, index: usize) {
+        let path = &self.assets[index];
+
+        let voxel_data = match open_asset(path) {
+            Ok(voxel_data) => voxel_data,
+            Err(e) => {
+                eprintln!("Failed to load {}: {e}", path.display());
+                return;
+            }
+        };
+
+        println!("Loaded {}", path.display());
+
+        self.world = Chunks::new(&voxel_data, self.world.size());
+        self.voxel_data = voxel_data;
+        self.asset_index = index;
+
+        let stats = self
+            .world
+            .stats(&self.player_controller.translation.as_ivec3());
+        println!("World: {stats}");
+
+        if stats.instances as u64 > self.device_max_instance_count {
+            eprintln!(
+                "The model needs {} instances, only the {} closest are kept",
+                stats.instances, self.device_max_instance_count
+            );
+        }
+
+        self.render_task().rt_resources.reload_palette(self);
+        self.occlusion_culler.invalidate();
+        self.visibility_origin = None;
+        self.rebuild_world_instances();
+    }
+
+    pub fn cycle_model(&mut self) {
+        if self.assets.len() > 1 {
+            self.select_model((self.asset_index + 1) % self.assets.len());
+        }
+    }
+
+    pub fn load_world(&mut self) {
+        match Chunks::load(Path::new(WORLD_SAVE_PATH)) {
+            Ok(world) => {
+                self.world = world;
+                self.world
+                    .set_translucent_materials(&self.voxel_data.palette());
+                self.occlusion_culler.invalidate();
+                self.visibility_origin = None;
+                self.rebuild_world_instances();
+            }
+            Err(e) => eprintln!("Failed to load world: {e}"),
+        }
+    }
+
+    // Switches between FIFO and the best supported non-vsync present mode
+    pub fn toggle_vsync(&mut self) {
+        let rcx = self.rcx.as_mut().unwrap();
+
+        self.preferred_present_mode = if rcx.present_mode == PresentMode::Fifo {
+            [PresentMode::Mailbox, PresentMode::Immediate]
+                .into_iter()
+                .find(|mode| rcx.supported_present_modes.contains(mode))
+                .unwrap_or(PresentMode::Fifo)
+        } else {
+            PresentMode::Fifo
+        };
+
+        rcx.present_mode =
+            select_present_mode(&rcx.supported_present_modes, self.preferred_present_mode);
+        rcx.recreate_swapchain = true;
+
+        println!("Present mode: {:?}", rcx.present_mode);
+    }
+
+    // While paused the worker isn't woken up, so the last built TLAS keeps being rendered
+    pub fn toggle_tlas_updates(&mut self) {
+        self.tlas_updates = !self.tlas_updates;
+        self.tlas_step_requested = false;
+
+        println!("TLAS updates: {}", self.tlas_updates);
+    }
+
+    pub fn step_tlas_update(&mut self) {
+        if !self.tlas_updates {
+            self.tlas_step_requested = true;
+        }
+    }
+
+    // Switches the spatial sort of the world instances, the ray trace time of the overlay and
+    // of the benchmark shows its effect
+    pub fn toggle_instance_sorting(&mut self) {
+        self.sort_instances = !self.sort_instances;
+        println!("Spatial instance sorting: {}", self.sort_instances);
+
+        if self.debug_scene == DebugScene::World {
+            self.rebuild_world_instances();
+        }
+    }
+
+    // The shadows and reflections of the geometry out of view disappear while the culling is on
+    pub fn toggle_frustum_culling(&mut self) {
+        self.frustum_culling = !self.frustum_culling;
+        println!("Frustum culling: {}", self.frustum_culling);
+
+        if self.debug_scene == DebugScene::World {
+            self.rebuild_world_instances();
+        }
+    }
+
+    // Scales the instances moved per TLAS update, capped to the instance buffer. Halving down
+    // from 1 reaches 0, which leaves every instance in place
+    pub fn scale_tlas_updates(&mut self, factor: f32) {
+        let updates = self.tlas_updates_per_frame.load(Ordering::Relaxed);
+        let updates = if updates == 0 && factor > 1.0 {
+            1
+        } else {
+            (updates as f32 * factor) as u64
+        };
+        let updates = updates.min(self.max_instance_count) as u32;
+
+        self.tlas_updates_per_frame
+            .store(updates, Ordering::Relaxed);
+
+        println!("TLAS updates per frame: {updates}");
+    }
+
+    // Cycles the rendered TLAS between the live one, index 0 and index 1. The worker skips the
+    // updates of the locked TLAS, so unlocking resumes the swaps where they stopped. Locking waits
+    // for a running update to complete
+    pub fn cycle_tlas_lock(&mut self) {
+        let forced_index = {
+            let mut forced_index = self.render_task().forced_as_index.lock().unwrap();
+
+            *forced_index = match *forced_index {
+                None => Some(false),
+                Some(false) => Some(true),
+                Some(true) => None,
+            };
+
+            *forced_index
+        };
+
+        self.rcx.as_mut().unwrap().accumulated_frames = 0;
+
+        match forced_index {
+            Some(index) => println!("Rendering locked to TLAS {}", index as u32),
+            None => println!("Rendering the live TLAS"),
+        }
+    }
+
+    pub fn print_tlas_index(&self) {
+        let render_task = self.render_task();
+        let live_index = render_task.current_as_index.load(Ordering::Acquire) as u32;
+
+        match *render_task.forced_as_index.lock().unwrap() {
+            Some(index) => println!("Live TLAS: {live_index}, rendering TLAS {}", index as u32),
+            None => println!("Live TLAS: {live_index}"),
+        }
+    }
+
+    // Switches between borderless fullscreen on the monitor of the window and the configured
+    // window size, the swapchain and storage images follow on the next frame
+    pub fn toggle_fullscreen(&mut self) {
+        let rcx = self.rcx.as_mut().unwrap();
+
+        if rcx.window.fullscreen().is_some() {
+            rcx.window.set_fullscreen(None);
+
+            let [width, height] = self.window_size;
+            let _ = rcx
+                .window
+                .request_inner_size(PhysicalSize::new(width, height));
+        } else {
+            let monitor = rcx.window.current_monitor();
+            rcx.window
+                .set_fullscreen(Some(Fullscreen::Borderless(monitor)));
+        }
+
+        rcx.recreate_swapchain = true;
+    }
+
+    pub fn scale_sensitivity(&mut self, factor: f64) {
+        let sensitivity = self.player_controller.sensitivity() * factor;
+        self.player_controller.set_sensitivity(sensitivity);
+
+        println!(
+            "Mouse sensitivity: {}",
+            self.player_controller.sensitivity()
+        );
+    }
+
+    // Cycles through the bounce counts allowed by the pipeline recursion depth
+    pub fn cycle_max_bounces(&mut self) {
+        let rcx = self.rcx.as_mut().unwrap();
+
+        rcx.max_bounces = (rcx.max_bounces + 1) % self.max_ray_recursion_depth;
+        rcx.accumulated_frames = 0;
+
+        println!("Max bounces: {}", rcx.max_bounces);
+    }
+
+    pub fn toggle_overlay_stats(&mut self) {
+        let overlay_stats = &mut self.rcx.as_mut().unwrap().overlay_stats;
+
+        overlay_stats.visible = !overlay_stats.visible;
+    }
+
+    fn update_overlay_stats(&mut self) {
+        let fps = match self.frame_stats.avg().as_secs_f32() {
+            0.0 => 0,
+            avg => (1.0 / avg).round() as u32,
+        };
+
+        let overlay_stats = &mut self.rcx.as_mut().unwrap().overlay_stats;
+
+        overlay_stats.fps = fps;
+        overlay_stats.speed = (self.player_controller.speed * 100.0).round() as u32;
+    }
+
+    // Flies the camera along `path` and renders `frames` frames, without reading any input
+    pub fn run_benchmark(&mut self, path: &[CameraKeyframe], frames: u32) -> io::Result<()> {
+        let mut results = Vec::with_capacity(frames as usize);
+
+        for frame in 0..frames {
+            let t = frame as f32 / frames.saturating_sub(1).max(1) as f32;
+            let keyframe = benchmark::sample_path(path, t);
+
+            self.set_camera_view(PlayerController::view_from(
+                keyframe.position,
+                keyframe.yaw,
+                keyframe.pitch,
+            ));
+
+            self.rcx
+                .as_ref()
+                .unwrap()
+                .channel
+                .send(WorkerRequest::Update)
+                .unwrap();
+
+            let start = Instant::now();
+            self.render_frame();
+            let cpu = start.elapsed();
+
+            // Waiting for the frame keeps the timestamp queries from being reused while pending
+            self.resources
+                .flight(self.graphics_flight_id)
+                .unwrap()
+                .wait_idle()
+                .unwrap();
+
+            self.read_gpu_timings();
+
+            results.push(BenchmarkFrame {
+                cpu,
+                frame: start.elapsed(),
+                ray_trace: self.gpu_timings.ray_trace,
+                tlas_update: self.rcx.as_ref().unwrap().tlas_timer.elapsed(),
+            });
+        }
+
+        benchmark::write_csv(Path::new(BENCHMARK_CSV_PATH), &results)
+    }
+
+    // Records and submits one frame, recreating the swapchain first if needed
+    fn render_frame(&mut self) {
+        {
+            let rcx = self.rcx.as_mut().unwrap();
+
+            let window_size = rcx.window.inner_size();
+
+            if window_size.width == 0 || window_size.height == 0 {
+                return;
+            }
+
+            if rcx.recreate_swapchain {
+                rcx.on_resize(&self.resources, window_size);
+            }
+        }
+
+        self.resources
+            .flight(self.graphics_flight_id)
+            .unwrap()
+            .wait_idle()
+            .unwrap();
+
+        self.read_gpu_timings();
+
+        // The previous frame is done, so its egui buffers can be overwritten
+        #[cfg(feature = "egui")]
+        self.update_tuning_ui();
+
+        let rcx = self.rcx.as_mut().unwrap();
+
+        if rcx.screenshot_requested {
+            rcx.screenshot_buffer_id = screenshot_buffer_setup(&self.resources, rcx.swapchain_id);
+            rcx.screenshot_requested = rcx.screenshot_buffer_id.is_some();
+        }
+
+        let execute_result = rcx.execute();
+
+        let presented = match execute_result {
+            Ok(()) => {
+                rcx.accumulated_frames = rcx.accumulated_frames.saturating_add(1);
+                true
+            }
+            Err(ExecuteError::Swapchain {
+                error: VulkanError::OutOfDate,
+                ..
+            }) => {
+                rcx.recreate_swapchain = true;
+                false
+            }
+            Err(e) => {
+                panic!("failed to execute next frame: {e:?}");
+            }
+        };
+
+        // A frame that wasn't presented is captured again once the swapchain is recreated
+        if let Some(buffer_id) = rcx.screenshot_buffer_id.take() {
+            if presented {
+                self.save_screenshot(buffer_id);
+            }
+
+            let mut batch = self.resources.create_deferred_batch();
+            batch.destroy_buffer(buffer_id);
+            batch.enqueue();
+        }
+    }
+
+    // Shows or hides the window with the lighting parameters, it can only be used while the
+    // mouse isn't captured
+    #[cfg(feature = "egui")]
+    pub fn toggle_tuning_ui(&mut self) {
+        let tuning_ui = &mut self.rcx.as_mut().unwrap().tuning_ui;
+        tuning_ui.visible = !tuning_ui.visible;
+    }
+
+    // Runs the tuning window and uploads what it draws, the edited parameters apply to this
+    // frame
+    #[cfg(feature = "egui")]
+    fn update_tuning_ui(&mut self) {
+        let rcx = self.rcx.as_mut().unwrap();
+
+        if !rcx.tuning_ui.visible {
+            return;
+        }
+
+        let mut fog = self.fog;
+        let mut asset_index = self.asset_index;
+
+        let edited_material = rcx.edited_material;
+        let mut material_index = edited_material;
+        let previous_color = self.voxel_data.palette()[edited_material as usize];
+        let previous_material = self.voxel_data.materials()[edited_material as usize];
+        let mut material_color = previous_color;
+        let mut material = previous_material;
+        let mut save_materials = false;
+
+        let frame = rcx.tuning_ui.run(
+            &rcx.window,
+            TuningParams {
+                sunlight: &mut rcx.rt_sunlight_data,
+                fog: &mut fog,
+                ambient_occlusion: &mut rcx.ambient_occlusion,
+                max_bounces: &mut rcx.max_bounces,
+                max_ray_recursion_depth: self.max_ray_recursion_depth,
+                assets: &self.assets,
+                asset_index: &mut asset_index,
+                material_index: &mut material_index,
+                material_color: &mut material_color,
+                material: &mut material,
+                save_materials: &mut save_materials,
+            },
+        );
+
+        let extent = rcx.viewport.extent.map(|length| length as u32);
+
+        rcx.task_graph
+            .task_node_mut(rcx.egui_node_id)
+            .unwrap()
+            .task_mut()
+            .downcast_mut::<EguiTask>()
+            .unwrap()
+            .upload(
+                &self.resources,
+                &self.graphics_queue,
+                self.graphics_flight_id,
+                &frame.textures_delta,
+                &frame.primitives,
+                frame.pixels_per_point,
+                extent,
+            );
+
+        if frame.changed {
+            rcx.accumulated_frames = 0;
+            // Converts the fog to voxels
+            self.set_fog(fog[0], fog[1]);
+        }
+
+        // The values shown were the ones of the previous index when it changed
+        rcx.edited_material = material_index;
+
+        if material_index == edited_material
+            && (material_color != previous_color || material != previous_material)
+        {
+            self.set_material(material_index, material_color, material);
+        }
+
+        if save_materials {
+            self.save_material_overrides();
+        }
+
+        if asset_index != self.asset_index {
+            self.select_model(asset_index);
+        }
+    }
+
+    // Whether the tuning window used the event, it only gets the events while it is visible and
+    // the mouse isn't captured. The right button, F1 and key releases always reach the app, so
+    // the capture can be toggled and no key stays held
+    #[cfg(feature = "egui")]
+    fn tuning_ui_consumes(&mut self, event: &WindowEvent) -> bool {
+        let Some(rcx) = self.rcx.as_mut() else {
+            return false;
+        };
+
+        if !rcx.tuning_ui.visible || self.focused {
+            return false;
+        }
+
+        let consumed = rcx.tuning_ui.on_window_event(&rcx.window, event).consumed;
+
+        consumed
+            && !matches!(
+                event,
+                WindowEvent::MouseInput {
+                    button: MouseButton::Right,
+                    ..
+                } | WindowEvent::KeyboardInput {
+                    event: KeyEvent {
+                        state: ElementState::Released,
+                        ..
+                    },
+                    ..
+                } | WindowEvent::KeyboardInput {
+                    event: KeyEvent {
+                        logical_key: Key::Named(NamedKey::F1),
+                        ..
+                    },
+                    ..
+                }
+            )
+    }
+
+    // Gravity pulls the camera down in walk mode, replays aren't affected
+    fn update_physics(&mut self) {
+        let translation = self.physics_controller.request_update(
+            &self.world,
+            self.player_controller.translation,
+            self.player_controller.jump_pressed() && self.camera_replay.is_none(),
+        );
+
+        if self.camera_replay.is_none() {
+            self.player_controller.set_translation(translation);
+        }
+    }
+
+    // The camera collides with the voxels and falls in walk mode
+    pub fn toggle_walk_mode(&mut self) {
+        self.physics_controller.walk_mode = !self.physics_controller.walk_mode;
+
+        println!("Walk mode: {}", self.physics_controller.walk_mode);
+    }
+
+    pub fn teleport_to_densest_chunk(&mut self) {
+        match self.world.densest_chunk_center() {
+            Some(center) => self.player_controller.teleport(center),
+            None => println!("The world is empty"),
+        }
+    }
+
+    // Captures the next presented frame
+    pub fn take_screenshot(&mut self) {
+        self.rcx.as_mut().unwrap().screenshot_requested = true;
+    }
+
+    // Reads back the copied swapchain image, the PNG is encoded on another thread so rendering
+    // isn't held up by it
+    fn save_screenshot(&mut self, buffer_id: Id<Buffer>) {
+        self.resources
+            .flight(self.graphics_flight_id)
+            .unwrap()
+            .wait_idle()
+            .unwrap();
+
         let rcx = self.rcx.as_mut().unwrap();
+        rcx.screenshot_requested = false;
 
-        self.player_controller.fly_movement(self.delta_time);
-        let view = self.player_controller.view();
+        let (format, [width, height]) = {
+            let swapchain_state = self.resources.swapchain(rcx.swapchain_id).unwrap();
+            let swapchain = swapchain_state.swapchain();
 
-        let size = rcx.window.inner_size();
+            (swapchain.image_format(), swapchain.image_extent())
+        };
 
-        let proj = Mat4::perspective_lh(
-            PI / 2.0,
-            (size.width as f32) / (size.height as f32),
-            0.01,
-            10000.0,
-        );
+        let mut bytes = Vec::new();
 
-        rcx.rt_camera_data = raygen::Camera {
-            proj_inverse: proj.inverse().to_cols_array_2d(),
-            view_inverse: view.inverse().to_cols_array_2d(),
-            view_proj: (view * proj).to_cols_array_2d(),
-        };
+        unsafe {
+            vulkano_taskgraph::execute(
+                &self.transfer_queue,
+                &self.resources,
+                self.graphics_flight_id,
+                |_cbf, tcx| {
+                    bytes.extend_from_slice(tcx.read_buffer::<[u8]>(buffer_id, ..)?);
 
-        #[cfg(debug_assertions)]
-        {
-            rcx.debug_constant_data = debug::shader::vert::PushConstants {
-                world: Mat4::default().to_cols_array_2d(),
-                view: view.to_cols_array_2d(),
-                proj: proj.to_cols_array_2d(),
-            };
+                    Ok(())
+                },
+                [(buffer_id, HostAccessType::Read)],
+                [],
+                [],
+            )
+        }
+        .unwrap();
+
+        std::thread::spawn(move || {
+            let path = screenshot::timestamped_path();
+            // The format is checked before the buffer is created
+            let rgba = screenshot::to_rgba8(format, &bytes).unwrap();
+
+            match screenshot::write_png(&path, width, height, &rgba) {
+                Ok(()) => println!("Saved screenshot to {}", path.display()),
+                Err(e) => eprintln!("Failed to save screenshot: {e}"),
+            }
+        });
+    }
+
+    fn handle_key_pressed(&mut self, key: &Key) {
+        match key {
+            Key::Named(NamedKey::F5) => self.save_world(),
+            Key::Named(NamedKey::F6) => self.toggle_camera_recording(),
+            Key::Named(NamedKey::F7) => self.toggle_camera_replay(self.modifiers.shift_key()),
+            Key::Named(NamedKey::F8) => self.export_world(),
+            Key::Named(NamedKey::F9) => self.load_world(),
+            Key::Named(NamedKey::F10) => self.toggle_debug_normals(),
+            Key::Named(NamedKey::F11) => self.toggle_fullscreen(),
+            Key::Named(NamedKey::F12) => self.take_screenshot(),
+            Key::Named(NamedKey::Home) => self.player_controller.reset(),
+            Key::Named(NamedKey::End) if self.modifiers.shift_key() => self.load_camera_dump(),
+            Key::Named(NamedKey::End) => self.dump_camera(),
+            Key::Named(NamedKey::Insert) => self.toggle_flat_colors(),
+            Key::Named(NamedKey::Delete) => self.toggle_frustum_culling(),
+            Key::Named(NamedKey::PageUp) => self.scale_exposure(1.25),
+            Key::Named(NamedKey::PageDown) => self.scale_exposure(0.8),
+            Key::Named(NamedKey::ArrowUp) => self.scale_tlas_updates(2.0),
+            Key::Named(NamedKey::ArrowDown) => self.scale_tlas_updates(0.5),
+            Key::Named(NamedKey::ArrowRight) => self.adjust_visibility_radius(1),
+            Key::Named(NamedKey::ArrowLeft) => self.adjust_visibility_radius(-1),
+            #[cfg(feature = "egui")]
+            Key::Named(NamedKey::F1) => self.toggle_tuning_ui(),
+            Key::Named(NamedKey::F2) => self.toggle_denoiser(),
+            Key::Named(NamedKey::F3) => {
+                print!("{}", self.world.debug_dump_chunk(self.camera_chunk()))
+            }
+            Key::Named(NamedKey::F4) => self.toggle_instance_sorting(),
+            _ => match key.to_text() {
+                Some("r") => self.toggle_tlas_updates(),
+                Some("p") => self.step_tlas_update(),
+                Some("l") => self.cycle_tlas_lock(),
+                Some("k") => self.print_tlas_index(),
+                Some("v") => self.toggle_vsync(),
+                Some("h") => self.toggle_overlay_stats(),
+                Some("b") => self.cycle_max_bounces(),
+                Some("c") => self.toggle_chunk_colors(),
+                Some("x") => self.toggle_heatmap(),
+                Some("o") => self.toggle_ambient_occlusion(),
+                Some("g") => self.cycle_background(),
+                Some("m") => println!(
+                    "World: {}",
+                    self.world
+                        .stats(&self.player_controller.translation.as_ivec3())
+                ),
+                Some("n") => self.next_animation_frame(),
+                Some("t") => self.teleport_to_densest_chunk(),
+                Some("e") => self.toggle_walk_mode(),
+                Some("a") => self.cycle_aperture(),
+                Some("f") => self.focus_on_center(),
+                Some("u") => self.cycle_tonemap(),
+                Some("j") => self.cycle_model(),
+                #[cfg(debug_assertions)]
+                Some("w") => self.toggle_wireframe(),
+                Some("+") => self.scale_sensitivity(1.25),
+                Some("-") => self.scale_sensitivity(0.8),
+                Some("y") => {
+                    self.player_controller.free_fly = !self.player_controller.free_fly;
+                    println!("Free fly: {}", self.player_controller.free_fly);
+                }
+                Some("i") => {
+                    self.player_controller.invert_y = !self.player_controller.invert_y;
+                    println!("Invert Y: {}", self.player_controller.invert_y);
+                }
+                _ => {}
+            },
         }
     }
 
@@ -354,52 +2845,81 @@ impl App {
 
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let window_attributes =
-            WindowAttributes::default().with_inner_size(PhysicalSize::new(1920, 1080));
+        let [width, height] = self.window_size;
+        let window_attributes = WindowAttributes::default()
+            .with_inner_size(PhysicalSize::new(width, height))
+            .with_fullscreen(self.fullscreen.then_some(Fullscreen::Borderless(None)));
 
         let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
 
         let window_size = window.inner_size();
         let surface = Surface::from_window(&self.instance, &window).unwrap();
 
+        let supported_present_modes = self
+            .device
+            .physical_device()
+            .surface_present_modes(&surface, &Default::default())
+            .unwrap()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let present_mode =
+            select_present_mode(&supported_present_modes, self.preferred_present_mode);
+
         let swapchain_id = {
             let surface_capabilities = self
                 .device
                 .physical_device()
                 .surface_capabilities(&surface, &Default::default())
                 .unwrap();
-            let (image_format, image_color_space) = self
+            let usable_formats = self
                 .device
                 .physical_device()
                 .surface_formats(&surface, &Default::default())
                 .unwrap()
                 .into_iter()
-                .find(|(format, _)| {
+                .filter(|(format, _)| {
                     self.device
                         .physical_device()
                         .image_format_properties(&ImageFormatInfo {
                             format: *format,
-                            usage: ImageUsage::STORAGE | ImageUsage::COLOR_ATTACHMENT,
+                            usage: ImageUsage::STORAGE
+                                | ImageUsage::COLOR_ATTACHMENT
+                                | ImageUsage::TRANSFER_SRC
+                                | ImageUsage::TRANSFER_DST,
                             ..Default::default()
                         })
                         .unwrap()
                         .is_some()
                 })
-                .unwrap();
+                .collect::<Vec<_>>();
 
-            let present_mode = PresentMode::Immediate;
+            let (image_format, image_color_space) =
+                select_surface_format(&usable_formats, self.hdr);
+
+            if self.hdr
+                && OutputEncoding::new(image_format, image_color_space) == OutputEncoding::Sdr
+            {
+                eprintln!("The surface has no usable HDR color space, presenting in SDR");
+            }
 
             self.resources
                 .create_swapchain(
                     &surface,
                     &SwapchainCreateInfo {
                         present_mode,
-                        min_image_count: surface_capabilities
-                            .min_image_count
-                            .max(MIN_SWAPCHAIN_IMAGES),
+                        min_image_count: swapchain_image_count(
+                            self.min_swapchain_images,
+                            self.max_frames_in_flight,
+                            surface_capabilities.min_image_count,
+                            surface_capabilities.max_image_count,
+                        ),
                         image_format,
                         image_extent: window_size.into(),
-                        image_usage: ImageUsage::STORAGE | ImageUsage::COLOR_ATTACHMENT,
+                        image_usage: ImageUsage::STORAGE
+                            | ImageUsage::COLOR_ATTACHMENT
+                            | ImageUsage::TRANSFER_SRC
+                            | ImageUsage::TRANSFER_DST,
                         image_color_space,
                         composite_alpha: surface_capabilities
                             .supported_composite_alpha
@@ -419,13 +2939,76 @@ impl ApplicationHandler for App {
 
         let virtual_swapchain_id = task_graph.add_swapchain(&SwapchainCreateInfo::default());
 
-        let rt_pass =
-            RayTracingRenderTask::new(&self, virtual_swapchain_id, self.max_instance_count);
+        let render_extent = scaled_extent(window_size.into(), self.render_scale);
+
+        // The accumulation image holds the running average of the ray traced frames
+        let (accumulation_image_id, accumulation_storage_image_id) =
+            storage_image_setup(&self.resources, Format::R32G32B32A32_SFLOAT, render_extent);
+
+        let virtual_accumulation_image_id = task_graph.add_image(&storage_image_create_info(
+            Format::R32G32B32A32_SFLOAT,
+            render_extent,
+        ));
+
+        // Allocated with the denoise images, the denoiser being the only pass reading it
+        let g_buffer = self.denoiser.then(|| {
+            PassImages::new(
+                &self.resources,
+                &mut task_graph,
+                [Format::R32G32B32A32_SFLOAT, Format::R32_UINT],
+                render_extent,
+                false,
+            )
+        });
+
+        // The iterations filter radiance like the accumulation image holds. They're allocated
+        // when the denoiser is first enabled
+        let denoise_images = self.denoiser.then(|| {
+            PassImages::new(
+                &self.resources,
+                &mut task_graph,
+                [Format::R32G32B32A32_SFLOAT; 2],
+                render_extent,
+                false,
+            )
+        });
+
+        let render_image = (self.render_scale != 1.0).then(|| {
+            let (image_id, storage_image_id) =
+                render_image_setup(&self.resources, swapchain_id, render_extent);
+
+            RenderImage {
+                image_id,
+                virtual_image_id: task_graph.add_image(&render_image_create_info(
+                    &self.resources,
+                    swapchain_id,
+                    render_extent,
+                )),
+                storage_image_id,
+            }
+        });
+
+        let rt_pass = RayTracingRenderTask::new(
+            self,
+            virtual_swapchain_id,
+            self.max_instance_count,
+            self.geometry_kind,
+        );
+        self.max_instance_count = rt_pass.max_instance_count;
+
+        let tlas_timer = Arc::new(GpuTimer::new(
+            &self.device,
+            self.compute_queue.queue_family_index(),
+        ));
 
         let update_as_task = UpdateAccelerationStructureTask::new(
             self,
             rt_pass.instance_buffer_id,
-            rt_pass.blas.device_address().into(),
+            rt_pass.blas[0].device_address().into(),
+            rt_pass.instance_count.clone(),
+            self.tlas_updates_per_frame.clone(),
+            rt_pass.dirty_instances.clone(),
+            tlas_timer.clone(),
         );
 
         let (channel, receiver) = mpsc::channel();
@@ -440,18 +3023,203 @@ impl ApplicationHandler for App {
             rt_pass.acceleration_structures.clone(),
             rt_pass.current_as_index.clone(),
             rt_pass.show_current_index.clone(),
+            rt_pass.forced_as_index.clone(),
+            rt_pass.tlas_lock.clone(),
         );
 
+        let sunlight_storage_buffer_id = rt_pass.rt_resources.sunlight_storage_buffer_id;
+        let overlay_task = OverlayTask::new(self, virtual_swapchain_id);
+
+        let (storage_read, storage_write) = self.render_path.storage_access_types();
+
+        let render_target = render_image
+            .as_ref()
+            .map_or(virtual_swapchain_id.current_image_id(), |render_image| {
+                render_image.virtual_image_id
+            });
+
+        let render_node_id = {
+            let mut node =
+                task_graph.create_task_node("Render", QueueFamilyType::Graphics, rt_pass);
+
+            node.image_access(render_target, storage_write, ImageLayoutType::General)
+                .image_access(
+                    virtual_accumulation_image_id,
+                    storage_read | storage_write,
+                    ImageLayoutType::General,
+                );
+
+            if let Some(g_buffer) = &g_buffer {
+                for &image_id in &g_buffer.virtual_image_ids {
+                    node.image_access(image_id, storage_write, ImageLayoutType::General);
+                }
+            }
+
+            node.build()
+        };
+
+        // The last node writing the ray traced image
+        let mut image_node_id = render_node_id;
+
+        // Overwrites the ray traced image with the filtered one when the denoiser is on
+        if let (Some(g_buffer), Some(denoise_images)) = (&g_buffer, &denoise_images) {
+            let [ping_image_id, pong_image_id] = denoise_images.virtual_image_ids;
+
+            let denoise_node_id = task_graph
+                .create_task_node(
+                    "Denoise",
+                    QueueFamilyType::Graphics,
+                    DenoiseTask::new(self, virtual_swapchain_id, sunlight_storage_buffer_id),
+                )
+                .image_access(
+                    render_target,
+                    AccessTypes::COMPUTE_SHADER_STORAGE_WRITE,
+                    ImageLayoutType::General,
+                )
+                .image_access(
+                    virtual_accumulation_image_id,
+                    AccessTypes::COMPUTE_SHADER_STORAGE_READ,
+                    ImageLayoutType::General,
+                )
+                .image_access(
+                    g_buffer.virtual_image_ids[0],
+                    AccessTypes::COMPUTE_SHADER_STORAGE_READ,
+                    ImageLayoutType::General,
+                )
+                .image_access(
+                    ping_image_id,
+                    AccessTypes::COMPUTE_SHADER_STORAGE_READ
+                        | AccessTypes::COMPUTE_SHADER_STORAGE_WRITE,
+                    ImageLayoutType::General,
+                )
+                .image_access(
+                    pong_image_id,
+                    AccessTypes::COMPUTE_SHADER_STORAGE_READ
+                        | AccessTypes::COMPUTE_SHADER_STORAGE_WRITE,
+                    ImageLayoutType::General,
+                )
+                .build();
+
+            task_graph
+                .add_edge(render_node_id, denoise_node_id)
+                .unwrap();
+
+            image_node_id = denoise_node_id;
+        }
+
+        // The overlay is composited on top of the ray traced image
+        let overlay_node_id = task_graph
+            .create_task_node("Overlay", QueueFamilyType::Graphics, overlay_task)
+            .image_access(
+                virtual_swapchain_id.current_image_id(),
+                AccessTypes::COMPUTE_SHADER_STORAGE_READ
+                    | AccessTypes::COMPUTE_SHADER_STORAGE_WRITE,
+                ImageLayoutType::General,
+            )
+            .build();
+
+        // Outlines the targeted voxel under the overlay, so the crosshair stays on top
+        let highlight_node_id = task_graph
+            .create_task_node(
+                "Highlight",
+                QueueFamilyType::Graphics,
+                HighlightTask::new(self),
+            )
+            .color_attachment(
+                virtual_swapchain_id.current_image_id(),
+                AccessTypes::COLOR_ATTACHMENT_READ | AccessTypes::COLOR_ATTACHMENT_WRITE,
+                ImageLayoutType::Optimal,
+                &AttachmentInfo::default(),
+            )
+            .build();
+
         task_graph
-            .create_task_node("Render", QueueFamilyType::Graphics, rt_pass)
+            .add_edge(highlight_node_id, overlay_node_id)
+            .unwrap();
+
+        // Only copies the image when a screenshot was requested
+        let screenshot_node_id = task_graph
+            .create_task_node(
+                "Screenshot",
+                QueueFamilyType::Graphics,
+                ScreenshotTask::new(virtual_swapchain_id),
+            )
             .image_access(
                 virtual_swapchain_id.current_image_id(),
-                AccessTypes::RAY_TRACING_SHADER_STORAGE_WRITE,
+                AccessTypes::COPY_TRANSFER_READ,
                 ImageLayoutType::General,
             )
             .build();
 
-        let task_graph = unsafe {
+        match &render_image {
+            Some(render_image) => {
+                let upscale_node_id = task_graph
+                    .create_task_node(
+                        "Upscale",
+                        QueueFamilyType::Graphics,
+                        UpscaleTask::new(virtual_swapchain_id, render_image.virtual_image_id),
+                    )
+                    .image_access(
+                        render_image.virtual_image_id,
+                        AccessTypes::BLIT_TRANSFER_READ,
+                        ImageLayoutType::General,
+                    )
+                    .image_access(
+                        virtual_swapchain_id.current_image_id(),
+                        AccessTypes::BLIT_TRANSFER_WRITE,
+                        ImageLayoutType::General,
+                    )
+                    .build();
+
+                task_graph.add_edge(image_node_id, upscale_node_id).unwrap();
+                task_graph
+                    .add_edge(upscale_node_id, highlight_node_id)
+                    .unwrap();
+            }
+            None => {
+                task_graph
+                    .add_edge(image_node_id, highlight_node_id)
+                    .unwrap();
+            }
+        }
+        #[cfg(not(feature = "egui"))]
+        task_graph
+            .add_edge(overlay_node_id, screenshot_node_id)
+            .unwrap();
+
+        // The tuning window is drawn over the overlay, and captured in screenshots
+        #[cfg(feature = "egui")]
+        let egui_node_id = {
+            let swapchain_format = self
+                .resources
+                .swapchain(swapchain_id)
+                .unwrap()
+                .swapchain()
+                .image_format();
+
+            let egui_node_id = task_graph
+                .create_task_node(
+                    "Egui",
+                    QueueFamilyType::Graphics,
+                    EguiTask::new(self, virtual_swapchain_id, swapchain_format),
+                )
+                .color_attachment(
+                    virtual_swapchain_id.current_image_id(),
+                    AccessTypes::COLOR_ATTACHMENT_READ | AccessTypes::COLOR_ATTACHMENT_WRITE,
+                    ImageLayoutType::Optimal,
+                    &AttachmentInfo::default(),
+                )
+                .build();
+
+            task_graph.add_edge(overlay_node_id, egui_node_id).unwrap();
+            task_graph
+                .add_edge(egui_node_id, screenshot_node_id)
+                .unwrap();
+
+            egui_node_id
+        };
+
+        let mut task_graph = unsafe {
             task_graph.compile(&CompileInfo {
                 queues: &[&self.graphics_queue],
                 present_queue: Some(&self.graphics_queue),
@@ -461,7 +3229,22 @@ impl ApplicationHandler for App {
         }
         .unwrap();
 
-        #[cfg(debug_assertions)]
+        let node = task_graph.task_node_mut(highlight_node_id).unwrap();
+        let pipeline = debug::line_pipeline(self, node.subpass().unwrap());
+
+        node.task_mut()
+            .downcast_mut::<HighlightTask>()
+            .unwrap()
+            .pipeline = Some(pipeline);
+
+        #[cfg(feature = "egui")]
+        {
+            let node = task_graph.task_node_mut(egui_node_id).unwrap();
+            let pipeline = EguiTask::create_pipeline(self, node.subpass().unwrap());
+
+            node.task_mut().downcast_mut::<EguiTask>().unwrap().pipeline = Some(pipeline);
+        }
+
         let viewport = Viewport {
             offset: [0.0, 0.0],
             extent: window_size.into(),
@@ -473,35 +3256,122 @@ impl ApplicationHandler for App {
             proj_inverse: [[0.0; 4]; 4],
             view_inverse: [[0.0; 4]; 4],
             view_proj: [[0.0; 4]; 4],
+            aperture: 0.0,
+            focus_distance: DEFAULT_FOCUS_DISTANCE,
+            max_ray_distance: f32::MAX,
+            max_reflection_distance: f32::MAX,
+        };
+
+        let [fog_start, fog_density] = fog_in_voxels(self.fog);
+
+        let output_encoding = {
+            let swapchain_state = self.resources.swapchain(swapchain_id).unwrap();
+            let swapchain = swapchain_state.swapchain();
+
+            OutputEncoding::new(swapchain.image_format(), swapchain.image_color_space())
         };
 
         let rt_sunlight_data = raygen::Sunlight {
-            direction: vec3(0.5, -0.5, 0.5).to_array(),
+            direction: Padded(vec3(0.5, 0.5, 0.5).normalize().to_array()),
+            horizon_color: Padded(vec3(0.85, 0.9, 1.0).to_array()),
+            zenith_color: vec3(0.25, 0.45, 0.85).to_array(),
+            sun_angular_radius: DEFAULT_SUN_ANGULAR_RADIUS,
+            // Shadow rays need a recursion depth of 2, like ambient occlusion rays
+            shadow_sample_count: if self.max_ray_recursion_depth >= 2 {
+                DEFAULT_SHADOW_SAMPLE_COUNT
+            } else {
+                0
+            },
+            ao_sample_count: DEFAULT_AO_SAMPLE_COUNT,
+            ao_radius: DEFAULT_AO_RADIUS,
+            surface_bias: DEFAULT_SURFACE_BIAS,
+            fog_start,
+            fog_density,
+            exposure: DEFAULT_EXPOSURE,
+            tonemap: 0,
+            output_encoding: output_encoding as u32,
+            hdr_white_nits: HDR_WHITE_NITS,
+            hdr_max_nits: HDR_MAX_NITS,
         };
 
         #[cfg(debug_assertions)]
+        let (wireframe_task_graph, wireframe_swapchain_id, wireframe_node_id) =
+            self.create_wireframe_task_graph();
+
         let debug_constant_data = debug::shader::vert::PushConstants {
             world: Mat4::default().to_cols_array_2d(),
             view: Mat4::default().to_cols_array_2d(),
             proj: Mat4::default().to_cols_array_2d(),
         };
 
+        #[cfg(feature = "egui")]
+        let tuning_ui = TuningUi::new(&window);
+
         self.rcx = Some(RenderContext {
             window,
             swapchain_id,
             virtual_swapchain_id,
+            supported_present_modes,
+            present_mode,
             recreate_swapchain: false,
+            screenshot_requested: false,
+            screenshot_buffer_id: None,
             task_graph,
+            render_node_id,
+            #[cfg(debug_assertions)]
+            wireframe: false,
+            #[cfg(debug_assertions)]
+            wireframe_task_graph,
+            #[cfg(debug_assertions)]
+            wireframe_swapchain_id,
+            #[cfg(debug_assertions)]
+            wireframe_node_id,
+            #[cfg(feature = "egui")]
+            tuning_ui,
+            #[cfg(feature = "egui")]
+            edited_material: 0,
+            #[cfg(feature = "egui")]
+            egui_node_id,
             // scene_params,
             rt_camera_data,
             rt_sunlight_data,
-            #[cfg(debug_assertions)]
             debug_constant_data,
-            #[cfg(debug_assertions)]
+            highlighted_voxel: None,
             viewport,
+            overlay_stats: OverlayStats::default(),
             swapchain_storage_image_ids,
+            accumulation_image_id,
+            virtual_accumulation_image_id,
+            accumulation_storage_image_id,
+            g_buffer,
+            denoise_images,
+            denoise: false,
+            render_scale: self.render_scale,
+            render_extent,
+            render_image,
+            accumulated_frames: 0,
+            jitter: [0.0; 2],
+            max_bounces: DEFAULT_MAX_BOUNCES.min(self.max_ray_recursion_depth - 1),
+            chunk_colors: false,
+            debug_normals: false,
+            flat_colors: false,
+            ambient_occlusion: self.max_ray_recursion_depth >= 2,
+            background_preset: 0,
+            aperture_preset: 0,
+            heatmap: false,
+            focus_distance: DEFAULT_FOCUS_DISTANCE,
+            tlas_timer,
             channel,
         });
+
+        if let Some(frames) = self.benchmark_frames {
+            match self.run_benchmark(&benchmark::default_path(), frames) {
+                Ok(()) => println!("Wrote benchmark results to {BENCHMARK_CSV_PATH}"),
+                Err(e) => eprintln!("Failed to write benchmark results: {e}"),
+            }
+
+            event_loop.exit();
+        }
     }
 
     fn window_event(
@@ -510,6 +3380,11 @@ impl ApplicationHandler for App {
         _window_id: winit::window::WindowId,
         event: WindowEvent,
     ) {
+        #[cfg(feature = "egui")]
+        if self.tuning_ui_consumes(&event) {
+            return;
+        }
+
         match event {
             WindowEvent::CloseRequested
             | WindowEvent::KeyboardInput {
@@ -529,92 +3404,30 @@ impl ApplicationHandler for App {
             WindowEvent::RedrawRequested => {
                 self.update_delta_time();
                 self.update_camera();
-                self.physics_controller.request_update();
+                self.apply_world_commands();
+                self.update_voxel_highlight();
+                self.cull_occluded_chunks();
+                self.update_frustum_culling();
+                self.update_lods();
+                self.stream_chunks();
+                self.update_chunk_visibility();
+                self.update_physics();
                 self.update_log_instant();
+                self.update_overlay_stats();
 
-                {
-                    let rcx = self.rcx.as_mut().unwrap();
-
-                    let window_size = rcx.window.inner_size();
-
-                    if window_size.width == 0 || window_size.height == 0 {
-                        return;
-                    }
-
-                    if rcx.recreate_swapchain {
-                        rcx.swapchain_id = self
-                            .resources
-                            .recreate_swapchain(rcx.swapchain_id, |create_info| {
-                                SwapchainCreateInfo {
-                                    image_extent: window_size.into(),
-                                    ..create_info.clone()
-                                }
-                            })
-                            .expect("failed to recreate swapchain");
-
-                        #[cfg(debug_assertions)]
-                        {
-                            rcx.viewport = Viewport {
-                                offset: [0.0, 0.0],
-                                extent: window_size.into(),
-                                min_depth: 0.0,
-                                max_depth: 1.0,
-                            };
-                        }
-
-                        let mut batch = self.resources.create_deferred_batch();
-
-                        for &id in &rcx.swapchain_storage_image_ids {
-                            batch.destroy_storage_image(id);
-                        }
-
-                        batch.enqueue();
-
-                        rcx.swapchain_storage_image_ids =
-                            window_size_dependent_setup(&self.resources, rcx.swapchain_id);
-
-                        // let renderer = rcx
-                        //     .task_graph
-                        //     .task_node_mut(rcx.renderer_node_id)
-                        //     .unwrap()
-                        //     .task_mut()
-                        //     .downcast_mut::<RayTracingPass>()
-                        //     .unwrap();
-
-                        // renderer.swapchain_id = rcx.swapchain_id;
-
-                        rcx.recreate_swapchain = false;
-                    }
-                }
-
-                self.resources
-                    .flight(self.graphics_flight_id)
-                    .unwrap()
-                    .wait_idle()
-                    .unwrap();
+                self.render_frame();
 
-                let rcx = self.rcx.as_mut().unwrap();
+                // The worker waits for the frame to be submitted before updating the back TLAS.
+                // Only the random sphere changes on updates, the world TLAS would be refit as is
+                let update = self.tlas_updates || std::mem::take(&mut self.tlas_step_requested);
 
-                let resource_map =
-                    resource_map!(&rcx.task_graph, rcx.virtual_swapchain_id => rcx.swapchain_id)
+                if update && self.debug_scene == DebugScene::RandomSphere {
+                    self.rcx
+                        .as_ref()
+                        .unwrap()
+                        .channel
+                        .send(WorkerRequest::Update)
                         .unwrap();
-
-                let execute_result = unsafe {
-                    rcx.task_graph
-                        .execute(resource_map, rcx, || rcx.window.pre_present_notify())
-                };
-
-                match execute_result {
-                    Ok(()) => {}
-                    Err(ExecuteError::Swapchain {
-                        error: VulkanError::OutOfDate,
-                        ..
-                    }) => {
-                        rcx.recreate_swapchain = true;
-                    }
-                    Err(e) => {
-                        panic!("failed to execute next frame: {e:?}");
-                    }
                 }
             }
             WindowEvent::MouseInput {
@@ -622,20 +3435,25 @@ impl ApplicationHandler for App {
                 button: MouseButton::Right,
                 ..
             } => self.toggle_capture_mouse(),
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                if self.focused {
+                    self.edit_voxel(self.modifiers.shift_key());
+                }
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+            }
             WindowEvent::MouseWheel {
                 delta: MouseScrollDelta::LineDelta(_, y),
                 ..
             } => self.player_controller.handle_speed_change(y),
             WindowEvent::KeyboardInput { event, .. } => {
-                match event.state {
-                    ElementState::Pressed => {
-                        if let Some(txt) = event.logical_key.to_text() {
-                            if txt == "r" {
-                                self.rcx.as_ref().unwrap().channel.send(()).unwrap();
-                            }
-                        }
-                    }
-                    _ => {}
+                if event.state == ElementState::Pressed && !event.repeat {
+                    self.handle_key_pressed(&event.logical_key);
                 }
                 self.player_controller.handle_keyboard_event(event)
             }
@@ -663,6 +3481,310 @@ impl ApplicationHandler for App {
     }
 }
 
+// Radical inverse of the index in the base, the Halton sequence of that base
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+
+    result
+}
+
+// The first accumulated frame goes through the pixel centers so it stays sharp, the next ones
+// spread over the pixel with the (2, 3) Halton sequence
+fn pixel_jitter(frame: u32) -> [f32; 2] {
+    if frame == 0 {
+        return [0.0; 2];
+    }
+
+    [halton(frame, 2) - 0.5, halton(frame, 3) - 0.5]
+}
+
+// The model at `path` with the material overrides saved next to it, invalid overrides are
+// reported and ignored
+fn open_asset(path: &Path) -> Result<OverriddenSource, LoadError> {
+    let source = open_file(path)?;
+    let overrides_path = MaterialOverrides::sidecar_path(path);
+
+    let overrides = MaterialOverrides::load(&overrides_path).unwrap_or_else(|e| {
+        eprintln!("Failed to load {}: {e}", overrides_path.display());
+        MaterialOverrides::default()
+    });
+
+    if !overrides.is_empty() {
+        println!(
+            "Overriding {} materials from {}",
+            overrides.len(),
+            overrides_path.display()
+        );
+    }
+
+    Ok(OverriddenSource::new(source, overrides))
+}
+
+// The models of the directory of `asset_path` and the index of `asset_path` among them, which
+// is listed even when the directory can't be read
+fn asset_list(asset_path: &Path) -> (Vec<PathBuf>, usize) {
+    let directory = match asset_path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+
+    let mut assets = list_assets(directory).unwrap_or_else(|e| {
+        eprintln!("Failed to list the models in {}: {e}", directory.display());
+        vec![]
+    });
+
+    let index = assets
+        .iter()
+        .position(|path| path.file_name() == asset_path.file_name())
+        .unwrap_or_else(|| {
+            assets.push(asset_path.to_path_buf());
+            assets.len() - 1
+        });
+
+    (assets, index)
+}
+
+// The requested instance count lowered to what the device supports, the build sizes are
+// queried with 32 bit counts
+fn instance_limit(requested: u64, device_max: u64) -> u64 {
+    let limit = requested.min(device_max).min(u32::MAX as u64);
+
+    if limit != requested {
+        eprintln!("Requested {requested} instances, using {limit} supported by the device");
+    }
+
+    limit
+}
+
+// Ray distances in voxels, at least a voxel so the tmax stays above the tmin and at most the
+// largest finite tmax
+fn distance_in_voxels(meters: f32) -> f32 {
+    (meters / VOXEL_PHYSICAL_LENGTH).clamp(1.0, f32::MAX)
+}
+
+// The shaders measure distances in voxels, the fog start and density are given in meters
+fn fog_in_voxels([start, density]: [f32; 2]) -> [f32; 2] {
+    [
+        start / VOXEL_PHYSICAL_LENGTH,
+        density * VOXEL_PHYSICAL_LENGTH,
+    ]
+}
+
+// How the ray tracer encodes its output for the color space of the swapchain, in the order of
+// the `OUTPUT_*` constants of the shaders
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputEncoding {
+    // Tonemapped to [0, 1]
+    #[default]
+    Sdr,
+    // Linear BT.709 in a 16 bit float image, values above 1 are brighter than the SDR white
+    ScRgb,
+    // BT.2020 with the PQ curve in a 10 bit image
+    Hdr10,
+}
+
+impl OutputEncoding {
+    pub fn new(format: Format, color_space: ColorSpace) -> Self {
+        match (format, color_space) {
+            (Format::R16G16B16A16_SFLOAT, ColorSpace::ExtendedSrgbLinear) => OutputEncoding::ScRgb,
+            (Format::A2B10G10R10_UNORM_PACK32, ColorSpace::Hdr10St2084) => OutputEncoding::Hdr10,
+            _ => OutputEncoding::Sdr,
+        }
+    }
+}
+
+// HDR prefers scRGB, which keeps the precision of the float image, over HDR10. Otherwise the
+// first usable sRGB color space is picked, so SDR displays never get an extended range one
+fn select_surface_format(usable: &[(Format, ColorSpace)], hdr: bool) -> (Format, ColorSpace) {
+    let hdr_format = [OutputEncoding::ScRgb, OutputEncoding::Hdr10]
+        .into_iter()
+        .filter(|_| hdr)
+        .find_map(|encoding| {
+            usable.iter().find(|(format, color_space)| {
+                OutputEncoding::new(*format, *color_space) == encoding
+            })
+        });
+
+    let sdr_format = || {
+        usable
+            .iter()
+            .find(|(_, color_space)| *color_space == ColorSpace::SrgbNonLinear)
+    };
+
+    *hdr_format
+        .or_else(sdr_format)
+        .or(usable.first())
+        .expect("No usable surface format")
+}
+
+// Uses the preferred present mode when supported, falling back to Mailbox, then FIFO which is
+// always supported, then Immediate
+fn select_present_mode(supported: &[PresentMode], preferred: PresentMode) -> PresentMode {
+    [
+        preferred,
+        PresentMode::Mailbox,
+        PresentMode::Fifo,
+        PresentMode::Immediate,
+    ]
+    .into_iter()
+    .find(|mode| supported.contains(mode))
+    .unwrap_or(PresentMode::Fifo)
+}
+
+// Clamps the requested image count to what the surface supports, a `None` maximum means unbounded
+fn swapchain_image_count(
+    requested: u32,
+    max_frames_in_flight: u32,
+    min_image_count: u32,
+    max_image_count: Option<u32>,
+) -> u32 {
+    let image_count = requested
+        .max(min_image_count)
+        .min(max_image_count.unwrap_or(u32::MAX));
+
+    if image_count != requested {
+        eprintln!(
+            "Requested {requested} swapchain images, using {image_count} supported by the surface"
+        );
+    }
+
+    if image_count <= max_frames_in_flight {
+        eprintln!(
+            "{image_count} swapchain images for {max_frames_in_flight} frames in flight, frames will wait on presentation"
+        );
+    }
+
+    image_count
+}
+
+// An image only read and written by shaders, sized like the image rays are traced into
+fn storage_image_create_info(format: Format, extent: [u32; 2]) -> ImageCreateInfo<'static> {
+    ImageCreateInfo {
+        image_type: ImageType::Dim2d,
+        format,
+        extent: [extent[0], extent[1], 1],
+        usage: ImageUsage::STORAGE,
+        ..Default::default()
+    }
+}
+
+// The extent rays are traced at, at least a pixel wide on each axis
+fn scaled_extent(extent: [u32; 2], scale: f32) -> [u32; 2] {
+    extent.map(|length| ((length as f32 * scale).round() as u32).max(1))
+}
+
+// The image has the format of the swapchain, so the blit copies the texels without any
+// conversion
+fn render_image_create_info(
+    resources: &Resources,
+    swapchain_id: Id<Swapchain>,
+    extent: [u32; 2],
+) -> ImageCreateInfo<'static> {
+    let format = resources
+        .swapchain(swapchain_id)
+        .unwrap()
+        .swapchain()
+        .image_format();
+
+    ImageCreateInfo {
+        image_type: ImageType::Dim2d,
+        format,
+        extent: [extent[0], extent[1], 1],
+        usage: ImageUsage::STORAGE | ImageUsage::TRANSFER_SRC,
+        ..Default::default()
+    }
+}
+
+// The image rays are traced into at a reduced resolution
+fn render_image_setup(
+    resources: &Resources,
+    swapchain_id: Id<Swapchain>,
+    extent: [u32; 2],
+) -> (Id<Image>, StorageImageId) {
+    let bcx = resources.bindless_context().unwrap();
+
+    let image_id = resources
+        .create_image(
+            &render_image_create_info(resources, swapchain_id, extent),
+            &AllocationCreateInfo::default(),
+        )
+        .unwrap();
+
+    let image_view = ImageView::new_default(resources.image(image_id).unwrap().image()).unwrap();
+
+    let storage_image_id = bcx
+        .global_set()
+        .add_storage_image(image_view, ImageLayout::General);
+
+    (image_id, storage_image_id)
+}
+
+fn storage_image_setup(
+    resources: &Resources,
+    format: Format,
+    extent: [u32; 2],
+) -> (Id<Image>, StorageImageId) {
+    let bcx = resources.bindless_context().unwrap();
+
+    let image_id = resources
+        .create_image(
+            &storage_image_create_info(format, extent),
+            &AllocationCreateInfo::default(),
+        )
+        .unwrap();
+
+    let image_view = ImageView::new_default(resources.image(image_id).unwrap().image()).unwrap();
+
+    let storage_image_id = bcx
+        .global_set()
+        .add_storage_image(image_view, ImageLayout::General);
+
+    (image_id, storage_image_id)
+}
+
+// A host buffer holding every texel of the swapchain images, `None` when screenshots of the
+// swapchain format aren't supported
+fn screenshot_buffer_setup(
+    resources: &Resources,
+    swapchain_id: Id<Swapchain>,
+) -> Option<Id<Buffer>> {
+    let swapchain_state = resources.swapchain(swapchain_id).unwrap();
+    let swapchain = swapchain_state.swapchain();
+    let format = swapchain.image_format();
+
+    if !screenshot::supports_format(format) {
+        eprintln!("Screenshots of {format:?} swapchains aren't supported");
+        return None;
+    }
+
+    let [width, height] = swapchain.image_extent();
+    let size = width as DeviceSize * height as DeviceSize * 4;
+
+    let buffer_id = resources
+        .create_buffer(
+            &BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            &AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                    | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..Default::default()
+            },
+            DeviceLayout::new_unsized::<[u8]>(size).unwrap(),
+        )
+        .unwrap();
+
+    Some(buffer_id)
+}
+
 fn window_size_dependent_setup(
     resources: &Resources,
     swapchain_id: Id<Swapchain>,
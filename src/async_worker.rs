@@ -1,11 +1,11 @@
 use std::{
     sync::{
-        Arc,
+        Arc, Mutex,
         atomic::{AtomicBool, Ordering},
         mpsc,
     },
     thread,
-    time::{Duration, Instant},
+    time::Duration,
 };
 
 use vulkano::{acceleration_structure::AccelerationStructure, device::Queue};
@@ -20,6 +20,16 @@ use crate::tasks::update_as::{AsyncRenderContext, UpdateAccelerationStructureTas
 
 const TRANSFER_GRANULARITY: u32 = 4096;
 
+pub enum WorkerRequest {
+    // Updates the back TLAS once the last submitted frame started
+    Update,
+    // Switches to the update task and TLAS of a grown instance buffer
+    Resize(
+        UpdateAccelerationStructureTask,
+        [Arc<AccelerationStructure>; 2],
+    ),
+}
+
 fn init_worker(
     update_as_task: UpdateAccelerationStructureTask,
     queue: Arc<Queue>,
@@ -46,23 +56,61 @@ fn init_worker(
 
 #[allow(clippy::too_many_arguments)]
 pub fn run_worker(
-    channel: mpsc::Receiver<()>,
+    channel: mpsc::Receiver<WorkerRequest>,
     update_as_task: UpdateAccelerationStructureTask,
     queue: Arc<Queue>,
     resources: Arc<Resources>,
     graphics_flight_id: Id<Flight>,
     compute_flight_id: Id<Flight>,
-    acceleration_structures: [Arc<AccelerationStructure>; 2],
+    mut acceleration_structures: [Arc<AccelerationStructure>; 2],
     current_as_index: Arc<AtomicBool>,
     show_current_index: Arc<AtomicBool>,
+    forced_as_index: Arc<Mutex<Option<bool>>>,
+    tlas_lock: Arc<Mutex<()>>,
 ) {
-    let task_graph = init_worker(update_as_task, queue, resources.clone(), compute_flight_id);
+    let mut buffer_ids = update_as_task.buffer_ids();
+    let mut task_graph = init_worker(
+        update_as_task,
+        queue.clone(),
+        resources.clone(),
+        compute_flight_id,
+    );
 
     thread::spawn(move || {
         let mut last_frame = 0;
 
-        while let Ok(()) = channel.recv() {
-            let now = Instant::now();
+        while let Ok(request) = channel.recv() {
+            let mut update = false;
+
+            // Requests sent while the previous update was running are merged into this one
+            for request in std::iter::once(request).chain(channel.try_iter()) {
+                match request {
+                    WorkerRequest::Update => update = true,
+                    WorkerRequest::Resize(update_as_task, resized_structures) => {
+                        // The frames in flight may still read the old instance buffer
+                        let mut batch = resources.create_deferred_batch();
+
+                        for id in buffer_ids {
+                            batch.destroy_buffer(id);
+                        }
+
+                        batch.enqueue();
+
+                        buffer_ids = update_as_task.buffer_ids();
+                        task_graph = init_worker(
+                            update_as_task,
+                            queue.clone(),
+                            resources.clone(),
+                            compute_flight_id,
+                        );
+                        acceleration_structures = resized_structures;
+                    }
+                }
+            }
+
+            if !update {
+                continue;
+            }
 
             let graphics_flight = resources.flight(graphics_flight_id).unwrap();
 
@@ -72,7 +120,20 @@ pub fn run_worker(
 
             graphics_flight.wait_for_frame(last_frame, None).unwrap();
 
-            let back_index = !current_as_index.load(Ordering::Relaxed);
+            // The app rebuilds the instances and both TLAS between frames, the update waits for
+            // the rebuild and reads the new instance count
+            let tlas_guard = tlas_lock.lock().unwrap();
+
+            let back_index = !current_as_index.load(Ordering::Acquire);
+
+            // The locked TLAS may be read by any frame, so it's left alone until it's unlocked. The
+            // lock is held until the update has completed, so the back TLAS can't be locked while
+            // it's being written
+            let forced_index = forced_as_index.lock().unwrap();
+
+            if *forced_index == Some(back_index) {
+                continue;
+            }
             // println!("Updating TLAS at index: {back_index}");
 
             let resource_map = resource_map!(&task_graph).unwrap();
@@ -94,14 +155,14 @@ pub fn run_worker(
                 .wait_idle()
                 .unwrap();
 
-            last_frame = graphics_flight.current_frame();
-
-            current_as_index.store(back_index, Ordering::Relaxed);
+            current_as_index.store(back_index, Ordering::Release);
             show_current_index.store(true, Ordering::Relaxed);
-            println!(
-                "TLAS update took: {:.2}ms",
-                now.elapsed().as_micros() as f64 / 1000.
-            );
+            drop(forced_index);
+            drop(tlas_guard);
+
+            // Frames up to this one may still read the previous front TLAS, which becomes the
+            // back TLAS of the next update, so it's only written once they have completed
+            last_frame = graphics_flight.current_frame();
         }
     });
 }
@@ -0,0 +1,153 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+use glam::Vec3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraKeyframe {
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl CameraKeyframe {
+    pub fn lerp(&self, rhs: &Self, t: f32) -> Self {
+        Self {
+            position: self.position.lerp(rhs.position, t),
+            yaw: self.yaw + (rhs.yaw - self.yaw) * t,
+            pitch: self.pitch + (rhs.pitch - self.pitch) * t,
+        }
+    }
+}
+
+// Samples the path at `t` in the [0, 1] range, the keyframes are evenly spaced
+pub fn sample_path(path: &[CameraKeyframe], t: f32) -> CameraKeyframe {
+    assert!(
+        !path.is_empty(),
+        "a camera path needs at least one keyframe"
+    );
+
+    let position = t.clamp(0.0, 1.0) * (path.len() - 1) as f32;
+    let index = (position.floor() as usize).min(path.len() - 1);
+    let next_index = (index + 1).min(path.len() - 1);
+
+    path[index].lerp(&path[next_index], position - index as f32)
+}
+
+// A default path circling the loaded world
+pub fn default_path() -> Vec<CameraKeyframe> {
+    [
+        (Vec3::new(-16.0, 32.0, -16.0), 0.0),
+        (Vec3::new(96.0, 48.0, -16.0), 1.5),
+        (Vec3::new(96.0, 64.0, 96.0), 3.0),
+        (Vec3::new(-16.0, 48.0, 96.0), 4.5),
+        (Vec3::new(-16.0, 32.0, -16.0), 6.0),
+    ]
+    .into_iter()
+    .map(|(position, yaw)| CameraKeyframe {
+        position,
+        yaw,
+        pitch: -0.3,
+    })
+    .collect()
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BenchmarkFrame {
+    // Time spent recording and submitting the frame
+    pub cpu: Duration,
+    // Time until the frame completed on the GPU
+    pub frame: Duration,
+    pub ray_trace: Option<Duration>,
+    // The last completed TLAS update, it runs asynchronously to the frames
+    pub tlas_update: Option<Duration>,
+}
+
+pub fn write_csv(path: &Path, frames: &[BenchmarkFrame]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    write_frames(&mut writer, frames)?;
+
+    writer.flush()
+}
+
+fn write_frames(writer: &mut impl Write, frames: &[BenchmarkFrame]) -> io::Result<()> {
+    fn millis(duration: Option<Duration>) -> String {
+        duration
+            .map(|d| format!("{:.3}", d.as_secs_f64() * 1000.0))
+            .unwrap_or_default()
+    }
+
+    writeln!(writer, "frame,cpu_ms,frame_ms,ray_trace_ms,tlas_update_ms")?;
+
+    for (index, frame) in frames.iter().enumerate() {
+        writeln!(
+            writer,
+            "{index},{},{},{},{}",
+            millis(Some(frame.cpu)),
+            millis(Some(frame.frame)),
+            millis(frame.ray_trace),
+            millis(frame.tlas_update),
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use glam::Vec3;
+
+    use super::{BenchmarkFrame, CameraKeyframe, sample_path, write_frames};
+
+    #[test]
+    fn benchmark_path_interpolation() {
+        let path = [
+            CameraKeyframe {
+                position: Vec3::ZERO,
+                yaw: 0.0,
+                pitch: 0.0,
+            },
+            CameraKeyframe {
+                position: Vec3::new(10.0, 0.0, 0.0),
+                yaw: 1.0,
+                pitch: 0.0,
+            },
+            CameraKeyframe {
+                position: Vec3::new(10.0, 10.0, 0.0),
+                yaw: 2.0,
+                pitch: 0.0,
+            },
+        ];
+
+        assert!(sample_path(&path, 0.0) == path[0]);
+        assert!(sample_path(&path, 0.5) == path[1]);
+        assert!(sample_path(&path, 1.0) == path[2]);
+        assert!(sample_path(&path, 0.25).position == Vec3::new(5.0, 0.0, 0.0));
+        assert!(sample_path(&path[..1], 0.7) == path[0]);
+    }
+
+    #[test]
+    fn benchmark_csv() {
+        let frames = [BenchmarkFrame {
+            cpu: Duration::from_micros(1500),
+            frame: Duration::from_millis(4),
+            ray_trace: Some(Duration::from_millis(2)),
+            tlas_update: None,
+        }];
+
+        let mut csv = Vec::new();
+        write_frames(&mut csv, &frames).unwrap();
+
+        assert!(
+            String::from_utf8(csv).unwrap()
+                == "frame,cpu_ms,frame_ms,ray_trace_ms,tlas_update_ms\n0,1.500,4.000,2.000,\n"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+// The amount of frame durations kept in the ring buffer
+pub const FRAME_STATS_CAPACITY: usize = 512;
+
+pub struct FrameStats {
+    frame_times: [Duration; FRAME_STATS_CAPACITY],
+    next_index: usize,
+    len: usize,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self {
+            frame_times: [Duration::ZERO; FRAME_STATS_CAPACITY],
+            next_index: 0,
+            len: 0,
+        }
+    }
+}
+
+impl FrameStats {
+    pub fn push(&mut self, frame_time: Duration) {
+        self.frame_times[self.next_index] = frame_time;
+        self.next_index = (self.next_index + 1) % FRAME_STATS_CAPACITY;
+        self.len = (self.len + 1).min(FRAME_STATS_CAPACITY);
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn samples(&self) -> &[Duration] {
+        &self.frame_times[..self.len]
+    }
+
+    pub fn avg(&self) -> Duration {
+        if self.is_empty() {
+            return Duration::ZERO;
+        }
+
+        self.samples().iter().sum::<Duration>() / self.len as u32
+    }
+
+    pub fn min(&self) -> Duration {
+        self.samples().iter().min().copied().unwrap_or_default()
+    }
+
+    pub fn max(&self) -> Duration {
+        self.samples().iter().max().copied().unwrap_or_default()
+    }
+
+    // Nearest-rank percentile, `percentile` is in the [0, 100] range
+    pub fn percentile(&self, percentile: f32) -> Duration {
+        if self.is_empty() {
+            return Duration::ZERO;
+        }
+
+        let mut sorted = self.samples().to_vec();
+        sorted.sort_unstable();
+
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * self.len as f32).ceil() as usize;
+
+        sorted[rank.saturating_sub(1)]
+    }
+
+    pub fn p1(&self) -> Duration {
+        self.percentile(1.0)
+    }
+
+    pub fn p99(&self) -> Duration {
+        self.percentile(99.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{FRAME_STATS_CAPACITY, FrameStats};
+
+    #[test]
+    fn frame_stats_percentiles() {
+        let mut stats = FrameStats::default();
+
+        for ms in 1..=100 {
+            stats.push(Duration::from_millis(ms));
+        }
+
+        assert!(stats.min() == Duration::from_millis(1));
+        assert!(stats.max() == Duration::from_millis(100));
+        assert!(stats.p1() == Duration::from_millis(1));
+        assert!(stats.p99() == Duration::from_millis(99));
+        assert!(stats.avg() == Duration::from_micros(50_500));
+    }
+
+    #[test]
+    fn frame_stats_wraps() {
+        let mut stats = FrameStats::default();
+
+        for _ in 0..FRAME_STATS_CAPACITY {
+            stats.push(Duration::from_millis(100));
+        }
+
+        for _ in 0..FRAME_STATS_CAPACITY {
+            stats.push(Duration::from_millis(1));
+        }
+
+        assert!(stats.len() == FRAME_STATS_CAPACITY);
+        assert!(stats.max() == Duration::from_millis(1));
+    }
+}
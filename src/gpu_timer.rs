@@ -0,0 +1,106 @@
+use std::{sync::Arc, time::Duration};
+
+use vulkano::{
+    device::Device,
+    query::{QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType},
+    sync::PipelineStage,
+};
+use vulkano_taskgraph::{TaskResult, command_buffer::RecordingCommandBuffer};
+
+// GPU durations of the last completed frame, `None` when they couldn't be measured
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GpuTimings {
+    pub ray_trace: Option<Duration>,
+    pub tlas_update: Option<Duration>,
+}
+
+// Measures the GPU time spent between two timestamps of a command buffer. Queues without
+// timestamp support get a timer that records nothing and never has a result
+pub struct GpuTimer {
+    query_pool: Option<Arc<QueryPool>>,
+    // Nanoseconds per timestamp tick
+    timestamp_period: f32,
+    // Mask of the bits of the timestamps the queue family writes
+    timestamp_mask: u64,
+}
+
+impl GpuTimer {
+    // The command buffers measured have to be submitted to a queue of `queue_family_index`
+    pub fn new(device: &Arc<Device>, queue_family_index: u32) -> Self {
+        let physical_device = device.physical_device();
+        let valid_bits = physical_device.queue_family_properties()[queue_family_index as usize]
+            .timestamp_valid_bits
+            .unwrap_or(0);
+
+        let query_pool = (valid_bits > 0).then(|| {
+            QueryPool::new(
+                device,
+                &QueryPoolCreateInfo {
+                    query_count: 2,
+                    ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+                },
+            )
+            .unwrap()
+        });
+
+        Self {
+            query_pool,
+            timestamp_period: physical_device.properties().timestamp_period,
+            timestamp_mask: u64::MAX >> (64 - valid_bits.clamp(1, 64)),
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.query_pool.is_some()
+    }
+
+    pub unsafe fn begin(&self, cbf: &mut RecordingCommandBuffer<'_>) -> TaskResult {
+        let Some(query_pool) = &self.query_pool else {
+            return Ok(());
+        };
+
+        unsafe { cbf.as_raw().reset_query_pool(query_pool, 0..2) }?;
+        unsafe {
+            cbf.as_raw()
+                .write_timestamp(query_pool, 0, PipelineStage::TopOfPipe)
+        }?;
+
+        Ok(())
+    }
+
+    pub unsafe fn end(&self, cbf: &mut RecordingCommandBuffer<'_>) -> TaskResult {
+        let Some(query_pool) = &self.query_pool else {
+            return Ok(());
+        };
+
+        unsafe {
+            cbf.as_raw()
+                .write_timestamp(query_pool, 1, PipelineStage::BottomOfPipe)
+        }?;
+
+        Ok(())
+    }
+
+    // Returns `None` while the measured commands haven't completed or when timestamps aren't
+    // supported
+    pub fn elapsed(&self) -> Option<Duration> {
+        let mut timestamps = [0u64; 2];
+
+        let available = self
+            .query_pool
+            .as_ref()?
+            .get_results(0..2, &mut timestamps, QueryResultFlags::empty())
+            .ok()?;
+
+        if !available {
+            return None;
+        }
+
+        // The counter wraps around at the valid bits
+        let ticks = timestamps[1].wrapping_sub(timestamps[0]) & self.timestamp_mask;
+
+        Some(Duration::from_nanos(
+            (ticks as f64 * self.timestamp_period as f64) as u64,
+        ))
+    }
+}
@@ -1,7 +1,14 @@
 pub mod app;
 mod async_worker;
+pub mod benchmark;
+pub mod frame_stats;
+pub mod gpu_timer;
 mod physics;
 mod player_controller;
+pub mod recording;
 mod rt;
+pub mod screenshot;
 mod tasks;
+#[cfg(feature = "egui")]
+mod tuning;
 mod world;
@@ -1,11 +1,50 @@
-use a_tlas::app::App;
+use a_tlas::app::{App, AppConfig, DEFAULT_SEED};
 use std::error::Error;
 use winit::event_loop::EventLoop;
 
-fn main() -> Result<(), impl Error> {
+const DEFAULT_BENCHMARK_FRAMES: u32 = 1000;
+
+fn main() -> Result<(), Box<dyn Error>> {
     let event_loop = EventLoop::new().unwrap();
 
-    let mut app = App::new(&event_loop);
+    // `--benchmark [frames]` renders the benchmark path instead of the interactive loop
+    let mut args = std::env::args().skip_while(|arg| arg != "--benchmark");
+    let benchmark_frames = args.next().map(|_| {
+        args.next()
+            .and_then(|frames| frames.parse().ok())
+            .unwrap_or(DEFAULT_BENCHMARK_FRAMES)
+    });
+
+    // `--seed <seed>` changes the layout of the random stress-test instances
+    let mut args = std::env::args().skip_while(|arg| arg != "--seed");
+    let seed = match args.next() {
+        Some(_) => {
+            let seed = args.next().ok_or("--seed expects a number")?;
+
+            seed.parse::<u64>()
+                .map_err(|e| format!("invalid seed {seed:?}: {e}"))?
+        }
+        None => DEFAULT_SEED,
+    };
+
+    // `--sort-instances` orders the world instances spatially, to compare benchmark runs
+    let sort_instances = std::env::args().any(|arg| arg == "--sort-instances");
+
+    let config = AppConfig::builder()
+        .benchmark_frames(benchmark_frames)
+        .seed(seed)
+        .sort_instances(sort_instances)
+        .build()?;
+    // Printed with `Display`, returning it would show the `Debug` output
+    let mut app = match App::with_config(&event_loop, config) {
+        Ok(app) => app,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    event_loop.run_app(&mut app)?;
 
-    event_loop.run_app(&mut app)
+    Ok(())
 }
@@ -1,31 +1,266 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use crate::app::TICKS_PER_SECOND;
+use glam::{BVec3, IVec3, Vec3};
+
+use crate::{
+    app::TICKS_PER_SECOND,
+    world::chunk::{Chunks, VOXEL_PHYSICAL_LENGTH},
+};
+
+// Radius of the sphere around the camera that collides with the voxels in walk mode, in meters
+pub const PLAYER_RADIUS: f32 = 0.3;
+// The largest distance the sphere moves before checking for collisions again, as a fraction
+// of its radius, so fast movement can't skip over thin walls
+const MAX_STEP: f32 = 0.5;
+// In meters per second squared
+pub const GRAVITY: f32 = 9.81;
+// How high a jump lifts the player, in meters
+pub const JUMP_HEIGHT: f32 = 1.0;
+// The fastest the player falls, in meters per second
+pub const TERMINAL_VELOCITY: f32 = 50.0;
+// How far below the player the ground is looked for, in voxels
+const GROUND_PROBE: f32 = 0.05;
+// Ticks run at most per update, the rest of a long frame is dropped instead of catching up
+const MAX_TICKS_PER_UPDATE: u32 = 8;
 
 pub struct PhysicsController {
     last_update: Instant,
+    // Time not simulated yet, less than a tick after each update
+    accumulated: Duration,
+    // The camera collides with the voxels and falls instead of flying through them
+    pub walk_mode: bool,
+    // In voxels per second, positive downwards like the world Y axis
+    vertical_velocity: f32,
+    grounded: bool,
+}
+
+// The player radius in voxels
+pub fn player_radius() -> f32 {
+    PLAYER_RADIUS / VOXEL_PHYSICAL_LENGTH
+}
+
+// Whether a sphere overlaps a solid voxel, voxels are unit cubes centered on integer positions
+pub fn sphere_collides(world: &Chunks, center: Vec3, radius: f32) -> bool {
+    let min = (center - radius).round().as_ivec3();
+    let max = (center + radius).round().as_ivec3();
+
+    for x in min.x..=max.x {
+        for y in min.y..=max.y {
+            for z in min.z..=max.z {
+                let voxel = IVec3::new(x, y, z);
+                let closest = center.clamp(voxel.as_vec3() - 0.5, voxel.as_vec3() + 0.5);
+
+                if closest.distance_squared(center) < radius * radius && world.is_solid(&voxel) {
+                    return true;
+                }
+            }
+        }
+    }
+
+    false
+}
+
+// Moves a sphere by `delta` one axis at a time, so it slides along the voxels blocking it
+// instead of stopping. Returns the reached position and the axes that were blocked. A sphere
+// already stuck in voxels moves freely until it gets out
+pub fn move_and_slide(world: &Chunks, position: Vec3, delta: Vec3, radius: f32) -> (Vec3, BVec3) {
+    if sphere_collides(world, position, radius) {
+        return (position + delta, BVec3::FALSE);
+    }
+
+    let steps = (delta.abs().max_element() / (radius * MAX_STEP))
+        .ceil()
+        .max(1.0) as u32;
+    let step = delta / steps as f32;
+
+    let mut position = position;
+    let mut blocked = BVec3::FALSE;
+
+    for _ in 0..steps {
+        for axis in 0..3 {
+            if blocked.test(axis) || step[axis] == 0.0 {
+                continue;
+            }
+
+            let mut next = position;
+            next[axis] += step[axis];
+
+            if sphere_collides(world, next, radius) {
+                blocked.set(axis, true);
+            } else {
+                position = next;
+            }
+        }
+    }
+
+    (position, blocked)
 }
 
 impl PhysicsController {
     pub fn new() -> Self {
         PhysicsController {
             last_update: Instant::now(),
+            accumulated: Duration::ZERO,
+            walk_mode: false,
+            vertical_velocity: 0.0,
+            grounded: false,
+        }
+    }
+
+    pub fn grounded(&self) -> bool {
+        self.grounded
+    }
+
+    // Applies gravity to the player in walk mode, `TICKS_PER_SECOND` fixed steps per second of
+    // elapsed time. `jump` is whether the jump key is held. Returns the new player position
+    pub fn request_update(&mut self, world: &Chunks, position: Vec3, jump: bool) -> Vec3 {
+        let now = Instant::now();
+        let elapsed = now - self.last_update;
+        self.last_update = now;
+
+        if !self.walk_mode {
+            self.accumulated = Duration::ZERO;
+            self.vertical_velocity = 0.0;
+            self.grounded = false;
+
+            return position;
+        }
+
+        let tick = Duration::from_secs(1) / TICKS_PER_SECOND;
+
+        self.accumulated = (self.accumulated + elapsed).min(tick * MAX_TICKS_PER_UPDATE);
+
+        let mut position = position;
+
+        while self.accumulated >= tick {
+            self.accumulated -= tick;
+            position = self.step(world, position, jump, tick.as_secs_f32());
+        }
+
+        position
+    }
+
+    // A player resting on the ground doesn't move at all, so standing is stable
+    fn step(&mut self, world: &Chunks, position: Vec3, jump: bool, delta_time: f32) -> Vec3 {
+        let radius = player_radius();
+
+        self.grounded = self.vertical_velocity >= 0.0
+            && sphere_collides(world, position + Vec3::Y * GROUND_PROBE, radius);
+
+        if self.grounded {
+            self.vertical_velocity = 0.0;
+
+            if !jump {
+                return position;
+            }
+
+            self.vertical_velocity = -(2.0 * GRAVITY * JUMP_HEIGHT).sqrt() / VOXEL_PHYSICAL_LENGTH;
+            self.grounded = false;
+        }
+
+        self.vertical_velocity = (self.vertical_velocity
+            + GRAVITY / VOXEL_PHYSICAL_LENGTH * delta_time)
+            .min(TERMINAL_VELOCITY / VOXEL_PHYSICAL_LENGTH);
+
+        let (position, blocked) = move_and_slide(
+            world,
+            position,
+            Vec3::Y * self.vertical_velocity * delta_time,
+            radius,
+        );
+
+        // Landed or hit a ceiling
+        if blocked.y {
+            self.vertical_velocity = 0.0;
         }
+
+        position
     }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{IVec3, Vec3};
 
-    fn should_step(&self) -> bool {
-        let elapsed = self.last_update.elapsed().as_secs_f32();
+    use super::{PhysicsController, move_and_slide, player_radius, sphere_collides};
+    use crate::world::{HostVoxel, chunk::Chunks};
 
-        elapsed > 1.0 / TICKS_PER_SECOND as f32
+    // A wall in the x = 4 plane
+    fn wall() -> Chunks {
+        let mut chunks = Chunks::default();
+
+        for y in -8..8 {
+            for z in -8..8 {
+                chunks.insert(IVec3::new(4, y, z), HostVoxel::default());
+            }
+        }
+
+        chunks
     }
 
-    fn step(&mut self) {
-        self.last_update = Instant::now();
+    #[test]
+    fn physics_sphere_collides() {
+        let chunks = wall();
+
+        assert!(!sphere_collides(&chunks, Vec3::ZERO, 1.0));
+        assert!(sphere_collides(&chunks, Vec3::new(2.9, 0.0, 0.0), 1.0));
+        // Only the corner of the box is close, the sphere doesn't reach it
+        assert!(!sphere_collides(&chunks, Vec3::new(2.8, 8.3, 0.0), 1.0));
     }
 
-    pub fn request_update(&mut self) {
-        if self.should_step() {
-            self.step();
+    #[test]
+    fn physics_move_and_slide() {
+        let chunks = wall();
+
+        // Pushed diagonally into the wall, slides along it
+        let (position, blocked) =
+            move_and_slide(&chunks, Vec3::ZERO, Vec3::new(10.0, 0.0, 5.0), 1.0);
+
+        assert!(blocked.x && !blocked.z);
+        assert!(position.x > 2.0 && position.x <= 2.5);
+        assert!((position.z - 5.0).abs() < 1e-4);
+
+        // Fast movement doesn't tunnel through
+        let (position, _) = move_and_slide(&chunks, Vec3::ZERO, Vec3::new(100.0, 0.0, 0.0), 1.0);
+        assert!(position.x < 3.5);
+    }
+
+    #[test]
+    fn physics_gravity() {
+        // A floor in the y = 0 plane, the world is Y down
+        let mut chunks = Chunks::default();
+
+        for x in -8..8 {
+            for z in -8..8 {
+                chunks.insert(IVec3::new(x, 0, z), HostVoxel::default());
+            }
+        }
+
+        let mut physics = PhysicsController::new();
+        let mut position = Vec3::new(0.0, -20.0, 0.0);
+
+        for _ in 0..120 {
+            position = physics.step(&chunks, position, false, 1.0 / 60.0);
         }
+
+        assert!(physics.grounded());
+        assert!(position.y < -player_radius() + 0.5 && position.y > -player_radius() - 1.0);
+
+        // Standing still doesn't jitter
+        let resting = position;
+
+        for _ in 0..60 {
+            assert!(physics.step(&chunks, position, false, 1.0 / 60.0) == resting);
+        }
+
+        // A jump goes up then lands back
+        position = physics.step(&chunks, position, true, 1.0 / 60.0);
+        assert!(position.y < resting.y && !physics.grounded());
+
+        for _ in 0..120 {
+            position = physics.step(&chunks, position, false, 1.0 / 60.0);
+        }
+
+        assert!(physics.grounded() && (position.y - resting.y).abs() < 1.0);
     }
 }
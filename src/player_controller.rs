@@ -1,16 +1,18 @@
 use core::f32;
 use std::{
     collections::HashSet,
-    f32::consts::{FRAC_PI_2, TAU},
+    f32::consts::{FRAC_PI_2, SQRT_2, TAU},
     time::Duration,
 };
 
-use glam::{Mat4, Quat, Vec3, vec3};
+use glam::{BVec3, Mat4, Quat, Vec2, Vec3, vec3};
 use winit::{
     event::{ElementState, KeyEvent},
     keyboard::{Key, NamedKey, SmolStr},
 };
 
+use crate::{benchmark::CameraKeyframe, world::chunk::VOXEL_PHYSICAL_LENGTH};
+
 const FORWARD: Key = Key::Character(SmolStr::new_static("z"));
 const LEFT: Key = Key::Character(SmolStr::new_static("q"));
 const BACKWARD: Key = Key::Character(SmolStr::new_static("s"));
@@ -19,14 +21,70 @@ const RIGHT: Key = Key::Character(SmolStr::new_static("d"));
 const UP: Key = Key::Named(NamedKey::Space);
 const CONTROL: Key = Key::Named(NamedKey::Control);
 
+// Stick and trigger values below this are treated as resting
+const GAMEPAD_DEADZONE: f32 = 0.15;
+// Radians per second at full right stick tilt
+const GAMEPAD_LOOK_SPEED: f32 = 3.0;
+// Speed factor at full trigger, the right trigger speeds up and the left one slows down
+const GAMEPAD_TRIGGER_SCALE: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+// The last raw value of each axis, sticks are in [-1, 1] with Y up and triggers in [0, 1]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GamepadState {
+    pub left_stick: Vec2,
+    pub right_stick: Vec2,
+    pub left_trigger: f32,
+    pub right_trigger: f32,
+}
+
+// Rescales the stick so its magnitude goes from 0 at the edge of the deadzone to 1, keeping
+// its direction
+fn stick_with_deadzone(stick: Vec2) -> Vec2 {
+    let length = stick.length();
+
+    if length <= GAMEPAD_DEADZONE {
+        return Vec2::ZERO;
+    }
+
+    stick / length * ((length - GAMEPAD_DEADZONE) / (1.0 - GAMEPAD_DEADZONE)).min(1.0)
+}
+
+fn trigger_with_deadzone(trigger: f32) -> f32 {
+    ((trigger - GAMEPAD_DEADZONE) / (1.0 - GAMEPAD_DEADZONE)).clamp(0.0, 1.0)
+}
+
 pub struct PlayerController {
+    // In meters per second
     pub speed: f32,
+    // How fast the velocity reaches the pressed direction, per second. High values mimic
+    // instantaneous movement
+    pub acceleration: f32,
+    // How fast the velocity decays once no movement key is pressed, per second
+    pub damping: f32,
+    velocity: Vec3,
     pub pressed_keys: HashSet<Key>,
-    pub sensitivity: f64,
+    pub gamepad: GamepadState,
+    sensitivity: f64,
+    // Moving the mouse up looks down when set
+    pub invert_y: bool,
+    // Flying forward follows the pitch instead of staying horizontal when set
+    pub free_fly: bool,
     pub translation: Vec3,
 
     yaw: f32,
     pitch: f32,
+    // The pose `reset` goes back to
+    start: CameraKeyframe,
 
     view: Mat4,
     needs_view_update: bool,
@@ -35,14 +93,26 @@ pub struct PlayerController {
 impl Default for PlayerController {
     fn default() -> Self {
         let translation = Vec3::new(-16.0, 32.0, -16.0);
+        let start = CameraKeyframe {
+            position: translation,
+            yaw: 0.0,
+            pitch: 0.0,
+        };
 
         Self {
-            speed: 64.0,
+            speed: 4.0,
+            acceleration: 40.0,
+            damping: 20.0,
+            velocity: Vec3::ZERO,
             pressed_keys: HashSet::new(),
+            gamepad: GamepadState::default(),
             sensitivity: 0.001,
+            invert_y: false,
+            free_fly: false,
             translation,
             yaw: 0.0,
             pitch: 0.0,
+            start,
             view: Mat4::IDENTITY,
             needs_view_update: true,
         }
@@ -52,6 +122,55 @@ impl Default for PlayerController {
 impl PlayerController {
     const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
     const MIN_PITCH: f32 = -Self::MAX_PITCH;
+    const MIN_SENSITIVITY: f64 = 0.0001;
+    const MAX_SENSITIVITY: f64 = 0.01;
+
+    // A camera at `eye` looking towards `target`, which it also goes back to on `reset`. The
+    // orientation is stored as yaw and pitch so the mouse input continues from it
+    pub fn looking_at(eye: Vec3, target: Vec3) -> Self {
+        let (yaw, pitch) = Self::yaw_pitch(target - eye);
+        let start = CameraKeyframe {
+            position: eye,
+            yaw,
+            pitch,
+        };
+
+        let mut controller = Self {
+            start,
+            ..Self::default()
+        };
+        controller.set_keyframe(&start);
+
+        controller
+    }
+
+    // Looks at the center of the box from above one of its corners, far enough for the 90°
+    // field of view to fit the sphere around the box
+    pub fn framing(min: Vec3, max: Vec3) -> Self {
+        let center = (min + max) / 2.0;
+        let radius = (max - min).length() / 2.0;
+        // The world is Y down
+        let direction = Vec3::new(-1.0, -0.5, -1.0).normalize();
+
+        Self::looking_at(center + direction * radius * SQRT_2, center)
+    }
+
+    // The yaw and pitch that `orientation` turns into `direction`, the camera looks along +Z.
+    // Vertical directions keep a yaw of 0 and are clamped like the mouse input
+    fn yaw_pitch(direction: Vec3) -> (f32, f32) {
+        let direction = direction.normalize_or_zero();
+
+        if direction == Vec3::ZERO {
+            return (0.0, 0.0);
+        }
+
+        let yaw = direction.x.atan2(direction.z).rem_euclid(TAU);
+        let pitch = (-direction.y.clamp(-1.0, 1.0))
+            .asin()
+            .clamp(Self::MIN_PITCH, Self::MAX_PITCH);
+
+        (yaw, pitch)
+    }
 
     fn is_pressed(&self, key: Key) -> bool {
         self.pressed_keys.contains(&key)
@@ -65,40 +184,117 @@ impl PlayerController {
         self.view
     }
 
+    // The direction rays are cast towards from the camera
+    pub fn forward(&mut self) -> Vec3 {
+        self.view().inverse().transform_vector3(Vec3::Z)
+    }
+
     pub fn fly_movement(&mut self, delta_time: Duration) {
+        self.movement(delta_time, true);
+    }
+
+    // Only moves horizontally, the vertical movement is left to the physics
+    pub fn walk_movement(&mut self, delta_time: Duration) {
+        self.movement(delta_time, false);
+    }
+
+    // Space jumps instead of flying up in walk mode
+    pub fn jump_pressed(&self) -> bool {
+        self.is_pressed(UP)
+    }
+
+    fn movement(&mut self, delta_time: Duration, vertical: bool) {
         let view_inverse = self.view().inverse();
         let absolute_forward = view_inverse.transform_vector3(Vec3::Z);
-        let forward = vec3(absolute_forward.x, 0.0, absolute_forward.z).normalize();
+        let forward = if vertical && self.free_fly {
+            absolute_forward
+        } else {
+            vec3(absolute_forward.x, 0.0, absolute_forward.z).normalize()
+        };
         let right = view_inverse.transform_vector3(-Vec3::X);
 
-        let mut velocity = glam::Vec3::ZERO;
+        let mut direction = glam::Vec3::ZERO;
 
         if self.is_pressed(FORWARD) {
-            velocity += forward;
+            direction += forward;
         } else if self.is_pressed(BACKWARD) {
-            velocity -= forward;
+            direction -= forward;
         }
         if self.is_pressed(LEFT) {
-            velocity += right;
+            direction += right;
         } else if self.is_pressed(RIGHT) {
-            velocity -= right;
+            direction -= right;
+        }
+        if vertical {
+            if self.is_pressed(UP) {
+                direction -= glam::Vec3::Y;
+            } else if self.is_pressed(CONTROL) {
+                direction += glam::Vec3::Y;
+            }
+        }
+
+        // Normalized so diagonal movement isn't faster, the velocity only blends between
+        // vectors no longer than `speed`. The left stick adds to the keys, a partial tilt moves
+        // slower
+        let left_stick = stick_with_deadzone(self.gamepad.left_stick);
+        let direction = (direction.normalize_or_zero() + forward * left_stick.y
+            - right * left_stick.x)
+            .clamp_length_max(1.0);
+        let delta_time = delta_time.as_secs_f32();
+
+        // The velocity is in voxels per second like the translation
+        let speed = self.speed / VOXEL_PHYSICAL_LENGTH
+            * GAMEPAD_TRIGGER_SCALE.powf(
+                trigger_with_deadzone(self.gamepad.right_trigger)
+                    - trigger_with_deadzone(self.gamepad.left_trigger),
+            );
+
+        let right_stick = stick_with_deadzone(self.gamepad.right_stick) * GAMEPAD_LOOK_SPEED;
+        if right_stick != Vec2::ZERO {
+            self.rotate_radians(right_stick.x * delta_time, -right_stick.y * delta_time);
         }
-        if self.is_pressed(UP) {
-            velocity -= glam::Vec3::Y;
-        } else if self.is_pressed(CONTROL) {
-            velocity += glam::Vec3::Y;
+
+        // Exponential smoothing, so the feel doesn't depend on the frame rate
+        self.velocity = if direction == Vec3::ZERO {
+            self.velocity * (-self.damping * delta_time).exp()
+        } else {
+            let blend = 1.0 - (-self.acceleration * delta_time).exp();
+
+            self.velocity.lerp(direction * speed, blend)
+        };
+
+        if !vertical {
+            self.velocity.y = 0.0;
         }
 
-        velocity = velocity.normalize_or_zero();
+        self.translation += self.velocity * delta_time;
 
-        self.translation += velocity * delta_time.as_secs_f32() * self.speed;
+        self.needs_view_update = true;
+    }
+
+    // Moves the camera without touching its velocity
+    pub fn set_translation(&mut self, translation: Vec3) {
+        self.translation = translation;
 
         self.needs_view_update = true;
     }
 
     pub fn rotate(&mut self, delta: (f64, f64)) {
-        self.yaw += (delta.0 * self.sensitivity) as f32;
-        self.pitch -= (delta.1 * self.sensitivity) as f32;
+        self.rotate_radians(
+            (delta.0 * self.sensitivity) as f32,
+            (delta.1 * self.sensitivity) as f32,
+        );
+    }
+
+    // A positive pitch delta looks down, unless `invert_y` is set
+    fn rotate_radians(&mut self, yaw_delta: f32, pitch_delta: f32) {
+        self.yaw += yaw_delta;
+
+        if self.invert_y {
+            self.pitch += pitch_delta;
+        } else {
+            self.pitch -= pitch_delta;
+        }
 
         self.yaw = self.yaw.rem_euclid(TAU);
 
@@ -107,19 +303,84 @@ impl PlayerController {
         self.needs_view_update = true;
     }
 
-    fn orientation(&self) -> Quat {
-        let yaw_q = Quat::from_rotation_y(self.yaw);
-        let pitch_q = Quat::from_rotation_x(self.pitch);
+    // Back to the starting position and orientation, the settings and held inputs are kept
+    pub fn reset(&mut self) {
+        let start = self.start;
+
+        *self = Self {
+            speed: self.speed,
+            acceleration: self.acceleration,
+            damping: self.damping,
+            pressed_keys: std::mem::take(&mut self.pressed_keys),
+            gamepad: self.gamepad,
+            sensitivity: self.sensitivity,
+            invert_y: self.invert_y,
+            start,
+            ..Self::default()
+        };
+        self.set_keyframe(&start);
+    }
+
+    pub fn teleport(&mut self, translation: Vec3) {
+        self.translation = translation;
+        self.velocity = Vec3::ZERO;
+
+        self.needs_view_update = true;
+    }
+
+    // Moves the camera back to where a collision stopped it, the velocity into the blocked
+    // axes is dropped so it doesn't keep pushing against the voxels
+    pub fn resolve_collision(&mut self, translation: Vec3, blocked: BVec3) {
+        self.translation = translation;
+        self.velocity = Vec3::select(blocked, Vec3::ZERO, self.velocity);
+
+        self.needs_view_update = true;
+    }
+
+    pub fn keyframe(&self) -> CameraKeyframe {
+        CameraKeyframe {
+            position: self.translation,
+            yaw: self.yaw,
+            pitch: self.pitch,
+        }
+    }
+
+    // Places the camera at the pose of a keyframe, the movement stops
+    pub fn set_keyframe(&mut self, keyframe: &CameraKeyframe) {
+        self.translation = keyframe.position;
+        self.yaw = keyframe.yaw.rem_euclid(TAU);
+        self.pitch = keyframe.pitch.clamp(Self::MIN_PITCH, Self::MAX_PITCH);
+        self.velocity = Vec3::ZERO;
+
+        self.needs_view_update = true;
+    }
+
+    fn orientation(yaw: f32, pitch: f32) -> Quat {
+        let yaw_q = Quat::from_rotation_y(yaw);
+        let pitch_q = Quat::from_rotation_x(pitch);
 
         yaw_q * pitch_q
     }
 
-    fn compute_view(&mut self) {
-        let rot = self.orientation();
+    pub fn view_from(translation: Vec3, yaw: f32, pitch: f32) -> Mat4 {
+        let rot = Self::orientation(yaw, pitch);
         let forward = rot * Vec3::new(0.0, 0.0, -1.0);
         let up = rot * Vec3::new(0.0, 1.0, 0.0);
 
-        self.view = Mat4::look_at_rh(self.translation, self.translation + forward, up);
+        Mat4::look_at_rh(translation, translation + forward, up)
+    }
+
+    fn compute_view(&mut self) {
+        self.view = Self::view_from(self.translation, self.yaw, self.pitch);
+    }
+
+    pub fn sensitivity(&self) -> f64 {
+        self.sensitivity
+    }
+
+    // Only scales the upcoming mouse deltas, the current orientation is kept
+    pub fn set_sensitivity(&mut self, sensitivity: f64) {
+        self.sensitivity = sensitivity.clamp(Self::MIN_SENSITIVITY, Self::MAX_SENSITIVITY);
     }
 
     pub fn handle_speed_change(&mut self, y_delta: f32) {
@@ -140,4 +401,49 @@ impl PlayerController {
             }
         };
     }
+
+    pub fn handle_gamepad_axis(&mut self, axis: GamepadAxis, value: f32) {
+        match axis {
+            GamepadAxis::LeftStickX => self.gamepad.left_stick.x = value,
+            GamepadAxis::LeftStickY => self.gamepad.left_stick.y = value,
+            GamepadAxis::RightStickX => self.gamepad.right_stick.x = value,
+            GamepadAxis::RightStickY => self.gamepad.right_stick.y = value,
+            GamepadAxis::LeftTrigger => self.gamepad.left_trigger = value,
+            GamepadAxis::RightTrigger => self.gamepad.right_trigger = value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::Vec3;
+
+    use super::PlayerController;
+
+    #[test]
+    fn looking_at_recovers_direction() {
+        let eye = Vec3::new(4.0, -2.0, 7.0);
+
+        for direction in [
+            Vec3::new(1.0, 0.3, -2.0),
+            Vec3::new(-1.0, -0.5, -1.0),
+            Vec3::new(0.0, 0.2, 1.0),
+            Vec3::new(-3.0, 1.0, 0.5),
+        ] {
+            let mut controller = PlayerController::looking_at(eye, eye + direction);
+
+            assert!((controller.forward() - direction.normalize()).length() < 1e-5);
+
+            // The mouse input continues from the recovered orientation
+            let (yaw, pitch) = (controller.yaw, controller.pitch);
+            controller.rotate_radians(0.0, 0.0);
+            assert!(controller.yaw == yaw && controller.pitch == pitch);
+
+            // Reset goes back to the same pose
+            controller.rotate_radians(1.0, 0.5);
+            controller.reset();
+            assert!(controller.translation == eye);
+            assert!((controller.forward() - direction.normalize()).length() < 1e-5);
+        }
+    }
 }
@@ -0,0 +1,380 @@
+use std::{
+    f32::consts::{PI, TAU},
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    time::Duration,
+};
+
+use glam::Vec3;
+
+use crate::benchmark::CameraKeyframe;
+
+const MAGIC: &[u8; 4] = b"ACAM";
+const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraSample {
+    // Seconds since the start of the recording
+    pub time: f32,
+    pub keyframe: CameraKeyframe,
+}
+
+// Camera poses sampled over time. The yaw is unwrapped while recording, so interpolating
+// between samples never turns the long way around
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CameraRecording {
+    samples: Vec<CameraSample>,
+}
+
+impl CameraRecording {
+    pub fn samples(&self) -> &[CameraSample] {
+        &self.samples
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.samples.last().map_or(0.0, |sample| sample.time)
+    }
+
+    pub fn push(&mut self, time: f32, mut keyframe: CameraKeyframe) {
+        if let Some(last) = self.samples.last() {
+            let delta = (keyframe.yaw - last.keyframe.yaw + PI).rem_euclid(TAU) - PI;
+            keyframe.yaw = last.keyframe.yaw + delta;
+        }
+
+        self.samples.push(CameraSample { time, keyframe });
+    }
+
+    // Catmull-Rom interpolation through the samples around `time`, so the speed doesn't jump
+    // at each sample
+    pub fn sample(&self, time: f32) -> CameraKeyframe {
+        assert!(!self.samples.is_empty(), "the recording has no samples");
+
+        let last = self.samples.len() - 1;
+        let next = self
+            .samples
+            .partition_point(|sample| sample.time <= time)
+            .clamp(1, last.max(1))
+            .min(last);
+        let index = next.saturating_sub(1);
+
+        let (start, end) = (&self.samples[index], &self.samples[next]);
+        let span = end.time - start.time;
+        let t = if span > 0.0 {
+            ((time - start.time) / span).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        let p0 = &self.samples[index.saturating_sub(1)].keyframe;
+        let p3 = &self.samples[(next + 1).min(last)].keyframe;
+
+        let catmull_rom = |p0: f32, p1: f32, p2: f32, p3: f32| {
+            0.5 * (2.0 * p1
+                + (p2 - p0) * t
+                + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t
+                + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t * t * t)
+        };
+
+        let (p1, p2) = (&start.keyframe, &end.keyframe);
+
+        CameraKeyframe {
+            position: Vec3::from_array(std::array::from_fn(|axis| {
+                catmull_rom(
+                    p0.position[axis],
+                    p1.position[axis],
+                    p2.position[axis],
+                    p3.position[axis],
+                )
+            })),
+            yaw: catmull_rom(p0.yaw, p1.yaw, p2.yaw, p3.yaw),
+            pitch: catmull_rom(p0.pitch, p1.pitch, p2.pitch, p3.pitch),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        self.write_to(&mut writer)?;
+
+        writer.flush()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    // The magic, the version, the sample count then the time, position, yaw and pitch of each
+    // sample, in little endian
+    fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(self.samples.len() as u32).to_le_bytes())?;
+
+        for sample in &self.samples {
+            let CameraKeyframe {
+                position,
+                yaw,
+                pitch,
+            } = sample.keyframe;
+
+            for value in [sample.time, position.x, position.y, position.z, yaw, pitch] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_from(reader: &mut impl Read) -> io::Result<Self> {
+        let mut word = [0; 4];
+
+        reader.read_exact(&mut word)?;
+        if &word != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a camera recording",
+            ));
+        }
+
+        reader.read_exact(&mut word)?;
+        if u32::from_le_bytes(word) != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unsupported camera recording version",
+            ));
+        }
+
+        reader.read_exact(&mut word)?;
+        let sample_count = u32::from_le_bytes(word);
+
+        let mut recording = CameraRecording::default();
+
+        for _ in 0..sample_count {
+            let mut values = [0.0; 6];
+
+            for value in &mut values {
+                reader.read_exact(&mut word)?;
+                *value = f32::from_le_bytes(word);
+            }
+
+            let [time, x, y, z, yaw, pitch] = values;
+
+            recording.samples.push(CameraSample {
+                time,
+                keyframe: CameraKeyframe {
+                    position: Vec3::new(x, y, z),
+                    yaw,
+                    pitch,
+                },
+            });
+        }
+
+        Ok(recording)
+    }
+}
+
+// A single camera pose as text, one `name values...` line per field, followed by the camera
+// matrices for reference. Floats are written in their shortest form that parses back to the
+// same value, so a loaded pose renders the same frame. The matrices are column major
+pub fn camera_dump(keyframe: &CameraKeyframe, matrices: &[(&str, [[f32; 4]; 4])]) -> String {
+    let mut dump = format!(
+        "translation {} {} {}\nyaw {}\npitch {}\n",
+        keyframe.position.x, keyframe.position.y, keyframe.position.z, keyframe.yaw, keyframe.pitch
+    );
+
+    for (name, matrix) in matrices {
+        let values = matrix.as_flattened().iter().map(f32::to_string);
+        dump += &format!("{name} {}\n", values.collect::<Vec<_>>().join(" "));
+    }
+
+    dump
+}
+
+// Reads back the pose of `camera_dump`, the matrices are derived from it and skipped
+pub fn parse_camera_dump(dump: &str) -> io::Result<CameraKeyframe> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_owned());
+
+    let field = |name: &str| {
+        dump.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .ok_or_else(|| invalid(&format!("missing camera {name}")))?
+            .split_whitespace()
+            .map(|value| value.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid(&format!("invalid camera {name}: {e}")))
+    };
+
+    let (translation, yaw, pitch) = (field("translation")?, field("yaw")?, field("pitch")?);
+
+    match (translation.as_slice(), yaw.as_slice(), pitch.as_slice()) {
+        (&[x, y, z], &[yaw], &[pitch]) => Ok(CameraKeyframe {
+            position: Vec3::new(x, y, z),
+            yaw,
+            pitch,
+        }),
+        _ => Err(invalid("wrong amount of camera values")),
+    }
+}
+
+pub fn load_camera_dump(path: &Path) -> io::Result<CameraKeyframe> {
+    parse_camera_dump(&std::fs::read_to_string(path)?)
+}
+
+// Samples the camera every frame while active
+#[derive(Debug, Default)]
+pub struct CameraRecorder {
+    recording: CameraRecording,
+    elapsed: Duration,
+}
+
+impl CameraRecorder {
+    pub fn record(&mut self, delta_time: Duration, keyframe: CameraKeyframe) {
+        if !self.recording.is_empty() {
+            self.elapsed += delta_time;
+        }
+
+        self.recording.push(self.elapsed.as_secs_f32(), keyframe);
+    }
+
+    pub fn finish(self) -> CameraRecording {
+        self.recording
+    }
+}
+
+// Plays a recording back at the speed it was recorded
+#[derive(Debug)]
+pub struct CameraReplay {
+    recording: CameraRecording,
+    time: f32,
+    looping: bool,
+}
+
+impl CameraReplay {
+    pub fn new(recording: CameraRecording, looping: bool) -> Option<Self> {
+        (!recording.is_empty()).then_some(Self {
+            recording,
+            time: 0.0,
+            looping,
+        })
+    }
+
+    // The pose after `delta_time`, `None` once a non looping replay is over
+    pub fn advance(&mut self, delta_time: Duration) -> Option<CameraKeyframe> {
+        let duration = self.recording.duration();
+
+        self.time += delta_time.as_secs_f32();
+
+        if self.time > duration {
+            if !self.looping {
+                return None;
+            }
+
+            self.time = if duration > 0.0 {
+                self.time % duration
+            } else {
+                0.0
+            };
+        }
+
+        Some(self.recording.sample(self.time))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{f32::consts::TAU, time::Duration};
+
+    use glam::Vec3;
+
+    use super::{CameraRecorder, CameraRecording, CameraReplay, camera_dump, parse_camera_dump};
+    use crate::benchmark::CameraKeyframe;
+
+    fn keyframe(x: f32, yaw: f32) -> CameraKeyframe {
+        CameraKeyframe {
+            position: Vec3::new(x, 0.0, 0.0),
+            yaw,
+            pitch: 0.0,
+        }
+    }
+
+    fn recording() -> CameraRecording {
+        let mut recorder = CameraRecorder::default();
+
+        for x in 0..4 {
+            recorder.record(Duration::from_millis(500), keyframe(x as f32, 0.0));
+        }
+
+        recorder.finish()
+    }
+
+    #[test]
+    fn recording_interpolation() {
+        let recording = recording();
+
+        assert!(recording.duration() == 1.5);
+        // Samples are passed through, evenly spaced samples on a line stay on it
+        assert!(recording.sample(0.5) == keyframe(1.0, 0.0));
+        assert!((recording.sample(0.75).position.x - 1.5).abs() < 1e-5);
+        assert!(recording.sample(10.0) == keyframe(3.0, 0.0));
+
+        let mut wrapping = CameraRecording::default();
+        wrapping.push(0.0, keyframe(0.0, TAU - 0.1));
+        wrapping.push(1.0, keyframe(0.0, 0.1));
+
+        assert!((wrapping.sample(0.5).yaw - TAU).abs() < 1e-5);
+    }
+
+    #[test]
+    fn recording_replay() {
+        let mut replay = CameraReplay::new(recording(), false).unwrap();
+
+        assert!(replay.advance(Duration::from_secs(1)).is_some());
+        assert!(replay.advance(Duration::from_secs(1)).is_none());
+
+        let mut replay = CameraReplay::new(recording(), true).unwrap();
+        replay.advance(Duration::from_secs(2));
+
+        assert!((replay.advance(Duration::ZERO).unwrap().position.x - 1.0).abs() < 1e-5);
+        assert!(CameraReplay::new(CameraRecording::default(), true).is_none());
+    }
+
+    #[test]
+    fn recording_round_trip() {
+        let recording = recording();
+
+        let mut bytes = Vec::new();
+        recording.write_to(&mut bytes).unwrap();
+
+        assert!(CameraRecording::read_from(&mut bytes.as_slice()).unwrap() == recording);
+        assert!(CameraRecording::read_from(&mut &bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn camera_dump_round_trip() {
+        let keyframe = CameraKeyframe {
+            position: Vec3::new(0.1, -1.0e-7, 12345.678),
+            yaw: TAU / 3.0,
+            pitch: -0.3,
+        };
+        let matrix = [[0.1, 0.2, 0.3, 0.4]; 4];
+
+        let dump = camera_dump(&keyframe, &[("view_proj", matrix)]);
+
+        assert!(dump.lines().count() == 4);
+        assert!(parse_camera_dump(&dump).unwrap() == keyframe);
+
+        // Bit exact, not just close
+        let loaded = parse_camera_dump(&dump).unwrap();
+        assert!(loaded.position.x.to_bits() == keyframe.position.x.to_bits());
+        assert!(loaded.yaw.to_bits() == keyframe.yaw.to_bits());
+
+        assert!(parse_camera_dump("yaw 0\npitch 0\n").is_err());
+        assert!(parse_camera_dump("translation 1 2\nyaw 0\npitch 0\n").is_err());
+        assert!(parse_camera_dump("translation 1 2 x\nyaw 0\npitch 0\n").is_err());
+    }
+}
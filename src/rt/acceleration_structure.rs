@@ -1,13 +1,18 @@
-use std::{sync::Arc, time::Instant};
+use std::{
+    fmt::Display,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use vulkano::{
     acceleration_structure::{
-        AccelerationStructure, AccelerationStructureBuildGeometryInfo,
+        AabbPositions, AccelerationStructure, AccelerationStructureBuildGeometryInfo,
         AccelerationStructureBuildRangeInfo, AccelerationStructureBuildType,
         AccelerationStructureCreateInfo, AccelerationStructureGeometries,
-        AccelerationStructureGeometryInstancesData, AccelerationStructureGeometryInstancesDataType,
-        AccelerationStructureGeometryTrianglesData, AccelerationStructureInstance,
-        AccelerationStructureType, BuildAccelerationStructureFlags, BuildAccelerationStructureMode,
+        AccelerationStructureGeometryAabbsData, AccelerationStructureGeometryInstancesData,
+        AccelerationStructureGeometryInstancesDataType, AccelerationStructureGeometryTrianglesData,
+        AccelerationStructureInstance, AccelerationStructureType, BuildAccelerationStructureFlags,
+        BuildAccelerationStructureMode, GeometryFlags,
     },
     buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
     device::{Device, Queue},
@@ -19,20 +24,69 @@ use vulkano_taskgraph::{
     resource::{Flight, Resources},
 };
 
+use super::scratch::ScratchBufferPool;
 use crate::world::Vertex3D;
 
+// How long building an acceleration structure took on the host, for the caller to report
+#[derive(Clone, Copy, Debug)]
+pub struct BuildTimings {
+    // Whether a TLAS or a BLAS was built
+    pub ty: AccelerationStructureType,
+    // Creating the buffers, recording and submitting the build command
+    pub cmd: Duration,
+    // Waiting for the build to complete on the device
+    pub wait: Duration,
+}
+
+impl BuildTimings {
+    // The timings of builds of the same type one after the other
+    pub fn sum(self, other: Self) -> Self {
+        Self {
+            ty: self.ty,
+            cmd: self.cmd + other.cmd,
+            wait: self.wait + other.wait,
+        }
+    }
+}
+
+impl Display for BuildTimings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self.ty {
+            AccelerationStructureType::TopLevel => "TLAS",
+            AccelerationStructureType::BottomLevel => "BLAS",
+            _ => "acceleration structure",
+        };
+
+        write!(
+            f,
+            "{name} built in {:.2?} ({:.2?} recording, {:.2?} on the device)",
+            self.cmd + self.wait,
+            self.cmd,
+            self.wait
+        )
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn build_acceleration_structure_common(
     geometries: AccelerationStructureGeometries,
     mode: BuildAccelerationStructureMode,
     primitive_count: u32,
+    max_primitive_count: u32,
+    dst_acceleration_structure: Option<Arc<AccelerationStructure>>,
     ty: AccelerationStructureType,
     memory_allocator: Arc<dyn MemoryAllocator>,
+    scratch_buffers: &mut ScratchBufferPool,
     device: Arc<Device>,
     queue: Arc<Queue>,
     resources: &Arc<Resources>,
     flight_id: Id<Flight>,
-) -> Arc<AccelerationStructure> {
+) -> (Arc<AccelerationStructure>, BuildTimings) {
+    assert!(
+        primitive_count <= max_primitive_count,
+        "{primitive_count} primitives built into an acceleration structure sized for {max_primitive_count}"
+    );
+
     let now = Instant::now();
 
     let flags = match ty {
@@ -54,45 +108,42 @@ pub fn build_acceleration_structure_common(
         ..AccelerationStructureBuildGeometryInfo::new(geometries)
     };
 
+    // Building zero primitives is valid and leaves an empty acceleration structure, but the
+    // sizes of an empty build can be zero, which no buffer can be allocated with
     let as_build_sizes_info = device
         .acceleration_structure_build_sizes(
             AccelerationStructureBuildType::Device,
             &as_build_geometry_info,
-            &[primitive_count],
+            &[max_primitive_count.max(1)],
         )
         .unwrap();
 
-    let scratch_buffer = Buffer::new_slice::<u8>(
-        &memory_allocator,
-        &BufferCreateInfo {
-            usage: BufferUsage::SHADER_DEVICE_ADDRESS | BufferUsage::STORAGE_BUFFER,
-            ..Default::default()
-        },
-        &AllocationCreateInfo::default(),
-        as_build_sizes_info.build_scratch_size,
-    )
-    .unwrap();
-
-    let as_buffer = Buffer::new_slice::<u8>(
-        &memory_allocator,
-        &BufferCreateInfo {
-            usage: BufferUsage::ACCELERATION_STRUCTURE_STORAGE | BufferUsage::SHADER_DEVICE_ADDRESS,
-            ..Default::default()
-        },
-        &AllocationCreateInfo::default(),
-        as_build_sizes_info.acceleration_structure_size,
-    )
-    .unwrap();
+    let scratch_buffer =
+        scratch_buffers.acquire(&memory_allocator, as_build_sizes_info.build_scratch_size);
 
-    let as_create_info = AccelerationStructureCreateInfo {
-        ty,
-        ..AccelerationStructureCreateInfo::new(&as_buffer)
-    };
+    let acceleration = dst_acceleration_structure.unwrap_or_else(|| {
+        let as_buffer = Buffer::new_slice::<u8>(
+            &memory_allocator,
+            &BufferCreateInfo {
+                usage: BufferUsage::ACCELERATION_STRUCTURE_STORAGE
+                    | BufferUsage::SHADER_DEVICE_ADDRESS,
+                ..Default::default()
+            },
+            &AllocationCreateInfo::default(),
+            as_build_sizes_info.acceleration_structure_size,
+        )
+        .unwrap();
+
+        let as_create_info = AccelerationStructureCreateInfo {
+            ty,
+            ..AccelerationStructureCreateInfo::new(&as_buffer)
+        };
 
-    let acceleration = unsafe { AccelerationStructure::new(&device, &as_create_info) }.unwrap();
+        unsafe { AccelerationStructure::new(&device, &as_create_info) }.unwrap()
+    });
 
     as_build_geometry_info.dst_acceleration_structure = Some(acceleration.clone());
-    as_build_geometry_info.scratch_data = Some(scratch_buffer);
+    as_build_geometry_info.scratch_data = Some(scratch_buffer.clone());
 
     let as_build_range_info = AccelerationStructureBuildRangeInfo {
         primitive_count,
@@ -118,37 +169,36 @@ pub fn build_acceleration_structure_common(
         .unwrap()
     };
 
-    match ty {
-        AccelerationStructureType::TopLevel => {
-            print!("build tlas ");
-        }
-        AccelerationStructureType::BottomLevel => {
-            print!("build blas ");
-        }
-        _ => {}
-    }
+    let cmd = now.elapsed();
 
     resources.flight(flight_id).unwrap().wait_idle().unwrap();
 
-    let elapsed = now.elapsed();
-    println!("{elapsed:.2?}");
+    let wait = now.elapsed() - cmd;
 
-    acceleration
+    scratch_buffers.release(scratch_buffer);
+
+    (acceleration, BuildTimings { ty, cmd, wait })
 }
 
+// Geometry built without `GeometryFlags::OPAQUE` invokes the any-hit shader of the rays that
+// don't force it opaque
+#[allow(clippy::too_many_arguments)]
 pub fn build_blas(
     vertex_buffer: Subbuffer<[Vertex3D]>,
+    geometry_flags: GeometryFlags,
     memory_allocator: Arc<dyn MemoryAllocator>,
+    scratch_buffers: &mut ScratchBufferPool,
     device: Arc<Device>,
     queue: Arc<Queue>,
     resources: &Arc<Resources>,
     flight_id: Id<Flight>,
-) -> Arc<AccelerationStructure> {
+) -> (Arc<AccelerationStructure>, BuildTimings) {
     let primitive_count = (vertex_buffer.len() / 3) as u32;
     let as_geometry_triangles_data = AccelerationStructureGeometryTrianglesData {
         max_vertex: vertex_buffer.len() as _,
         vertex_data: Some(vertex_buffer.into_bytes()),
         vertex_stride: size_of::<Vertex3D>() as _,
+        flags: geometry_flags,
         ..AccelerationStructureGeometryTrianglesData::new(Format::R32G32B32_SFLOAT)
     };
 
@@ -158,8 +208,48 @@ pub fn build_blas(
         geometries,
         BuildAccelerationStructureMode::Build,
         primitive_count,
+        primitive_count,
+        None,
+        AccelerationStructureType::BottomLevel,
+        memory_allocator,
+        scratch_buffers,
+        device,
+        queue,
+        resources,
+        flight_id,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn build_blas_aabb(
+    aabb_buffer: Subbuffer<[AabbPositions]>,
+    geometry_flags: GeometryFlags,
+    memory_allocator: Arc<dyn MemoryAllocator>,
+    scratch_buffers: &mut ScratchBufferPool,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    resources: &Arc<Resources>,
+    flight_id: Id<Flight>,
+) -> (Arc<AccelerationStructure>, BuildTimings) {
+    let primitive_count = aabb_buffer.len() as u32;
+    let as_geometry_aabbs_data = AccelerationStructureGeometryAabbsData {
+        data: Some(aabb_buffer.into_bytes()),
+        stride: size_of::<AabbPositions>() as _,
+        flags: geometry_flags,
+        ..Default::default()
+    };
+
+    let geometries = AccelerationStructureGeometries::Aabbs(vec![as_geometry_aabbs_data]);
+
+    build_acceleration_structure_common(
+        geometries,
+        BuildAccelerationStructureMode::Build,
+        primitive_count,
+        primitive_count,
+        None,
         AccelerationStructureType::BottomLevel,
         memory_allocator,
+        scratch_buffers,
         device,
         queue,
         resources,
@@ -167,15 +257,20 @@ pub fn build_blas(
     )
 }
 
+// Builds a TLAS able to hold up to `max_primitive_count` instances, so that it can later be
+// rebuilt in place with `rebuild_tlas`
+#[allow(clippy::too_many_arguments)]
 pub fn build_tlas(
     instance_buffer: Subbuffer<[AccelerationStructureInstance]>,
     primitive_count: u32,
+    max_primitive_count: u32,
     allocator: Arc<dyn MemoryAllocator>,
+    scratch_buffers: &mut ScratchBufferPool,
     device: Arc<Device>,
     queue: Arc<Queue>,
     resources: &Arc<Resources>,
     flight_id: Id<Flight>,
-) -> Arc<AccelerationStructure> {
+) -> (Arc<AccelerationStructure>, BuildTimings) {
     let as_geometry_instances_data = AccelerationStructureGeometryInstancesData::new(
         AccelerationStructureGeometryInstancesDataType::Values(Some(instance_buffer)),
     );
@@ -186,8 +281,46 @@ pub fn build_tlas(
         geometries,
         BuildAccelerationStructureMode::Build,
         primitive_count,
+        max_primitive_count,
+        None,
+        AccelerationStructureType::TopLevel,
+        allocator,
+        scratch_buffers,
+        device,
+        queue,
+        resources,
+        flight_id,
+    )
+}
+
+// Fully rebuilds an existing TLAS, `primitive_count` must not exceed the count it was created with
+#[allow(clippy::too_many_arguments)]
+pub fn rebuild_tlas(
+    tlas: Arc<AccelerationStructure>,
+    instance_buffer: Subbuffer<[AccelerationStructureInstance]>,
+    primitive_count: u32,
+    allocator: Arc<dyn MemoryAllocator>,
+    scratch_buffers: &mut ScratchBufferPool,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    resources: &Arc<Resources>,
+    flight_id: Id<Flight>,
+) -> (Arc<AccelerationStructure>, BuildTimings) {
+    let as_geometry_instances_data = AccelerationStructureGeometryInstancesData::new(
+        AccelerationStructureGeometryInstancesDataType::Values(Some(instance_buffer)),
+    );
+
+    let geometries = AccelerationStructureGeometries::Instances(as_geometry_instances_data);
+
+    build_acceleration_structure_common(
+        geometries,
+        BuildAccelerationStructureMode::Build,
+        primitive_count,
+        primitive_count,
+        Some(tlas),
         AccelerationStructureType::TopLevel,
         allocator,
+        scratch_buffers,
         device,
         queue,
         resources,
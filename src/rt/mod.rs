@@ -1,9 +1,19 @@
 pub mod acceleration_structure;
+pub mod scratch;
 
+// The heatmap variant needs the shader clock features, so it's a separate shader
 pub(crate) mod raygen {
     vulkano_shaders::shader! {
-        ty: "raygen",
-        path: "shaders/rt/simple.rgen",
+        shaders: {
+            default: {
+                ty: "raygen",
+                path: "shaders/rt/simple.rgen",
+            },
+            heatmap: {
+                ty: "raygen",
+                path: "shaders/rt/heatmap.rgen",
+            },
+        },
         vulkan_version: "1.3"
     }
 }
@@ -31,3 +41,19 @@ pub(crate) mod closest_hit {
         vulkan_version: "1.3"
     }
 }
+
+pub(crate) mod any_hit {
+    vulkano_shaders::shader! {
+        ty: "anyhit",
+        path: "shaders/rt/simple.rahit",
+        vulkan_version: "1.3"
+    }
+}
+
+pub(crate) mod query {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "shaders/rt/query.comp",
+        vulkan_version: "1.3"
+    }
+}
@@ -0,0 +1,104 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use vulkano::{
+    DeviceSize,
+    buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    device::Device,
+    memory::allocator::{AllocationCreateInfo, DeviceLayout, MemoryAllocator},
+};
+
+// The amount of requests remembered to decide which buffers are still useful
+const RECENT_REQUESTS: usize = 16;
+// A pooled buffer this many times larger than every recent request is freed
+const MAX_OVERSIZE: DeviceSize = 4;
+
+// Scratch buffers reused across acceleration structure builds instead of allocating one per
+// build. A build waits for the device before returning, so its buffer can be released right
+// after and handed to the next build
+pub struct ScratchBufferPool {
+    // `min_acceleration_structure_scratch_offset_alignment` of the device
+    alignment: DeviceSize,
+    // The most buffers kept around between builds, 0 allocates a buffer for every build
+    capacity: usize,
+    buffers: Vec<Subbuffer<[u8]>>,
+    recent_sizes: VecDeque<DeviceSize>,
+}
+
+impl ScratchBufferPool {
+    pub fn new(device: &Device, capacity: usize) -> Self {
+        let alignment = device
+            .physical_device()
+            .properties()
+            .min_acceleration_structure_scratch_offset_alignment
+            .expect("Min acceleration structure scratch offset alignment not found")
+            as DeviceSize;
+
+        Self {
+            alignment,
+            capacity,
+            buffers: Vec::new(),
+            recent_sizes: VecDeque::with_capacity(RECENT_REQUESTS),
+        }
+    }
+
+    // Returns the smallest pooled buffer of at least `size` bytes, or a new one when none fits
+    pub fn acquire(
+        &mut self,
+        memory_allocator: &Arc<dyn MemoryAllocator>,
+        size: DeviceSize,
+    ) -> Subbuffer<[u8]> {
+        if self.recent_sizes.len() == RECENT_REQUESTS {
+            self.recent_sizes.pop_front();
+        }
+        self.recent_sizes.push_back(size);
+
+        self.trim();
+
+        let best_fit = self
+            .buffers
+            .iter()
+            .enumerate()
+            .filter(|(_, buffer)| buffer.size() >= size)
+            .min_by_key(|(_, buffer)| buffer.size())
+            .map(|(index, _)| index);
+
+        if let Some(index) = best_fit {
+            return self.buffers.swap_remove(index);
+        }
+
+        let buffer = Buffer::new(
+            memory_allocator,
+            &BufferCreateInfo {
+                usage: BufferUsage::SHADER_DEVICE_ADDRESS | BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            &AllocationCreateInfo::default(),
+            DeviceLayout::from_size_alignment(size, self.alignment).unwrap(),
+        )
+        .expect("Scratch buffer creation failed");
+
+        Subbuffer::new(buffer)
+    }
+
+    // The buffer must not be used by the device anymore
+    pub fn release(&mut self, buffer: Subbuffer<[u8]>) {
+        self.buffers.push(buffer);
+
+        // The smallest buffers are the least likely to fit the next builds
+        if self.buffers.len() > self.capacity {
+            self.buffers.sort_by_key(|buffer| buffer.size());
+            let excess = self.buffers.len() - self.capacity;
+            self.buffers.drain(..excess);
+        }
+    }
+
+    // Frees the buffers much larger than every recent request, left over from a bigger build
+    fn trim(&mut self) {
+        let Some(largest_recent) = self.recent_sizes.iter().copied().max() else {
+            return;
+        };
+
+        self.buffers
+            .retain(|buffer| buffer.size() <= largest_recent.saturating_mul(MAX_OVERSIZE));
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use vulkano::format::Format;
+
+// `screenshot-<unix milliseconds>.png` in the working directory
+pub fn timestamped_path() -> PathBuf {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+
+    PathBuf::from(format!("screenshot-{timestamp}.png"))
+}
+
+// Whether `to_rgba8` can convert images of the format
+pub fn supports_format(format: Format) -> bool {
+    matches!(
+        format,
+        Format::R8G8B8A8_UNORM
+            | Format::R8G8B8A8_SRGB
+            | Format::B8G8R8A8_UNORM
+            | Format::B8G8R8A8_SRGB
+            | Format::A2B10G10R10_UNORM_PACK32
+            | Format::A2R10G10B10_UNORM_PACK32
+    )
+}
+
+// Converts the texels of a swapchain image to RGBA8. The values are kept encoded the way the
+// swapchain presents them, which for sRGB formats is already what PNG expects. The swapchain
+// alpha isn't meaningful once composited, so the image is made opaque
+pub fn to_rgba8(format: Format, bytes: &[u8]) -> Option<Vec<u8>> {
+    fn unpack_10bit(texel: u32, red_shift: u32, blue_shift: u32) -> [u8; 4] {
+        let channel = |shift: u32| (((texel >> shift) & 0x3FF) * 255 / 0x3FF) as u8;
+
+        [channel(red_shift), channel(10), channel(blue_shift), 255]
+    }
+
+    let texels = bytes.chunks_exact(4);
+
+    let rgba = match format {
+        Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB => {
+            texels.flat_map(|t| [t[0], t[1], t[2], 255]).collect()
+        }
+        Format::B8G8R8A8_UNORM | Format::B8G8R8A8_SRGB => {
+            texels.flat_map(|t| [t[2], t[1], t[0], 255]).collect()
+        }
+        Format::A2B10G10R10_UNORM_PACK32 => texels
+            .flat_map(|t| unpack_10bit(u32::from_le_bytes([t[0], t[1], t[2], t[3]]), 0, 20))
+            .collect(),
+        Format::A2R10G10B10_UNORM_PACK32 => texels
+            .flat_map(|t| unpack_10bit(u32::from_le_bytes([t[0], t[1], t[2], t[3]]), 20, 0))
+            .collect(),
+        _ => return None,
+    };
+
+    Some(rgba)
+}
+
+pub fn write_png(path: &Path, width: u32, height: u32, rgba: &[u8]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use vulkano::format::Format;
+
+    use super::{to_rgba8, write_png};
+
+    #[test]
+    fn screenshot_swizzles_bgra() {
+        let bgra = [10, 20, 30, 0, 40, 50, 60, 128];
+
+        assert!(
+            to_rgba8(Format::B8G8R8A8_SRGB, &bgra).unwrap() == [30, 20, 10, 255, 60, 50, 40, 255]
+        );
+        assert!(
+            to_rgba8(Format::R8G8B8A8_UNORM, &bgra).unwrap() == [10, 20, 30, 255, 40, 50, 60, 255]
+        );
+    }
+
+    #[test]
+    fn screenshot_unpacks_10bit_formats() {
+        // Full red, half green and no blue
+        let texel = 0x3FF | (0x200 << 10);
+
+        assert!(
+            to_rgba8(Format::A2B10G10R10_UNORM_PACK32, &u32::to_le_bytes(texel)).unwrap()
+                == [255, 127, 0, 255]
+        );
+        assert!(
+            to_rgba8(Format::A2R10G10B10_UNORM_PACK32, &u32::to_le_bytes(texel)).unwrap()
+                == [0, 127, 255, 255]
+        );
+        assert!(to_rgba8(Format::R16G16B16A16_SFLOAT, &[0; 8]).is_none());
+    }
+
+    #[test]
+    fn screenshot_write_png() {
+        let path = std::env::temp_dir().join("a-tlas-screenshot-test.png");
+        let rgba = [255, 0, 0, 255, 0, 255, 0, 255];
+
+        write_png(&path, 2, 1, &rgba).unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert!(bytes.starts_with(b"\x89PNG"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
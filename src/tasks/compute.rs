@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use rand::{Rng, SeedableRng, rngs::StdRng};
 use vulkano::{
     DeviceSize,
     buffer::{Buffer, BufferCreateInfo, BufferUsage},
@@ -193,10 +194,11 @@ impl RendererTree64 {
 
                     let write_materials_buffer =
                         tcx.write_buffer::<[tree64::Material]>(self.material_buffer_id, ..)?;
+                    let mut rng = StdRng::seed_from_u64(app.seed);
 
                     for (o, _) in write_materials_buffer.iter_mut().zip(nodes) {
                         *o = tree64::Material {
-                            data: [rand::random::<u32>(), 0],
+                            data: [rng.random::<u32>(), 0],
                         };
                     }
 
@@ -234,7 +236,7 @@ impl Task for RendererTree64 {
                 self.pipeline.layout(),
                 0,
                 &tree64::PushConstants {
-                    image_id: rcx.swapchain_storage_image_ids[image_index as usize],
+                    image_id: rcx.swapchain_storage_image_id(image_index),
                     scene_params_id: self.params_storage_buffer_id,
                     node_buffer_id: self.nodes_storage_buffer_id,
                     material_buffer_id: self.materials_storage_buffer_id,
@@ -246,7 +248,7 @@ impl Task for RendererTree64 {
             cbf.bind_pipeline_compute(&self.pipeline)?;
         }
 
-        unsafe { cbf.dispatch([extent[0] / 8, extent[1] / 8, 1]) }?;
+        unsafe { cbf.dispatch([extent[0].div_ceil(8), extent[1].div_ceil(8), 1]) }?;
 
         Ok(())
     }
@@ -1,14 +1,43 @@
 use core::slice;
 use std::sync::Arc;
+
+use glam::{Mat4, Vec3};
 use vulkano::{
-    buffer::Buffer,
-    pipeline::{GraphicsPipeline, Pipeline},
+    DeviceSize,
+    buffer::{Buffer, BufferCreateInfo, BufferUsage},
+    memory::allocator::{AllocationCreateInfo, DeviceLayout, MemoryTypeFilter},
+    pipeline::{
+        DynamicState, GraphicsPipeline, Pipeline, PipelineShaderStageCreateInfo,
+        graphics::{
+            GraphicsPipelineCreateInfo,
+            color_blend::{ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::{InputAssemblyState, PrimitiveTopology},
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::ViewportState,
+        },
+    },
+    render_pass::Subpass,
 };
 use vulkano_taskgraph::{
     Id, Task, TaskContext, TaskResult, command_buffer::RecordingCommandBuffer,
+    resource::HostAccessType,
+};
+
+#[cfg(debug_assertions)]
+use vulkano::swapchain::Swapchain;
+#[cfg(debug_assertions)]
+use vulkano_taskgraph::ClearValues;
+
+use crate::{
+    app::{App, RenderContext},
+    world::{Vertex3DColor, box_lines},
 };
 
-use crate::app::RenderContext;
+// Drawn slightly larger than a voxel so the outline isn't hidden inside its faces
+const HIGHLIGHT_MARGIN: f32 = 0.02;
+const HIGHLIGHT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
 
 pub mod shader {
     pub(crate) mod vert {
@@ -28,21 +57,174 @@ pub mod shader {
     }
 }
 
+// Draws the chunk wireframes of the world on a cleared swapchain image
+#[cfg(debug_assertions)]
 pub struct DrawDebugTask {
+    pub swapchain_id: Id<Swapchain>,
     pub vertex_count: u32,
     pub vertex_buffer_id: Id<Buffer>,
+    // Needs the subpass of the compiled task graph, set once it is compiled
     pub pipeline: Option<Arc<GraphicsPipeline>>,
 }
 
+#[cfg(debug_assertions)]
+impl DrawDebugTask {
+    pub fn new(app: &App, virtual_swapchain_id: Id<Swapchain>) -> Self {
+        let (vertex_buffer_id, vertex_count) = line_buffer(app, &app.world.debug_lines());
+
+        Self {
+            swapchain_id: virtual_swapchain_id,
+            vertex_count,
+            vertex_buffer_id,
+            pipeline: None,
+        }
+    }
+}
+
+// Outlines the voxel targeted by the crosshair on top of the ray traced image, the outline of
+// a voxel at the origin is moved to the target with the world matrix
+pub struct HighlightTask {
+    vertex_count: u32,
+    vertex_buffer_id: Id<Buffer>,
+    // Needs the subpass of the compiled task graph, set once it is compiled
+    pub pipeline: Option<Arc<GraphicsPipeline>>,
+}
+
+impl HighlightTask {
+    pub fn new(app: &App) -> Self {
+        let (vertex_buffer_id, vertex_count) = line_buffer(
+            app,
+            &box_lines(
+                Vec3::splat(-0.5 - HIGHLIGHT_MARGIN),
+                1.0 + 2.0 * HIGHLIGHT_MARGIN,
+                HIGHLIGHT_COLOR,
+            ),
+        );
+
+        Self {
+            vertex_count,
+            vertex_buffer_id,
+            pipeline: None,
+        }
+    }
+}
+
+// The pipeline drawing line lists of `Vertex3DColor`, for the wireframes and the highlight
+pub fn line_pipeline(app: &App, subpass: &Subpass) -> Arc<GraphicsPipeline> {
+    let bcx = app.resources.bindless_context().unwrap();
+
+    let vs = shader::vert::load(&app.device)
+        .unwrap()
+        .entry_point("main")
+        .unwrap();
+    let fs = shader::frag::load(&app.device)
+        .unwrap()
+        .entry_point("main")
+        .unwrap();
+
+    let vertex_input_state = Vertex3DColor::per_vertex().definition(&vs).unwrap();
+
+    let stages = [
+        PipelineShaderStageCreateInfo::new(&vs),
+        PipelineShaderStageCreateInfo::new(&fs),
+    ];
+
+    let layout = bcx.pipeline_layout_from_stages(&stages).unwrap();
+
+    GraphicsPipeline::new(
+        &app.device,
+        None,
+        &GraphicsPipelineCreateInfo {
+            stages: &stages,
+            vertex_input_state: Some(&vertex_input_state),
+            input_assembly_state: Some(&InputAssemblyState {
+                topology: PrimitiveTopology::LineList,
+                ..Default::default()
+            }),
+            viewport_state: Some(&ViewportState::default()),
+            rasterization_state: Some(&RasterizationState::default()),
+            multisample_state: Some(&MultisampleState::default()),
+            color_blend_state: Some(&ColorBlendState::with_attachment_states(
+                subpass.num_color_attachments(),
+                &ColorBlendAttachmentState::default(),
+            )),
+            dynamic_state: &[DynamicState::Viewport],
+            subpass: Some(subpass.into()),
+            ..GraphicsPipelineCreateInfo::new(&layout)
+        },
+    )
+    .unwrap()
+}
+
+// Uploads the lines, returns the vertex buffer and its vertex count
+pub fn line_buffer(app: &App, lines: &[Vertex3DColor]) -> (Id<Buffer>, u32) {
+    let vertex_count = lines.len() as u32;
+
+    // Buffers can't be empty, no lines allocate a single unused vertex
+    let vertex_buffer_id = app
+        .resources
+        .create_buffer(
+            &BufferCreateInfo {
+                usage: BufferUsage::VERTEX_BUFFER,
+                ..Default::default()
+            },
+            &AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            DeviceLayout::new_unsized::<[Vertex3DColor]>(lines.len().max(1) as DeviceSize).unwrap(),
+        )
+        .unwrap();
+
+    unsafe {
+        vulkano_taskgraph::execute(
+            &app.transfer_queue,
+            &app.resources,
+            app.graphics_flight_id,
+            |_cbf, tcx| {
+                let vertices = tcx.write_buffer::<[Vertex3DColor]>(vertex_buffer_id, ..)?;
+
+                for (vertex, line_vertex) in vertices.iter_mut().zip(lines) {
+                    *vertex = *line_vertex;
+                }
+
+                Ok(())
+            },
+            [(vertex_buffer_id, HostAccessType::Write)],
+            [],
+            [],
+        )
+    }
+    .unwrap();
+
+    app.resources
+        .flight(app.graphics_flight_id)
+        .unwrap()
+        .wait_idle()
+        .unwrap();
+
+    (vertex_buffer_id, vertex_count)
+}
+
+#[cfg(debug_assertions)]
 impl Task for DrawDebugTask {
     type World = RenderContext;
 
+    fn clear_values(&self, clear_values: &mut ClearValues<'_>) {
+        clear_values.set(self.swapchain_id.current_image_id(), [0.0, 0.0, 0.0, 1.0]);
+    }
+
     unsafe fn execute(
         &self,
         cbf: &mut RecordingCommandBuffer<'_>,
         _tcx: &mut TaskContext<'_>,
         rcx: &Self::World,
     ) -> TaskResult {
+        if self.vertex_count == 0 {
+            return Ok(());
+        }
+
         let push_constants = rcx.debug_constant_data;
 
         let pipeline = self.pipeline.as_ref().unwrap();
@@ -56,3 +238,33 @@ impl Task for DrawDebugTask {
         Ok(())
     }
 }
+
+impl Task for HighlightTask {
+    type World = RenderContext;
+
+    unsafe fn execute(
+        &self,
+        cbf: &mut RecordingCommandBuffer<'_>,
+        _tcx: &mut TaskContext<'_>,
+        rcx: &Self::World,
+    ) -> TaskResult {
+        let Some(position) = rcx.highlighted_voxel else {
+            return Ok(());
+        };
+
+        let push_constants = shader::vert::PushConstants {
+            world: Mat4::from_translation(position.as_vec3()).to_cols_array_2d(),
+            ..rcx.debug_constant_data
+        };
+
+        let pipeline = self.pipeline.as_ref().unwrap();
+
+        unsafe { cbf.set_viewport(0, slice::from_ref(&rcx.viewport)) }?;
+        unsafe { cbf.bind_pipeline_graphics(pipeline) }?;
+        unsafe { cbf.bind_vertex_buffers(0, &[self.vertex_buffer_id], &[0], &[], &[]) }?;
+        unsafe { cbf.push_constants(pipeline.layout(), 0, &push_constants) }?;
+        unsafe { cbf.draw(self.vertex_count, 1, 0, 0) }?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use vulkano::{
+    pipeline::{
+        ComputePipeline, Pipeline, PipelineShaderStageCreateInfo,
+        compute::ComputePipelineCreateInfo,
+    },
+    swapchain::Swapchain,
+    sync::{AccessFlags, PipelineStages},
+};
+use vulkano_taskgraph::{
+    Id, Task, TaskContext, TaskResult,
+    command_buffer::{DependencyInfo, MemoryBarrier, RecordingCommandBuffer},
+    descriptor_set::StorageBufferId,
+};
+
+use crate::app::{App, RenderContext};
+
+pub mod shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "shaders/rt/denoise.comp",
+        vulkan_version: "1.3"
+    }
+}
+
+// The taps of the last iteration are `2^(DENOISE_ITERATIONS - 1)` pixels apart
+pub const DENOISE_ITERATIONS: u32 = 4;
+// The luminance sigma of a single frame, it shrinks as the noise averages out over the
+// accumulated frames
+const COLOR_SIGMA: f32 = 1.0;
+
+// Filters the accumulated radiance with the edge-aware à-trous filter and writes the tonemapped
+// result over the ray traced image. The iterations ping-pong between two images, only the last
+// one writes the output. Nothing is dispatched while the denoiser is off
+pub struct DenoiseTask {
+    swapchain_id: Id<Swapchain>,
+    sunlight_storage_buffer_id: StorageBufferId,
+    pipeline: Arc<ComputePipeline>,
+}
+
+impl DenoiseTask {
+    pub fn new(
+        app: &App,
+        virtual_swapchain_id: Id<Swapchain>,
+        sunlight_storage_buffer_id: StorageBufferId,
+    ) -> Self {
+        let bcx = app.resources.bindless_context().unwrap();
+
+        let pipeline = {
+            let shader = shader::load(&app.device)
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+
+            let stage = PipelineShaderStageCreateInfo::new(&shader);
+
+            let layout = bcx
+                .pipeline_layout_from_stages(std::slice::from_ref(&stage))
+                .unwrap();
+
+            ComputePipeline::new(
+                &app.device,
+                None,
+                &ComputePipelineCreateInfo::new(stage, &layout),
+            )
+            .unwrap()
+        };
+
+        Self {
+            swapchain_id: virtual_swapchain_id,
+            sunlight_storage_buffer_id,
+            pipeline,
+        }
+    }
+}
+
+impl Task for DenoiseTask {
+    type World = RenderContext;
+
+    unsafe fn execute(
+        &self,
+        cbf: &mut RecordingCommandBuffer<'_>,
+        tcx: &mut TaskContext<'_>,
+        rcx: &Self::World,
+    ) -> TaskResult {
+        if !rcx.denoising() {
+            return Ok(());
+        }
+
+        let swapchain_state = tcx.swapchain(self.swapchain_id)?;
+        let image_index = swapchain_state.current_image_index().unwrap();
+        let extent = rcx.render_extent;
+
+        // The task is only part of the task graph along with its images
+        let [guide_image_id, _] = rcx.g_buffer_storage_image_ids().unwrap();
+        let [ping_image_id, pong_image_id] = rcx.denoise_storage_image_ids().unwrap();
+        let color_sigma = COLOR_SIGMA / ((rcx.accumulated_frames + 1) as f32).sqrt();
+
+        // Each iteration reads what the previous one wrote
+        let dependency_info = DependencyInfo {
+            memory_barriers: &[MemoryBarrier {
+                src_stages: PipelineStages::COMPUTE_SHADER,
+                dst_stages: PipelineStages::COMPUTE_SHADER,
+                src_access: AccessFlags::SHADER_STORAGE_WRITE,
+                dst_access: AccessFlags::SHADER_STORAGE_READ,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        unsafe { cbf.bind_pipeline_compute(&self.pipeline) }?;
+
+        let mut source_image_id = rcx.accumulation_storage_image_id;
+
+        for iteration in 0..DENOISE_ITERATIONS {
+            let target_image_id = if iteration % 2 == 0 {
+                ping_image_id
+            } else {
+                pong_image_id
+            };
+
+            if iteration > 0 {
+                unsafe { cbf.pipeline_barrier(&dependency_info) }?;
+            }
+
+            unsafe {
+                cbf.push_constants(
+                    self.pipeline.layout(),
+                    0,
+                    &shader::PushConstants {
+                        source_image_id,
+                        target_image_id,
+                        guide_image_id,
+                        image_id: rcx.render_target(image_index),
+                        step_width: 1 << iteration,
+                        last_iteration: (iteration == DENOISE_ITERATIONS - 1) as u32,
+                        color_sigma,
+                        sunlight_buffer_id: self.sunlight_storage_buffer_id,
+                    },
+                )
+            }?;
+
+            unsafe { cbf.dispatch([extent[0].div_ceil(8), extent[1].div_ceil(8), 1]) }?;
+
+            source_image_id = target_image_id;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,541 @@
+use std::{collections::HashMap, slice, sync::Arc};
+
+use egui::{
+    ClippedPrimitive, ImageData, TextureId, TexturesDelta,
+    epaint::{ImageDelta, Primitive},
+};
+use vulkano::{
+    DeviceSize,
+    buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, IndexType},
+    device::Queue,
+    format::{Format, NumericFormat},
+    image::{
+        Image, ImageCreateInfo, ImageLayout, ImageSubresourceLayers, ImageType, ImageUsage,
+        sampler::{Filter, SamplerAddressMode, SamplerCreateInfo},
+        view::ImageView,
+    },
+    memory::allocator::{AllocationCreateInfo, DeviceLayout, MemoryTypeFilter},
+    pipeline::{
+        DynamicState, GraphicsPipeline, Pipeline, PipelineShaderStageCreateInfo,
+        graphics::{
+            GraphicsPipelineCreateInfo,
+            color_blend::{
+                AttachmentBlend, BlendFactor, BlendOp, ColorBlendAttachmentState, ColorBlendState,
+            },
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            vertex_input::{Vertex, VertexDefinition},
+            viewport::{Scissor, ViewportState},
+        },
+    },
+    render_pass::Subpass,
+    swapchain::Swapchain,
+};
+use vulkano_taskgraph::{
+    Id, Task, TaskContext, TaskResult,
+    command_buffer::{BufferImageCopy, CopyBufferToImageInfo, RecordingCommandBuffer},
+    descriptor_set::{SampledImageId, SamplerId},
+    resource::{AccessTypes, Flight, HostAccessType, ImageLayoutType, Resources},
+};
+
+use crate::app::{App, RenderContext};
+
+pub mod shader {
+    pub(crate) mod vert {
+        vulkano_shaders::shader! {
+            ty: "vertex",
+            path: "shaders/egui/vert.glsl",
+            vulkan_version: "1.3"
+        }
+    }
+
+    pub(crate) mod frag {
+        vulkano_shaders::shader! {
+            ty: "fragment",
+            path: "shaders/egui/frag.glsl",
+            vulkan_version: "1.3"
+        }
+    }
+}
+
+#[derive(BufferContents, Vertex)]
+#[repr(C)]
+struct EguiVertex {
+    #[format(R32G32_SFLOAT)]
+    position: [f32; 2],
+    #[format(R32G32_SFLOAT)]
+    uv: [f32; 2],
+    #[format(R8G8B8A8_UNORM)]
+    color: [u8; 4],
+}
+
+struct EguiTexture {
+    image_id: Id<Image>,
+    sampled_image_id: SampledImageId,
+    extent: [u32; 2],
+}
+
+// A mesh of the uploaded buffers drawn with a single texture and clip rectangle
+struct EguiDraw {
+    scissor: Scissor,
+    texture: SampledImageId,
+    first_index: u32,
+    index_count: u32,
+    vertex_offset: i32,
+}
+
+// A host buffer reused across frames until the meshes outgrow it
+struct GrowableBuffer {
+    id: Id<Buffer>,
+    // In elements
+    capacity: DeviceSize,
+}
+
+// Draws the egui meshes of the frame over the swapchain image, after the overlay
+pub struct EguiTask {
+    swapchain_id: Id<Swapchain>,
+    // Needs the subpass of the compiled task graph, set once it is compiled
+    pub pipeline: Option<Arc<GraphicsPipeline>>,
+    sampler_id: SamplerId,
+    textures: HashMap<TextureId, EguiTexture>,
+    vertex_buffer: Option<GrowableBuffer>,
+    index_buffer: Option<GrowableBuffer>,
+    draws: Vec<EguiDraw>,
+    // In points
+    screen_size: [f32; 2],
+    srgb_target: bool,
+}
+
+impl EguiTask {
+    pub fn new(app: &App, virtual_swapchain_id: Id<Swapchain>, swapchain_format: Format) -> Self {
+        let bcx = app.resources.bindless_context().unwrap();
+
+        let sampler_id = bcx
+            .global_set()
+            .create_sampler(&SamplerCreateInfo {
+                mag_filter: Filter::Linear,
+                min_filter: Filter::Linear,
+                address_mode: [SamplerAddressMode::ClampToEdge; 3],
+                ..Default::default()
+            })
+            .unwrap();
+
+        Self {
+            swapchain_id: virtual_swapchain_id,
+            pipeline: None,
+            sampler_id,
+            textures: HashMap::new(),
+            vertex_buffer: None,
+            index_buffer: None,
+            draws: Vec::new(),
+            screen_size: [0.0; 2],
+            srgb_target: swapchain_format.numeric_format_color() == Some(NumericFormat::SRGB),
+        }
+    }
+
+    pub fn create_pipeline(app: &App, subpass: &Subpass) -> Arc<GraphicsPipeline> {
+        let bcx = app.resources.bindless_context().unwrap();
+
+        let vs = shader::vert::load(&app.device)
+            .unwrap()
+            .entry_point("main")
+            .unwrap();
+        let fs = shader::frag::load(&app.device)
+            .unwrap()
+            .entry_point("main")
+            .unwrap();
+
+        let vertex_input_state = EguiVertex::per_vertex().definition(&vs).unwrap();
+
+        let stages = [
+            PipelineShaderStageCreateInfo::new(&vs),
+            PipelineShaderStageCreateInfo::new(&fs),
+        ];
+
+        let layout = bcx.pipeline_layout_from_stages(&stages).unwrap();
+
+        // egui outputs premultiplied alpha
+        let blend = AttachmentBlend {
+            src_color_blend_factor: BlendFactor::One,
+            dst_color_blend_factor: BlendFactor::OneMinusSrcAlpha,
+            color_blend_op: BlendOp::Add,
+            src_alpha_blend_factor: BlendFactor::OneMinusDstAlpha,
+            dst_alpha_blend_factor: BlendFactor::One,
+            alpha_blend_op: BlendOp::Add,
+        };
+
+        GraphicsPipeline::new(
+            &app.device,
+            None,
+            &GraphicsPipelineCreateInfo {
+                stages: &stages,
+                vertex_input_state: Some(&vertex_input_state),
+                input_assembly_state: Some(&InputAssemblyState::default()),
+                viewport_state: Some(&ViewportState::default()),
+                rasterization_state: Some(&RasterizationState::default()),
+                multisample_state: Some(&MultisampleState::default()),
+                color_blend_state: Some(&ColorBlendState::with_attachment_states(
+                    subpass.num_color_attachments(),
+                    &ColorBlendAttachmentState {
+                        blend: Some(blend),
+                        ..Default::default()
+                    },
+                )),
+                dynamic_state: &[DynamicState::Viewport, DynamicState::Scissor],
+                subpass: Some(subpass.into()),
+                ..GraphicsPipelineCreateInfo::new(&layout)
+            },
+        )
+        .unwrap()
+    }
+
+    // Uploads the textures and meshes of an egui frame, the device must not be using the
+    // previous ones anymore. `extent` is the size of the swapchain in pixels
+    pub fn upload(
+        &mut self,
+        resources: &Arc<Resources>,
+        queue: &Arc<Queue>,
+        flight_id: Id<Flight>,
+        textures_delta: &TexturesDelta,
+        primitives: &[ClippedPrimitive],
+        pixels_per_point: f32,
+        extent: [u32; 2],
+    ) {
+        for (texture_id, delta) in &textures_delta.set {
+            self.set_texture(resources, queue, flight_id, *texture_id, delta);
+        }
+
+        self.screen_size = extent.map(|length| length as f32 / pixels_per_point);
+        self.draws.clear();
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        for ClippedPrimitive {
+            clip_rect,
+            primitive,
+        } in primitives
+        {
+            // Callbacks paint with another renderer, the windows here only use meshes
+            let Primitive::Mesh(mesh) = primitive else {
+                continue;
+            };
+
+            let Some(texture) = self.textures.get(&mesh.texture_id) else {
+                continue;
+            };
+
+            let min = (clip_rect.min.to_vec2() * pixels_per_point)
+                .round()
+                .max(egui::Vec2::ZERO);
+            let max = (clip_rect.max.to_vec2() * pixels_per_point)
+                .round()
+                .min(egui::vec2(extent[0] as f32, extent[1] as f32));
+
+            if mesh.indices.is_empty() || min.x >= max.x || min.y >= max.y {
+                continue;
+            }
+
+            self.draws.push(EguiDraw {
+                scissor: Scissor {
+                    offset: [min.x as u32, min.y as u32],
+                    extent: [(max.x - min.x) as u32, (max.y - min.y) as u32],
+                },
+                texture: texture.sampled_image_id,
+                first_index: indices.len() as u32,
+                index_count: mesh.indices.len() as u32,
+                vertex_offset: vertices.len() as i32,
+            });
+
+            vertices.extend(mesh.vertices.iter().map(|vertex| EguiVertex {
+                position: [vertex.pos.x, vertex.pos.y],
+                uv: [vertex.uv.x, vertex.uv.y],
+                color: vertex.color.to_array(),
+            }));
+            indices.extend_from_slice(&mesh.indices);
+        }
+
+        if !self.draws.is_empty() {
+            let vertex_buffer_id = grow_buffer::<EguiVertex>(
+                resources,
+                &mut self.vertex_buffer,
+                vertices.len() as DeviceSize,
+                BufferUsage::VERTEX_BUFFER,
+            );
+            let index_buffer_id = grow_buffer::<u32>(
+                resources,
+                &mut self.index_buffer,
+                indices.len() as DeviceSize,
+                BufferUsage::INDEX_BUFFER,
+            );
+
+            unsafe {
+                vulkano_taskgraph::execute(
+                    queue,
+                    resources,
+                    flight_id,
+                    |_cbf, tcx| {
+                        tcx.write_buffer::<[EguiVertex]>(vertex_buffer_id, ..)?
+                            .iter_mut()
+                            .zip(vertices)
+                            .for_each(|(dst, src)| *dst = src);
+                        tcx.write_buffer::<[u32]>(index_buffer_id, ..)?[..indices.len()]
+                            .copy_from_slice(&indices);
+
+                        Ok(())
+                    },
+                    [
+                        (vertex_buffer_id, HostAccessType::Write),
+                        (index_buffer_id, HostAccessType::Write),
+                    ],
+                    [],
+                    [],
+                )
+            }
+            .unwrap();
+        }
+
+        // Freed after the meshes using them were replaced
+        if !textures_delta.free.is_empty() {
+            let mut batch = resources.create_deferred_batch();
+
+            for texture_id in &textures_delta.free {
+                if let Some(texture) = self.textures.remove(texture_id) {
+                    batch.destroy_sampled_image(texture.sampled_image_id);
+                    batch.destroy_image(texture.image_id);
+                }
+            }
+
+            batch.enqueue();
+        }
+    }
+
+    // Creates the texture or replaces its content, a delta with a position only updates a
+    // region of an existing texture
+    fn set_texture(
+        &mut self,
+        resources: &Arc<Resources>,
+        queue: &Arc<Queue>,
+        flight_id: Id<Flight>,
+        texture_id: TextureId,
+        delta: &ImageDelta,
+    ) {
+        let pixels: Vec<[u8; 4]> = match &delta.image {
+            ImageData::Color(image) => image.pixels.iter().map(|color| color.to_array()).collect(),
+            ImageData::Font(image) => image.srgba_pixels(None).map(|c| c.to_array()).collect(),
+        };
+        let [width, height] = delta.image.size().map(|length| length as u32);
+
+        let offset = match delta.pos {
+            Some([x, y]) => [x as u32, y as u32],
+            None => {
+                let replaced = self
+                    .textures
+                    .insert(texture_id, create_texture(resources, [width, height]));
+
+                if let Some(texture) = replaced {
+                    let mut batch = resources.create_deferred_batch();
+                    batch.destroy_sampled_image(texture.sampled_image_id);
+                    batch.destroy_image(texture.image_id);
+                    batch.enqueue();
+                }
+
+                [0, 0]
+            }
+        };
+
+        let Some(texture) = self.textures.get(&texture_id) else {
+            return;
+        };
+
+        debug_assert!(
+            offset[0] + width <= texture.extent[0] && offset[1] + height <= texture.extent[1]
+        );
+
+        let staging_buffer_id = resources
+            .create_buffer(
+                &BufferCreateInfo {
+                    usage: BufferUsage::TRANSFER_SRC,
+                    ..Default::default()
+                },
+                &AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_HOST
+                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                    ..Default::default()
+                },
+                DeviceLayout::new_unsized::<[[u8; 4]]>(pixels.len() as DeviceSize).unwrap(),
+            )
+            .unwrap();
+
+        let image_id = texture.image_id;
+
+        unsafe {
+            vulkano_taskgraph::execute(
+                queue,
+                resources,
+                flight_id,
+                |cbf, tcx| {
+                    tcx.write_buffer::<[[u8; 4]]>(staging_buffer_id, ..)?
+                        .copy_from_slice(&pixels);
+
+                    cbf.copy_buffer_to_image(&CopyBufferToImageInfo {
+                        src_buffer: staging_buffer_id,
+                        dst_image: image_id,
+                        regions: &[BufferImageCopy {
+                            image_subresource: ImageSubresourceLayers::from_parameters(
+                                Format::R8G8B8A8_UNORM,
+                                1,
+                            ),
+                            image_offset: [offset[0], offset[1], 0],
+                            image_extent: [width, height, 1],
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    })?;
+
+                    Ok(())
+                },
+                [(staging_buffer_id, HostAccessType::Write)],
+                [(staging_buffer_id, AccessTypes::COPY_TRANSFER_READ)],
+                [(
+                    image_id,
+                    AccessTypes::COPY_TRANSFER_WRITE,
+                    ImageLayoutType::General,
+                )],
+            )
+        }
+        .unwrap();
+
+        resources.flight(flight_id).unwrap().wait_idle().unwrap();
+
+        let mut batch = resources.create_deferred_batch();
+        batch.destroy_buffer(staging_buffer_id);
+        batch.enqueue();
+    }
+}
+
+// Sampled in the general layout, so the uploads don't need any transition afterwards
+fn create_texture(resources: &Resources, extent: [u32; 2]) -> EguiTexture {
+    let bcx = resources.bindless_context().unwrap();
+
+    let image_id = resources
+        .create_image(
+            &ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: Format::R8G8B8A8_UNORM,
+                extent: [extent[0], extent[1], 1],
+                usage: ImageUsage::SAMPLED | ImageUsage::TRANSFER_DST,
+                ..Default::default()
+            },
+            &AllocationCreateInfo::default(),
+        )
+        .unwrap();
+
+    let image_view = ImageView::new_default(resources.image(image_id).unwrap().image()).unwrap();
+
+    let sampled_image_id = bcx
+        .global_set()
+        .add_sampled_image(image_view, ImageLayout::General);
+
+    EguiTexture {
+        image_id,
+        sampled_image_id,
+        extent,
+    }
+}
+
+// Returns the buffer, recreated larger when it can't hold `len` elements
+fn grow_buffer<T: BufferContents>(
+    resources: &Resources,
+    buffer: &mut Option<GrowableBuffer>,
+    len: DeviceSize,
+    usage: BufferUsage,
+) -> Id<Buffer> {
+    match buffer {
+        Some(buffer) if buffer.capacity >= len => buffer.id,
+        _ => {
+            if let Some(old) = buffer.take() {
+                let mut batch = resources.create_deferred_batch();
+                batch.destroy_buffer(old.id);
+                batch.enqueue();
+            }
+
+            let capacity = len.next_power_of_two();
+
+            let id = resources
+                .create_buffer(
+                    &BufferCreateInfo {
+                        usage,
+                        ..Default::default()
+                    },
+                    &AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                            | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                        ..Default::default()
+                    },
+                    DeviceLayout::new_unsized::<[T]>(capacity).unwrap(),
+                )
+                .unwrap();
+
+            *buffer = Some(GrowableBuffer { id, capacity });
+
+            id
+        }
+    }
+}
+
+impl Task for EguiTask {
+    type World = RenderContext;
+
+    unsafe fn execute(
+        &self,
+        cbf: &mut RecordingCommandBuffer<'_>,
+        _tcx: &mut TaskContext<'_>,
+        rcx: &Self::World,
+    ) -> TaskResult {
+        let (Some(vertex_buffer), Some(index_buffer)) = (&self.vertex_buffer, &self.index_buffer)
+        else {
+            return Ok(());
+        };
+
+        if !rcx.tuning_ui.visible || self.draws.is_empty() {
+            return Ok(());
+        }
+
+        let pipeline = self.pipeline.as_ref().unwrap();
+
+        unsafe { cbf.set_viewport(0, slice::from_ref(&rcx.viewport)) }?;
+        unsafe { cbf.bind_pipeline_graphics(pipeline) }?;
+        unsafe { cbf.bind_vertex_buffers(0, &[vertex_buffer.id], &[0], &[], &[]) }?;
+        unsafe {
+            cbf.bind_index_buffer(
+                index_buffer.id,
+                0,
+                index_buffer.capacity * size_of::<u32>() as DeviceSize,
+                IndexType::U32,
+            )
+        }?;
+
+        for draw in &self.draws {
+            unsafe { cbf.set_scissor(0, slice::from_ref(&draw.scissor)) }?;
+            unsafe {
+                cbf.push_constants(
+                    pipeline.layout(),
+                    0,
+                    &shader::vert::PushConstants {
+                        screen_size: self.screen_size,
+                        texture_id: draw.texture,
+                        sampler_id: self.sampler_id,
+                        srgb_target: self.srgb_target as u32,
+                    },
+                )
+            }?;
+            unsafe {
+                cbf.draw_indexed(draw.index_count, 1, draw.first_index, draw.vertex_offset, 0)
+            }?;
+        }
+
+        Ok(())
+    }
+}
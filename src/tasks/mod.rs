@@ -1,4 +1,9 @@
-#[cfg(debug_assertions)]
 pub mod debug;
+pub mod denoise;
+#[cfg(feature = "egui")]
+pub mod egui;
+pub mod overlay;
 pub mod render;
+pub mod screenshot;
 pub mod update_as;
+pub mod upscale;
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use vulkano::{
+    pipeline::{
+        ComputePipeline, Pipeline, PipelineShaderStageCreateInfo,
+        compute::ComputePipelineCreateInfo,
+    },
+    swapchain::Swapchain,
+};
+use vulkano_taskgraph::{
+    Id, Task, TaskContext, TaskResult, command_buffer::RecordingCommandBuffer,
+};
+
+use crate::app::{App, RenderContext};
+
+pub mod shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "shaders/overlay/overlay.glsl",
+        vulkan_version: "1.3"
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OverlayStats {
+    pub visible: bool,
+    pub fps: u32,
+    // In centimeters per second, the overlay only draws integers
+    pub speed: u32,
+}
+
+// Draws the crosshair and the optional counters on top of the ray traced image
+pub struct OverlayTask {
+    swapchain_id: Id<Swapchain>,
+    pipeline: Arc<ComputePipeline>,
+}
+
+impl OverlayTask {
+    pub fn new(app: &App, virtual_swapchain_id: Id<Swapchain>) -> Self {
+        let bcx = app.resources.bindless_context().unwrap();
+
+        let pipeline = {
+            let shader = shader::load(&app.device)
+                .unwrap()
+                .entry_point("main")
+                .unwrap();
+
+            let stage = PipelineShaderStageCreateInfo::new(&shader);
+
+            let layout = bcx
+                .pipeline_layout_from_stages(std::slice::from_ref(&stage))
+                .unwrap();
+
+            ComputePipeline::new(
+                &app.device,
+                None,
+                &ComputePipelineCreateInfo::new(stage, &layout),
+            )
+            .unwrap()
+        };
+
+        Self {
+            swapchain_id: virtual_swapchain_id,
+            pipeline,
+        }
+    }
+}
+
+impl Task for OverlayTask {
+    type World = RenderContext;
+
+    unsafe fn execute(
+        &self,
+        cbf: &mut RecordingCommandBuffer<'_>,
+        tcx: &mut TaskContext<'_>,
+        rcx: &Self::World,
+    ) -> TaskResult {
+        let swapchain_state = tcx.swapchain(self.swapchain_id)?;
+        let image_index = swapchain_state.current_image_index().unwrap();
+
+        let extent = rcx.viewport.extent.map(|x| x as u32);
+
+        unsafe {
+            cbf.push_constants(
+                self.pipeline.layout(),
+                0,
+                &shader::PushConstants {
+                    image_id: rcx.swapchain_storage_image_id(image_index),
+                    extent,
+                    fps: rcx.overlay_stats.fps,
+                    speed: rcx.overlay_stats.speed,
+                    show_stats: rcx.overlay_stats.visible as u32,
+                    output_encoding: rcx.rt_sunlight_data.output_encoding,
+                },
+            )
+        }?;
+
+        unsafe {
+            cbf.bind_pipeline_compute(&self.pipeline)?;
+        }
+
+        unsafe { cbf.dispatch([extent[0].div_ceil(8), extent[1].div_ceil(8), 1]) }?;
+
+        Ok(())
+    }
+}
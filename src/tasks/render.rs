@@ -1,25 +1,35 @@
 use crate::{
     app::{App, RenderContext},
-    rt::{acceleration_structure, closest_hit, intersection, miss, raygen},
-    world::voxel::{get_palette, triangles_from_box},
+    gpu_timer::GpuTimer,
+    rt::{acceleration_structure, any_hit, closest_hit, intersection, miss, query, raygen},
+    world::{
+        chunk::{
+            LOD_COUNT, dirty_instance_ranges, invalid_instance_transform, sort_instances_spatially,
+        },
+        loader::VoxelSource,
+        voxel::triangles_from_box,
+    },
 };
-use glam::Vec3;
-use std::sync::{
-    Arc,
-    atomic::{AtomicBool, Ordering},
+use glam::{IVec3, Vec3, Vec4};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::{
+    ops::Range,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
 };
 use vulkano::{
     DeviceSize, Packed24_8,
     acceleration_structure::{
-        AccelerationStructure, AccelerationStructureBuildGeometryInfo,
-        AccelerationStructureBuildType, AccelerationStructureGeometries,
-        AccelerationStructureGeometryInstancesData, AccelerationStructureGeometryInstancesDataType,
-        AccelerationStructureInstance,
+        AabbPositions, AccelerationStructure, AccelerationStructureInstance, GeometryFlags,
     },
     buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer},
+    device::{DeviceExtensions, DeviceFeatures},
     memory::allocator::{AllocationCreateInfo, DeviceLayout, MemoryTypeFilter},
     pipeline::{
-        Pipeline, PipelineShaderStageCreateInfo,
+        ComputePipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+        compute::ComputePipelineCreateInfo,
         ray_tracing::{
             RayTracingPipeline, RayTracingPipelineCreateInfo, RayTracingShaderGroupCreateInfo,
             ShaderBindingTable,
@@ -32,129 +42,420 @@ use vulkano_taskgraph::{
     Id, Task, TaskContext, TaskResult,
     command_buffer::{DependencyInfo, MemoryBarrier, RecordingCommandBuffer},
     descriptor_set::{AccelerationStructureId, StorageBufferId},
-    resource::HostAccessType,
+    resource::{AccessTypes, HostAccessType},
 };
 
-pub struct RayTracingRenderTask {
-    swapchain_id: Id<Swapchain>,
-    pub acceleration_structure_ids: [AccelerationStructureId; 2],
-    pub camera_buffer_id: Id<Buffer>,
-    pub sunlight_buffer_id: Id<Buffer>,
-    pub instance_buffer_id: Id<Buffer>,
-    camera_storage_buffer_id: StorageBufferId,
-    palette_storage_buffer_id: StorageBufferId,
-    sunlight_storage_buffer_id: StorageBufferId,
-    shader_binding_table: ShaderBindingTable,
-    pub blas: Arc<AccelerationStructure>,
-    pub acceleration_structures: [Arc<AccelerationStructure>; 2],
-    pub current_as_index: Arc<AtomicBool>,
-    pub show_current_index: Arc<AtomicBool>,
-    pipeline: Arc<RayTracingPipeline>,
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DebugScene {
+    // The instances of the loaded voxel world
+    #[default]
+    World,
+    // Randomly placed voxels inside of a sphere, used for stress testing
+    RandomSphere,
 }
 
-impl RayTracingRenderTask {
-    pub fn new(app: &App, virtual_swapchain_id: Id<Swapchain>, max_instance_count: u64) -> Self {
-        let vertices = triangles_from_box(Vec3::ZERO);
-        let vertex_buffer = Buffer::from_iter(
-            &app.memory_allocator,
-            &BufferCreateInfo {
-                usage: BufferUsage::VERTEX_BUFFER
-                    | BufferUsage::SHADER_DEVICE_ADDRESS
-                    | BufferUsage::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY,
+fn random_sphere_instances(
+    acceleration_structure_reference: u64,
+    max_instance_count: u64,
+    seed: u64,
+) -> Vec<AccelerationStructureInstance> {
+    fn sample_uniform_sphere(rng: &mut StdRng, radius: f32) -> (f32, f32, f32) {
+        let sample = Vec3::new(
+            rng.random_range(-1.0..=1.0),
+            rng.random_range(-1.0..=1.0),
+            rng.random_range(-1.0..=1.0),
+        )
+        .normalize()
+            * rng.random_range(0.0..=1.0)
+            * radius;
+
+        (sample.x.floor(), sample.y.floor(), sample.z.floor())
+    }
+
+    let radius: f32 = max_instance_count.ilog2().pow(3) as f32;
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    (0..max_instance_count)
+        .map(|_| {
+            let (x, y, z) = sample_uniform_sphere(&mut rng, radius);
+
+            AccelerationStructureInstance {
+                acceleration_structure_reference,
+                instance_custom_index_and_mask: Packed24_8::new(rng.random::<u8>() as u32, 0xFF),
+                transform: [[1.0, 0.0, 0.0, x], [0.0, 1.0, 0.0, y], [0.0, 0.0, 1.0, z]],
                 ..Default::default()
+            }
+        })
+        .collect()
+}
+
+// Only checked in debug builds, a bad transform from the world or the random scenes would
+// otherwise corrupt the TLAS without any error. The instances start at `first_instance` in the
+// instance buffer, the reported index is the one in the buffer
+pub fn validate_instance_transforms(
+    instances: &[AccelerationStructureInstance],
+    first_instance: u64,
+) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+
+    if let Some(index) = invalid_instance_transform(instances) {
+        panic!(
+            "Instance {} has an invalid transform: {:?}",
+            first_instance + index as u64,
+            instances[index].transform
+        );
+    }
+}
+
+// The geometry used to represent a single voxel in the BLAS
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GeometryKind {
+    // A 36 vertices box
+    #[default]
+    Triangles,
+    // A unit AABB intersected by `simple.rint`
+    Aabb,
+}
+
+// One BLAS per LOD, the box of LOD `n` is `2^n` voxels wide. The opaque boxes come first and
+// skip the any-hit shader, followed by the same boxes for translucent voxels
+fn build_lod_blas(app: &App, geometry_kind: GeometryKind) -> Vec<Arc<AccelerationStructure>> {
+    let (blas, timings): (Vec<_>, Vec<_>) = [GeometryFlags::OPAQUE, GeometryFlags::empty()]
+        .into_iter()
+        .flat_map(|geometry_flags| (0..LOD_COUNT).map(move |lod| (geometry_flags, lod)))
+        .map(|(geometry_flags, lod)| {
+            let size = 2u32.pow(lod) as f32;
+
+            match geometry_kind {
+                GeometryKind::Triangles => {
+                    let vertices = triangles_from_box(Vec3::ZERO, size);
+                    let vertex_buffer = Buffer::from_iter(
+                        &app.memory_allocator,
+                        &BufferCreateInfo {
+                            usage: BufferUsage::VERTEX_BUFFER
+                                | BufferUsage::SHADER_DEVICE_ADDRESS
+                                | BufferUsage::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY,
+                            ..Default::default()
+                        },
+                        &AllocationCreateInfo {
+                            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                            ..Default::default()
+                        },
+                        vertices,
+                    )
+                    .expect("Vertex buffer creation failed");
+
+                    acceleration_structure::build_blas(
+                        vertex_buffer,
+                        geometry_flags,
+                        app.memory_allocator.clone(),
+                        &mut app.scratch_buffers.lock().unwrap(),
+                        app.device.clone(),
+                        app.compute_queue.clone(),
+                        &app.resources,
+                        app.compute_flight_id,
+                    )
+                }
+                GeometryKind::Aabb => {
+                    let aabb_buffer = Buffer::from_iter(
+                        &app.memory_allocator,
+                        &BufferCreateInfo {
+                            usage: BufferUsage::SHADER_DEVICE_ADDRESS
+                                | BufferUsage::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY,
+                            ..Default::default()
+                        },
+                        &AllocationCreateInfo {
+                            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                                | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                            ..Default::default()
+                        },
+                        [AabbPositions {
+                            min: [-size / 2.0; 3],
+                            max: [size / 2.0; 3],
+                        }],
+                    )
+                    .expect("AABB buffer creation failed");
+
+                    acceleration_structure::build_blas_aabb(
+                        aabb_buffer,
+                        geometry_flags,
+                        app.memory_allocator.clone(),
+                        &mut app.scratch_buffers.lock().unwrap(),
+                        app.device.clone(),
+                        app.compute_queue.clone(),
+                        &app.resources,
+                        app.compute_flight_id,
+                    )
+                }
+            }
+        })
+        .unzip();
+
+    if let Some(timings) = timings
+        .into_iter()
+        .reduce(acceleration_structure::BuildTimings::sum)
+    {
+        println!("{} LOD {timings}", blas.len());
+    }
+
+    blas
+}
+
+// The hit group of the pipeline has to match the geometry of the BLAS it traces
+fn ray_tracing_pipeline(
+    app: &App,
+    geometry_kind: GeometryKind,
+    heatmap: bool,
+) -> Arc<RayTracingPipeline> {
+    let bcx = app.resources.bindless_context().unwrap();
+
+    let raygen = if heatmap {
+        raygen::load_heatmap(&app.device)
+    } else {
+        raygen::load_default(&app.device)
+    }
+    .unwrap()
+    .entry_point("main")
+    .unwrap();
+    let miss = miss::load(&app.device)
+        .unwrap()
+        .entry_point("main")
+        .unwrap();
+    let intersection = intersection::load(&app.device)
+        .unwrap()
+        .entry_point("main")
+        .unwrap();
+    let closest_hit = closest_hit::load(&app.device)
+        .unwrap()
+        .entry_point("main")
+        .unwrap();
+    let any_hit = any_hit::load(&app.device)
+        .unwrap()
+        .entry_point("main")
+        .unwrap();
+
+    let mut stages = vec![
+        PipelineShaderStageCreateInfo::new(&raygen),
+        PipelineShaderStageCreateInfo::new(&miss),
+        PipelineShaderStageCreateInfo::new(&closest_hit),
+        PipelineShaderStageCreateInfo::new(&any_hit),
+    ];
+
+    // The hit group type has to match the geometry type of the BLAS. The any-hit shader only
+    // runs for the translucent BLAS, the opaque ones are built with `GeometryFlags::OPAQUE`
+    let hit_group = match geometry_kind {
+        GeometryKind::Triangles => RayTracingShaderGroupCreateInfo::TrianglesHit {
+            closest_hit_shader: Some(2),
+            any_hit_shader: Some(3),
+        },
+        GeometryKind::Aabb => {
+            stages.push(PipelineShaderStageCreateInfo::new(&intersection));
+
+            RayTracingShaderGroupCreateInfo::ProceduralHit {
+                closest_hit_shader: Some(2),
+                any_hit_shader: Some(3),
+                intersection_shader: 4,
+            }
+        }
+    };
+
+    let groups = [
+        RayTracingShaderGroupCreateInfo::General { general_shader: 0 },
+        RayTracingShaderGroupCreateInfo::General { general_shader: 1 },
+        hit_group,
+    ];
+
+    let layout = bcx.pipeline_layout_from_stages(&stages).unwrap();
+
+    let base_info = RayTracingPipelineCreateInfo::new(&layout);
+
+    RayTracingPipeline::new(
+        &app.device,
+        None,
+        &RayTracingPipelineCreateInfo {
+            stages: &stages,
+            groups: &groups,
+            // Primary rays take one level, each reflection another one
+            max_pipeline_ray_recursion_depth: app.max_ray_recursion_depth,
+            ..base_info
+        },
+    )
+    .unwrap()
+}
+
+fn ray_query_pipeline(app: &App) -> Arc<ComputePipeline> {
+    let bcx = app.resources.bindless_context().unwrap();
+
+    let shader = query::load(&app.device)
+        .unwrap()
+        .entry_point("main")
+        .unwrap();
+
+    let stage = PipelineShaderStageCreateInfo::new(&shader);
+
+    let layout = bcx
+        .pipeline_layout_from_stages(std::slice::from_ref(&stage))
+        .unwrap();
+
+    ComputePipeline::new(
+        &app.device,
+        None,
+        &ComputePipelineCreateInfo::new(stage, &layout),
+    )
+    .unwrap()
+}
+
+// How rays are traced, chosen from the features of the device
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderPath {
+    // Ray generation, miss and hit shaders
+    #[default]
+    Pipeline,
+    // A compute shader tracing with ray queries, for devices without ray tracing pipelines
+    RayQuery,
+}
+
+impl RenderPath {
+    pub fn required_extensions(self) -> DeviceExtensions {
+        match self {
+            RenderPath::Pipeline => DeviceExtensions {
+                khr_ray_tracing_maintenance1: true,
+                khr_ray_tracing_pipeline: true,
+                ..DeviceExtensions::empty()
             },
-            &AllocationCreateInfo {
-                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                ..Default::default()
+            RenderPath::RayQuery => DeviceExtensions {
+                khr_ray_query: true,
+                ..DeviceExtensions::empty()
             },
-            vertices,
-        )
-        .expect("Vertex buffer creation failed");
+        }
+    }
 
-        let blas = acceleration_structure::build_blas(
-            vertex_buffer,
-            app.memory_allocator.clone(),
-            app.device.clone(),
-            app.compute_queue.clone(),
-            &app.resources,
-            app.compute_flight_id,
-        );
+    pub fn required_features(self) -> DeviceFeatures {
+        match self {
+            RenderPath::Pipeline => DeviceFeatures {
+                ray_tracing_pipeline: true,
+                ..DeviceFeatures::empty()
+            },
+            RenderPath::RayQuery => DeviceFeatures {
+                ray_query: true,
+                ..DeviceFeatures::empty()
+            },
+        }
+    }
 
-        // let render_instances: Vec<AccelerationStructureInstance> = app.world.to_instances(
-        //     0,
-        //     &IVec3::ZERO,
-        //     blas.device_address().into(),
-        //     max_instance_count,
-        // );
-
-        fn sample_uniform_sphere(radius: f32) -> (f32, f32, f32) {
-            let sample = Vec3::new(
-                rand::random_range(-1.0..=1.0),
-                rand::random_range(-1.0..=1.0),
-                rand::random_range(-1.0..=1.0),
-            )
-            .normalize()
-                * rand::random_range(0.0..=1.0)
-                * radius;
+    // The read and write accesses of the shaders storing to the output images
+    pub fn storage_access_types(self) -> (AccessTypes, AccessTypes) {
+        match self {
+            RenderPath::Pipeline => (
+                AccessTypes::RAY_TRACING_SHADER_STORAGE_READ,
+                AccessTypes::RAY_TRACING_SHADER_STORAGE_WRITE,
+            ),
+            RenderPath::RayQuery => (
+                AccessTypes::COMPUTE_SHADER_STORAGE_READ,
+                AccessTypes::COMPUTE_SHADER_STORAGE_WRITE,
+            ),
+        }
+    }
+}
+
+pub enum RenderPipeline {
+    RayTracing {
+        pipeline: Arc<RayTracingPipeline>,
+        shader_binding_table: ShaderBindingTable,
+        // Outputs the cost of each pixel, only built when the device has shader clocks
+        heatmap: Option<(Arc<RayTracingPipeline>, ShaderBindingTable)>,
+    },
+    RayQuery(Arc<ComputePipeline>),
+}
 
-            (sample.x.floor(), sample.y.floor(), sample.z.floor())
+impl RenderPipeline {
+    pub fn layout(&self) -> &Arc<PipelineLayout> {
+        match self {
+            RenderPipeline::RayTracing { pipeline, .. } => pipeline.layout(),
+            RenderPipeline::RayQuery(pipeline) => pipeline.layout(),
         }
+    }
+}
 
-        let radius: f32 = max_instance_count.ilog2().pow(3) as f32;
-        let render_instances = (0..max_instance_count)
-            .map(|_| {
-                let (x, y, z) = sample_uniform_sphere(radius);
-
-                AccelerationStructureInstance {
-                    acceleration_structure_reference: blas.device_address().into(),
-                    instance_custom_index_and_mask: Packed24_8::new(
-                        rand::random::<u8>() as u32,
-                        0xFF,
-                    ),
-                    transform: [[1.0, 0.0, 0.0, x], [0.0, 1.0, 0.0, y], [0.0, 0.0, 1.0, z]],
-                    ..Default::default()
+// The palette buffer contents of a model
+fn palette(source: &dyn VoxelSource) -> raygen::Palette {
+    let materials = source.materials();
+
+    raygen::Palette {
+        colors: source.palette().map(|color| color.to_array()),
+        materials: materials.map(|material| [material.metallic, material.roughness]),
+        emission: materials.map(|material| {
+            material
+                .emission
+                .extend(material.emission_strength)
+                .to_array()
+        }),
+    }
+}
+
+// The pipeline and scene buffers shared by both render paths, their bindless ids are only
+// created here
+pub struct RayTracingResources {
+    pub pipeline: RenderPipeline,
+    pub camera_buffer_id: Id<Buffer>,
+    pub palette_buffer_id: Id<Buffer>,
+    pub sunlight_buffer_id: Id<Buffer>,
+    pub camera_storage_buffer_id: StorageBufferId,
+    pub palette_storage_buffer_id: StorageBufferId,
+    pub sunlight_storage_buffer_id: StorageBufferId,
+}
+
+impl RayTracingResources {
+    pub fn new(app: &App, geometry_kind: GeometryKind) -> Self {
+        let bcx = app.resources.bindless_context().unwrap();
+
+        let pipeline = match app.render_path {
+            RenderPath::Pipeline => {
+                let pipeline = ray_tracing_pipeline(app, geometry_kind, false);
+                let shader_binding_table =
+                    ShaderBindingTable::new(&app.memory_allocator, &pipeline).unwrap();
+
+                let heatmap = app.heatmap_supported.then(|| {
+                    let pipeline = ray_tracing_pipeline(app, geometry_kind, true);
+                    let shader_binding_table =
+                        ShaderBindingTable::new(&app.memory_allocator, &pipeline).unwrap();
+
+                    (pipeline, shader_binding_table)
+                });
+
+                RenderPipeline::RayTracing {
+                    pipeline,
+                    shader_binding_table,
+                    heatmap,
                 }
-            })
-            .collect::<Vec<_>>();
-
-        let build_geometry_info = AccelerationStructureBuildGeometryInfo {
-            ..AccelerationStructureBuildGeometryInfo::new(
-                AccelerationStructureGeometries::Instances(
-                    AccelerationStructureGeometryInstancesData::new(
-                        AccelerationStructureGeometryInstancesDataType::Values(None),
-                    ),
-                ),
-            )
+            }
+            RenderPath::RayQuery => RenderPipeline::RayQuery(ray_query_pipeline(app)),
         };
 
-        let build_sizes_info = app
-            .device
-            .acceleration_structure_build_sizes(
-                AccelerationStructureBuildType::Device,
-                &build_geometry_info,
-                &[max_instance_count as u32],
-            )
-            .unwrap();
-
-        let scratch_buffer_id = app
+        let camera_buffer_id = app
             .resources
             .create_buffer(
                 &BufferCreateInfo {
-                    usage: BufferUsage::SHADER_DEVICE_ADDRESS | BufferUsage::STORAGE_BUFFER,
+                    usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
                     ..Default::default()
                 },
-                &AllocationCreateInfo::default(),
-                DeviceLayout::new_unsized::<[u8]>(build_sizes_info.build_scratch_size).unwrap(),
+                &AllocationCreateInfo {
+                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                    ..Default::default()
+                },
+                DeviceLayout::new_sized::<raygen::Camera>(),
             )
             .unwrap();
 
-        let instance_buffer_id = app
+        let sunlight_buffer_id = app
             .resources
             .create_buffer(
                 &BufferCreateInfo {
-                    usage: BufferUsage::SHADER_DEVICE_ADDRESS
-                        | BufferUsage::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY,
+                    usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
                     ..Default::default()
                 },
                 &AllocationCreateInfo {
@@ -162,22 +463,10 @@ impl RayTracingRenderTask {
                         | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
                     ..Default::default()
                 },
-                DeviceLayout::new_unsized::<[AccelerationStructureInstance]>(max_instance_count)
-                    .unwrap(),
+                DeviceLayout::new_sized::<raygen::Sunlight>(),
             )
             .unwrap();
 
-        let instance_buffer = Subbuffer::new(
-            app.resources
-                .buffer(instance_buffer_id)
-                .expect("Instance buffer not found")
-                .buffer()
-                .clone(),
-        )
-        .cast_aligned::<AccelerationStructureInstance>();
-
-        let palette = get_palette(&app.voxel_data).map(|color| [color.x, color.y, color.z, 1.0]);
-
         let palette_buffer_id = app
             .resources
             .create_buffer(
@@ -200,21 +489,11 @@ impl RayTracingRenderTask {
                 &app.resources,
                 app.graphics_flight_id,
                 |_cbf, tcx| {
-                    *tcx.write_buffer(palette_buffer_id, ..)? = raygen::Palette { colors: palette };
-
-                    let write_instance_buffer = tcx
-                        .write_buffer::<[AccelerationStructureInstance]>(instance_buffer_id, ..)?;
-
-                    for (dst, src) in write_instance_buffer.iter_mut().zip(render_instances) {
-                        *dst = src;
-                    }
+                    *tcx.write_buffer(palette_buffer_id, ..)? = palette(&app.voxel_data);
 
                     Ok(())
                 },
-                [
-                    (palette_buffer_id, HostAccessType::Write),
-                    (instance_buffer_id, HostAccessType::Write),
-                ],
+                [(palette_buffer_id, HostAccessType::Write)],
                 [],
                 [],
             )
@@ -227,123 +506,6 @@ impl RayTracingRenderTask {
             .wait_idle()
             .unwrap();
 
-        let acceleration_structures = [
-            acceleration_structure::build_tlas(
-                instance_buffer.clone(),
-                max_instance_count as u32,
-                app.memory_allocator.clone(),
-                app.device.clone(),
-                app.compute_queue.clone(),
-                &app.resources,
-                app.compute_flight_id,
-            ),
-            acceleration_structure::build_tlas(
-                instance_buffer,
-                max_instance_count as u32,
-                app.memory_allocator.clone(),
-                app.device.clone(),
-                app.compute_queue.clone(),
-                &app.resources,
-                app.compute_flight_id,
-            ),
-        ];
-
-        let bcx = app.resources.bindless_context().unwrap();
-
-        let pipeline = {
-            let raygen = raygen::load(&app.device)
-                .unwrap()
-                .entry_point("main")
-                .unwrap();
-            let miss = miss::load(&app.device)
-                .unwrap()
-                .entry_point("main")
-                .unwrap();
-            let intersection = intersection::load(&app.device)
-                .unwrap()
-                .entry_point("main")
-                .unwrap();
-            let closest_hit = closest_hit::load(&app.device)
-                .unwrap()
-                .entry_point("main")
-                .unwrap();
-
-            let stages = [
-                PipelineShaderStageCreateInfo::new(&raygen),
-                PipelineShaderStageCreateInfo::new(&miss),
-                PipelineShaderStageCreateInfo::new(&intersection),
-                PipelineShaderStageCreateInfo::new(&closest_hit),
-            ];
-
-            let groups = [
-                RayTracingShaderGroupCreateInfo::General { general_shader: 0 },
-                RayTracingShaderGroupCreateInfo::General { general_shader: 1 },
-                RayTracingShaderGroupCreateInfo::ProceduralHit {
-                    closest_hit_shader: Some(3),
-                    any_hit_shader: None,
-                    intersection_shader: 2,
-                },
-            ];
-
-            let layout = bcx.pipeline_layout_from_stages(&stages).unwrap();
-
-            let base_info = RayTracingPipelineCreateInfo::new(&layout);
-
-            RayTracingPipeline::new(
-                &app.device,
-                None,
-                &RayTracingPipelineCreateInfo {
-                    stages: &stages,
-                    groups: &groups,
-                    max_pipeline_ray_recursion_depth: 1,
-                    ..base_info
-                },
-            )
-            .unwrap()
-        };
-
-        let camera_buffer_id = app
-            .resources
-            .create_buffer(
-                &BufferCreateInfo {
-                    usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
-                    ..Default::default()
-                },
-                &AllocationCreateInfo {
-                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                    ..Default::default()
-                },
-                DeviceLayout::new_sized::<raygen::Camera>(),
-            )
-            .unwrap();
-
-        let sunlight_buffer_id = app
-            .resources
-            .create_buffer(
-                &BufferCreateInfo {
-                    usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST,
-                    ..Default::default()
-                },
-                &AllocationCreateInfo {
-                    memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
-                        | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-                    ..Default::default()
-                },
-                DeviceLayout::new_sized::<raygen::Sunlight>(),
-            )
-            .unwrap();
-
-        let shader_binding_table =
-            ShaderBindingTable::new(&app.memory_allocator, &pipeline).unwrap();
-
-        let acceleration_structure_ids = [
-            bcx.global_set()
-                .add_acceleration_structure(acceleration_structures[0].clone()),
-            bcx.global_set()
-                .add_acceleration_structure(acceleration_structures[1].clone()),
-        ];
-
         let camera_storage_buffer_id = bcx
             .global_set()
             .create_storage_buffer(
@@ -371,25 +533,415 @@ impl RayTracingRenderTask {
             )
             .unwrap();
 
-        RayTracingRenderTask {
-            swapchain_id: virtual_swapchain_id,
+        RayTracingResources {
+            pipeline,
             camera_buffer_id,
+            palette_buffer_id,
             sunlight_buffer_id,
-            instance_buffer_id,
-            acceleration_structure_ids,
             camera_storage_buffer_id,
             palette_storage_buffer_id,
             sunlight_storage_buffer_id,
-            shader_binding_table,
+        }
+    }
+
+    // Rewrites the colors, materials and emission from the loaded model, after it is replaced.
+    // The frames in flight are waited for so no trace reads the buffer while it is written
+    pub fn reload_palette(&self, app: &App) {
+        app.resources
+            .flight(app.graphics_flight_id)
+            .unwrap()
+            .wait_idle()
+            .unwrap();
+
+        unsafe {
+            vulkano_taskgraph::execute(
+                &app.transfer_queue,
+                &app.resources,
+                app.graphics_flight_id,
+                |_cbf, tcx| {
+                    *tcx.write_buffer(self.palette_buffer_id, ..)? = palette(&app.voxel_data);
+
+                    Ok(())
+                },
+                [(self.palette_buffer_id, HostAccessType::Write)],
+                [],
+                [],
+            )
+        }
+        .unwrap();
+
+        app.resources
+            .flight(app.graphics_flight_id)
+            .unwrap()
+            .wait_idle()
+            .unwrap();
+    }
+
+    // Replaces one color of the palette, see `set_palette`
+    pub fn set_palette_color(&self, app: &App, index: u8, color: Vec4) {
+        let offset = index as DeviceSize * size_of::<[f32; 4]>() as DeviceSize;
+
+        self.write_palette_colors(app, offset, &[color.to_array()]);
+    }
+
+    // Replaces the colors of the palette, the materials and emission are kept
+    pub fn set_palette(&self, app: &App, colors: &[Vec4; 256]) {
+        self.write_palette_colors(app, 0, &colors.map(|color| color.to_array()));
+    }
+
+    // The colors are the first field of the palette, `offset` is in bytes. The frames in flight
+    // are waited for so no trace reads the buffer while it is written
+    fn write_palette_colors(&self, app: &App, offset: DeviceSize, colors: &[[f32; 4]]) {
+        let range = offset..offset + size_of_val(colors) as DeviceSize;
+
+        app.resources
+            .flight(app.graphics_flight_id)
+            .unwrap()
+            .wait_idle()
+            .unwrap();
+
+        unsafe {
+            vulkano_taskgraph::execute(
+                &app.transfer_queue,
+                &app.resources,
+                app.graphics_flight_id,
+                |_cbf, tcx| {
+                    tcx.write_buffer::<[[f32; 4]]>(self.palette_buffer_id, range)?
+                        .copy_from_slice(colors);
+
+                    Ok(())
+                },
+                [(self.palette_buffer_id, HostAccessType::Write)],
+                [],
+                [],
+            )
+        }
+        .unwrap();
+
+        app.resources
+            .flight(app.graphics_flight_id)
+            .unwrap()
+            .wait_idle()
+            .unwrap();
+    }
+}
+
+pub struct RayTracingRenderTask {
+    swapchain_id: Id<Swapchain>,
+    pub acceleration_structure_ids: [AccelerationStructureId; 2],
+    pub instance_buffer_id: Id<Buffer>,
+    pub instance_count: Arc<AtomicU32>,
+    pub max_instance_count: u64,
+    // What the instance buffer held after the last upload
+    uploaded_instances: Mutex<Vec<AccelerationStructureInstance>>,
+    // Ranges of the instance buffer written behind the back of `rebuild_instances`, they are
+    // uploaded again on the next rebuild
+    pub dirty_instances: Arc<Mutex<Vec<Range<u64>>>>,
+    pub rt_resources: RayTracingResources,
+    pub blas: Vec<Arc<AccelerationStructure>>,
+    pub acceleration_structures: [Arc<AccelerationStructure>; 2],
+    pub current_as_index: Arc<AtomicBool>,
+    pub show_current_index: Arc<AtomicBool>,
+    // Renders this TLAS instead of the front one while set, the worker doesn't update it
+    pub forced_as_index: Arc<Mutex<Option<bool>>>,
+    // Held by the worker for a whole TLAS update and by the app for a whole rebuild of the
+    // instances, so they never build the same TLAS at once
+    pub tlas_lock: Arc<Mutex<()>>,
+    // Measures the ray trace dispatch
+    pub timer: GpuTimer,
+}
+
+impl RayTracingRenderTask {
+    pub fn new(
+        app: &App,
+        virtual_swapchain_id: Id<Swapchain>,
+        max_instance_count: u64,
+        geometry_kind: GeometryKind,
+    ) -> Self {
+        let blas = build_lod_blas(app, geometry_kind);
+
+        let blas_references = blas
+            .iter()
+            .map(|blas| blas.device_address().into())
+            .collect::<Vec<u64>>();
+
+        let render_instances = match app.debug_scene {
+            DebugScene::World => {
+                let mut instances = app.world.to_instances(
+                    &IVec3::ZERO,
+                    &blas_references,
+                    app.device_max_instance_count,
+                    None,
+                );
+
+                if app.sort_instances {
+                    sort_instances_spatially(&mut instances);
+                }
+
+                instances
+            }
+            DebugScene::RandomSphere => {
+                random_sphere_instances(blas_references[0], max_instance_count, app.seed)
+            }
+        };
+
+        validate_instance_transforms(&render_instances, 0);
+
+        // Grown like in `App::grow_instance_buffer` when the startup world doesn't fit
+        let max_instance_count = if render_instances.len() as u64 > max_instance_count {
+            (render_instances.len() as u64)
+                .next_power_of_two()
+                .min(app.device_max_instance_count)
+        } else {
+            max_instance_count
+        };
+
+        let instance_count = render_instances.len() as u32;
+        let uploaded_instances = render_instances.clone();
+
+        // The empty TLAS is still built and traced, every ray misses
+        if render_instances.is_empty() {
+            eprintln!("The scene has no voxels, only the sky is rendered");
+        }
+
+        let instance_buffer_id = create_instance_buffer(app, max_instance_count);
+        let instance_buffer = instance_subbuffer(app, instance_buffer_id);
+
+        unsafe {
+            vulkano_taskgraph::execute(
+                &app.transfer_queue,
+                &app.resources,
+                app.graphics_flight_id,
+                |_cbf, tcx| {
+                    let write_instance_buffer = tcx
+                        .write_buffer::<[AccelerationStructureInstance]>(instance_buffer_id, ..)?;
+
+                    for (dst, src) in write_instance_buffer.iter_mut().zip(render_instances) {
+                        *dst = src;
+                    }
+
+                    Ok(())
+                },
+                [(instance_buffer_id, HostAccessType::Write)],
+                [],
+                [],
+            )
+        }
+        .unwrap();
+
+        app.resources
+            .flight(app.graphics_flight_id)
+            .unwrap()
+            .wait_idle()
+            .unwrap();
+
+        let acceleration_structures =
+            build_tlas_pair(app, instance_buffer, instance_count, max_instance_count);
+        let acceleration_structure_ids = add_tlas_pair(app, &acceleration_structures);
+
+        RayTracingRenderTask {
+            swapchain_id: virtual_swapchain_id,
+            instance_buffer_id,
+            instance_count: Arc::new(AtomicU32::new(instance_count)),
+            max_instance_count,
+            uploaded_instances: Mutex::new(uploaded_instances),
+            dirty_instances: Arc::default(),
+            acceleration_structure_ids,
+            rt_resources: RayTracingResources::new(app, geometry_kind),
             blas,
             acceleration_structures,
             current_as_index: Arc::new(AtomicBool::new(false)),
             show_current_index: Arc::new(AtomicBool::new(true)),
-            pipeline,
+            forced_as_index: Arc::new(Mutex::new(None)),
+            tlas_lock: Arc::default(),
+            timer: GpuTimer::new(&app.device, app.graphics_queue.queue_family_index()),
         }
     }
 }
 
+impl RayTracingRenderTask {
+    // Device addresses of the BLAS, indexed by LOD for opaque voxels and offset by `LOD_COUNT`
+    // for translucent ones
+    pub fn blas_references(&self) -> Vec<u64> {
+        self.blas
+            .iter()
+            .map(|blas| blas.device_address().into())
+            .collect()
+    }
+
+    // Uploads the instances that changed since the last upload and rebuilds both TLAS in place,
+    // the caller must make sure that no frame is in flight and hold `tlas_lock`
+    pub fn rebuild_instances(&self, app: &App, instances: Vec<AccelerationStructureInstance>) {
+        const AS_SIZE: DeviceSize = size_of::<AccelerationStructureInstance>() as DeviceSize;
+
+        let instance_count = instances.len() as u32;
+
+        assert!(instance_count as u64 <= self.max_instance_count);
+        validate_instance_transforms(&instances, 0);
+
+        let mut uploaded_instances = self.uploaded_instances.lock().unwrap();
+        let dirty_instances = std::mem::take(&mut *self.dirty_instances.lock().unwrap());
+
+        if instances.is_empty() && !uploaded_instances.is_empty() {
+            eprintln!("The scene has no voxels, only the sky is rendered");
+        }
+        let dirty_ranges = dirty_instance_ranges(&uploaded_instances, &instances, &dirty_instances);
+
+        if dirty_ranges.is_empty() && instances.len() == uploaded_instances.len() {
+            return;
+        }
+
+        if !dirty_ranges.is_empty() {
+            unsafe {
+                vulkano_taskgraph::execute(
+                    &app.graphics_queue,
+                    &app.resources,
+                    app.graphics_flight_id,
+                    |_cbf, tcx| {
+                        for range in &dirty_ranges {
+                            tcx.write_buffer::<[AccelerationStructureInstance]>(
+                                self.instance_buffer_id,
+                                range.start * AS_SIZE..range.end * AS_SIZE,
+                            )?
+                            .copy_from_slice(&instances[range.start as usize..range.end as usize]);
+                        }
+
+                        Ok(())
+                    },
+                    [(self.instance_buffer_id, HostAccessType::Write)],
+                    [],
+                    [],
+                )
+            }
+            .unwrap();
+
+            app.resources
+                .flight(app.graphics_flight_id)
+                .unwrap()
+                .wait_idle()
+                .unwrap();
+        }
+
+        let instance_buffer = instance_subbuffer(app, self.instance_buffer_id);
+
+        for tlas in &self.acceleration_structures {
+            acceleration_structure::rebuild_tlas(
+                tlas.clone(),
+                instance_buffer.clone(),
+                instance_count,
+                app.memory_allocator.clone(),
+                &mut app.scratch_buffers.lock().unwrap(),
+                app.device.clone(),
+                app.compute_queue.clone(),
+                &app.resources,
+                app.compute_flight_id,
+            );
+        }
+
+        self.instance_count.store(instance_count, Ordering::Relaxed);
+        *uploaded_instances = instances;
+    }
+
+    // Switches to a new instance buffer and TLAS pair for up to `max_instance_count` instances,
+    // left empty until the next `rebuild_instances`. The update task of the old buffer can
+    // still run on the worker until it gets the new one, so the old buffer is freed by the
+    // worker and the instance count and dirty ranges it writes to are replaced too. The caller
+    // holds `tlas_lock`
+    pub fn grow(&mut self, app: &App, max_instance_count: u64) {
+        assert!(max_instance_count > self.max_instance_count);
+
+        let instance_buffer_id = create_instance_buffer(app, max_instance_count);
+        let instance_buffer = instance_subbuffer(app, instance_buffer_id);
+        let acceleration_structures = build_tlas_pair(app, instance_buffer, 0, max_instance_count);
+
+        let mut batch = app.resources.create_deferred_batch();
+
+        for &id in &self.acceleration_structure_ids {
+            batch.destroy_acceleration_structure(id);
+        }
+
+        batch.enqueue();
+
+        self.acceleration_structure_ids = add_tlas_pair(app, &acceleration_structures);
+        self.acceleration_structures = acceleration_structures;
+        self.instance_buffer_id = instance_buffer_id;
+        self.max_instance_count = max_instance_count;
+        self.instance_count = Arc::new(AtomicU32::new(0));
+        self.dirty_instances = Arc::default();
+        self.uploaded_instances.get_mut().unwrap().clear();
+    }
+}
+
+fn create_instance_buffer(app: &App, max_instance_count: u64) -> Id<Buffer> {
+    app.resources
+        .create_buffer(
+            &BufferCreateInfo {
+                usage: BufferUsage::SHADER_DEVICE_ADDRESS
+                    | BufferUsage::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY,
+                ..Default::default()
+            },
+            &AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            DeviceLayout::new_unsized::<[AccelerationStructureInstance]>(max_instance_count)
+                .unwrap(),
+        )
+        .unwrap()
+}
+
+fn instance_subbuffer(
+    app: &App,
+    instance_buffer_id: Id<Buffer>,
+) -> Subbuffer<[AccelerationStructureInstance]> {
+    Subbuffer::new(
+        app.resources
+            .buffer(instance_buffer_id)
+            .expect("Instance buffer not found")
+            .buffer()
+            .clone(),
+    )
+    .cast_aligned::<AccelerationStructureInstance>()
+}
+
+// The two TLAS the worker swaps between, sized for `max_instance_count` instances
+fn build_tlas_pair(
+    app: &App,
+    instance_buffer: Subbuffer<[AccelerationStructureInstance]>,
+    instance_count: u32,
+    max_instance_count: u64,
+) -> [Arc<AccelerationStructure>; 2] {
+    let [(front, front_timings), (back, back_timings)] = [(); 2].map(|_| {
+        acceleration_structure::build_tlas(
+            instance_buffer.clone(),
+            instance_count,
+            max_instance_count as u32,
+            app.memory_allocator.clone(),
+            &mut app.scratch_buffers.lock().unwrap(),
+            app.device.clone(),
+            app.compute_queue.clone(),
+            &app.resources,
+            app.compute_flight_id,
+        )
+    });
+
+    println!("2 {}", front_timings.sum(back_timings));
+
+    [front, back]
+}
+
+fn add_tlas_pair(
+    app: &App,
+    acceleration_structures: &[Arc<AccelerationStructure>; 2],
+) -> [AccelerationStructureId; 2] {
+    let bcx = app.resources.bindless_context().unwrap();
+
+    acceleration_structures
+        .each_ref()
+        .map(|tlas| bcx.global_set().add_acceleration_structure(tlas.clone()))
+}
+
 impl Task for RayTracingRenderTask {
     type World = RenderContext;
 
@@ -401,12 +953,22 @@ impl Task for RayTracingRenderTask {
     ) -> TaskResult {
         let swapchain_state = tcx.swapchain(self.swapchain_id)?;
         let image_index = swapchain_state.current_image_index().unwrap();
-        let extent = swapchain_state.images()[0].extent();
+        let extent = [rcx.render_extent[0], rcx.render_extent[1], 1];
 
-        unsafe { cbf.update_buffer(self.camera_buffer_id, 0, &rcx.rt_camera_data) }?;
-        unsafe { cbf.update_buffer(self.sunlight_buffer_id, 0, &rcx.rt_sunlight_data) }?;
+        unsafe { cbf.update_buffer(self.rt_resources.camera_buffer_id, 0, &rcx.rt_camera_data) }?;
+        unsafe {
+            cbf.update_buffer(
+                self.rt_resources.sunlight_buffer_id,
+                0,
+                &rcx.rt_sunlight_data,
+            )
+        }?;
 
-        let front_index = self.current_as_index.load(Ordering::Relaxed);
+        let front_index = self
+            .forced_as_index
+            .lock()
+            .unwrap()
+            .unwrap_or_else(|| self.current_as_index.load(Ordering::Acquire));
 
         // if self.show_current_index.load(Ordering::Relaxed) {
         //     println!("Now rendering TLAS with index: {front_index}");
@@ -414,26 +976,69 @@ impl Task for RayTracingRenderTask {
 
         self.show_current_index.store(false, Ordering::Relaxed);
 
+        // The shaders leave the G-buffer alone while it's disabled, any image fits then
+        let g_buffer_image_ids = rcx
+            .g_buffer_storage_image_ids()
+            .unwrap_or([rcx.accumulation_storage_image_id; 2]);
+
         unsafe {
             cbf.push_constants(
-                self.pipeline.layout(),
+                self.rt_resources.pipeline.layout(),
                 0,
                 &raygen::PushConstants {
-                    image_id: rcx.swapchain_storage_image_ids[image_index as usize],
+                    background_color: rcx.background_color(),
+                    jitter: rcx.jitter,
+                    image_id: rcx.render_target(image_index),
+                    accumulation_image_id: rcx.accumulation_storage_image_id,
+                    accumulated_frames: rcx.accumulated_frames,
+                    max_bounces: rcx.max_bounces,
+                    chunk_colors: rcx.chunk_colors as u32,
+                    debug_normals: rcx.debug_normals as u32,
+                    flat_colors: rcx.flat_colors as u32,
+                    ao_enabled: rcx.ambient_occlusion as u32,
+                    normal_image_id: g_buffer_image_ids[0],
+                    instance_image_id: g_buffer_image_ids[1],
+                    g_buffer_enabled: rcx.writes_g_buffer() as u32,
+                    denoise_enabled: rcx.denoising() as u32,
                     acceleration_structure_id: self.acceleration_structure_ids
                         [front_index as usize],
-                    camera_buffer_id: self.camera_storage_buffer_id,
-                    palette_buffer_id: self.palette_storage_buffer_id,
-                    sunlight_buffer_id: self.sunlight_storage_buffer_id,
+                    camera_buffer_id: self.rt_resources.camera_storage_buffer_id,
+                    palette_buffer_id: self.rt_resources.palette_storage_buffer_id,
+                    sunlight_buffer_id: self.rt_resources.sunlight_storage_buffer_id,
                 },
             )
         }?;
 
-        unsafe {
-            cbf.bind_pipeline_ray_tracing(&self.pipeline)?;
-        }
+        match &self.rt_resources.pipeline {
+            RenderPipeline::RayTracing {
+                pipeline,
+                shader_binding_table,
+                heatmap,
+            } => {
+                let (pipeline, shader_binding_table) = match heatmap {
+                    Some((pipeline, shader_binding_table)) if rcx.heatmap => {
+                        (pipeline, shader_binding_table)
+                    }
+                    _ => (pipeline, shader_binding_table),
+                };
+
+                unsafe { cbf.bind_pipeline_ray_tracing(pipeline) }?;
 
-        unsafe { cbf.trace_rays(self.shader_binding_table.addresses(), extent) }?;
+                unsafe { self.timer.begin(cbf) }?;
+                unsafe { cbf.trace_rays(shader_binding_table.addresses(), extent) }?;
+                unsafe { self.timer.end(cbf) }?;
+            }
+            RenderPipeline::RayQuery(pipeline) => {
+                unsafe { cbf.bind_pipeline_compute(pipeline) }?;
+
+                // One invocation per pixel, in groups of 8x8
+                let group_counts = [extent[0].div_ceil(8), extent[1].div_ceil(8), 1];
+
+                unsafe { self.timer.begin(cbf) }?;
+                unsafe { cbf.dispatch(group_counts) }?;
+                unsafe { self.timer.end(cbf) }?;
+            }
+        }
 
         let dependency_info = DependencyInfo {
             memory_barriers: &[MemoryBarrier {
@@ -0,0 +1,45 @@
+use vulkano::swapchain::Swapchain;
+use vulkano_taskgraph::{
+    Id, Task, TaskContext, TaskResult,
+    command_buffer::{CopyImageToBufferInfo, RecordingCommandBuffer},
+};
+
+use crate::app::RenderContext;
+
+// Copies the finished swapchain image to the host buffer of a pending screenshot
+pub struct ScreenshotTask {
+    swapchain_id: Id<Swapchain>,
+}
+
+impl ScreenshotTask {
+    pub fn new(virtual_swapchain_id: Id<Swapchain>) -> Self {
+        Self {
+            swapchain_id: virtual_swapchain_id,
+        }
+    }
+}
+
+impl Task for ScreenshotTask {
+    type World = RenderContext;
+
+    unsafe fn execute(
+        &self,
+        cbf: &mut RecordingCommandBuffer<'_>,
+        _tcx: &mut TaskContext<'_>,
+        rcx: &Self::World,
+    ) -> TaskResult {
+        let Some(buffer_id) = rcx.screenshot_buffer_id else {
+            return Ok(());
+        };
+
+        unsafe {
+            cbf.copy_image_to_buffer(&CopyImageToBufferInfo {
+                src_image: self.swapchain_id.current_image_id(),
+                dst_buffer: buffer_id,
+                ..Default::default()
+            })
+        }?;
+
+        Ok(())
+    }
+}
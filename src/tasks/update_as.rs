@@ -1,5 +1,12 @@
-use std::sync::Arc;
+use std::{
+    ops::Range,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+};
 
+use rand::{Rng, SeedableRng, rngs::StdRng};
 use vulkano::{
     DeviceSize, Packed24_8,
     acceleration_structure::{
@@ -16,19 +23,43 @@ use vulkano_taskgraph::{
     Id, Task, TaskContext, TaskResult, command_buffer::RecordingCommandBuffer,
 };
 
-use crate::app::App;
+use crate::{
+    app::App,
+    gpu_timer::GpuTimer,
+    tasks::render::{DebugScene, validate_instance_transforms},
+};
 
-const UPDATES_PER_FRAME: u64 = 1000;
-// const UPDATES_PER_FRAME: u64 = 2u64.pow(10);
+pub const DEFAULT_UPDATES_PER_FRAME: u32 = 1000;
 
 pub struct UpdateAccelerationStructureTask {
     blas_reference: u64,
+    instance_count: Arc<AtomicU32>,
+    // The instance count the scratch buffer is sized for
+    max_instance_count: u32,
+    // Instances scrambled per update, adjusted at runtime. 0 keeps building the TLAS without
+    // moving any instance
+    pub updates_per_frame: Arc<AtomicU32>,
+    // Only the random sphere is scrambled, the instances of the world are left as they are
+    scramble: bool,
+    // Shared with the render task, which has to upload the scrambled instances again
+    dirty_instances: Arc<Mutex<Vec<Range<u64>>>>,
     pub instance_buffer_id: Id<Buffer>,
     scratch_buffer_id: Id<Buffer>,
+    // Seeded from `App::seed`, so the updates scramble the instances the same way every run
+    rng: Mutex<StdRng>,
+    timer: Arc<GpuTimer>,
 }
 
 impl UpdateAccelerationStructureTask {
-    pub fn new(app: &App, instance_buffer_id: Id<Buffer>, blas_reference: u64) -> Self {
+    pub fn new(
+        app: &App,
+        instance_buffer_id: Id<Buffer>,
+        blas_reference: u64,
+        instance_count: Arc<AtomicU32>,
+        updates_per_frame: Arc<AtomicU32>,
+        dirty_instances: Arc<Mutex<Vec<Range<u64>>>>,
+        timer: Arc<GpuTimer>,
+    ) -> Self {
         let geometry_instances_data = AccelerationStructureGeometryInstancesData::new(
             AccelerationStructureGeometryInstancesDataType::Values(None),
         );
@@ -42,7 +73,7 @@ impl UpdateAccelerationStructureTask {
             .acceleration_structure_build_sizes(
                 AccelerationStructureBuildType::Device,
                 &build_info,
-                &[UPDATES_PER_FRAME as u32],
+                &[app.max_instance_count as u32],
             )
             .unwrap();
 
@@ -60,10 +91,22 @@ impl UpdateAccelerationStructureTask {
 
         Self {
             blas_reference,
+            instance_count,
+            max_instance_count: app.max_instance_count as u32,
+            updates_per_frame,
+            scramble: app.debug_scene == DebugScene::RandomSphere,
+            dirty_instances,
             instance_buffer_id,
             scratch_buffer_id: update_scratch_buffer,
+            rng: Mutex::new(StdRng::seed_from_u64(app.seed)),
+            timer,
         }
     }
+
+    // The buffers only this task uses, to free once it's replaced
+    pub fn buffer_ids(&self) -> [Id<Buffer>; 2] {
+        [self.instance_buffer_id, self.scratch_buffer_id]
+    }
 }
 
 pub struct AsyncRenderContext {
@@ -81,27 +124,53 @@ impl Task for UpdateAccelerationStructureTask {
     ) -> TaskResult {
         const AS_SIZE: DeviceSize = size_of::<AccelerationStructureInstance>() as DeviceSize;
 
-        let write_instance_buffer = tcx.write_buffer::<[AccelerationStructureInstance]>(
-            self.instance_buffer_id,
-            0..(UPDATES_PER_FRAME * AS_SIZE),
-        )?;
-
-        for instance in write_instance_buffer.iter_mut() {
-            const RANGE: i32 = 32;
-            let x = rand::random_range(-RANGE..=RANGE);
-            let y = rand::random_range(-RANGE..=RANGE);
-            let z = rand::random_range(-RANGE..=RANGE);
-
-            *instance = AccelerationStructureInstance {
-                acceleration_structure_reference: self.blas_reference,
-                instance_custom_index_and_mask: Packed24_8::new(rand::random::<u8>() as u32, 0xFF),
-                transform: [
-                    [1.0, 0.0, 0.0, x as f32],
-                    [0.0, 1.0, 0.0, y as f32],
-                    [0.0, 0.0, 1.0, z as f32],
-                ],
-                ..Default::default()
-            };
+        // An update must keep the primitive count of the original build, so only a window of
+        // the built instances gets scrambled
+        let instance_count = self.instance_count.load(Ordering::Relaxed);
+        assert!(instance_count <= self.max_instance_count);
+
+        let updated_instances = if self.scramble {
+            self.updates_per_frame
+                .load(Ordering::Relaxed)
+                .min(instance_count) as u64
+        } else {
+            0
+        };
+
+        if updated_instances != 0 {
+            let rng = &mut *self.rng.lock().unwrap();
+            let start_instance = rng.random_range(0..=instance_count as u64 - updated_instances);
+            let updated_range = start_instance..start_instance + updated_instances;
+
+            let write_instance_buffer = tcx.write_buffer::<[AccelerationStructureInstance]>(
+                self.instance_buffer_id,
+                (updated_range.start * AS_SIZE)..(updated_range.end * AS_SIZE),
+            )?;
+
+            for instance in write_instance_buffer.iter_mut() {
+                const RANGE: i32 = 32;
+                let x = rng.random_range(-RANGE..=RANGE);
+                let y = rng.random_range(-RANGE..=RANGE);
+                let z = rng.random_range(-RANGE..=RANGE);
+
+                *instance = AccelerationStructureInstance {
+                    acceleration_structure_reference: self.blas_reference,
+                    instance_custom_index_and_mask: Packed24_8::new(
+                        rng.random::<u8>() as u32,
+                        0xFF,
+                    ),
+                    transform: [
+                        [1.0, 0.0, 0.0, x as f32],
+                        [0.0, 1.0, 0.0, y as f32],
+                        [0.0, 0.0, 1.0, z as f32],
+                    ],
+                    ..Default::default()
+                };
+            }
+
+            validate_instance_transforms(&write_instance_buffer, updated_range.start);
+
+            self.dirty_instances.lock().unwrap().push(updated_range);
         }
 
         let instance_buffer = Subbuffer::new(
@@ -133,16 +202,20 @@ impl Task for UpdateAccelerationStructureTask {
         build_geometry_info.dst_acceleration_structure = Some(rcx.tlas.clone());
         build_geometry_info.scratch_data = Some(scratch_buffer);
 
+        unsafe { self.timer.begin(cbf) }?;
+
         unsafe {
             cbf.as_raw().build_acceleration_structure(
                 &build_geometry_info,
                 &[AccelerationStructureBuildRangeInfo {
-                    primitive_count: UPDATES_PER_FRAME as u32,
+                    primitive_count: instance_count,
                     ..Default::default()
                 }],
             )
         }?;
 
+        unsafe { self.timer.end(cbf) }?;
+
         Ok(())
     }
 }
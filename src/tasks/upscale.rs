@@ -0,0 +1,50 @@
+use vulkano::{
+    image::{Image, sampler::Filter},
+    swapchain::Swapchain,
+};
+use vulkano_taskgraph::{
+    Id, Task, TaskContext, TaskResult,
+    command_buffer::{BlitImageInfo, RecordingCommandBuffer},
+};
+
+use crate::app::RenderContext;
+
+// Stretches the image rays were traced into over the swapchain image, only part of the task
+// graph when the render scale isn't 1
+pub struct UpscaleTask {
+    swapchain_id: Id<Swapchain>,
+    render_image_id: Id<Image>,
+}
+
+impl UpscaleTask {
+    pub fn new(virtual_swapchain_id: Id<Swapchain>, virtual_render_image_id: Id<Image>) -> Self {
+        Self {
+            swapchain_id: virtual_swapchain_id,
+            render_image_id: virtual_render_image_id,
+        }
+    }
+}
+
+impl Task for UpscaleTask {
+    type World = RenderContext;
+
+    unsafe fn execute(
+        &self,
+        cbf: &mut RecordingCommandBuffer<'_>,
+        _tcx: &mut TaskContext<'_>,
+        _rcx: &Self::World,
+    ) -> TaskResult {
+        // Without regions the whole source is blitted over the whole destination, the linear
+        // filter handles any ratio between the two
+        unsafe {
+            cbf.blit_image(&BlitImageInfo {
+                src_image: self.render_image_id,
+                dst_image: self.swapchain_id.current_image_id(),
+                filter: Filter::Linear,
+                ..Default::default()
+            })
+        }?;
+
+        Ok(())
+    }
+}
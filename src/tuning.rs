@@ -0,0 +1,275 @@
+use std::{
+    f32::consts::{FRAC_PI_2, PI},
+    path::PathBuf,
+};
+
+use egui::{ClippedPrimitive, Context, TexturesDelta, ViewportId};
+use egui_winit::EventResponse;
+use glam::{Vec3, Vec4};
+use winit::{event::WindowEvent, window::Window};
+
+use crate::{app::TONEMAP_OPERATORS, rt::raygen, world::voxel::Material};
+
+// The parameters the tuning window edits, the fog is in meters like `App::set_fog`
+pub struct TuningParams<'a> {
+    pub sunlight: &'a mut raygen::Sunlight,
+    pub fog: &'a mut [f32; 2],
+    pub ambient_occlusion: &'a mut bool,
+    pub max_bounces: &'a mut u32,
+    pub max_ray_recursion_depth: u32,
+    // Changing the index loads another model, see `App::select_model`
+    pub assets: &'a [PathBuf],
+    pub asset_index: &'a mut usize,
+    // The palette entry edited by the material editor with its current values, edits are
+    // applied with `App::set_material`
+    pub material_index: &'a mut u8,
+    pub material_color: &'a mut Vec4,
+    pub material: &'a mut Material,
+    // Set when the overrides have to be written next to the model
+    pub save_materials: &'a mut bool,
+}
+
+// The tessellated window of a frame, drawn by the egui task
+pub struct TuningFrame {
+    pub primitives: Vec<ClippedPrimitive>,
+    pub textures_delta: TexturesDelta,
+    pub pixels_per_point: f32,
+    // Whether a parameter changed, the accumulated frames are stale then
+    pub changed: bool,
+}
+
+// A window with sliders for the lighting parameters, only receiving input while it is visible
+// and the mouse isn't captured by the camera
+pub struct TuningUi {
+    context: Context,
+    state: egui_winit::State,
+    pub visible: bool,
+}
+
+impl TuningUi {
+    pub fn new(window: &Window) -> Self {
+        let context = Context::default();
+        let state = egui_winit::State::new(
+            context.clone(),
+            ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            None,
+            None,
+        );
+
+        Self {
+            context,
+            state,
+            visible: false,
+        }
+    }
+
+    pub fn on_window_event(&mut self, window: &Window, event: &WindowEvent) -> EventResponse {
+        self.state.on_window_event(window, event)
+    }
+
+    pub fn run(&mut self, window: &Window, mut params: TuningParams<'_>) -> TuningFrame {
+        let raw_input = self.state.take_egui_input(window);
+        let mut changed = false;
+
+        let output = self.context.run(raw_input, |context| {
+            changed |= params_window(context, &mut params);
+        });
+
+        self.state
+            .handle_platform_output(window, output.platform_output);
+
+        TuningFrame {
+            primitives: self
+                .context
+                .tessellate(output.shapes, output.pixels_per_point),
+            textures_delta: output.textures_delta,
+            pixels_per_point: output.pixels_per_point,
+            changed,
+        }
+    }
+}
+
+fn params_window(context: &Context, params: &mut TuningParams<'_>) -> bool {
+    let mut changed = false;
+
+    egui::Window::new("Tuning").show(context, |ui| {
+        let asset_name = |index: usize| {
+            params.assets[index]
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+
+        // Not a parameter change, the world is rebuilt instead
+        egui::ComboBox::from_label("model")
+            .selected_text(asset_name(*params.asset_index))
+            .show_ui(ui, |ui| {
+                for index in 0..params.assets.len() {
+                    ui.selectable_value(&mut *params.asset_index, index, asset_name(index));
+                }
+            });
+
+        ui.heading("Sun");
+
+        // Light from a sun above the horizon travels downwards, towards +Y in the world
+        let direction = Vec3::from_array(*params.sunlight.direction);
+        let mut elevation = direction.y.clamp(-1.0, 1.0).asin();
+        let mut azimuth = direction.z.atan2(direction.x);
+
+        let sun_changed = ui
+            .add(egui::Slider::new(&mut elevation, 0.0..=FRAC_PI_2).text("elevation"))
+            .changed()
+            | ui.add(egui::Slider::new(&mut azimuth, -PI..=PI).text("azimuth"))
+                .changed();
+
+        if sun_changed {
+            *params.sunlight.direction = Vec3::new(
+                elevation.cos() * azimuth.cos(),
+                elevation.sin(),
+                elevation.cos() * azimuth.sin(),
+            )
+            .to_array();
+        }
+
+        changed |= sun_changed;
+
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut params.sunlight.sun_angular_radius, 0.0..=0.2)
+                    .text("angular radius (rad)"),
+            )
+            .changed();
+
+        // Shadow rays need a recursion depth of 2
+        ui.add_enabled_ui(params.max_ray_recursion_depth >= 2, |ui| {
+            changed |= ui
+                .add(
+                    egui::Slider::new(&mut params.sunlight.shadow_sample_count, 0..=16)
+                        .text("shadow samples"),
+                )
+                .changed();
+        });
+
+        ui.horizontal(|ui| {
+            changed |= ui
+                .color_edit_button_rgb(&mut *params.sunlight.horizon_color)
+                .changed();
+            ui.label("horizon");
+            changed |= ui
+                .color_edit_button_rgb(&mut params.sunlight.zenith_color)
+                .changed();
+            ui.label("zenith");
+        });
+
+        ui.heading("Exposure");
+
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut params.sunlight.exposure, 1.0 / 64.0..=64.0)
+                    .logarithmic(true)
+                    .text("exposure"),
+            )
+            .changed();
+
+        egui::ComboBox::from_label("tonemap")
+            .selected_text(TONEMAP_OPERATORS[params.sunlight.tonemap as usize])
+            .show_ui(ui, |ui| {
+                for (index, name) in TONEMAP_OPERATORS.iter().enumerate() {
+                    changed |= ui
+                        .selectable_value(&mut params.sunlight.tonemap, index as u32, *name)
+                        .changed();
+                }
+            });
+
+        ui.heading("Fog");
+
+        changed |= ui
+            .add(egui::Slider::new(&mut params.fog[0], 0.0..=100.0).text("start (m)"))
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut params.fog[1], 0.0..=1.0)
+                    .logarithmic(true)
+                    .text("density (1/m)"),
+            )
+            .changed();
+
+        ui.heading("Ambient occlusion");
+
+        // Ambient occlusion rays need a recursion depth of 2
+        ui.add_enabled_ui(params.max_ray_recursion_depth >= 2, |ui| {
+            changed |= ui
+                .checkbox(&mut *params.ambient_occlusion, "enabled")
+                .changed();
+        });
+        changed |= ui
+            .add(egui::Slider::new(&mut params.sunlight.ao_sample_count, 1..=16).text("samples"))
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut params.sunlight.ao_radius, 0.5..=32.0)
+                    .text("radius (voxels)"),
+            )
+            .changed();
+
+        ui.heading("Reflections");
+
+        changed |= ui
+            .add(
+                egui::Slider::new(
+                    &mut *params.max_bounces,
+                    0..=params.max_ray_recursion_depth - 1,
+                )
+                .text("bounces"),
+            )
+            .changed();
+
+        ui.heading("Material");
+
+        // Not parameter changes either, the app compares the values with the model's
+        ui.add(egui::Slider::new(&mut *params.material_index, 0..=255).text("palette index"));
+
+        ui.horizontal(|ui| {
+            let mut color = params.material_color.to_array();
+
+            if ui.color_edit_button_rgba_unmultiplied(&mut color).changed() {
+                *params.material_color = Vec4::from_array(color);
+            }
+            ui.label("color");
+
+            let mut emission = params.material.emission.to_array();
+
+            if ui.color_edit_button_rgb(&mut emission).changed() {
+                params.material.emission = Vec3::from_array(emission);
+            }
+            ui.label("emission");
+        });
+
+        ui.add(egui::Slider::new(&mut params.material.metallic, 0.0..=1.0).text("metallic"));
+        ui.add(egui::Slider::new(&mut params.material.roughness, 0.0..=1.0).text("roughness"));
+        ui.add(
+            egui::Slider::new(&mut params.material.emission_strength, 0.0..=64.0)
+                .text("emission strength"),
+        );
+
+        if ui.button("Save materials").clicked() {
+            *params.save_materials = true;
+        }
+
+        ui.heading("Secondary rays");
+
+        // Too small and the shadows show acne on flat faces, too large and they detach from
+        // the contact points
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut params.sunlight.surface_bias, 0.0001..=0.1)
+                    .logarithmic(true)
+                    .text("surface bias (voxels)"),
+            )
+            .changed();
+    });
+
+    changed
+}
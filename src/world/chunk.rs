@@ -1,136 +1,560 @@
 #![allow(unused)]
-use std::{collections::HashMap, fmt::Display};
-
-use dot_vox::DotVoxData;
-use glam::{IVec3, UVec3, Vec4, Vec4Swizzles};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    ops::Range,
+    path::Path,
+    sync::Arc,
+};
+
+use glam::{IVec3, Mat3, UVec3, Vec3, Vec4, Vec4Swizzles};
 use vulkano::{Packed24_8, acceleration_structure::AccelerationStructureInstance};
 
-use crate::world::{HostVoxel, loader::SceneGraphTraverser};
+use crate::world::{
+    HostVoxel,
+    frustum::Frustum,
+    loader::{ModelInstance, SceneGraphTraverser, VoxelSource},
+    streaming::ChunkLoader,
+};
 
 #[cfg(debug_assertions)]
-use super::Vertex3DColor;
+use super::{Vertex3DColor, box_lines};
 
-// The voxel length in meters
+// The voxel length in meters. World space is measured in voxels, so the instances aren't
+// scaled by it, values given in meters like the camera speed and fog are converted with it
 pub const VOXEL_PHYSICAL_LENGTH: f32 = 1.0 / 16.0;
 
-// The amount of voxels per chunk dimension
+// The default amount of voxels per chunk dimension
 pub const CHUNK_WIDTH: u32 = 64;
+// Local positions are encoded in a byte per axis by `Chunk::write_voxels`
+pub const MAX_CHUNK_WIDTH: u32 = u8::MAX as u32 + 1;
+
+// The amount of LODs a chunk can be rendered at, a voxel at LOD `n` is `2^n` voxels wide
+pub const LOD_COUNT: u32 = 4;
+
+// The amount of chunks rendered at full resolution around the camera, LOD `n` starts
+// `LOD_DISTANCE * (2^n - 1)` chunks away
+pub const LOD_DISTANCE: i32 = 4;
+
+// Material indices are stored in 16 bits, the largest value is reserved
+pub const MAX_MATERIAL_INDEX: u32 = u16::MAX as u32 - 1;
+// The amount of palette entries, the instances only keep the 8 low bits of the material index
+pub const PALETTE_SIZE: u32 = 256;
 
-// The amount of chunks in the world's X axis
+// The amount of chunks `Chunks::stream` brings in, evicts and requests per call
+pub const MAX_STREAMED_CHUNKS: usize = 8;
+
+// The default amount of chunks on each side of the origin in the world's X axis
 pub const WORLD_WIDTH: i32 = 64;
-// The amount of chunks in the world's Y axis
+// The default amount of chunks on each side of the origin in the world's Y axis
 pub const WORLD_HEIGHT: i32 = 64;
-// The amount of chunks in the world's Z axis
+// The default amount of chunks on each side of the origin in the world's Z axis
 pub const WORLD_DEPTH: i32 = 64;
 
-struct Bounds(i32, i32);
+// Packs the material index in the low 8 bits, the LOD in the next 4 bits and the chunk id in
+// the last 12 bits, matching `instance_material`, `instance_lod` and `instance_chunk` in the
+// shaders
+pub fn instance_custom_index(material_index: u32, lod: u32, chunk_id: u32) -> u32 {
+    (material_index & 0xFF) | ((lod & 0xF) << 8) | ((chunk_id & 0xFFF) << 12)
+}
+
+// The LOD of a chunk `distance` chunks away from the camera, each LOD spans twice as many
+// chunks as the previous one
+pub fn lod_for_distance(distance: i32) -> u32 {
+    ((distance.max(0) / LOD_DISTANCE) as u32 + 1)
+        .ilog2()
+        .min(LOD_COUNT - 1)
+}
+
+// Spreads the 10 low bits of `value` so that two zero bits follow each of them
+fn spread_bits(value: u32) -> u32 {
+    let mut value = value & 0x3FF;
+    value = (value | (value << 16)) & 0x0300_00FF;
+    value = (value | (value << 8)) & 0x0300_F00F;
+    value = (value | (value << 4)) & 0x030C_30C3;
+    value = (value | (value << 2)) & 0x0924_9249;
+
+    value
+}
+
+// The position along the Z-order curve of a position with 10 bits per axis
+pub fn morton_code(position: UVec3) -> u32 {
+    spread_bits(position.x) | (spread_bits(position.y) << 1) | (spread_bits(position.z) << 2)
+}
+
+// Orders the instances along the Z-order curve of their translation, so that neighbouring
+// instances are built into the same nodes of the TLAS. The translations are quantized to 10 bits
+// per axis over their bounds, instances with the same code keep their order
+pub fn sort_instances_spatially(instances: &mut [AccelerationStructureInstance]) {
+    let translation = |instance: &AccelerationStructureInstance| {
+        Vec3::new(
+            instance.transform[0][3],
+            instance.transform[1][3],
+            instance.transform[2][3],
+        )
+    };
+
+    let (min, max) = instances
+        .iter()
+        .map(translation)
+        .fold((Vec3::MAX, Vec3::MIN), |(min, max), translation| {
+            (min.min(translation), max.max(translation))
+        });
+    let scale = 1023.0 / (max - min).max(Vec3::ONE);
+
+    instances.sort_by_cached_key(|instance| {
+        morton_code(((translation(instance) - min) * scale).as_uvec3())
+    });
+}
+
+// The index of the first instance with a non-finite transform or one collapsing an axis, like
+// a zero scale. These corrupt the TLAS build without any error
+pub fn invalid_instance_transform(instances: &[AccelerationStructureInstance]) -> Option<usize> {
+    instances.iter().position(|instance| {
+        // The rows of the linear part, the transpose has the same determinant
+        let [x, y, z] = instance.transform.map(|row| Vec3::from_slice(&row));
+        let determinant = Mat3::from_cols(x, y, z).determinant();
+
+        instance
+            .transform
+            .as_flattened()
+            .iter()
+            .any(|v| !v.is_finite())
+            || determinant == 0.0
+    })
+}
+
+// The sorted and merged ranges of `current` that differ from `previous` or overlap one of
+// `dirty`, clamped to the length of `current`. Only these have to be written to the instance
+// buffer when it already holds `previous`
+pub fn dirty_instance_ranges<T: PartialEq>(
+    previous: &[T],
+    current: &[T],
+    dirty: &[Range<u64>],
+) -> Vec<Range<u64>> {
+    let len = current.len() as u64;
+
+    let mut ranges = dirty
+        .iter()
+        .map(|range| range.start.min(len)..range.end.min(len))
+        .filter(|range| !range.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut changed_start = None;
+
+    for (index, instance) in current.iter().enumerate() {
+        let index = index as u64;
+
+        match (
+            previous.get(index as usize) != Some(instance),
+            changed_start,
+        ) {
+            (true, None) => changed_start = Some(index),
+            (false, Some(start)) => {
+                ranges.push(start..index);
+                changed_start = None;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(start) = changed_start {
+        ranges.push(start..len);
+    }
+
+    ranges.sort_by_key(|range| range.start);
+
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+// A 12 bit hash of the chunk position, only used to tell neighbouring chunks apart
+pub fn chunk_id(grid_position: IVec3) -> u32 {
+    let hash = (grid_position.x as u32).wrapping_mul(73856093)
+        ^ (grid_position.y as u32).wrapping_mul(19349663)
+        ^ (grid_position.z as u32).wrapping_mul(83492791);
+
+    (hash ^ (hash >> 12) ^ (hash >> 24)) & 0xFFF
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorldSize {
+    // The amount of voxels per chunk dimension
+    pub chunk_width: u32,
+    // The grid position of the first chunk, per axis
+    pub min: IVec3,
+    // The grid position past the last chunk, per axis
+    pub max: IVec3,
+}
+
+impl Default for WorldSize {
+    fn default() -> Self {
+        Self::symmetric(
+            CHUNK_WIDTH,
+            IVec3::new(WORLD_WIDTH, WORLD_HEIGHT, WORLD_DEPTH),
+        )
+    }
+}
+
+impl WorldSize {
+    // `chunks` chunks on each side of the origin, per axis
+    pub fn symmetric(chunk_width: u32, chunks: IVec3) -> Self {
+        Self {
+            chunk_width,
+            min: -chunks,
+            max: chunks,
+        }
+    }
+
+    // Whether the world holds at least one voxel, in chunks that can be encoded
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_CHUNK_WIDTH).contains(&self.chunk_width) && self.max.cmpgt(self.min).all()
+    }
+
+    // The amount of chunks per axis
+    pub fn extent(&self) -> IVec3 {
+        self.max - self.min
+    }
+
+    // The first and last voxel positions of the world, per axis
+    pub fn voxel_bounds(&self) -> (IVec3, IVec3) {
+        let chunk_width = self.chunk_width as i32;
+
+        (self.min * chunk_width, self.max * chunk_width - 1)
+    }
 
-impl Bounds {
-    pub const fn inside(&self, value: i32) -> bool {
-        value > self.0 && value < self.1
+    // A voxel is in bounds when its chunk is, so the lower bound is inclusive
+    pub fn in_bounds(&self, position: &IVec3) -> bool {
+        let (min, max) = self.voxel_bounds();
+
+        position.cmpge(min).all() && position.cmple(max).all()
+    }
+
+    pub fn grid_in_bounds(&self, grid_position: &IVec3) -> bool {
+        grid_position.cmpge(self.min).all() && grid_position.cmplt(self.max).all()
     }
 }
 
+// Voxels are stored densely, the arrays are only allocated once the first voxel is inserted
 #[derive(Debug)]
 pub struct Chunk {
+    // Cleared by the occlusion culler
     visible: bool,
-    voxels: HashMap<UVec3, HostVoxel>,
+    // Cleared for the chunks past the visibility radius around the camera, see
+    // `Chunks::update_visibility_radius`
+    in_range: bool,
+    width: u32,
+    voxel_count: u32,
+    // One bit per voxel, set when the voxel is solid
+    occupancy: Box<[u64]>,
+    // The material index of each voxel plus one, 0 for empty voxels
+    materials: Box<[u16]>,
 }
 
 impl Default for Chunk {
     fn default() -> Self {
+        Chunk::new(CHUNK_WIDTH)
+    }
+}
+
+impl Chunk {
+    pub fn new(width: u32) -> Self {
         Chunk {
             visible: true,
-            voxels: HashMap::new(),
+            in_range: true,
+            width,
+            voxel_count: 0,
+            occupancy: Box::default(),
+            materials: Box::default(),
         }
     }
-}
 
-impl Chunk {
+    fn index(&self, position: &UVec3) -> usize {
+        (position.x + position.y * self.width + position.z * self.width * self.width) as usize
+    }
+
+    fn position(&self, index: usize) -> UVec3 {
+        let index = index as u32;
+
+        UVec3::new(
+            index % self.width,
+            index / self.width % self.width,
+            index / (self.width * self.width),
+        )
+    }
+
+    fn allocate(&mut self) {
+        let voxel_count = self.width.pow(3) as usize;
+
+        self.occupancy = vec![0; voxel_count.div_ceil(64)].into_boxed_slice();
+        self.materials = vec![0; voxel_count].into_boxed_slice();
+    }
+
+    fn occupied(&self, index: usize) -> bool {
+        self.occupancy
+            .get(index / 64)
+            .is_some_and(|word| word & (1 << (index % 64)) != 0)
+    }
+
+    // The dense storage doesn't keep the voxel scale, every voxel has a scale of 1
+    fn voxel_at(&self, index: usize) -> HostVoxel {
+        HostVoxel {
+            scale: 1.0,
+            material_index: self.materials[index] as u32 - 1,
+        }
+    }
+
+    pub fn voxel_count(&self) -> usize {
+        self.voxel_count as usize
+    }
+
+    pub fn get(&self, position: &UVec3) -> Option<HostVoxel> {
+        let index = self.index(position);
+
+        self.occupied(index).then(|| self.voxel_at(index))
+    }
+
+    // Iterates over the solid voxels, skipping empty words of the occupancy bitset
+    pub fn iter(&self) -> impl Iterator<Item = (UVec3, HostVoxel)> + '_ {
+        self.occupancy
+            .iter()
+            .enumerate()
+            .filter(|(_, word)| **word != 0)
+            .flat_map(|(word_index, word)| {
+                (0..64)
+                    .filter(move |bit| word & (1 << bit) != 0)
+                    .map(move |bit| word_index * 64 + bit)
+            })
+            .map(|index| (self.position(index), self.voxel_at(index)))
+    }
+
     pub fn set_visible(&mut self, value: bool) {
         self.visible = value;
     }
 
     pub fn empty(&self) -> bool {
-        self.voxels.is_empty()
+        self.voxel_count == 0
+    }
+
+    pub fn set_in_range(&mut self, value: bool) {
+        self.in_range = value;
     }
 
+    // Hidden chunks are left out of the instances
     pub fn visible(&self) -> bool {
-        self.visible
+        self.visible && self.in_range
     }
 
     pub fn contains(&self, position: &UVec3) -> bool {
-        self.voxels.contains_key(position)
+        self.occupied(self.index(position))
+    }
+
+    // The amount of instances `to_instances` emits at the LOD
+    pub fn instance_count(&self, lod: u32) -> usize {
+        self.merged_boxes(lod).len()
+    }
+
+    // The first solid voxel of the cell at the LOD. A cell is solid as soon as one of its voxels
+    // is, so thin surfaces don't open gaps next to chunks rendered at a finer LOD
+    fn sample_cell(&self, cell: UVec3, stride: u32) -> Option<HostVoxel> {
+        if stride == 1 {
+            return self.get(&cell);
+        }
+
+        let min = cell * stride;
+        let max = (min + stride).min(UVec3::splat(self.width));
+
+        (min.z..max.z)
+            .flat_map(|z| (min.y..max.y).flat_map(move |y| (min.x..max.x).map(move |x| (x, y, z))))
+            .find_map(|(x, y, z)| self.get(&UVec3::new(x, y, z)))
+    }
+
+    // Greedily merges the voxels sampled at the LOD into boxes of equal voxels, each box is
+    // returned as its minimum cell, its extent in cells and its voxel
+    fn merged_boxes(&self, lod: u32) -> Vec<(UVec3, UVec3, HostVoxel)> {
+        let stride = 2u32.pow(lod);
+        let width = self.width.div_ceil(stride);
+
+        let index = |cell: UVec3| (cell.x + cell.y * width + cell.z * width * width) as usize;
+
+        let mut cells: Vec<Option<HostVoxel>> = vec![None; width.pow(3) as usize];
+
+        if !self.empty() {
+            for z in 0..width {
+                for y in 0..width {
+                    for x in 0..width {
+                        let cell = UVec3::new(x, y, z);
+
+                        cells[index(cell)] = self.sample_cell(cell, stride);
+                    }
+                }
+            }
+        }
+
+        let mut boxes = Vec::new();
+
+        for z in 0..width {
+            for y in 0..width {
+                for x in 0..width {
+                    let min = UVec3::new(x, y, z);
+
+                    let Some(voxel) = cells[index(min)] else {
+                        continue;
+                    };
+
+                    // Merged cells are cleared, so they can't be part of another box
+                    let matches = |cells: &[Option<HostVoxel>], cell: UVec3| {
+                        cells[index(cell)] == Some(voxel)
+                    };
+
+                    let mut extent = UVec3::ONE;
+
+                    while min.x + extent.x < width
+                        && matches(&cells, min + UVec3::new(extent.x, 0, 0))
+                    {
+                        extent.x += 1;
+                    }
+
+                    while min.y + extent.y < width
+                        && (0..extent.x)
+                            .all(|dx| matches(&cells, min + UVec3::new(dx, extent.y, 0)))
+                    {
+                        extent.y += 1;
+                    }
+
+                    while min.z + extent.z < width
+                        && (0..extent.x).all(|dx| {
+                            (0..extent.y)
+                                .all(|dy| matches(&cells, min + UVec3::new(dx, dy, extent.z)))
+                        })
+                    {
+                        extent.z += 1;
+                    }
+
+                    for dz in 0..extent.z {
+                        for dy in 0..extent.y {
+                            for dx in 0..extent.x {
+                                cells[index(min + UVec3::new(dx, dy, dz))] = None;
+                            }
+                        }
+                    }
+
+                    boxes.push((min, extent, voxel));
+                }
+            }
+        }
+
+        boxes
     }
 
     pub fn to_instances(
         &self,
         lod: u32,
         grid_position: IVec3,
-        acceleration_structure_reference: u64,
+        acceleration_structure_references: &[u64],
+        translucent_materials: &[bool],
     ) -> Vec<AccelerationStructureInstance> {
+        // The BLAS of each LOD is already `lod_exponent` voxels wide, it is scaled by the extent
+        // of the merged box and centered on the voxels it covers. Transforms are in voxels, not
+        // meters
         let lod_exponent = 2u32.pow(lod);
-        let offset: f32 = (0..lod).map(|sublod| sublod as f32 / 2.0).sum();
-
-        self.voxels
-            .iter()
-            .filter_map(|(local_position, voxel)| {
-                if self.voxels.contains_key(local_position)
-                    && local_position.x % lod_exponent == 0
-                    && local_position.y % lod_exponent == 0
-                    && local_position.z % lod_exponent == 0
-                {
-                    Some(AccelerationStructureInstance {
-                        acceleration_structure_reference,
-                        instance_custom_index_and_mask: Packed24_8::new(
-                            voxel.material_index,
-                            if self.visible { 0xFF } else { 0x00 },
-                        ),
-                        transform: [
-                            [
-                                voxel.scale * lod_exponent as f32,
-                                0.0,
-                                0.0,
-                                (CHUNK_WIDTH as i32 * grid_position.x + local_position.x as i32)
-                                    as f32
-                                    + offset,
-                            ],
-                            [
-                                0.0,
-                                voxel.scale * lod_exponent as f32,
-                                0.0,
-                                (CHUNK_WIDTH as i32 * grid_position.y + local_position.y as i32)
-                                    as f32
-                                    + offset,
-                            ],
-                            [
-                                0.0,
-                                0.0,
-                                voxel.scale * lod_exponent as f32,
-                                (CHUNK_WIDTH as i32 * grid_position.z + local_position.z as i32)
-                                    as f32
-                                    + offset,
-                            ],
-                        ],
-                        ..Default::default()
-                    })
-                } else {
-                    None
+        let offset = (lod_exponent - 1) as f32 / 2.0;
+
+        let chunk_origin = grid_position * self.width as i32;
+        let chunk_id = chunk_id(grid_position);
+
+        self.merged_boxes(lod)
+            .into_iter()
+            .map(|(min, extent, voxel)| {
+                let scale = extent.as_vec3() * voxel.scale;
+                let center = (chunk_origin + (min * lod_exponent).as_ivec3()).as_vec3()
+                    + offset
+                    + (extent - 1).as_vec3() * lod_exponent as f32 / 2.0;
+
+                // The translucent BLAS follow the opaque ones
+                let translucent = translucent_materials
+                    .get(voxel.material_index as usize)
+                    .is_some_and(|translucent| *translucent);
+                let acceleration_structure_reference = acceleration_structure_references
+                    [lod as usize + translucent as usize * LOD_COUNT as usize];
+
+                AccelerationStructureInstance {
+                    acceleration_structure_reference,
+                    instance_custom_index_and_mask: Packed24_8::new(
+                        instance_custom_index(voxel.material_index, lod, chunk_id),
+                        if self.visible() { 0xFF } else { 0x00 },
+                    ),
+                    transform: [
+                        [scale.x, 0.0, 0.0, center.x],
+                        [0.0, scale.y, 0.0, center.y],
+                        [0.0, 0.0, scale.z, center.z],
+                    ],
+                    ..Default::default()
                 }
             })
             .collect()
     }
 
     pub fn insert(&mut self, position: UVec3, voxel: HostVoxel) -> bool {
-        if position.x >= CHUNK_WIDTH || position.y >= CHUNK_WIDTH || position.z >= CHUNK_WIDTH {
+        if position.x >= self.width || position.y >= self.width || position.z >= self.width {
             panic!("Inserted voxel outside of chunk bounds: {position}");
         }
 
-        if self.voxels.contains_key(&position) {
+        assert!(
+            voxel.material_index <= MAX_MATERIAL_INDEX,
+            "Material index too large: {}",
+            voxel.material_index
+        );
+
+        if self.occupancy.is_empty() {
+            self.allocate();
+        }
+
+        let index = self.index(&position);
+
+        if self.occupied(index) {
             return false;
         }
 
-        self.voxels.insert(position, voxel).is_none()
+        self.occupancy[index / 64] |= 1 << (index % 64);
+        self.materials[index] = voxel.material_index as u16 + 1;
+        self.voxel_count += 1;
+
+        true
+    }
+
+    // Like `insert`, but an existing voxel is replaced
+    pub fn set(&mut self, position: UVec3, voxel: HostVoxel) {
+        if !self.insert(position, voxel) {
+            let index = self.index(&position);
+            self.materials[index] = voxel.material_index as u16 + 1;
+        }
+    }
+
+    pub fn remove(&mut self, position: &UVec3) -> Option<HostVoxel> {
+        let index = self.index(position);
+
+        if !self.occupied(index) {
+            return None;
+        }
+
+        let voxel = self.voxel_at(index);
+
+        self.occupancy[index / 64] &= !(1 << (index % 64));
+        self.materials[index] = 0;
+        self.voxel_count -= 1;
+
+        Some(voxel)
     }
 
     #[cfg(debug_assertions)]
@@ -143,192 +567,234 @@ impl Chunk {
             [1.0, 0.0, 0.0, 1.0]
         };
 
-        let origin = grid_position * CHUNK_WIDTH as i32;
-        let origin_array = origin.to_array();
-        let origin_array = [
-            origin_array[0] as f32 - 0.5,
-            origin_array[1] as f32 - 0.5,
-            origin_array[2] as f32 - 0.5,
-        ];
+        let origin = (grid_position * self.width as i32).as_vec3() - 0.5;
 
-        let width = CHUNK_WIDTH as f32;
+        box_lines(origin, self.width as f32, color)
+    }
+}
 
-        let dlf = origin_array;
-        let dlb = [origin_array[0], origin_array[1], origin_array[2] + width];
-        let drf = [origin_array[0] + width, origin_array[1], origin_array[2]];
-        let drb = [
-            origin_array[0] + width,
-            origin_array[1],
-            origin_array[2] + width,
-        ];
+pub type ChunksInner = HashMap<IVec3, Chunk>;
 
-        let ulf = [origin_array[0], origin_array[1] + width, origin_array[2]];
-        let ulb = [
-            origin_array[0],
-            origin_array[1] + width,
-            origin_array[2] + width,
-        ];
-        let urf = [
-            origin_array[0] + width,
-            origin_array[1] + width,
-            origin_array[2],
-        ];
-        let urb = [
-            origin_array[0] + width,
-            origin_array[1] + width,
-            origin_array[2] + width,
-        ];
+// Identifies world files written by `Chunks::save`
+const WORLD_FILE_MAGIC: &[u8; 4] = b"ATLW";
+// Bumped whenever the layout of world files changes, version 1 files have the default size
+const WORLD_FILE_VERSION: u32 = 3;
+
+// The result of `Chunks::raycast`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    // The first solid voxel hit by the ray
+    pub position: IVec3,
+    pub material_index: u32,
+    // The normal of the face the ray entered the voxel through, zero when it starts inside
+    pub normal: IVec3,
+    // Along the ray, to the entered face
+    pub distance: f32,
+}
 
-        vec![
-            // bottom
-            Vertex3DColor {
-                position: dlf,
-                color,
-            },
-            Vertex3DColor {
-                position: dlb,
-                color,
-            },
-            Vertex3DColor {
-                position: dlb,
-                color,
-            },
-            Vertex3DColor {
-                position: drb,
-                color,
-            },
-            Vertex3DColor {
-                position: drb,
-                color,
-            },
-            Vertex3DColor {
-                position: drf,
-                color,
-            },
-            Vertex3DColor {
-                position: drf,
-                color,
-            },
-            Vertex3DColor {
-                position: dlf,
-                color,
-            },
-            // up
-            Vertex3DColor {
-                position: ulf,
-                color,
-            },
-            Vertex3DColor {
-                position: ulb,
-                color,
-            },
-            Vertex3DColor {
-                position: ulb,
-                color,
-            },
-            Vertex3DColor {
-                position: urb,
-                color,
-            },
-            Vertex3DColor {
-                position: urb,
-                color,
-            },
-            Vertex3DColor {
-                position: urf,
-                color,
-            },
-            Vertex3DColor {
-                position: urf,
-                color,
-            },
-            Vertex3DColor {
-                position: ulf,
-                color,
-            },
-            // sides
-            Vertex3DColor {
-                position: dlf,
-                color,
-            },
-            Vertex3DColor {
-                position: ulf,
-                color,
-            },
-            Vertex3DColor {
-                position: dlb,
-                color,
-            },
-            Vertex3DColor {
-                position: ulb,
-                color,
-            },
-            Vertex3DColor {
-                position: drf,
-                color,
-            },
-            Vertex3DColor {
-                position: urf,
-                color,
-            },
-            Vertex3DColor {
-                position: drb,
-                color,
-            },
-            Vertex3DColor {
-                position: urb,
-                color,
-            },
-        ]
-    }
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WorldStats {
+    pub chunks: usize,
+    pub voxels: usize,
+    // Instances `to_instances` emits at the LOD, before the instance limit
+    pub instances: usize,
+    // Size of the instance buffer holding them
+    pub instance_bytes: u64,
 }
 
-pub type ChunksInner = HashMap<IVec3, Chunk>;
+impl Display for WorldStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "chunks: {}, voxels: {}, instances: {}, instance buffer: {:.2} MiB",
+            self.chunks,
+            self.voxels,
+            self.instances,
+            self.instance_bytes as f64 / (1024.0 * 1024.0)
+        )
+    }
+}
 
+// Chunks are only created once a voxel is inserted in them
 #[derive(Default)]
 pub struct Chunks {
+    // Only holds chunks with voxels, a chunk is dropped with its last voxel. The chunk queries
+    // and `to_instances` walk it, so they never visit the empty parts of the world
     inner: ChunksInner,
+    size: WorldSize,
+    // Every model of the loaded files with the frames it is shown in, replayed when the
+    // animation frame changes
+    animation: Vec<ModelInstance>,
+    animation_frame: u32,
+    // Chunks streamed out by `stream`, encoded like in world files. Voxel queries don't see
+    // them, edits decode them first
+    evicted: HashMap<IVec3, Arc<[u8]>>,
+    // Evicted chunks being decoded by the loader, with the id of their request
+    pending: HashMap<IVec3, u64>,
+    next_request: u64,
+    loader: Option<ChunkLoader>,
+    // Indexed by material, the voxels of these materials get the translucent BLAS. Every
+    // material is opaque until `set_translucent_materials` is called
+    translucent_materials: Vec<bool>,
+    // The camera chunk and radius of the last `update_visibility_radius`, the chunks created
+    // since are hidden by it too
+    visibility_radius: Option<(IVec3, u32)>,
+}
+
+// Whether the chunk is within the radius of the camera chunk, or there's no radius
+fn in_visibility_radius(visibility_radius: Option<(IVec3, u32)>, grid_position: IVec3) -> bool {
+    visibility_radius.is_none_or(|(camera_chunk, radius)| {
+        grid_position
+            .as_i64vec3()
+            .distance_squared(camera_chunk.as_i64vec3()) as u64
+            <= radius as u64 * radius as u64
+    })
 }
 
 impl Chunks {
-    const X_BOUNDS: Bounds = Bounds(
-        -WORLD_WIDTH * CHUNK_WIDTH as i32,
-        WORLD_WIDTH * CHUNK_WIDTH as i32,
-    );
+    pub fn with_size(size: WorldSize) -> Self {
+        assert!(
+            (1..=MAX_CHUNK_WIDTH).contains(&size.chunk_width),
+            "Chunks must be 1 to {MAX_CHUNK_WIDTH} voxels wide"
+        );
+        assert!(
+            size.max.cmpgt(size.min).all(),
+            "The world must be at least one chunk wide: {size:?}"
+        );
 
-    const Y_BOUNDS: Bounds = Bounds(
-        -WORLD_HEIGHT * CHUNK_WIDTH as i32,
-        WORLD_HEIGHT * CHUNK_WIDTH as i32,
-    );
+        Self {
+            inner: ChunksInner::new(),
+            size,
+            animation: vec![],
+            animation_frame: 0,
+            evicted: HashMap::new(),
+            pending: HashMap::new(),
+            next_request: 0,
+            loader: None,
+            translucent_materials: vec![],
+            visibility_radius: None,
+        }
+    }
 
-    const Z_BOUNDS: Bounds = Bounds(
-        -WORLD_DEPTH * CHUNK_WIDTH as i32,
-        WORLD_DEPTH * CHUNK_WIDTH as i32,
-    );
+    pub fn size(&self) -> WorldSize {
+        self.size
+    }
 
-    const fn in_bounds(position: &IVec3) -> bool {
-        Chunks::X_BOUNDS.inside(position.x)
-            && Chunks::Y_BOUNDS.inside(position.y)
-            && Chunks::Z_BOUNDS.inside(position.z)
+    fn in_bounds(&self, position: &IVec3) -> bool {
+        self.size.in_bounds(position)
     }
 
-    fn distance_to_chunk(grid_position: &IVec3, position: &IVec3) -> i32 {
-        (position / CHUNK_WIDTH as i32)
+    fn distance_to_chunk(&self, grid_position: &IVec3, position: &IVec3) -> i32 {
+        (position / self.size.chunk_width as i32)
             .distance_squared(*grid_position)
             .isqrt()
     }
 
-    fn create_empty_chunks() -> ChunksInner {
-        (-WORLD_WIDTH..WORLD_WIDTH)
-            .flat_map(move |x| {
-                (-WORLD_HEIGHT..WORLD_HEIGHT).flat_map(move |y| {
-                    (-WORLD_DEPTH..WORLD_DEPTH)
-                        .map(move |z| (IVec3::new(x, y, z), Chunk::default()))
+    // World space bounds of a chunk, padded by the size of a voxel at the given LOD
+    pub fn chunk_aabb(&self, grid_position: &IVec3, lod: u32) -> (Vec3, Vec3) {
+        let padding = 2u32.pow(lod) as f32;
+        let chunk_width = self.size.chunk_width;
+        let origin = (grid_position * chunk_width as i32).as_vec3();
+
+        (
+            origin - padding,
+            origin + Vec3::splat(chunk_width as f32 + padding),
+        )
+    }
+
+    // World space bounds of the non-empty chunks, `None` for an empty world
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        let chunk_width = self.size.chunk_width as i32;
+
+        self.non_empty_chunks()
+            .map(|grid_position| {
+                let min = grid_position * chunk_width;
+
+                (min, min + chunk_width)
+            })
+            .reduce(|(min, max), (chunk_min, chunk_max)| (min.min(chunk_min), max.max(chunk_max)))
+            .map(|(min, max)| (min.as_vec3(), max.as_vec3()))
+    }
+
+    // The center of the chunk holding the most voxels, `None` for an empty world. Ties go to the
+    // lowest grid position so the result doesn't depend on the map order
+    pub fn densest_chunk_center(&self) -> Option<Vec3> {
+        let (grid_position, _) = self
+            .inner
+            .iter()
+            .filter(|(_, c)| !c.empty())
+            .max_by_key(|(p, c)| (c.voxel_count(), std::cmp::Reverse(p.to_array())))?;
+
+        let chunk_width = self.size.chunk_width as f32;
+
+        Some(grid_position.as_vec3() * chunk_width + chunk_width / 2.0)
+    }
+
+    pub fn non_empty_chunks(&self) -> impl Iterator<Item = &IVec3> {
+        self.inner
+            .iter()
+            .filter(|(_, c)| !c.empty())
+            .map(|(p, _)| p)
+    }
+
+    // Every voxel with its world position, in no particular order. The voxels are stored as
+    // material indices so they are yielded by value. Streamed out chunks are skipped like in
+    // the other voxel queries
+    pub fn iter_voxels(&self) -> impl Iterator<Item = (IVec3, HostVoxel)> + '_ {
+        self.inner
+            .iter()
+            .filter(|(_, chunk)| !chunk.empty())
+            .flat_map(move |(grid_position, chunk)| {
+                chunk.iter().map(move |(local_position, voxel)| {
+                    (
+                        self.position_to_translation(grid_position, &local_position),
+                        voxel,
+                    )
                 })
             })
+    }
+
+    // World space bounds of the merged boxes at least `min_extent` voxels wide on every axis,
+    // these are fully solid and can hide what's behind them
+    pub fn occluders(&self, min_extent: u32) -> Vec<(Vec3, Vec3)> {
+        self.inner
+            .iter()
+            .flat_map(|(grid_position, chunk)| {
+                let origin = (grid_position * self.size.chunk_width as i32).as_vec3();
+
+                chunk
+                    .merged_boxes(0)
+                    .into_iter()
+                    .filter(move |(_, extent, _)| extent.min_element() >= min_extent)
+                    .map(move |(min, extent, _)| {
+                        let min = origin + min.as_vec3() - 0.5;
+
+                        (min, min + extent.as_vec3())
+                    })
+            })
             .collect()
     }
 
+    // Counts the chunks, voxels and instances without building the instances, the LOD of each
+    // chunk is picked from `origin` like in `to_instances`
+    pub fn stats(&self, origin: &IVec3) -> WorldStats {
+        let instances = self
+            .active_chunks()
+            .map(|grid_position| {
+                let lod = lod_for_distance(self.distance_to_chunk(grid_position, origin));
+
+                self.inner[grid_position].instance_count(lod)
+            })
+            .sum::<usize>();
+
+        WorldStats {
+            chunks: self.non_empty_chunks().count(),
+            voxels: self.inner.values().map(Chunk::voxel_count).sum(),
+            instances,
+            instance_bytes: (instances * size_of::<AccelerationStructureInstance>()) as u64,
+        }
+    }
+
     pub fn active_chunks(&self) -> impl Iterator<Item = &IVec3> {
         self.inner
             .iter()
@@ -336,32 +802,19 @@ impl Chunks {
             .map(|(p, _)| p)
     }
 
-    fn translation_to_position(position: &IVec3) -> (IVec3, UVec3) {
-        if !Chunks::in_bounds(position) {
-            panic!("Out of bounds: {position}");
-        }
-
-        let IVec3 { x, y, z } = position;
+    // The chunk holding the voxel at `position`, also past the bounds of the world
+    pub fn chunk_position(&self, position: &IVec3) -> IVec3 {
+        position.div_euclid(IVec3::splat(self.size.chunk_width as i32))
+    }
 
-        let chunk_width = CHUNK_WIDTH as i32;
+    // The chunk and the position within it of a voxel, `None` outside of the world
+    fn translation_to_position(&self, position: &IVec3) -> Option<(IVec3, UVec3)> {
+        if !self.in_bounds(position) {
+            return None;
+        }
 
-        let grid_position = IVec3::new(
-            if x.is_negative() {
-                -chunk_width + x + 1
-            } else {
-                *x
-            } / chunk_width,
-            if y.is_negative() {
-                -chunk_width + y + 1
-            } else {
-                *y
-            } / chunk_width,
-            if z.is_negative() {
-                -chunk_width + z + 1
-            } else {
-                *z
-            } / chunk_width,
-        );
+        let chunk_width = self.size.chunk_width as i32;
+        let grid_position = self.chunk_position(position);
 
         let chunk_min_corner = grid_position * chunk_width;
         let IVec3 { x, y, z } = position - chunk_min_corner;
@@ -377,241 +830,2116 @@ impl Chunks {
 
         let local_position = UVec3::new(x as u32, y as u32, z as u32);
 
-        (grid_position, local_position)
+        Some((grid_position, local_position))
     }
 
-    pub fn new(voxel_data: &DotVoxData) -> Self {
-        let mut chunks = Chunks::create_empty_chunks();
-
-        let mut loader = SceneGraphTraverser {
-            chunks: &mut chunks,
-            scene: voxel_data,
-            models: vec![],
-        };
+    // The world position of a voxel of a chunk, the inverse of `translation_to_position`
+    fn position_to_translation(&self, grid_position: &IVec3, local_position: &UVec3) -> IVec3 {
+        grid_position * self.size.chunk_width as i32 + local_position.as_ivec3()
+    }
 
-        loader.traverse();
-
-        for (translation, rotation, size, voxels) in loader.models {
-            let transform = SceneGraphTraverser::to_transform(translation, rotation, size);
-
-            for voxel in voxels {
-                let local_position =
-                    UVec3::new(voxel.x as u32, voxel.z as u32, size.y - voxel.y as u32 - 1)
-                        .as_ivec3();
-
-                let position = (transform
-                    * Vec4::new(
-                        local_position.x as f32,
-                        local_position.y as f32,
-                        local_position.z as f32,
-                        1.0,
-                    ))
-                .xyz()
-                .as_ivec3();
-
-                let p = IVec3::new(position.x, -position.y, -position.z);
-
-                Chunks::insert_voxel(
-                    &mut chunks,
-                    p,
-                    HostVoxel {
-                        scale: 1.0,
-                        material_index: voxel.i.into(),
-                    },
-                );
-            }
-        }
+    pub fn new(source: &dyn VoxelSource, size: WorldSize) -> Self {
+        let mut chunks = Chunks::with_size(size);
+        chunks.add_model(source, IVec3::ZERO);
+        chunks.set_translucent_materials(&source.palette());
 
-        Self { inner: chunks }
+        chunks
     }
 
-    fn from(inner: ChunksInner) -> Self {
-        Self { inner }
-    }
+    // Loads the scene of another file with its origin at `offset`. Models are placed in loading
+    // order and overwrite the voxels already there, voxels outside of the world are dropped.
+    // The voxels of the source outside of its models are inserted as is and aren't replayed by
+    // `set_animation_frame`
+    pub fn add_model(&mut self, source: &dyn VoxelSource, offset: IVec3) {
+        for (position, material_index) in source.voxels() {
+            self.set_voxel(
+                offset + position,
+                HostVoxel {
+                    scale: 1.0,
+                    material_index: material_index.into(),
+                },
+            );
+        }
 
-    #[cfg(debug_assertions)]
-    pub fn debug_lines(&self) -> Vec<Vertex3DColor> {
-        self.inner
-            .iter()
-            .filter(|(_, c)| !c.empty())
-            .flat_map(|(grid_position, chunk)| chunk.debug_lines(*grid_position))
-            .collect()
-    }
+        let mut models = source.models();
 
-    pub fn to_instances(
-        &self,
-        lod: u32,
-        origin: &IVec3,
-        acceleration_structure_reference: u64,
-        max_instance_count: u64,
-    ) -> Vec<AccelerationStructureInstance> {
-        let mut chunks = self.active_chunks().collect::<Vec<_>>();
+        for model in &mut models {
+            model.offset = offset;
+        }
 
-        chunks.sort_by(|a, b| {
-            let distance_a = Chunks::distance_to_chunk(a, origin);
-            let distance_b = Chunks::distance_to_chunk(b, origin);
+        let frame = self.animation_frame;
 
-            distance_a.cmp(&distance_b)
-        });
+        for model in models.iter().filter(|model| model.frames.contains(&frame)) {
+            self.insert_model(model);
+        }
 
-        chunks
-            .iter()
-            .map(|grid_position| (grid_position, self.inner.get(grid_position).unwrap()))
-            .flat_map(|(grid_position, chunk)| {
-                chunk.to_instances(lod, **grid_position, acceleration_structure_reference)
-            })
-            .take(max_instance_count as usize)
-            .collect()
+        // Kept even for static files, they have to be replayed once another file is animated
+        self.animation.extend(models);
     }
 
-    pub fn set_chunk_visibility(&mut self, grid_position: IVec3, visible: bool) {
-        self.inner
-            .get_mut(&grid_position)
-            .unwrap()
-            .set_visible(visible);
+    fn insert_model(&mut self, model: &ModelInstance) {
+        let size = model.size;
+        let transform = SceneGraphTraverser::to_transform(model.translation, model.rotation, size);
+
+        for voxel in &model.voxels {
+            let local_position =
+                UVec3::new(voxel.x as u32, voxel.z as u32, size.y - voxel.y as u32 - 1).as_ivec3();
+
+            let position = (transform
+                * Vec4::new(
+                    local_position.x as f32,
+                    local_position.y as f32,
+                    local_position.z as f32,
+                    1.0,
+                ))
+            .xyz()
+            .as_ivec3();
+
+            let p = model.offset + IVec3::new(position.x, -position.y, -position.z);
+
+            self.set_voxel(
+                p,
+                HostVoxel {
+                    scale: 1.0,
+                    material_index: voxel.i.into(),
+                },
+            );
+        }
+    }
+
+    // Number of animation frames of the loaded scene, 1 when it is static
+    pub fn animation_frame_count(&self) -> u32 {
+        self.animation
+            .iter()
+            .flat_map(|model| [model.frames.start, model.frames.end])
+            .filter(|frame| *frame != u32::MAX)
+            .max()
+            .map_or(1, |last| last + 1)
+    }
+
+    pub fn animation_frame(&self) -> u32 {
+        self.animation_frame
+    }
+
+    // Replaces the voxels with the models shown at `frame`, wrapping around the frame count.
+    // Edited voxels are lost. Returns whether the world changed
+    pub fn set_animation_frame(&mut self, frame: u32) -> bool {
+        let frame = frame % self.animation_frame_count();
+
+        if self.animation.is_empty() || frame == self.animation_frame {
+            return false;
+        }
+
+        self.animation_frame = frame;
+        self.inner.clear();
+        self.evicted.clear();
+        self.pending.clear();
+
+        let animation = std::mem::take(&mut self.animation);
+
+        for model in animation
+            .iter()
+            .filter(|model| model.frames.contains(&frame))
+        {
+            self.insert_model(model);
+        }
+
+        self.animation = animation;
+
+        true
+    }
+
+    // Evicts the chunks farther than `radius` chunks from `origin`, by the distance
+    // `to_instances` picks LODs with, and brings back the evicted chunks inside of it. At most
+    // `MAX_STREAMED_CHUNKS` chunks come in, go out and are requested per call, so crossing a
+    // chunk boundary is spread over a few frames. Returns whether the resident chunks changed
+    pub fn stream(&mut self, origin: &IVec3, radius: u32) -> bool {
+        let radius = radius as i32;
+        let mut changed = false;
+
+        let loader = self
+            .loader
+            .get_or_insert_with(|| ChunkLoader::new(self.size.chunk_width));
+
+        for (grid_position, id, mut chunk) in loader.results().take(MAX_STREAMED_CHUNKS) {
+            // The chunk was restored by an edit or evicted again since
+            if self.pending.get(&grid_position) != Some(&id) {
+                continue;
+            }
+
+            self.pending.remove(&grid_position);
+            self.evicted.remove(&grid_position);
+
+            // The loader holds `self`, so the radius is applied like in `insert_chunk`
+            chunk.set_in_range(in_visibility_radius(self.visibility_radius, grid_position));
+            self.inner.insert(grid_position, chunk);
+            changed = true;
+        }
+
+        // One chunk of margin, so the chunks on the boundary aren't evicted and brought back as
+        // the camera moves back and forth
+        let mut far = self
+            .inner
+            .keys()
+            .map(|grid_position| {
+                (
+                    *grid_position,
+                    self.distance_to_chunk(grid_position, origin),
+                )
+            })
+            .filter(|(_, distance)| *distance > radius + 1)
+            .collect::<Vec<_>>();
+
+        far.sort_by_key(|(_, distance)| std::cmp::Reverse(*distance));
+
+        for (grid_position, _) in far.into_iter().take(MAX_STREAMED_CHUNKS) {
+            let chunk = self.inner.remove(&grid_position).unwrap();
+
+            if chunk.empty() {
+                continue;
+            }
+
+            let mut voxels = vec![];
+            chunk.write_voxels(&mut voxels).unwrap();
+
+            self.evicted.insert(grid_position, voxels.into());
+            changed = true;
+        }
+
+        let mut near = self
+            .evicted
+            .keys()
+            .filter(|grid_position| !self.pending.contains_key(grid_position))
+            .map(|grid_position| {
+                (
+                    *grid_position,
+                    self.distance_to_chunk(grid_position, origin),
+                )
+            })
+            .filter(|(_, distance)| *distance <= radius)
+            .collect::<Vec<_>>();
+
+        near.sort_by_key(|(_, distance)| *distance);
+
+        let loader = self.loader.as_ref().unwrap();
+
+        for (grid_position, _) in near.into_iter().take(MAX_STREAMED_CHUNKS) {
+            self.next_request += 1;
+            self.pending.insert(grid_position, self.next_request);
+
+            loader.request(
+                grid_position,
+                self.next_request,
+                self.evicted[&grid_position].clone(),
+            );
+        }
+
+        changed
+    }
+
+    // The voxels of a chunk with their local position and material, one per line, for debugging
+    // the loader and the chunking. Chunks out of the world, without voxels or streamed out are
+    // reported as such
+    pub fn debug_dump_chunk(&self, grid_position: IVec3) -> String {
+        if !self.size.grid_in_bounds(&grid_position) {
+            return format!("Chunk {grid_position}: out of the world\n");
+        }
+
+        if let Some(voxels) = self.evicted.get(&grid_position) {
+            return format!(
+                "Chunk {grid_position}: streamed out, {} encoded bytes\n",
+                voxels.len()
+            );
+        }
+
+        let Some(chunk) = self
+            .inner
+            .get(&grid_position)
+            .filter(|chunk| !chunk.empty())
+        else {
+            return format!("Chunk {grid_position}: empty\n");
+        };
+
+        let mut dump = format!(
+            "Chunk {grid_position}: voxels: {}, visible: {}, occluded: {}, in range: {}\n",
+            chunk.voxel_count(),
+            chunk.visible(),
+            !chunk.visible,
+            chunk.in_range
+        );
+
+        for (local_position, voxel) in chunk.iter() {
+            dump += &format!("  {local_position}: material {}\n", voxel.material_index);
+        }
+
+        dump
+    }
+
+    // Number of chunks streamed out by `stream`
+    pub fn evicted_chunk_count(&self) -> usize {
+        self.evicted.len()
+    }
+
+    // Decodes an evicted chunk right away, before it is edited
+    fn restore_chunk(&mut self, grid_position: &IVec3) {
+        let Some(voxels) = self.evicted.remove(grid_position) else {
+            return;
+        };
+
+        self.pending.remove(grid_position);
+
+        let chunk = Chunk::read_voxels(&mut &voxels[..], self.size.chunk_width)
+            .expect("evicted chunks are encoded by write_voxels");
+
+        self.insert_chunk(*grid_position, chunk);
+    }
+
+    fn chunk_or_insert(&mut self, grid_position: IVec3) -> &mut Chunk {
+        if !self.inner.contains_key(&grid_position) {
+            self.insert_chunk(grid_position, Chunk::new(self.size.chunk_width));
+        }
+
+        self.inner.get_mut(&grid_position).unwrap()
+    }
+
+    // Chunks created after the last `update_visibility_radius` get its radius right away
+    fn insert_chunk(&mut self, grid_position: IVec3, mut chunk: Chunk) {
+        chunk.set_in_range(in_visibility_radius(self.visibility_radius, grid_position));
+
+        self.inner.insert(grid_position, chunk);
+    }
+
+    #[cfg(debug_assertions)]
+    pub fn debug_lines(&self) -> Vec<Vertex3DColor> {
+        self.inner
+            .iter()
+            .filter(|(_, c)| !c.empty())
+            .flat_map(|(grid_position, chunk)| chunk.debug_lines(*grid_position))
+            .collect()
+    }
+
+    // The LOD of each chunk is picked by `lod_for_distance` from its distance to `origin`
+    pub fn to_instances(
+        &self,
+        origin: &IVec3,
+        acceleration_structure_references: &[u64],
+        max_instance_count: u64,
+        frustum: Option<&Frustum>,
+    ) -> Vec<AccelerationStructureInstance> {
+        let mut chunks = self
+            .active_chunks()
+            .map(|grid_position| (grid_position, self.distance_to_chunk(grid_position, origin)))
+            .filter(|(grid_position, distance)| {
+                frustum.is_none_or(|frustum| {
+                    let (min, max) = self.chunk_aabb(grid_position, lod_for_distance(*distance));
+
+                    frustum.intersects_aabb(min, max)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        chunks.sort_by_key(|(_, distance)| *distance);
+
+        chunks
+            .iter()
+            .flat_map(|(grid_position, distance)| {
+                self.inner[grid_position].to_instances(
+                    lod_for_distance(*distance),
+                    **grid_position,
+                    acceleration_structure_references,
+                    &self.translucent_materials,
+                )
+            })
+            .take(max_instance_count as usize)
+            .collect()
+    }
+
+    // Materials whose palette alpha is below 1 are translucent. Returns whether any material
+    // changed, the instances have to be rebuilt then
+    pub fn set_translucent_materials(&mut self, palette: &[Vec4; 256]) -> bool {
+        let translucent_materials = palette
+            .iter()
+            .map(|color| color.w < 1.0)
+            .collect::<Vec<_>>();
+
+        let changed = translucent_materials
+            .iter()
+            .zip(
+                self.translucent_materials
+                    .iter()
+                    .chain(std::iter::repeat(&false)),
+            )
+            .any(|(translucent, previous)| translucent != previous);
+
+        self.translucent_materials = translucent_materials;
+
+        changed
+    }
+
+    // Returns whether the material changed
+    pub fn set_material_translucent(&mut self, index: u8, translucent: bool) -> bool {
+        if self.translucent_materials.is_empty() {
+            self.translucent_materials = vec![false; 256];
+        }
+
+        let previous =
+            std::mem::replace(&mut self.translucent_materials[index as usize], translucent);
+
+        previous != translucent
+    }
+
+    // Returns whether the visibility of the chunk changed
+    pub fn set_chunk_visibility(&mut self, grid_position: IVec3, visible: bool) -> bool {
+        match self.inner.get_mut(&grid_position) {
+            Some(chunk) if chunk.visible != visible => {
+                chunk.set_visible(visible);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Hides the chunks farther than `radius` chunks from `camera_chunk`, every chunk is in range
+    // without a radius. Independent of `set_chunk_visibility`, a chunk is only visible when both
+    // show it. Returns whether any chunk changed
+    pub fn update_visibility_radius(&mut self, camera_chunk: IVec3, radius: Option<u32>) -> bool {
+        let mut changed = false;
+        self.visibility_radius = radius.map(|radius| (camera_chunk, radius));
+
+        for (grid_position, chunk) in &mut self.inner {
+            let in_range = in_visibility_radius(self.visibility_radius, *grid_position);
+
+            if chunk.in_range != in_range {
+                chunk.set_in_range(in_range);
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    // Whether the voxel at `position` is solid, `None` outside of the world
+    pub fn contains(&self, position: &IVec3) -> Option<bool> {
+        let (grid_position, local_position) = self.translation_to_position(position)?;
+
+        Some(
+            self.inner
+                .get(&grid_position)
+                .is_some_and(|chunk| chunk.contains(&local_position)),
+        )
+    }
+
+    // Like `contains`, positions outside of the world are empty
+    pub fn is_solid(&self, position: &IVec3) -> bool {
+        self.contains(position) == Some(true)
+    }
+
+    pub fn get_voxel(&self, position: &IVec3) -> Option<HostVoxel> {
+        let (grid_position, local_position) = self.translation_to_position(position)?;
+
+        self.inner.get(&grid_position)?.get(&local_position)
+    }
+
+    pub fn insert(&mut self, position: IVec3, voxel: HostVoxel) -> Option<IVec3> {
+        self.insert_voxel(position, voxel)
+    }
+
+    pub fn remove_voxel(&mut self, position: &IVec3) -> Option<HostVoxel> {
+        let (grid_position, local_position) = self.translation_to_position(position)?;
+        self.restore_chunk(&grid_position);
+
+        let chunk = self.inner.get_mut(&grid_position)?;
+        let voxel = chunk.remove(&local_position);
+
+        if chunk.empty() {
+            self.inner.remove(&grid_position);
+        }
+
+        voxel
+    }
+
+    // Walks the voxels along the ray one boundary at a time (Amanatides and Woo), crossing the
+    // chunks without voxels in a single step. Voxels are unit cubes centered on integer
+    // positions
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RayHit> {
+        let direction = direction.normalize_or_zero();
+
+        if direction == Vec3::ZERO {
+            return None;
+        }
+
+        // Shifted so that each voxel spans from its position to the next integer
+        let origin = origin + 0.5;
+        let moving = direction.cmpne(Vec3::ZERO);
+        let positive = direction.cmpgt(Vec3::ZERO);
+        let step = IVec3::select(positive, IVec3::ONE, IVec3::NEG_ONE);
+
+        let face = |axis: usize| {
+            let mut normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+            normal
+        };
+
+        // The distance along the ray to the side it leaves the box through, and that side's axis
+        let exit = |min: Vec3, max: Vec3| {
+            let t = (Vec3::select(positive, max, min) - origin) / direction;
+            let t = Vec3::select(moving, t, Vec3::INFINITY);
+            let axis = t.min_position();
+
+            (t[axis], axis)
+        };
+
+        // The ray is clipped to the world first
+        let (voxel_min, voxel_max) = self.size.voxel_bounds();
+        let (world_min, world_max) = (voxel_min.as_vec3(), (voxel_max + 1).as_vec3());
+
+        let inside = origin.cmpge(world_min) & origin.cmplt(world_max);
+
+        if (!moving & !inside).any() {
+            return None;
+        }
+
+        let near = (Vec3::select(positive, world_min, world_max) - origin) / direction;
+        let near = Vec3::select(moving, near, Vec3::NEG_INFINITY);
+        let near_axis = near.max_position();
+
+        let (mut distance, mut normal) = if near[near_axis] > 0.0 {
+            (near[near_axis], face(near_axis))
+        } else {
+            (0.0, IVec3::ZERO)
+        };
+
+        if distance > max_distance.min(exit(world_min, world_max).0) {
+            return None;
+        }
+
+        let chunk_width = self.size.chunk_width as i32;
+        let mut position = (origin + direction * distance)
+            .floor()
+            .as_ivec3()
+            .clamp(voxel_min, voxel_max);
+
+        loop {
+            let grid_position = self.chunk_position(&position);
+            let chunk_min = grid_position * chunk_width;
+
+            let (cell_min, cell_max) = match self.inner.get(&grid_position) {
+                Some(chunk) if !chunk.empty() => {
+                    let local_position = (position - chunk_min).as_uvec3();
+
+                    if let Some(voxel) = chunk.get(&local_position) {
+                        return Some(RayHit {
+                            position,
+                            material_index: voxel.material_index(),
+                            normal,
+                            distance,
+                        });
+                    }
+
+                    (position, position)
+                }
+                // Chunks without voxels are crossed at once
+                _ => (chunk_min, chunk_min + chunk_width - 1),
+            };
+
+            let (next_distance, axis) = exit(cell_min.as_vec3(), (cell_max + 1).as_vec3());
+            distance = distance.max(next_distance);
+            normal = face(axis);
+
+            if distance > max_distance {
+                return None;
+            }
+
+            // The other axes are kept within the cell, against rounding
+            position = (origin + direction * distance)
+                .floor()
+                .as_ivec3()
+                .clamp(cell_min, cell_max);
+            position[axis] = if step[axis] > 0 {
+                cell_max[axis] + 1
+            } else {
+                cell_min[axis] - 1
+            };
+
+            // Rays leaving the world don't come back
+            if !self.in_bounds(&position) {
+                return None;
+            }
+        }
+    }
+
+    // Places a voxel of a loaded model, replacing the voxel already there. Returns whether the
+    // position is inside of the world
+    pub fn set_voxel(&mut self, position: IVec3, voxel: HostVoxel) -> bool {
+        let Some((grid_position, local_position)) = self.translation_to_position(&position) else {
+            return false;
+        };
+        self.restore_chunk(&grid_position);
+
+        self.chunk_or_insert(grid_position)
+            .set(local_position, voxel);
+
+        true
+    }
+
+    // Returns the chunk of the voxel, `None` when the position is outside of the world or
+    // already solid
+    pub fn insert_voxel(&mut self, position: IVec3, voxel: HostVoxel) -> Option<IVec3> {
+        let (grid_position, local_position) = self.translation_to_position(&position)?;
+        self.restore_chunk(&grid_position);
+
+        if !self
+            .chunk_or_insert(grid_position)
+            .insert(local_position, voxel)
+        {
+            return None;
+        }
+
+        Some(grid_position)
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_i32(reader: &mut impl Read) -> io::Result<i32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+
+    Ok(i32::from_le_bytes(bytes))
+}
+
+fn read_ivec3(reader: &mut impl Read) -> io::Result<IVec3> {
+    Ok(IVec3::new(
+        read_i32(reader)?,
+        read_i32(reader)?,
+        read_i32(reader)?,
+    ))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// The face normals of exported meshes, in the Y-up space of OBJ files
+const OBJ_NORMALS: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+// The doubled offsets from a voxel center to the corners of its face along `normal`,
+// counter-clockwise seen from outside the voxel
+fn face_corners(normal: IVec3) -> [IVec3; 4] {
+    let axis = normal.abs().max_position();
+    let mut corners = [(-1, -1), (1, -1), (1, 1), (-1, 1)].map(|(u, v)| {
+        let mut corner = normal;
+        corner[(axis + 1) % 3] = u;
+        corner[(axis + 2) % 3] = v;
+        corner
+    });
+
+    if normal[axis] < 0 {
+        corners.reverse();
+    }
+
+    corners
+}
+
+impl Chunk {
+    // The voxel count then the local position and material index of each voxel, the layout of
+    // a chunk in world files
+    pub fn write_voxels(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&(self.voxel_count() as u32).to_le_bytes())?;
+
+        for (local_position, voxel) in self.iter() {
+            let local_position = local_position.to_array().map(|coordinate| {
+                u8::try_from(coordinate).expect("chunks are at most MAX_CHUNK_WIDTH voxels wide")
+            });
+
+            writer.write_all(&local_position)?;
+            writer.write_all(&voxel.material_index.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_voxels(reader: &mut impl Read, width: u32) -> io::Result<Chunk> {
+        let mut chunk = Chunk::new(width);
+
+        for _ in 0..read_u32(reader)? {
+            let mut local_position = [0; 3];
+            reader.read_exact(&mut local_position)?;
+
+            let local_position = UVec3::from_array(local_position.map(u32::from));
+
+            if local_position.cmpge(UVec3::splat(width)).any() {
+                return Err(invalid_data(format!(
+                    "Voxel outside of chunk bounds: {local_position}"
+                )));
+            }
+
+            let material_index = read_u32(reader)?;
+
+            if material_index >= PALETTE_SIZE {
+                return Err(invalid_data(format!(
+                    "Material index {material_index} out of the {PALETTE_SIZE} palette entries"
+                )));
+            }
+
+            chunk.insert(
+                local_position,
+                HostVoxel {
+                    scale: 1.0,
+                    material_index,
+                },
+            );
+        }
+
+        Ok(chunk)
+    }
+}
+
+impl Chunks {
+    // Writes the non-empty chunks, evicted ones included, as:
+    // magic, version, chunk width, world bounds in chunks, chunk count, then for each chunk its grid
+    // position, voxel count and the local position and material index of each voxel. The voxel
+    // scale isn't stored, the chunks don't keep it either and every loaded voxel has a scale of 1
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        let chunks = self
+            .inner
+            .iter()
+            .filter(|(_, chunk)| !chunk.empty())
+            .collect::<Vec<_>>();
+
+        writer.write_all(WORLD_FILE_MAGIC)?;
+        writer.write_all(&WORLD_FILE_VERSION.to_le_bytes())?;
+        writer.write_all(&self.size.chunk_width.to_le_bytes())?;
+
+        for coordinate in [self.size.min, self.size.max]
+            .iter()
+            .flat_map(IVec3::to_array)
+        {
+            writer.write_all(&coordinate.to_le_bytes())?;
+        }
+
+        writer.write_all(&((chunks.len() + self.evicted.len()) as u32).to_le_bytes())?;
+
+        for (grid_position, chunk) in chunks {
+            for coordinate in grid_position.to_array() {
+                writer.write_all(&coordinate.to_le_bytes())?;
+            }
+
+            chunk.write_voxels(&mut writer)?;
+        }
+
+        for (grid_position, voxels) in &self.evicted {
+            for coordinate in grid_position.to_array() {
+                writer.write_all(&coordinate.to_le_bytes())?;
+            }
+
+            writer.write_all(voxels)?;
+        }
+
+        writer.flush()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Chunks> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+
+        if &magic != WORLD_FILE_MAGIC {
+            return Err(invalid_data(format!(
+                "{} is not a world file",
+                path.display()
+            )));
+        }
+
+        let version = read_u32(&mut reader)?;
+
+        let size = match version {
+            1 => WorldSize::default(),
+            2 => WorldSize::symmetric(read_u32(&mut reader)?, read_ivec3(&mut reader)?),
+            WORLD_FILE_VERSION => WorldSize {
+                chunk_width: read_u32(&mut reader)?,
+                min: read_ivec3(&mut reader)?,
+                max: read_ivec3(&mut reader)?,
+            },
+            _ => {
+                return Err(invalid_data(format!(
+                    "Unsupported world file version {version}, expected {WORLD_FILE_VERSION}"
+                )));
+            }
+        };
+
+        if !size.is_valid() {
+            return Err(invalid_data(format!("Invalid world size: {size:?}")));
+        }
+
+        let mut chunks = Chunks::with_size(size);
+
+        for _ in 0..read_u32(&mut reader)? {
+            let grid_position = read_ivec3(&mut reader)?;
+
+            if !size.grid_in_bounds(&grid_position) {
+                return Err(invalid_data(format!(
+                    "Chunk out of bounds: {grid_position}"
+                )));
+            }
+
+            let chunk = Chunk::read_voxels(&mut reader, size.chunk_width)?;
+
+            if !chunk.empty() {
+                chunks.inner.insert(grid_position, chunk);
+            }
+        }
+
+        Ok(chunks)
+    }
+
+    // Writes the exposed faces of the resident voxels as an OBJ mesh, with an MTL file next to
+    // it holding a material per palette entry in use. The mesh is Y-up and in meters like the
+    // tools it is exported to, evicted chunks are left out
+    pub fn export_obj(&self, path: &Path, palette: &[Vec4; 256]) -> io::Result<()> {
+        let mtl_path = path.with_extension("mtl");
+
+        // Corners are stored doubled so the ones shared by neighboring voxels are merged
+        let mut vertices = HashMap::new();
+        let mut faces = BTreeMap::<u32, Vec<([usize; 4], usize)>>::new();
+
+        for (grid_position, chunk) in self.inner.iter() {
+            let origin = grid_position * self.size.chunk_width as i32;
+
+            for (local_position, voxel) in chunk.iter() {
+                let position = origin + local_position.as_ivec3();
+                let exported_position = position * IVec3::new(1, -1, 1);
+
+                for (normal_index, normal) in OBJ_NORMALS.iter().enumerate() {
+                    // The world is Y-down
+                    if self.is_solid(&(position + normal * IVec3::new(1, -1, 1))) {
+                        continue;
+                    }
+
+                    let corners = face_corners(*normal).map(|corner| {
+                        let vertex_count = vertices.len();
+
+                        *vertices
+                            .entry(exported_position * 2 + corner)
+                            .or_insert(vertex_count)
+                    });
+
+                    faces
+                        .entry(voxel.material_index)
+                        .or_default()
+                        .push((corners, normal_index));
+                }
+            }
+        }
+
+        let mut mtl_writer = BufWriter::new(File::create(&mtl_path)?);
+
+        for material_index in faces.keys() {
+            let color = palette[*material_index as usize & 0xFF];
+
+            writeln!(mtl_writer, "newmtl material_{material_index}")?;
+            writeln!(mtl_writer, "Kd {} {} {}", color.x, color.y, color.z)?;
+            writeln!(mtl_writer, "d {}", color.w)?;
+        }
+
+        mtl_writer.flush()?;
+
+        let mut vertices = vertices.into_iter().collect::<Vec<_>>();
+        vertices.sort_unstable_by_key(|(_, index)| *index);
+
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        if let Some(mtl_name) = mtl_path.file_name() {
+            writeln!(writer, "mtllib {}", mtl_name.to_string_lossy())?;
+        }
+
+        for (corner, _) in vertices {
+            let position = corner.as_vec3() * 0.5 * VOXEL_PHYSICAL_LENGTH;
+            writeln!(writer, "v {} {} {}", position.x, position.y, position.z)?;
+        }
+
+        for normal in OBJ_NORMALS {
+            writeln!(writer, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+        }
+
+        // OBJ indices start at 1
+        for (material_index, faces) in faces {
+            writeln!(writer, "usemtl material_{material_index}")?;
+
+            for ([a, b, c, d], normal_index) in faces {
+                let n = normal_index + 1;
+                writeln!(
+                    writer,
+                    "f {}//{n} {}//{n} {}//{n} {}//{n}",
+                    a + 1,
+                    b + 1,
+                    c + 1,
+                    d + 1
+                )?;
+            }
+        }
+
+        writer.flush()
+    }
+}
+
+impl Display for Chunks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (grid_position, voxel_count) in
+            self.inner.iter().filter_map(|(grid_position, chunk)| {
+                if chunk.visible() {
+                    Some((grid_position, chunk.voxel_count()))
+                } else {
+                    None
+                }
+            })
+        {
+            writeln!(f, "({grid_position:?}, voxels: {voxel_count})")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use dot_vox::{Dict, DotVoxData, Frame, Model, SceneNode, ShapeModel, Size, Voxel};
+    use glam::{IVec3, UVec3, Vec3, Vec4};
+    use vulkano::acceleration_structure::AccelerationStructureInstance;
+
+    use super::{
+        CHUNK_WIDTH, Chunk, Chunks, LOD_COUNT, LOD_DISTANCE, MAX_CHUNK_WIDTH, WorldSize, chunk_id,
+        dirty_instance_ranges, invalid_instance_transform, lod_for_distance, morton_code,
+        sort_instances_spatially,
+    };
+    use crate::world::{
+        HostVoxel,
+        chunk::{WORLD_DEPTH, WORLD_HEIGHT, WORLD_WIDTH},
+    };
+
+    #[test]
+    fn chunk_insert() {
+        use glam::UVec3;
+
+        let mut chunk = Chunk::default();
+
+        for x in 0..CHUNK_WIDTH {
+            for y in 0..CHUNK_WIDTH {
+                for z in 0..CHUNK_WIDTH {
+                    chunk.insert(
+                        UVec3::new(x, y, z),
+                        HostVoxel {
+                            material_index: 0,
+                            scale: 1.0,
+                        },
+                    );
+                }
+            }
+        }
+
+        assert!(chunk.voxel_count() as u32 == CHUNK_WIDTH * CHUNK_WIDTH * CHUNK_WIDTH);
+    }
+
+    #[test]
+    fn chunk_debug_lines() {
+        let chunk = Chunk::new(4);
+        let lines = chunk.debug_lines(IVec3::X);
+
+        assert!(lines.len() == 24);
+
+        // Each edge spans the chunk along a single axis
+        for line in lines.chunks(2) {
+            let start = Vec3::from_array(line[0].position);
+            let end = Vec3::from_array(line[1].position);
+
+            assert!((end - start).abs().element_sum() == 4.0);
+            assert!((end - start).abs().max_element() == 4.0);
+            assert!(start.cmpge(Vec3::new(3.5, -0.5, -0.5)).all());
+            assert!(end.cmple(Vec3::new(7.5, 3.5, 3.5)).all());
+        }
+    }
+
+    #[test]
+    fn chunk_dense_storage() {
+        let mut chunk = Chunk::default();
+
+        assert!(chunk.occupancy.is_empty() && chunk.materials.is_empty());
+
+        let position = UVec3::new(63, 2, 40);
+
+        assert!(chunk.insert(
+            position,
+            HostVoxel {
+                material_index: 255,
+                scale: 1.0,
+            },
+        ));
+        assert!(!chunk.insert(position, HostVoxel::default()));
+
+        let voxels = chunk.iter().collect::<Vec<_>>();
+
+        assert!(voxels.len() == 1);
+        assert!(voxels[0].0 == position && voxels[0].1.material_index == 255);
+
+        assert!(chunk.remove(&position).unwrap().material_index == 255);
+        assert!(chunk.empty() && chunk.iter().next().is_none());
+    }
+
+    #[test]
+    fn chunk_instances_carry_chunk_id() {
+        let mut chunk = Chunk::new(CHUNK_WIDTH);
+        chunk.insert(
+            UVec3::ZERO,
+            HostVoxel {
+                scale: 1.0,
+                material_index: 7,
+            },
+        );
+
+        let grid_position = IVec3::new(-3, 1, 2);
+        let instances = chunk.to_instances(2, grid_position, &[0; 4], &[]);
+        let custom_index = instances[0].instance_custom_index_and_mask.low_24();
+
+        assert!(custom_index & 0xFF == 7);
+        assert!((custom_index >> 8) & 0xF == 2);
+        assert!(custom_index >> 12 == chunk_id(grid_position));
+
+        // Neighbouring chunks get different ids
+        for offset in [
+            IVec3::X,
+            IVec3::Y,
+            IVec3::Z,
+            IVec3::NEG_X,
+            IVec3::NEG_Y,
+            IVec3::NEG_Z,
+        ] {
+            assert!(chunk_id(grid_position) != chunk_id(grid_position + offset));
+        }
+    }
+
+    #[test]
+    fn chunk_merges_equal_voxels() {
+        let mut chunk = Chunk::default();
+
+        for x in 0..4 {
+            for y in 0..2 {
+                for z in 0..3 {
+                    chunk.insert(
+                        UVec3::new(x, y, z),
+                        HostVoxel {
+                            material_index: 1,
+                            scale: 1.0,
+                        },
+                    );
+                }
+            }
+        }
+
+        let instances = chunk.to_instances(0, IVec3::new(1, 0, 0), &[0], &[]);
+
+        assert!(instances.len() == 1);
+
+        let transform = instances[0].transform;
+        let width = CHUNK_WIDTH as f32;
+
+        assert!([transform[0][0], transform[1][1], transform[2][2]] == [4.0, 2.0, 3.0]);
+        assert!([transform[0][3], transform[1][3], transform[2][3]] == [width + 1.5, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn chunk_merge_respects_materials() {
+        let mut chunk = Chunk::default();
+
+        for x in 0..4 {
+            chunk.insert(
+                UVec3::new(x, 0, 0),
+                HostVoxel {
+                    material_index: x / 2,
+                    scale: 1.0,
+                },
+            );
+        }
+
+        let mut boxes = chunk.merged_boxes(0);
+        boxes.sort_by_key(|(min, _, _)| min.x);
+
+        assert!(boxes.len() == 2);
+        assert!(boxes[0].0 == UVec3::ZERO && boxes[0].1 == UVec3::new(2, 1, 1));
+        assert!(boxes[1].0 == UVec3::new(2, 0, 0) && boxes[1].1 == UVec3::new(2, 1, 1));
+        assert!(boxes[0].2.material_index == 0 && boxes[1].2.material_index == 1);
+    }
+
+    #[test]
+    fn chunk_coarse_lods_keep_thin_surfaces() {
+        let mut chunk = Chunk::default();
+
+        // A single voxel thick floor at an odd height, skipped when sampling every other voxel
+        for x in 0..CHUNK_WIDTH {
+            for z in 0..CHUNK_WIDTH {
+                chunk.insert(UVec3::new(x, 5, z), HostVoxel::default());
+            }
+        }
+
+        for lod in 0..LOD_COUNT {
+            let boxes = chunk.merged_boxes(lod);
+            let stride = 2u32.pow(lod);
+
+            assert!(boxes.len() == 1);
+            assert!(boxes[0].0.y * stride <= 5 && 5 < (boxes[0].0.y + 1) * stride);
+            assert!(boxes[0].1 == UVec3::new(CHUNK_WIDTH / stride, 1, CHUNK_WIDTH / stride));
+        }
+    }
+
+    #[test]
+    fn chunks_lod_for_distance() {
+        assert!(lod_for_distance(0) == 0);
+        assert!(lod_for_distance(-1) == 0);
+
+        // LODs never decrease with the distance and the last one is used for everything far away
+        let lods = (0..1000).map(lod_for_distance).collect::<Vec<_>>();
+
+        assert!(lods.windows(2).all(|w| w[0] <= w[1]));
+        assert!(*lods.last().unwrap() == LOD_COUNT - 1);
+
+        let mut chunks = Chunks::default();
+        let far = WORLD_WIDTH - 1;
+
+        chunks.insert(IVec3::ZERO, HostVoxel::default());
+        chunks.insert(
+            IVec3::new(far * CHUNK_WIDTH as i32, 0, 0),
+            HostVoxel::default(),
+        );
+
+        let mut instance_lods = chunks
+            .to_instances(&IVec3::ZERO, &[0; 4], u64::MAX, None)
+            .iter()
+            .map(|instance| (instance.instance_custom_index_and_mask.low_24() >> 8) & 0xF)
+            .collect::<Vec<_>>();
+        instance_lods.sort();
+
+        assert!(instance_lods == [0, lod_for_distance(far)]);
+        assert!(lod_for_distance(far) > 0);
+    }
+
+    #[test]
+    fn chunks_insert() {
+        let mut chunks = Chunks::default();
+
+        let size = CHUNK_WIDTH as i32;
+
+        for x in 0..WORLD_WIDTH * size {
+            let position = IVec3::new(x, 0, 0);
+
+            let res = chunks.insert_voxel(
+                position,
+                HostVoxel {
+                    material_index: 0,
+                    scale: 1.0,
+                },
+            );
+
+            assert!(res.is_some());
+
+            let grid_position = res.unwrap();
+
+            assert!(grid_position == IVec3::new(x / size, 0, 0));
+        }
+
+        let sum = chunks
+            .inner
+            .values()
+            .map(|c| c.voxel_count() as u32)
+            .sum::<u32>();
+
+        assert!(sum == WORLD_WIDTH as u32 * CHUNK_WIDTH);
+        assert!(chunks.inner.len() == WORLD_WIDTH as usize);
+    }
+
+    #[test]
+    fn chunk_contains() {
+        let mut chunk = Chunk::default();
+
+        let pos1 = UVec3::new(1, 1, 1);
+        let pos2 = UVec3::new(32, 19, 15);
+        let pos3 = UVec3::new(8, 8, 8);
+
+        chunk.insert(pos1, HostVoxel::default());
+        chunk.insert(pos2, HostVoxel::default());
+        chunk.insert(pos3, HostVoxel::default());
+
+        assert!(chunk.contains(&pos1));
+        assert!(chunk.contains(&pos2));
+        assert!(chunk.contains(&pos3));
+    }
+
+    #[test]
+    fn chunks_contains() {
+        let mut chunks = Chunks::default();
+
+        let pos1 = IVec3::new(1, 1, 1);
+        let pos2 = IVec3::new(120, 129, -215);
+
+        chunks.insert_voxel(pos1, HostVoxel::default());
+        chunks.insert_voxel(pos2, HostVoxel::default());
+
+        assert!(chunks.contains(&pos1) == Some(true));
+        assert!(chunks.contains(&pos2) == Some(true));
+        assert!(chunks.contains(&IVec3::ZERO) == Some(false));
+
+        // Positions outside of the world don't panic
+        let outside = IVec3::new(WORLD_WIDTH * CHUNK_WIDTH as i32, 0, 0);
+
+        assert!(chunks.contains(&outside).is_none());
+        assert!(!chunks.is_solid(&outside));
+        assert!(chunks.get_voxel(&outside).is_none());
+        assert!(chunks.insert_voxel(outside, HostVoxel::default()).is_none());
+        assert!(chunks.remove_voxel(&outside).is_none());
+        assert!(!chunks.set_voxel(outside, HostVoxel::default()));
+        assert!(!chunks.set_chunk_visibility(chunks.chunk_position(&outside), false));
+    }
+
+    #[test]
+    fn chunks_raycast() {
+        let mut chunks = Chunks::default();
+
+        // Past an empty chunk and a chunk with a voxel off the ray
+        let far = IVec3::new(200, 0, 0);
+        chunks.insert(far, HostVoxel::new(1.0, 7));
+        chunks.insert(IVec3::new(100, 5, 0), HostVoxel::default());
+
+        let hit = chunks.raycast(Vec3::ZERO, Vec3::X, 256.0).unwrap();
+
+        assert!(hit.position == far);
+        assert!(hit.material_index == 7);
+        assert!(hit.normal == IVec3::NEG_X);
+        assert!((hit.distance - 199.5).abs() < 1e-3);
+
+        assert!(chunks.raycast(Vec3::ZERO, Vec3::X, 199.0).is_none());
+        assert!(chunks.raycast(Vec3::ZERO, Vec3::NEG_X, 256.0).is_none());
+
+        // Slanted, still within the row of the voxel when it's reached
+        let hit = chunks
+            .raycast(
+                Vec3::new(190.0, -0.2, 0.1),
+                Vec3::new(1.0, 0.05, 0.0),
+                256.0,
+            )
+            .unwrap();
+
+        assert!(hit.position == far);
+        assert!(hit.normal == IVec3::NEG_X);
+
+        // From above, the top face in the Y-down world is the negative one
+        let hit = chunks
+            .raycast(Vec3::new(200.2, -10.0, 0.3), Vec3::Y, 256.0)
+            .unwrap();
+
+        assert!(hit.position == far);
+        assert!(hit.normal == IVec3::NEG_Y);
+        assert!((hit.distance - 9.5).abs() < 1e-3);
+
+        // Starting inside of the voxel
+        let hit = chunks.raycast(far.as_vec3(), Vec3::Z, 256.0).unwrap();
+
+        assert!(hit.normal == IVec3::ZERO);
+        assert!(hit.distance == 0.0);
+    }
+
+    #[test]
+    fn chunks_raycast_from_outside() {
+        let mut chunks = Chunks::default();
+
+        let edge = WORLD_WIDTH * CHUNK_WIDTH as i32 - 1;
+        let wall = IVec3::new(edge, 0, 0);
+        chunks.insert(wall, HostVoxel::default());
+
+        let hit = chunks
+            .raycast(Vec3::new(edge as f32 + 10.0, 0.0, 0.0), Vec3::NEG_X, 64.0)
+            .unwrap();
+
+        assert!(hit.position == wall);
+        assert!(hit.normal == IVec3::X);
+        assert!((hit.distance - 9.5).abs() < 1e-3);
+
+        // Parallel to the world without entering it
+        let beside = Vec3::new(0.0, edge as f32 + 10.0, 0.0);
+
+        assert!(chunks.raycast(beside, Vec3::X, 64.0).is_none());
+        assert!(chunks.raycast(Vec3::ZERO, Vec3::ZERO, 64.0).is_none());
+    }
+
+    #[test]
+    fn chunks_save_load() {
+        let path = std::env::temp_dir().join("a-tlas-chunks-save-load.bin");
+
+        let mut chunks = Chunks::default();
+
+        let pos1 = IVec3::new(1, 2, 3);
+        let pos2 = IVec3::new(-130, 64, 500);
+
+        chunks.insert(
+            pos1,
+            HostVoxel {
+                material_index: 12,
+                scale: 1.0,
+            },
+        );
+        chunks.insert(
+            pos2,
+            HostVoxel {
+                material_index: 200,
+                scale: 1.0,
+            },
+        );
+
+        chunks.save(&path).unwrap();
+        let loaded = Chunks::load(&path).unwrap();
+
+        assert!(loaded.get_voxel(&pos1).unwrap().material_index == 12);
+        assert!(loaded.get_voxel(&pos2).unwrap().material_index == 200);
+        assert!(!loaded.is_solid(&IVec3::ZERO));
+
+        std::fs::write(&path, b"ATLW\xff\x00\x00\x00").unwrap();
+        assert!(Chunks::load(&path).is_err());
+
+        std::fs::write(&path, b"VOX \x01\x00\x00\x00").unwrap();
+        assert!(Chunks::load(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn chunks_load_invalid_material() {
+        let path = std::env::temp_dir().join("a-tlas-chunks-invalid-material.bin");
+
+        let mut chunks = Chunks::default();
+        chunks.insert(IVec3::new(1, 2, 3), HostVoxel::new(1.0, 255));
+        chunks.save(&path).unwrap();
+
+        assert!(Chunks::load(&path).is_ok());
+
+        // The material index of the only voxel ends the file
+        let mut bytes = std::fs::read(&path).unwrap();
+        let material_offset = bytes.len() - 4;
+        bytes[material_offset..].copy_from_slice(&256u32.to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+
+        let Err(error) = Chunks::load(&path) else {
+            panic!("the oversized material was loaded");
+        };
+
+        assert!(error.kind() == std::io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("256"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn chunks_export_obj() {
+        let path = std::env::temp_dir().join("a-tlas-chunks-export.obj");
+
+        let mut chunks = Chunks::default();
+
+        chunks.insert(
+            IVec3::ZERO,
+            HostVoxel {
+                material_index: 3,
+                scale: 1.0,
+            },
+        );
+        chunks.insert(
+            IVec3::X,
+            HostVoxel {
+                material_index: 7,
+                scale: 1.0,
+            },
+        );
+
+        let mut palette = [Vec4::ONE; 256];
+        palette[7] = Vec4::new(1.0, 0.0, 0.0, 1.0);
+
+        chunks.export_obj(&path, &palette).unwrap();
+
+        let obj = std::fs::read_to_string(&path).unwrap();
+        let mtl = std::fs::read_to_string(path.with_extension("mtl")).unwrap();
+
+        let count =
+            |text: &str, prefix: &str| text.lines().filter(|line| line.starts_with(prefix)).count();
+
+        // The shared face is hidden and its corners are merged
+        assert!(obj.starts_with("mtllib a-tlas-chunks-export.mtl"));
+        assert!(count(&obj, "v ") == 12);
+        assert!(count(&obj, "f ") == 10);
+        assert!(count(&obj, "usemtl ") == 2);
+        assert!(count(&mtl, "newmtl ") == 2);
+        assert!(mtl.contains("newmtl material_7\nKd 1 0 0"));
+
+        let parse_vec3 = |line: &str| {
+            let coordinates = line
+                .split_whitespace()
+                .skip(1)
+                .map(|coordinate| coordinate.parse::<f32>().unwrap())
+                .collect::<Vec<_>>();
+
+            Vec3::from_slice(&coordinates)
+        };
+        let vertices = obj
+            .lines()
+            .filter(|line| line.starts_with("v "))
+            .map(parse_vec3)
+            .collect::<Vec<_>>();
+        let normals = obj
+            .lines()
+            .filter(|line| line.starts_with("vn "))
+            .map(parse_vec3)
+            .collect::<Vec<_>>();
+
+        // Every face is a quad wound counter-clockwise around its normal
+        for face in obj.lines().filter(|line| line.starts_with("f ")) {
+            let corners = face
+                .split_whitespace()
+                .skip(1)
+                .map(|corner| {
+                    let (vertex, normal) = corner.split_once("//").unwrap();
+                    (
+                        vertices[vertex.parse::<usize>().unwrap() - 1],
+                        normals[normal.parse::<usize>().unwrap() - 1],
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            assert!(corners.len() == 4);
+
+            let (a, normal) = corners[0];
+            let winding = (corners[1].0 - a).cross(corners[2].0 - a);
+            assert!(winding.dot(normal) > 0.0);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(path.with_extension("mtl")).unwrap();
+    }
+
+    #[test]
+    fn chunks_stats() {
+        let mut chunks = Chunks::default();
+
+        for x in 0..4 {
+            chunks.insert(IVec3::new(x, 0, 0), HostVoxel::default());
+        }
+        chunks.insert(IVec3::new(0, 2, 0), HostVoxel::default());
+        chunks.insert(IVec3::new(100, 0, 0), HostVoxel::default());
+
+        let stats = chunks.stats(&IVec3::ZERO);
+        let instances = chunks.to_instances(&IVec3::ZERO, &[0; 4], u64::MAX, None);
+
+        assert!(stats.chunks == 2);
+        assert!(stats.voxels == 6);
+        assert!(stats.instances == instances.len());
+        assert!(stats.instances == 3);
+        assert!(stats.instance_bytes == 3 * 64);
+
+        // Hidden chunks still hold voxels but emit no instances
+        chunks.set_chunk_visibility(IVec3::new(1, 0, 0), false);
+
+        assert!(chunks.stats(&IVec3::ZERO).voxels == 6);
+        assert!(chunks.stats(&IVec3::ZERO).instances == 2);
+
+        // The far chunks are counted at their coarser LOD
+        let far = IVec3::new(LOD_DISTANCE * 2 * CHUNK_WIDTH as i32, 0, 0);
+
+        for offset in [IVec3::ZERO, IVec3::X, IVec3::Y, IVec3::ONE] {
+            chunks.insert(far + offset, HostVoxel::default());
+        }
+
+        for origin in [IVec3::ZERO, far] {
+            let instances = chunks.to_instances(&origin, &[0; 4], u64::MAX, None);
+
+            assert!(chunks.stats(&origin).instances == instances.len());
+        }
+
+        assert!(chunks.stats(&IVec3::ZERO).instances == 3);
     }
 
-    pub fn contains(&self, position: &IVec3) -> bool {
-        let (grid_position, local_position) = Chunks::translation_to_position(position);
+    #[test]
+    fn chunks_densest_chunk_center() {
+        let mut chunks = Chunks::default();
+
+        assert!(chunks.densest_chunk_center().is_none());
+
+        let width = CHUNK_WIDTH as i32;
+
+        chunks.insert(IVec3::ZERO, HostVoxel::default());
+        for x in 0..3 {
+            chunks.insert(IVec3::new(x, -width, width), HostVoxel::default());
+        }
 
-        let chunk = self.inner.get(&grid_position).unwrap();
+        let center = CHUNK_WIDTH as f32 / 2.0;
 
-        chunk.contains(&local_position)
+        assert!(
+            chunks.densest_chunk_center()
+                == Some(Vec3::new(
+                    center,
+                    center - width as f32,
+                    center + width as f32
+                ))
+        );
     }
 
-    pub fn get_voxel(&self, position: &IVec3) -> Option<&HostVoxel> {
-        let (grid_position, local_position) = Chunks::translation_to_position(position);
+    #[test]
+    fn chunks_custom_size() {
+        let size = WorldSize::symmetric(16, IVec3::new(2, 1, 2));
+
+        let mut chunks = Chunks::with_size(size);
+
+        assert!(chunks.inner.is_empty());
+
+        assert!(
+            chunks.insert(IVec3::new(31, 0, -31), HostVoxel::default())
+                == Some(IVec3::new(1, 0, -2))
+        );
+        assert!(
+            chunks
+                .insert(IVec3::new(32, 0, 0), HostVoxel::default())
+                .is_none()
+        );
+        assert!(
+            chunks
+                .insert(IVec3::new(0, 16, 0), HostVoxel::default())
+                .is_none()
+        );
+        assert!(chunks.inner.len() == 1);
+
+        assert!(chunks.remove_voxel(&IVec3::new(31, 0, -31)).is_some());
+        assert!(chunks.inner.is_empty());
+        assert!(!chunks.is_solid(&IVec3::new(31, 0, -31)));
+
+        let path = std::env::temp_dir().join("a-tlas-chunks-custom-size.bin");
+
+        chunks.insert(IVec3::new(-5, 3, 20), HostVoxel::default());
+        chunks.save(&path).unwrap();
+
+        let loaded = Chunks::load(&path).unwrap();
 
-        let chunk = self.inner.get(&grid_position).unwrap();
+        assert!(loaded.size() == size);
+        assert!(loaded.is_solid(&IVec3::new(-5, 3, 20)));
 
-        chunk.voxels.get(&local_position)
+        std::fs::remove_file(&path).unwrap();
     }
 
-    pub fn insert_voxel(
-        chunks: &mut ChunksInner,
-        position: IVec3,
-        voxel: HostVoxel,
-    ) -> Option<IVec3> {
-        let (grid_position, local_position) = Chunks::translation_to_position(&position);
+    #[test]
+    fn world_size_chunk_width_limit() {
+        let chunks = IVec3::new(1, 1, 1);
 
-        let current_chunk = chunks.get_mut(&grid_position).unwrap();
+        assert!(WorldSize::symmetric(MAX_CHUNK_WIDTH, chunks).is_valid());
+        assert!(!WorldSize::symmetric(MAX_CHUNK_WIDTH + 1, chunks).is_valid());
+        assert!(!WorldSize::symmetric(0, chunks).is_valid());
+    }
 
-        if !current_chunk.insert(local_position, voxel) {
-            return None;
+    #[test]
+    fn chunks_only_keep_non_empty_chunks() {
+        let mut chunks = Chunks::default();
+        let positions = [IVec3::ZERO, IVec3::new(1, 2, 3), IVec3::new(-1, 0, 0)];
+
+        for position in positions {
+            chunks.insert(position, HostVoxel::default());
         }
 
-        Some(grid_position)
+        assert!(chunks.inner.len() == 2);
+        assert!(chunks.active_chunks().count() == 2);
+
+        // The chunk stays while it holds a voxel
+        chunks.remove_voxel(&IVec3::ZERO);
+
+        assert!(chunks.inner.len() == 2);
+
+        chunks.remove_voxel(&IVec3::new(1, 2, 3));
+
+        assert!(chunks.inner.len() == 1);
+        assert!(chunks.active_chunks().eq([&IVec3::NEG_X]));
+        assert!(chunks.non_empty_chunks().eq([&IVec3::NEG_X]));
+
+        // Removing from an empty chunk doesn't create it
+        assert!(chunks.remove_voxel(&IVec3::ZERO).is_none());
+        assert!(chunks.inner.len() == 1);
+
+        chunks.insert(IVec3::new(5, 5, 5), HostVoxel::default());
+
+        assert!(chunks.inner.len() == 2);
+
+        chunks.remove_voxel(&IVec3::new(-1, 0, 0));
+        chunks.remove_voxel(&IVec3::new(5, 5, 5));
+
+        assert!(chunks.inner.is_empty());
+        assert!(chunks.active_chunks().next().is_none());
     }
-}
 
-impl Display for Chunks {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for (grid_position, voxel_count) in
-            self.inner.iter().filter_map(|(grid_position, chunk)| {
-                if chunk.visible() {
-                    Some((grid_position, chunk.voxels.len()))
-                } else {
-                    None
-                }
-            })
-        {
-            writeln!(f, "({grid_position:?}, voxels: {voxel_count})")?;
+    #[test]
+    fn chunks_iter_voxels() {
+        let mut chunks = Chunks::default();
+
+        assert!(chunks.iter_voxels().next().is_none());
+
+        let positions = [
+            IVec3::ZERO,
+            IVec3::new(-1, -1, -1),
+            IVec3::new(63, -64, 64),
+            IVec3::new(-4096, 4095, -65),
+        ];
+
+        for (material_index, &position) in positions.iter().enumerate() {
+            chunks.insert(position, HostVoxel::new(1.0, material_index as u32));
         }
 
-        Ok(())
+        // A chunk emptied by the removal isn't visited
+        chunks.insert(IVec3::new(200, 0, 0), HostVoxel::default());
+        chunks.remove_voxel(&IVec3::new(200, 0, 0));
+
+        let mut voxels = chunks.iter_voxels().collect::<Vec<_>>();
+        voxels.sort_by_key(|(_, voxel)| voxel.material_index);
+
+        assert!(voxels.len() == positions.len());
+
+        for (material_index, (position, voxel)) in voxels.into_iter().enumerate() {
+            assert!(position == positions[material_index]);
+            assert!(voxel.material_index == material_index as u32);
+
+            let (grid_position, local_position) =
+                chunks.translation_to_position(&position).unwrap();
+
+            assert!(chunks.position_to_translation(&grid_position, &local_position) == position);
+        }
     }
-}
 
-#[cfg(test)]
-mod test {
-    use glam::{IVec3, UVec3};
+    #[test]
+    fn chunks_lower_slice_in_bounds() {
+        let mut chunks = Chunks::default();
+
+        let grid_position = -IVec3::new(WORLD_WIDTH, WORLD_HEIGHT, WORLD_DEPTH);
+        let lower = grid_position * CHUNK_WIDTH as i32;
 
-    use super::{CHUNK_WIDTH, Chunk, Chunks};
-    use crate::world::{HostVoxel, chunk::WORLD_WIDTH};
+        assert!(chunks.size().voxel_bounds().0 == lower);
+        assert!(chunks.insert(lower, HostVoxel::default()) == Some(grid_position));
+        assert!(chunks.is_solid(&lower));
+
+        for axis in [IVec3::X, IVec3::Y, IVec3::Z] {
+            assert!(chunks.insert(lower - axis, HostVoxel::default()).is_none());
+        }
+    }
 
     #[test]
-    fn chunk_insert() {
-        use glam::UVec3;
+    fn chunks_asymmetric_size() {
+        // A flat world starting at the ground
+        let size = WorldSize {
+            chunk_width: 16,
+            min: IVec3::new(-4, 0, -1),
+            max: IVec3::new(2, 1, 3),
+        };
 
-        let mut chunk = Chunk::default();
+        let mut chunks = Chunks::with_size(size);
 
-        for x in 0..CHUNK_WIDTH {
-            for y in 0..CHUNK_WIDTH {
-                for z in 0..CHUNK_WIDTH {
-                    chunk.insert(
-                        UVec3::new(x, y, z),
-                        HostVoxel {
-                            material_index: 0,
-                            scale: 1.0,
-                        },
-                    );
-                }
-            }
+        assert!(size.extent() == IVec3::new(6, 1, 4));
+        assert!(
+            chunks.insert(IVec3::new(-64, 0, -16), HostVoxel::default())
+                == Some(IVec3::new(-4, 0, -1))
+        );
+        assert!(
+            chunks.insert(IVec3::new(31, 15, 47), HostVoxel::default())
+                == Some(IVec3::new(1, 0, 2))
+        );
+
+        for outside in [
+            IVec3::new(-65, 0, 0),
+            IVec3::new(32, 0, 0),
+            IVec3::new(0, -1, 0),
+            IVec3::new(0, 16, 0),
+            IVec3::new(0, 0, -17),
+            IVec3::new(0, 0, 48),
+        ] {
+            assert!(chunks.insert(outside, HostVoxel::default()).is_none());
         }
 
-        assert!(chunk.voxels.len() as u32 == CHUNK_WIDTH * CHUNK_WIDTH * CHUNK_WIDTH);
+        // The ray down stops on the ground, the one up leaves the world
+        let origin = Vec3::new(-64.0, 10.0, -16.0);
+        let hit = chunks.raycast(origin, Vec3::NEG_Y, 64.0).unwrap();
+
+        assert!(hit.position == IVec3::new(-64, 0, -16));
+        assert!(hit.normal == IVec3::Y);
+        assert!((hit.distance - 9.5).abs() < 1e-3);
+        assert!(chunks.raycast(origin, Vec3::Y, 64.0).is_none());
+
+        let path = std::env::temp_dir().join("a-tlas-chunks-asymmetric-size.bin");
+        chunks.save(&path).unwrap();
+
+        let loaded = Chunks::load(&path).unwrap();
+
+        assert!(loaded.size() == size);
+        assert!(loaded.is_solid(&IVec3::new(-64, 0, -16)));
+        assert!(loaded.is_solid(&IVec3::new(31, 15, 47)));
+
+        std::fs::remove_file(&path).unwrap();
     }
 
     #[test]
-    fn chunks_insert() {
-        let mut chunks = Chunks::create_empty_chunks();
+    fn chunks_raycast_out_of_bounds() {
+        let mut chunks = Chunks::default();
 
-        let size = CHUNK_WIDTH as i32;
+        let edge = (WORLD_WIDTH * CHUNK_WIDTH as i32) as f32 - 2.0;
 
-        for x in 0..WORLD_WIDTH * size {
-            let position = IVec3::new(x, 0, 0);
+        assert!(
+            chunks
+                .raycast(Vec3::new(edge, 0.0, 0.0), Vec3::X, 64.0)
+                .is_none()
+        );
+        assert!(chunks.remove_voxel(&IVec3::new(i32::MAX, 0, 0)).is_none());
+    }
 
-            let res = Chunks::insert_voxel(
-                &mut chunks,
-                position,
-                HostVoxel {
-                    material_index: 0,
-                    scale: 1.0,
+    fn animated_scene(keyframes: &[(u32, usize)]) -> DotVoxData {
+        let model = |voxel_count: u8| Model {
+            size: Size { x: 4, y: 4, z: 4 },
+            voxels: (0..voxel_count)
+                .map(|x| Voxel {
+                    x,
+                    y: 0,
+                    z: 0,
+                    i: 1,
+                })
+                .collect(),
+        };
+
+        DotVoxData {
+            version: 150,
+            index_map: vec![],
+            models: vec![model(1), model(2), model(3)],
+            palette: vec![],
+            materials: vec![],
+            scenes: vec![
+                SceneNode::Transform {
+                    attributes: Dict::new(),
+                    frames: vec![Frame {
+                        attributes: Dict::new(),
+                    }],
+                    child: 1,
+                    layer_id: 0,
                 },
-            );
+                SceneNode::Shape {
+                    attributes: Dict::new(),
+                    models: keyframes
+                        .iter()
+                        .map(|(frame, model_id)| ShapeModel {
+                            model_id: *model_id as u32,
+                            attributes: Dict::from([("_f".to_owned(), frame.to_string())]),
+                        })
+                        .collect(),
+                },
+            ],
+            layers: vec![],
+        }
+    }
 
-            assert!(res.is_some());
+    // A file without a scene graph, the voxels are placed as is
+    fn flat_scene(voxels: &[(u8, u8)]) -> DotVoxData {
+        DotVoxData {
+            version: 150,
+            index_map: vec![],
+            models: vec![Model {
+                size: Size { x: 4, y: 4, z: 4 },
+                voxels: voxels
+                    .iter()
+                    .map(|(x, i)| Voxel {
+                        x: *x,
+                        y: 0,
+                        z: 0,
+                        i: *i,
+                    })
+                    .collect(),
+            }],
+            palette: vec![],
+            materials: vec![],
+            scenes: vec![],
+            layers: vec![],
+        }
+    }
 
-            let grid_position = res.unwrap();
+    fn voxel_count(chunks: &Chunks) -> usize {
+        chunks.inner.values().map(|chunk| chunk.voxel_count()).sum()
+    }
 
-            assert!(grid_position == IVec3::new(x / size, 0, 0));
+    #[test]
+    fn chunks_empty_model() {
+        let chunks = Chunks::new(&flat_scene(&[]), WorldSize::default());
+
+        assert!(voxel_count(&chunks) == 0);
+        assert!(chunks.bounds().is_none());
+        assert!(
+            chunks
+                .to_instances(&IVec3::ZERO, &[0; 2 * LOD_COUNT as usize], 16, None)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn chunks_animation_frames() {
+        let mut chunks = Chunks::new(
+            &animated_scene(&[(3, 1), (0, 0), (5, 2)]),
+            WorldSize::default(),
+        );
+
+        assert!(chunks.animation_frame_count() == 6);
+        assert!(voxel_count(&chunks) == 1);
+
+        assert!(!chunks.set_animation_frame(0));
+        assert!(chunks.set_animation_frame(4));
+        assert!(voxel_count(&chunks) == 2);
+        assert!(chunks.set_animation_frame(5));
+        assert!(voxel_count(&chunks) == 3);
+
+        // Wraps around to the first frame
+        assert!(chunks.set_animation_frame(6));
+        assert!(chunks.animation_frame() == 0);
+        assert!(voxel_count(&chunks) == 1);
+
+        let mut chunks = Chunks::new(&animated_scene(&[(0, 1)]), WorldSize::default());
+
+        assert!(chunks.animation_frame_count() == 1);
+        assert!(voxel_count(&chunks) == 2);
+        assert!(!chunks.set_animation_frame(1));
+    }
+
+    #[test]
+    fn chunks_add_model() {
+        let mut chunks = Chunks::new(&flat_scene(&[(0, 1), (1, 1)]), WorldSize::default());
+
+        // The last model placed at a position wins
+        chunks.add_model(&flat_scene(&[(0, 2)]), IVec3::ZERO);
+
+        assert!(voxel_count(&chunks) == 2);
+        assert!(chunks.get_voxel(&IVec3::ZERO).unwrap().material_index == 2);
+        assert!(chunks.get_voxel(&IVec3::X).unwrap().material_index == 1);
+
+        chunks.add_model(&flat_scene(&[(0, 3)]), IVec3::new(100, 0, 0));
+
+        assert!(voxel_count(&chunks) == 3);
+        assert!(
+            chunks
+                .get_voxel(&IVec3::new(100, 0, 0))
+                .unwrap()
+                .material_index
+                == 3
+        );
+
+        // Out of the world
+        let edge = WORLD_WIDTH * CHUNK_WIDTH as i32;
+        chunks.add_model(&flat_scene(&[(0, 3)]), IVec3::new(edge, 0, 0));
+
+        assert!(voxel_count(&chunks) == 3);
+
+        // Models of every file share the instance budget
+        assert!(chunks.to_instances(&IVec3::ZERO, &[0; 4], 2, None).len() == 2);
+
+        // An animated file makes the whole world animated
+        chunks.add_model(&animated_scene(&[(0, 0), (1, 2)]), IVec3::new(0, 0, 100));
+
+        assert!(chunks.animation_frame_count() == 2);
+        assert!(chunks.set_animation_frame(1));
+    }
+
+    #[test]
+    fn chunks_dirty_instance_ranges() {
+        let previous = [0, 1, 2, 3, 4, 5];
+
+        assert!(dirty_instance_ranges(&previous, &previous, &[]).is_empty());
+        assert!(dirty_instance_ranges(&previous, &[0, 9, 9, 3, 4, 9, 6], &[]) == [1..3, 5..7]);
+        // The forced ranges are merged with the changes and clamped to the new length
+        let ranges = dirty_instance_ranges(&previous, &[0, 9, 2], &[0..2, 2..10]);
+        assert!(ranges.len() == 1 && ranges[0] == (0..3));
+    }
+
+    #[test]
+    fn chunks_stream() {
+        let path = std::env::temp_dir().join("a-tlas-chunks-stream.bin");
+
+        let mut chunks = Chunks::default();
+
+        let near = IVec3::new(1, 2, 3);
+        let far = IVec3::new(CHUNK_WIDTH as i32 * 8, 0, 0);
+        let voxel = HostVoxel {
+            material_index: 7,
+            scale: 1.0,
+        };
+
+        chunks.insert(near, voxel);
+        chunks.insert(far, voxel);
+
+        assert!(chunks.stream(&IVec3::ZERO, 2));
+        assert!(chunks.evicted_chunk_count() == 1 && !chunks.is_solid(&far));
+        assert!(chunks.is_solid(&near));
+
+        // Evicted chunks are still saved
+        chunks.save(&path).unwrap();
+        assert!(Chunks::load(&path).unwrap().is_solid(&far));
+        std::fs::remove_file(&path).unwrap();
+
+        // Brought back by the loader once the camera gets close
+        let timeout = Instant::now() + Duration::from_secs(5);
+
+        while !chunks.is_solid(&far) {
+            assert!(Instant::now() < timeout, "the chunk was never streamed in");
+
+            chunks.stream(&far, 2);
+            std::thread::sleep(Duration::from_millis(1));
         }
 
-        let sum = chunks.values().map(|c| c.voxels.len() as u32).sum::<u32>();
+        assert!(chunks.get_voxel(&far).unwrap().material_index == 7);
+        assert!(!chunks.is_solid(&near) && chunks.evicted_chunk_count() == 1);
 
-        assert!(sum == WORLD_WIDTH as u32 * CHUNK_WIDTH);
+        // Edits decode the evicted chunk first
+        chunks.remove_voxel(&near);
+        assert!(chunks.evicted_chunk_count() == 0);
     }
 
     #[test]
-    fn chunk_contains() {
-        let mut chunk = Chunk::default();
+    fn chunks_visibility_radius() {
+        let mut chunks = Chunks::default();
 
-        let pos1 = UVec3::new(1, 1, 1);
-        let pos2 = UVec3::new(32, 19, 15);
-        let pos3 = UVec3::new(8, 8, 8);
+        let width = CHUNK_WIDTH as i32;
 
-        chunk.insert(pos1, HostVoxel::default());
-        chunk.insert(pos2, HostVoxel::default());
-        chunk.insert(pos3, HostVoxel::default());
+        chunks.insert(IVec3::ZERO, HostVoxel::default());
+        chunks.insert(IVec3::new(width * 2, 0, 0), HostVoxel::default());
+        chunks.insert(IVec3::new(-1, 0, -width * 3), HostVoxel::default());
 
-        assert!(chunk.contains(&pos1));
-        assert!(chunk.contains(&pos2));
-        assert!(chunk.contains(&pos3));
+        assert!(chunks.chunk_position(&IVec3::new(-1, 0, -width * 3)) == IVec3::new(-1, 0, -3));
+
+        assert!(chunks.update_visibility_radius(IVec3::ZERO, Some(2)));
+        assert!(!chunks.update_visibility_radius(IVec3::ZERO, Some(2)));
+
+        let mut active = chunks.active_chunks().copied().collect::<Vec<_>>();
+        active.sort_by_key(|grid_position| grid_position.x);
+        assert!(active == [IVec3::ZERO, IVec3::new(2, 0, 0)]);
+        assert!(
+            chunks
+                .to_instances(&IVec3::ZERO, &[0; LOD_COUNT as usize], 16, None)
+                .len()
+                == 2
+        );
+
+        // The occlusion flag doesn't bring back a chunk out of range
+        assert!(chunks.set_chunk_visibility(IVec3::new(-1, 0, -3), false));
+        assert!(chunks.set_chunk_visibility(IVec3::new(-1, 0, -3), true));
+        assert!(chunks.active_chunks().count() == 2);
+
+        // Crossing into another chunk moves the range along
+        assert!(chunks.update_visibility_radius(IVec3::new(-1, 0, -2), Some(1)));
+        assert!(chunks.active_chunks().copied().collect::<Vec<_>>() == [IVec3::new(-1, 0, -3)]);
+
+        // Chunks created by edits get the current radius
+        assert!(
+            chunks
+                .insert(IVec3::new(width * 3, 0, 0), HostVoxel::default())
+                .is_some()
+        );
+        assert!(chunks.active_chunks().count() == 1);
+
+        assert!(chunks.update_visibility_radius(IVec3::ZERO, None));
+        assert!(chunks.active_chunks().count() == 4);
+
+        // The squared radius doesn't wrap
+        assert!(!chunks.update_visibility_radius(IVec3::ZERO, Some(u32::MAX)));
+        assert!(chunks.active_chunks().count() == 4);
     }
 
     #[test]
-    fn chunks_contains() {
-        let mut inner = Chunks::create_empty_chunks();
+    fn chunks_translucent_materials() {
+        let mut chunks = Chunks::default();
+
+        chunks.insert(
+            IVec3::ZERO,
+            HostVoxel {
+                scale: 1.0,
+                material_index: 1,
+            },
+        );
+        chunks.insert(
+            IVec3::new(2, 0, 0),
+            HostVoxel {
+                scale: 1.0,
+                material_index: 2,
+            },
+        );
 
-        let pos1 = IVec3::new(1, 1, 1);
-        let pos2 = IVec3::new(120, 129, -215);
+        // The opaque BLAS of each LOD, then the translucent ones
+        let references = (0..LOD_COUNT as u64 * 2).collect::<Vec<_>>();
+        let instance_references = |chunks: &Chunks| {
+            let mut references = chunks
+                .to_instances(&IVec3::ZERO, &references, u64::MAX, None)
+                .iter()
+                .map(|instance| instance.acceleration_structure_reference)
+                .collect::<Vec<_>>();
+            references.sort();
+            references
+        };
+
+        assert!(instance_references(&chunks) == [0, 0]);
+
+        let mut palette = [Vec4::ONE; 256];
+        palette[2].w = 0.5;
+
+        assert!(chunks.set_translucent_materials(&palette));
+        assert!(!chunks.set_translucent_materials(&palette));
+        assert!(instance_references(&chunks) == [0, LOD_COUNT as u64]);
+
+        assert!(chunks.set_material_translucent(1, true));
+        assert!(!chunks.set_material_translucent(1, true));
+        assert!(instance_references(&chunks) == [LOD_COUNT as u64, LOD_COUNT as u64]);
+
+        assert!(chunks.set_translucent_materials(&[Vec4::ONE; 256]));
+        assert!(instance_references(&chunks) == [0, 0]);
+    }
+
+    #[test]
+    fn chunks_debug_dump_chunk() {
+        let mut chunks = Chunks::default();
+
+        chunks.insert(
+            IVec3::new(-1, 2, 3),
+            HostVoxel {
+                scale: 1.0,
+                material_index: 5,
+            },
+        );
+
+        let dump = chunks.debug_dump_chunk(IVec3::new(-1, 0, 0));
+        let width = CHUNK_WIDTH - 1;
+
+        assert!(dump.lines().count() == 2);
+        assert!(dump.starts_with("Chunk [-1, 0, 0]: voxels: 1, visible: true"));
+        assert!(dump.contains(&format!("[{width}, 2, 3]: material 5")));
+
+        assert!(chunks.debug_dump_chunk(IVec3::ZERO) == "Chunk [0, 0, 0]: empty\n");
+        assert!(
+            chunks.debug_dump_chunk(IVec3::splat(i32::MAX))
+                == format!("Chunk {}: out of the world\n", IVec3::splat(i32::MAX))
+        );
+    }
+
+    #[test]
+    fn instances_spatial_sort() {
+        assert!(morton_code(UVec3::new(1, 0, 0)) == 1);
+        assert!(morton_code(UVec3::new(0, 1, 0)) == 2);
+        assert!(morton_code(UVec3::new(0, 0, 1)) == 4);
+        assert!(morton_code(UVec3::new(3, 0, 0)) == 0b1001);
+        assert!(morton_code(UVec3::splat(1023)) == (1 << 30) - 1);
+
+        let instance = |x: f32, y: f32, z: f32| AccelerationStructureInstance {
+            transform: [[1.0, 0.0, 0.0, x], [0.0, 1.0, 0.0, y], [0.0, 0.0, 1.0, z]],
+            ..Default::default()
+        };
+        let translations = |instances: &[AccelerationStructureInstance]| {
+            instances
+                .iter()
+                .map(|instance| instance.transform.map(|row| row[3]))
+                .collect::<Vec<_>>()
+        };
+
+        let mut instances = [
+            instance(100.0, 100.0, 100.0),
+            instance(-100.0, -100.0, -100.0),
+            instance(-99.0, -100.0, -100.0),
+            instance(100.0, 100.0, 99.0),
+        ];
+
+        sort_instances_spatially(&mut instances);
+
+        assert!(
+            translations(&instances)
+                == [
+                    [-100.0, -100.0, -100.0],
+                    [-99.0, -100.0, -100.0],
+                    [100.0, 100.0, 99.0],
+                    [100.0, 100.0, 100.0],
+                ]
+        );
+
+        sort_instances_spatially(&mut []);
+    }
+
+    #[test]
+    fn chunks_invalid_instance_transform() {
+        let instance = |transform| AccelerationStructureInstance {
+            transform,
+            ..Default::default()
+        };
+        let identity = [
+            [1.0, 0.0, 0.0, 3.0],
+            [0.0, 1.0, 0.0, -2.0],
+            [0.0, 0.0, 1.0, 7.0],
+        ];
+        let rotated = [
+            [0.0, -2.0, 0.0, 0.0],
+            [2.0, 0.0, 0.0, 0.0],
+            [0.0, 0.0, 2.0, 0.0],
+        ];
+
+        let mut not_finite = identity;
+        not_finite[1][3] = f32::NAN;
+
+        let mut zero_scale = identity;
+        zero_scale[2][2] = 0.0;
+
+        let mut infinite = identity;
+        infinite[0][0] = f32::INFINITY;
+
+        assert!(invalid_instance_transform(&[]).is_none());
+        assert!(invalid_instance_transform(&[instance(identity), instance(rotated)]).is_none());
+        assert!(invalid_instance_transform(&[instance(identity), instance(not_finite)]) == Some(1));
+
+        for transform in [zero_scale, infinite] {
+            assert!(
+                invalid_instance_transform(&[
+                    instance(identity),
+                    instance(rotated),
+                    instance(transform)
+                ]) == Some(2)
+            );
+        }
+    }
+
+    #[test]
+    fn chunks_bounds() {
+        let mut chunks = Chunks::default();
 
-        Chunks::insert_voxel(&mut inner, pos1, HostVoxel::default());
-        Chunks::insert_voxel(&mut inner, pos2, HostVoxel::default());
+        assert!(chunks.bounds().is_none());
 
-        let chunks = Chunks::from(inner);
+        let width = CHUNK_WIDTH as f32;
+
+        chunks.insert(IVec3::new(-1, 5, 0), HostVoxel::default());
+        chunks.insert(
+            IVec3::new(0, 0, CHUNK_WIDTH as i32 * 2),
+            HostVoxel::default(),
+        );
 
-        assert!(chunks.contains(&pos1));
-        assert!(chunks.contains(&pos2));
+        assert!(
+            chunks.bounds()
+                == Some((
+                    Vec3::new(-width, 0.0, 0.0),
+                    Vec3::new(width, width, width * 3.0)
+                ))
+        );
     }
 }
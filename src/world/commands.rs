@@ -0,0 +1,192 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+use glam::IVec3;
+
+use super::{
+    HostVoxel,
+    chunk::{Chunks, PALETTE_SIZE},
+};
+
+// A change to the world sent from another thread, the app applies them between frames
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WorldCommand {
+    // Ignored outside of the world or when a voxel is already there
+    InsertVoxel { position: IVec3, voxel: HostVoxel },
+    RemoveVoxel { position: IVec3 },
+    // The occlusion culling may change it again on its next pass
+    SetChunkVisible { grid_position: IVec3, visible: bool },
+}
+
+// A command the world can't apply, sent by another thread so it's reported instead of
+// panicking
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorldCommandError {
+    MaterialIndexTooLarge(u32),
+}
+
+impl Display for WorldCommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorldCommandError::MaterialIndexTooLarge(index) => write!(
+                f,
+                "material index {index} is out of the {PALETTE_SIZE} palette entries"
+            ),
+        }
+    }
+}
+
+impl Error for WorldCommandError {}
+
+impl WorldCommand {
+    // Returns whether the world changed
+    pub fn apply(self, world: &mut Chunks) -> Result<bool, WorldCommandError> {
+        match self {
+            WorldCommand::InsertVoxel { voxel, .. } if voxel.material_index() >= PALETTE_SIZE => {
+                Err(WorldCommandError::MaterialIndexTooLarge(
+                    voxel.material_index(),
+                ))
+            }
+            WorldCommand::InsertVoxel { position, voxel } => {
+                Ok(world.insert(position, voxel).is_some())
+            }
+            WorldCommand::RemoveVoxel { position } => Ok(world.remove_voxel(&position).is_some()),
+            WorldCommand::SetChunkVisible {
+                grid_position,
+                visible,
+            } => Ok(world.set_chunk_visibility(grid_position, visible)),
+        }
+    }
+}
+
+// Serializes the edits of other threads, which only hold a sender, with the ones of the app
+pub struct WorldCommandQueue {
+    sender: Sender<WorldCommand>,
+    receiver: Receiver<WorldCommand>,
+}
+
+impl Default for WorldCommandQueue {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        Self { sender, receiver }
+    }
+}
+
+impl WorldCommandQueue {
+    pub fn sender(&self) -> Sender<WorldCommand> {
+        self.sender.clone()
+    }
+
+    // Applies at most `max_commands` commands in the order they were sent, the others wait for
+    // the next call. The invalid commands are reported and skipped. Returns whether the world
+    // changed
+    pub fn drain(&self, world: &mut Chunks, max_commands: usize) -> bool {
+        self.receiver
+            .try_iter()
+            .take(max_commands)
+            .fold(false, |changed, command| match command.apply(world) {
+                Ok(applied) => applied | changed,
+                Err(e) => {
+                    eprintln!("Skipped a world command: {e}");
+                    changed
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use glam::IVec3;
+
+    use super::{WorldCommand, WorldCommandError, WorldCommandQueue};
+    use crate::world::{
+        HostVoxel,
+        chunk::{Chunks, PALETTE_SIZE},
+    };
+
+    #[test]
+    fn world_commands_drain() {
+        let mut chunks = Chunks::default();
+        let queue = WorldCommandQueue::default();
+        let sender = queue.sender();
+
+        thread::spawn(move || {
+            for x in 0..10 {
+                sender
+                    .send(WorldCommand::InsertVoxel {
+                        position: IVec3::new(x, 0, 0),
+                        voxel: HostVoxel::new(1.0, x as u32),
+                    })
+                    .unwrap();
+            }
+
+            sender
+                .send(WorldCommand::RemoveVoxel {
+                    position: IVec3::ZERO,
+                })
+                .unwrap();
+        })
+        .join()
+        .unwrap();
+
+        // Bounded per call, in the order they were sent
+        assert!(queue.drain(&mut chunks, 4));
+        assert!(chunks.is_solid(&IVec3::new(3, 0, 0)));
+        assert!(!chunks.is_solid(&IVec3::new(4, 0, 0)));
+
+        assert!(queue.drain(&mut chunks, usize::MAX));
+        assert!(!chunks.is_solid(&IVec3::ZERO));
+        assert!(chunks.is_solid(&IVec3::new(9, 0, 0)));
+
+        let voxel = chunks.get_voxel(&IVec3::new(9, 0, 0)).unwrap();
+        assert!(voxel.material_index() == 9 && voxel.scale() == 1.0);
+
+        assert!(!queue.drain(&mut chunks, usize::MAX));
+
+        // Inserting over an existing voxel changes nothing
+        queue
+            .sender()
+            .send(WorldCommand::InsertVoxel {
+                position: IVec3::new(9, 0, 0),
+                voxel: HostVoxel::default(),
+            })
+            .unwrap();
+        assert!(!queue.drain(&mut chunks, usize::MAX));
+    }
+
+    #[test]
+    fn world_commands_reject_material_index() {
+        let mut chunks = Chunks::default();
+        let queue = WorldCommandQueue::default();
+
+        let insert = |position: IVec3, material_index: u32| WorldCommand::InsertVoxel {
+            position,
+            voxel: HostVoxel::new(1.0, material_index),
+        };
+
+        assert!(
+            insert(IVec3::ZERO, 256).apply(&mut chunks)
+                == Err(WorldCommandError::MaterialIndexTooLarge(256))
+        );
+        assert!(insert(IVec3::ZERO, PALETTE_SIZE - 1).apply(&mut chunks) == Ok(true));
+
+        // The valid commands sent around it are still applied
+        for (x, material_index) in [(1, 300), (2, 0), (3, u32::MAX)] {
+            queue
+                .sender()
+                .send(insert(IVec3::new(x, 0, 0), material_index))
+                .unwrap();
+        }
+
+        assert!(queue.drain(&mut chunks, usize::MAX));
+        assert!(!chunks.is_solid(&IVec3::new(1, 0, 0)));
+        assert!(chunks.is_solid(&IVec3::new(2, 0, 0)));
+        assert!(!chunks.is_solid(&IVec3::new(3, 0, 0)));
+    }
+}
@@ -0,0 +1,112 @@
+use glam::{Mat4, Vec3, Vec4, Vec4Swizzles};
+
+// View frustum planes, each plane is stored as (normal, distance) with the normal
+// pointing inside of the frustum
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    // Extracts the planes from a `proj * view` matrix using a [0, 1] depth range
+    pub fn from_view_proj(view_proj: Mat4) -> Self {
+        let row_0 = view_proj.row(0);
+        let row_1 = view_proj.row(1);
+        let row_2 = view_proj.row(2);
+        let row_3 = view_proj.row(3);
+
+        let planes = [
+            row_3 + row_0,
+            row_3 - row_0,
+            row_3 + row_1,
+            row_3 - row_1,
+            row_2,
+            row_3 - row_2,
+        ]
+        .map(|plane| plane / plane.xyz().length());
+
+        Self { planes }
+    }
+
+    // Conservative test, only returns false when the box is fully outside of a plane
+    pub fn intersects_aabb(&self, min: Vec3, max: Vec3) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.xyz();
+            let positive_vertex = Vec3::select(normal.cmpge(Vec3::ZERO), max, min);
+
+            normal.dot(positive_vertex) + plane.w >= 0.0
+        })
+    }
+
+    pub fn contains_point(&self, point: Vec3) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.xyz().dot(point) + plane.w >= 0.0)
+    }
+
+    // The corners of the frustum of a `proj * view` matrix, the near ones first
+    pub fn corners(view_proj: Mat4) -> [Vec3; 8] {
+        let inverse = view_proj.inverse();
+
+        std::array::from_fn(|i| {
+            let ndc = Vec3::new(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                (i >> 2) as f32,
+            );
+
+            inverse.project_point3(ndc)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::PI;
+
+    use glam::{Mat4, Vec3};
+
+    use super::Frustum;
+
+    fn frustum() -> Frustum {
+        let view = Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y);
+        let proj = Mat4::perspective_lh(PI / 2.0, 1.0, 0.01, 10000.0);
+
+        Frustum::from_view_proj(proj * view)
+    }
+
+    #[test]
+    fn frustum_culls_behind() {
+        let frustum = frustum();
+
+        assert!(frustum.intersects_aabb(Vec3::new(-1.0, -1.0, 10.0), Vec3::new(1.0, 1.0, 12.0)));
+        assert!(!frustum.intersects_aabb(Vec3::new(-1.0, -1.0, -12.0), Vec3::new(1.0, 1.0, -10.0)));
+    }
+
+    #[test]
+    fn frustum_keeps_straddling() {
+        let frustum = frustum();
+
+        assert!(frustum.intersects_aabb(Vec3::new(-64.0, -64.0, -64.0), Vec3::new(0.0, 0.0, 64.0)));
+        assert!(frustum.intersects_aabb(Vec3::new(20.0, -1.0, 10.0), Vec3::new(60.0, 1.0, 30.0)));
+        assert!(!frustum.intersects_aabb(Vec3::new(40.0, -1.0, 10.0), Vec3::new(60.0, 1.0, 20.0)));
+    }
+
+    #[test]
+    fn frustum_contains_narrower() {
+        let view = Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y);
+        let narrow = Mat4::perspective_lh(PI / 2.0, 1.0, 0.01, 100.0) * view;
+        let wide = Mat4::perspective_lh(PI * 2.0 / 3.0, 1.0, 0.01, 200.0)
+            * Mat4::from_translation(Vec3::Z)
+            * view;
+
+        let contains = |frustum: Frustum, view_proj: Mat4| {
+            Frustum::corners(view_proj)
+                .iter()
+                .all(|corner| frustum.contains_point(*corner))
+        };
+
+        assert!(contains(Frustum::from_view_proj(wide), narrow));
+        assert!(!contains(Frustum::from_view_proj(narrow), wide));
+    }
+}
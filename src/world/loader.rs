@@ -1,80 +1,193 @@
-use dot_vox::{DotVoxData, Rotation, SceneNode, Voxel};
-use glam::{IVec3, Mat4, UVec3, Vec3A, Vec3Swizzles};
+use std::ops::Range;
 
-use crate::world::HostVoxel;
+use dot_vox::{Dict, DotVoxData, Rotation, SceneNode, Voxel};
+use glam::{IVec3, Mat4, UVec3, Vec3A, Vec3Swizzles, Vec4};
 
-use super::chunk::{Chunks, ChunksInner};
+use super::voxel::{Material, get_materials, get_palette};
+
+// Frames covered by a node without keyframes
+pub const ALL_FRAMES: Range<u32> = 0..u32::MAX;
+
+pub struct ModelInstance {
+    // The world position of the origin of the file the model comes from
+    pub offset: IVec3,
+    pub translation: IVec3,
+    pub rotation: Rotation,
+    pub size: UVec3,
+    pub voxels: Vec<Voxel>,
+    // Animation frames during which the model is shown
+    pub frames: Range<u32>,
+}
 
 pub struct SceneGraphTraverser<'a> {
-    pub chunks: &'a mut ChunksInner,
     pub scene: &'a DotVoxData,
-    pub models: Vec<(IVec3, Rotation, UVec3, Vec<Voxel>)>,
+    pub models: Vec<ModelInstance>,
 }
 
-impl SceneGraphTraverser<'_> {
-    pub fn traverse(&mut self) {
-        if self.scene.scenes.is_empty() {
-            for voxel in self.scene.models.iter().flat_map(|model| &model.voxels) {
-                Chunks::insert_voxel(
-                    self.chunks,
+// A file the world can be built from
+pub trait VoxelSource {
+    // Voxels placed as is relative to the origin of the file, with their palette index. They
+    // aren't part of the animation
+    fn voxels(&self) -> Vec<(IVec3, u8)>;
+
+    // Models placed by a scene graph, relative to the origin of the file
+    fn models(&self) -> Vec<ModelInstance>;
+
+    fn palette(&self) -> [Vec4; 256];
+
+    fn materials(&self) -> [Material; 256];
+}
+
+// Files without a scene graph only have voxels, the others only have models
+impl VoxelSource for DotVoxData {
+    fn voxels(&self) -> Vec<(IVec3, u8)> {
+        if !self.scenes.is_empty() {
+            return vec![];
+        }
+
+        self.models
+            .iter()
+            .flat_map(|model| &model.voxels)
+            .map(|voxel| {
+                (
                     IVec3::new(voxel.x as i32, voxel.z as i32, voxel.y as i32),
-                    HostVoxel {
-                        scale: 1.0,
-                        material_index: voxel.i as u32,
-                    },
-                );
-            }
-        } else {
-            self.traverse_recursive(0, IVec3::ZERO, Rotation::IDENTITY);
+                    voxel.i,
+                )
+            })
+            .collect()
+    }
+
+    fn models(&self) -> Vec<ModelInstance> {
+        if self.scenes.is_empty() {
+            return vec![];
         }
+
+        let mut traverser = SceneGraphTraverser {
+            scene: self,
+            models: vec![],
+        };
+
+        traverser.traverse_recursive(0, IVec3::ZERO, Rotation::IDENTITY, ALL_FRAMES);
+
+        traverser.models
+    }
+
+    fn palette(&self) -> [Vec4; 256] {
+        get_palette(self)
     }
 
-    pub fn traverse_recursive(&mut self, node: u32, translation: glam::IVec3, rotation: Rotation) {
+    fn materials(&self) -> [Material; 256] {
+        get_materials(self)
+    }
+}
+
+// The frame index of a keyframe, stored as `_f` by MagicaVoxel
+fn keyframe_index(attributes: &Dict) -> u32 {
+    attributes
+        .get("_f")
+        .and_then(|f| f.parse().ok())
+        .unwrap_or(0)
+}
+
+// Splits `frames` between keyframes, each one lasting until the next one. The first keyframe
+// also covers the frames before it
+fn keyframe_ranges<T>(
+    keyframes: &[T],
+    index: impl Fn(&T) -> u32,
+    frames: &Range<u32>,
+) -> Vec<(usize, Range<u32>)> {
+    let mut order = (0..keyframes.len()).collect::<Vec<_>>();
+    order.sort_by_key(|i| index(&keyframes[*i]));
+
+    order
+        .iter()
+        .enumerate()
+        .filter_map(|(n, i)| {
+            let start = if n == 0 { 0 } else { index(&keyframes[*i]) };
+            let end = order
+                .get(n + 1)
+                .map(|next| index(&keyframes[*next]))
+                .unwrap_or(u32::MAX);
+
+            let range = start.max(frames.start)..end.min(frames.end);
+
+            (!range.is_empty()).then_some((*i, range))
+        })
+        .collect()
+}
+
+impl SceneGraphTraverser<'_> {
+    // `frames` is the range of animation frames in which the node is reached
+    pub fn traverse_recursive(
+        &mut self,
+        node: u32,
+        translation: glam::IVec3,
+        rotation: Rotation,
+        frames: Range<u32>,
+    ) {
         let node = &self.scene.scenes[node as usize];
         match node {
-            SceneNode::Transform { frames, child, .. } => {
-                if frames.len() != 1 {
-                    unimplemented!("Multiple frames in transform node");
-                }
-                let frame = &frames[0];
-                let this_translation = frame
-                    .position()
-                    .map(|position| IVec3 {
-                        x: position.x,
-                        y: position.y,
-                        z: position.z,
-                    })
-                    .unwrap_or(IVec3::ZERO);
+            SceneNode::Transform {
+                frames: keyframes,
+                child,
+                ..
+            } => {
+                let ranges = keyframe_ranges(
+                    keyframes,
+                    |frame| keyframe_index(&frame.attributes),
+                    &frames,
+                );
 
-                let this_rotation = frame.orientation().unwrap_or(Rotation::IDENTITY);
+                for (i, frames) in ranges {
+                    let frame = &keyframes[i];
+                    let this_translation = frame
+                        .position()
+                        .map(|position| IVec3 {
+                            x: position.x,
+                            y: position.y,
+                            z: position.z,
+                        })
+                        .unwrap_or(IVec3::ZERO);
 
-                let translation = translation + this_translation;
+                    let this_rotation = frame.orientation().unwrap_or(Rotation::IDENTITY);
 
-                self.traverse_recursive(*child, translation, this_rotation);
+                    let translation = translation + this_translation;
+
+                    self.traverse_recursive(*child, translation, this_rotation, frames);
+                }
             }
             SceneNode::Group { children, .. } => {
                 for child in children {
-                    self.traverse_recursive(*child, IVec3::ZERO, Rotation::IDENTITY);
+                    self.traverse_recursive(
+                        *child,
+                        IVec3::ZERO,
+                        Rotation::IDENTITY,
+                        frames.clone(),
+                    );
                 }
             }
             SceneNode::Shape { models, .. } => {
-                if models.len() != 1 {
-                    unimplemented!("Multiple shape models in Shape node");
-                }
-                let shape_model = &models[0];
-                let model = &self.scene.models[shape_model.model_id as usize];
-                if model.voxels.is_empty() {
-                    return;
-                }
+                let ranges = keyframe_ranges(
+                    models,
+                    |shape_model| keyframe_index(&shape_model.attributes),
+                    &frames,
+                );
 
-                let size = self.scene.models[shape_model.model_id as usize].size;
+                for (i, frames) in ranges {
+                    let model = &self.scene.models[models[i].model_id as usize];
+                    if model.voxels.is_empty() {
+                        continue;
+                    }
 
-                self.models.push((
-                    translation,
-                    rotation,
-                    UVec3::new(size.x, size.y, size.z),
-                    model.voxels.clone(),
-                ));
+                    self.models.push(ModelInstance {
+                        offset: IVec3::ZERO,
+                        translation,
+                        rotation,
+                        size: UVec3::new(model.size.x, model.size.y, model.size.z),
+                        voxels: model.voxels.clone(),
+                        frames,
+                    });
+                }
             }
         }
     }
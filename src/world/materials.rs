@@ -0,0 +1,330 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use glam::{IVec3, Vec3, Vec4};
+
+use super::{
+    loader::{ModelInstance, VoxelSource},
+    voxel::Material,
+};
+
+// The values of a palette entry replacing the ones of the model file, the missing ones keep
+// the file's
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MaterialOverride {
+    pub color: Option<Vec4>,
+    pub metallic: Option<f32>,
+    pub roughness: Option<f32>,
+    pub emission: Option<Vec3>,
+    pub emission_strength: Option<f32>,
+}
+
+impl MaterialOverride {
+    pub fn apply(&self, color: &mut Vec4, material: &mut Material) {
+        *color = self.color.unwrap_or(*color);
+        material.metallic = self.metallic.unwrap_or(material.metallic);
+        material.roughness = self.roughness.unwrap_or(material.roughness);
+        material.emission = self.emission.unwrap_or(material.emission);
+        material.emission_strength = self.emission_strength.unwrap_or(material.emission_strength);
+    }
+}
+
+// Edits of the palette and the materials of a model, kept next to it since .vox materials are
+// awkward to write back. Stored as a TOML table per palette index:
+//
+// [12]
+// color = [1, 0.5, 0.25, 1]
+// roughness = 0.5
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MaterialOverrides {
+    entries: BTreeMap<u8, MaterialOverride>,
+}
+
+impl MaterialOverrides {
+    // `model.vox` is overridden by `model.materials.toml`
+    pub fn sidecar_path(asset_path: &Path) -> PathBuf {
+        asset_path.with_extension("materials.toml")
+    }
+
+    // A missing file overrides nothing
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => text.parse(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Overrides every value of the entry
+    pub fn set(&mut self, index: u8, color: Vec4, material: Material) {
+        self.entries.insert(
+            index,
+            MaterialOverride {
+                color: Some(color),
+                metallic: Some(material.metallic),
+                roughness: Some(material.roughness),
+                emission: Some(material.emission),
+                emission_strength: Some(material.emission_strength),
+            },
+        );
+    }
+
+    pub fn apply(&self, palette: &mut [Vec4; 256], materials: &mut [Material; 256]) {
+        for (&index, entry) in &self.entries {
+            entry.apply(&mut palette[index as usize], &mut materials[index as usize]);
+        }
+    }
+}
+
+// Floats are written with `Display`, which parses back to the same value
+impl Display for MaterialOverrides {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let array = |values: &[f32]| {
+            let values = values.iter().map(f32::to_string).collect::<Vec<_>>();
+
+            format!("[{}]", values.join(", "))
+        };
+
+        writeln!(
+            f,
+            "# Material overrides by palette index, missing values keep the model's"
+        )?;
+
+        for (index, entry) in &self.entries {
+            writeln!(f, "\n[{index}]")?;
+
+            if let Some(color) = entry.color {
+                writeln!(f, "color = {}", array(&color.to_array()))?;
+            }
+            if let Some(metallic) = entry.metallic {
+                writeln!(f, "metallic = {metallic}")?;
+            }
+            if let Some(roughness) = entry.roughness {
+                writeln!(f, "roughness = {roughness}")?;
+            }
+            if let Some(emission) = entry.emission {
+                writeln!(f, "emission = {}", array(&emission.to_array()))?;
+            }
+            if let Some(emission_strength) = entry.emission_strength {
+                writeln!(f, "emission_strength = {emission_strength}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Only the subset of TOML written by `Display`: comments, `[index]` headers, and numbers or
+// arrays of numbers
+impl std::str::FromStr for MaterialOverrides {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        let mut overrides = Self::default();
+        let mut current = None;
+
+        for (line_index, line) in text.lines().enumerate() {
+            let invalid = |message: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {message}", line_index + 1),
+                )
+            };
+
+            let line = line.split('#').next().unwrap().trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                let index = header
+                    .strip_suffix(']')
+                    .and_then(|index| index.trim().parse::<u8>().ok())
+                    .ok_or_else(|| invalid("expected a palette index from 0 to 255"))?;
+
+                overrides.entries.entry(index).or_default();
+                current = Some(index);
+
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid("expected `key = value`"))?;
+
+            let values = value
+                .trim()
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .split(',')
+                .map(|value| value.trim().parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| invalid(&e.to_string()))?;
+
+            let entry = current
+                .and_then(|index| overrides.entries.get_mut(&index))
+                .ok_or_else(|| invalid("value outside of a palette index table"))?;
+
+            match (key.trim(), &values[..]) {
+                ("color", &[r, g, b, a]) => entry.color = Some(Vec4::new(r, g, b, a)),
+                ("metallic", &[metallic]) => entry.metallic = Some(metallic),
+                ("roughness", &[roughness]) => entry.roughness = Some(roughness),
+                ("emission", &[r, g, b]) => entry.emission = Some(Vec3::new(r, g, b)),
+                ("emission_strength", &[strength]) => entry.emission_strength = Some(strength),
+                (key, _) => return Err(invalid(&format!("invalid value for `{key}`"))),
+            }
+        }
+
+        Ok(overrides)
+    }
+}
+
+// A model file seen through the overrides of its palette and materials
+pub struct OverriddenSource {
+    source: Box<dyn VoxelSource>,
+    pub overrides: MaterialOverrides,
+}
+
+impl OverriddenSource {
+    pub fn new(source: Box<dyn VoxelSource>, overrides: MaterialOverrides) -> Self {
+        Self { source, overrides }
+    }
+
+    fn palette_and_materials(&self) -> ([Vec4; 256], [Material; 256]) {
+        let mut palette = self.source.palette();
+        let mut materials = self.source.materials();
+        self.overrides.apply(&mut palette, &mut materials);
+
+        (palette, materials)
+    }
+}
+
+impl VoxelSource for OverriddenSource {
+    fn voxels(&self) -> Vec<(IVec3, u8)> {
+        self.source.voxels()
+    }
+
+    fn models(&self) -> Vec<ModelInstance> {
+        self.source.models()
+    }
+
+    fn palette(&self) -> [Vec4; 256] {
+        self.palette_and_materials().0
+    }
+
+    fn materials(&self) -> [Material; 256] {
+        self.palette_and_materials().1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use glam::{Vec3, Vec4};
+
+    use super::{MaterialOverride, MaterialOverrides};
+    use crate::world::voxel::Material;
+
+    #[test]
+    fn material_overrides_round_trip() {
+        let mut overrides = MaterialOverrides::default();
+
+        overrides.set(
+            3,
+            Vec4::new(0.1, 0.2, 0.3, 0.4),
+            Material {
+                metallic: 0.25,
+                roughness: 1.0 / 3.0,
+                emission: Vec3::new(1.0, 0.5, 0.0),
+                emission_strength: 12.5,
+            },
+        );
+        overrides.entries.insert(
+            255,
+            MaterialOverride {
+                roughness: Some(0.0),
+                ..Default::default()
+            },
+        );
+
+        let text = overrides.to_string();
+
+        assert!(text.parse::<MaterialOverrides>().unwrap() == overrides);
+        assert!("".parse::<MaterialOverrides>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn material_overrides_fall_back() {
+        let overrides = "# edited\n[1]\nroughness = 0.5 # glossy\n\n[2]\ncolor = [1, 0, 0, 1]\n"
+            .parse::<MaterialOverrides>()
+            .unwrap();
+
+        let file_material = Material {
+            metallic: 0.75,
+            ..Default::default()
+        };
+        let mut palette = [Vec4::ONE; 256];
+        let mut materials = [file_material; 256];
+
+        overrides.apply(&mut palette, &mut materials);
+
+        assert!(overrides.len() == 2);
+        assert!(palette[1] == Vec4::ONE);
+        assert!(materials[1].roughness == 0.5);
+        assert!(materials[1].metallic == 0.75);
+        assert!(palette[2] == Vec4::new(1.0, 0.0, 0.0, 1.0));
+        assert!(materials[2] == file_material);
+        assert!(palette[0] == Vec4::ONE && materials[0] == file_material);
+    }
+
+    #[test]
+    fn material_overrides_invalid() {
+        for text in [
+            "[256]",
+            "[1",
+            "roughness = 0.5",
+            "[1]\nroughness",
+            "[1]\nroughness = rough",
+            "[1]\ncolor = [1, 0, 0]",
+            "[1]\nshininess = 1",
+        ] {
+            assert!(text.parse::<MaterialOverrides>().is_err(), "{text}");
+        }
+
+        let error = "[1]\n\nmetallic = [1, 2]"
+            .parse::<MaterialOverrides>()
+            .unwrap_err();
+
+        assert!(error.to_string().starts_with("line 3"));
+    }
+
+    #[test]
+    fn material_overrides_sidecar() {
+        assert!(
+            MaterialOverrides::sidecar_path(Path::new("assets/castle.vox"))
+                == Path::new("assets/castle.materials.toml")
+        );
+
+        let missing = std::env::temp_dir().join("a-tlas-missing.materials.toml");
+
+        assert!(MaterialOverrides::load(&missing).unwrap().is_empty());
+    }
+}
@@ -1,7 +1,14 @@
-use vulkano::buffer::BufferContents;
+use glam::Vec3;
+use vulkano::{buffer::BufferContents, pipeline::graphics::vertex_input::Vertex};
 
 pub mod chunk;
+pub mod commands;
+pub mod frustum;
 pub mod loader;
+pub mod materials;
+pub mod occlusion;
+pub mod qubicle;
+pub mod streaming;
 pub mod voxel;
 
 #[derive(BufferContents)]
@@ -10,15 +17,60 @@ pub struct Vertex3D {
     position: [f32; 3],
 }
 
-#[derive(BufferContents)]
+#[derive(BufferContents, Vertex, Clone, Copy)]
 #[repr(C)]
 pub struct Vertex3DColor {
+    #[format(R32G32B32_SFLOAT)]
     position: [f32; 3],
+    #[format(R32G32B32A32_SFLOAT)]
     color: [f32; 4],
 }
 
-#[derive(Debug, Default)]
+// The 12 edges of the cube from `min` to `min + size` as a line list
+pub fn box_lines(min: Vec3, size: f32, color: [f32; 4]) -> Vec<Vertex3DColor> {
+    let vertex = |corner: Vec3| Vertex3DColor {
+        position: (min + corner * size).to_array(),
+        color,
+    };
+
+    // The 4 edges along each axis
+    (0..3)
+        .flat_map(|axis| {
+            [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)].map(|(u, v)| {
+                let mut start = Vec3::ZERO;
+                start[(axis + 1) % 3] = u;
+                start[(axis + 2) % 3] = v;
+
+                let mut end = start;
+                end[axis] = 1.0;
+
+                [vertex(start), vertex(end)]
+            })
+        })
+        .flatten()
+        .collect()
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub struct HostVoxel {
     scale: f32,
     material_index: u32,
 }
+
+impl HostVoxel {
+    pub fn new(scale: f32, material_index: u32) -> Self {
+        Self {
+            scale,
+            material_index,
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    // The palette entry of the voxel
+    pub fn material_index(&self) -> u32 {
+        self.material_index
+    }
+}
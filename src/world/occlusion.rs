@@ -0,0 +1,271 @@
+use glam::{IVec3, Mat4, Vec2, Vec3, Vec4Swizzles};
+
+use crate::world::chunk::Chunks;
+
+// Resolution of the coarse depth buffer
+const DEPTH_WIDTH: usize = 64;
+const DEPTH_HEIGHT: usize = 36;
+
+// Merged boxes thinner than this on any axis are not worth rasterizing
+const OCCLUDER_MIN_EXTENT: u32 = 4;
+
+// Points closer than this to the camera plane make a box untestable
+const NEAR: f32 = 0.01;
+
+// Hides the chunks fully behind large solid boxes, using a coarse depth buffer of view depths.
+// Occluder faces only write to the pixels they fully cover with their farthest depth, and
+// chunks are only hidden when every pixel they may cover is closer than their nearest point,
+// so a chunk with any visible voxel is never hidden
+pub struct OcclusionCuller {
+    depth: Vec<f32>,
+    // Cached until the world changes
+    occluders: Option<Vec<(Vec3, Vec3)>>,
+}
+
+impl Default for OcclusionCuller {
+    fn default() -> Self {
+        Self {
+            depth: vec![f32::INFINITY; DEPTH_WIDTH * DEPTH_HEIGHT],
+            occluders: None,
+        }
+    }
+}
+
+impl OcclusionCuller {
+    // Has to be called when voxels are edited
+    pub fn invalidate(&mut self) {
+        self.occluders = None;
+    }
+
+    // Updates the visibility of the chunks for a `proj * view` matrix, returns whether any
+    // visibility changed
+    pub fn cull(&mut self, chunks: &mut Chunks, view_proj: Mat4) -> bool {
+        self.depth.fill(f32::INFINITY);
+
+        let occluders = self
+            .occluders
+            .get_or_insert_with(|| chunks.occluders(OCCLUDER_MIN_EXTENT));
+
+        for (min, max) in occluders.iter() {
+            for face in box_faces(*min, *max) {
+                rasterize_face(&mut self.depth, view_proj, &face);
+            }
+        }
+
+        let grid_positions = chunks.non_empty_chunks().copied().collect::<Vec<IVec3>>();
+
+        let mut changed = false;
+
+        for grid_position in grid_positions {
+            let (min, max) = chunks.chunk_aabb(&grid_position, 0);
+            let visible = !occluded(&self.depth, view_proj, min, max);
+
+            changed |= chunks.set_chunk_visibility(grid_position, visible);
+        }
+
+        changed
+    }
+}
+
+// The corners of each face of the box, in order around the face
+fn box_faces(min: Vec3, max: Vec3) -> Vec<[Vec3; 4]> {
+    let mut faces = Vec::with_capacity(6);
+
+    for axis in 0..3 {
+        let u = (axis + 1) % 3;
+        let v = (axis + 2) % 3;
+
+        for side in [min[axis], max[axis]] {
+            let corner = |u_value: f32, v_value: f32| {
+                let mut corner = Vec3::ZERO;
+                corner[axis] = side;
+                corner[u] = u_value;
+                corner[v] = v_value;
+                corner
+            };
+
+            faces.push([
+                corner(min[u], min[v]),
+                corner(max[u], min[v]),
+                corner(max[u], max[v]),
+                corner(min[u], max[v]),
+            ]);
+        }
+    }
+
+    faces
+}
+
+// Projects the points to NDC, along with their farthest and nearest view depth
+fn project(view_proj: Mat4, points: &[Vec3]) -> Option<(Vec<Vec2>, f32, f32)> {
+    let mut projected = Vec::with_capacity(points.len());
+    let mut far = f32::MIN;
+    let mut near = f32::MAX;
+
+    for point in points {
+        let clip = view_proj * point.extend(1.0);
+
+        if clip.w <= NEAR {
+            return None;
+        }
+
+        projected.push(clip.xy() / clip.w);
+        far = far.max(clip.w);
+        near = near.min(clip.w);
+    }
+
+    Some((projected, far, near))
+}
+
+// The NDC position of a pixel corner
+fn pixel_corner(x: usize, y: usize) -> Vec2 {
+    Vec2::new(
+        x as f32 / DEPTH_WIDTH as f32 * 2.0 - 1.0,
+        y as f32 / DEPTH_HEIGHT as f32 * 2.0 - 1.0,
+    )
+}
+
+// The range of pixels overlapping the NDC rectangle, `None` when it is off screen
+fn pixel_range(min: Vec2, max: Vec2) -> Option<(usize, usize, usize, usize)> {
+    if max.x < -1.0 || max.y < -1.0 || min.x > 1.0 || min.y > 1.0 {
+        return None;
+    }
+
+    let to_pixel = |ndc: f32, size: usize| ((ndc + 1.0) / 2.0 * size as f32).max(0.0) as usize;
+
+    Some((
+        to_pixel(min.x, DEPTH_WIDTH).min(DEPTH_WIDTH - 1),
+        to_pixel(max.x, DEPTH_WIDTH).min(DEPTH_WIDTH - 1),
+        to_pixel(min.y, DEPTH_HEIGHT).min(DEPTH_HEIGHT - 1),
+        to_pixel(max.y, DEPTH_HEIGHT).min(DEPTH_HEIGHT - 1),
+    ))
+}
+
+fn inside_quad(quad: &[Vec2], point: Vec2) -> bool {
+    let edge = |i: usize| {
+        let a = quad[i];
+        let b = quad[(i + 1) % 4];
+
+        (b - a).perp_dot(point - a)
+    };
+
+    let edges = [edge(0), edge(1), edge(2), edge(3)];
+
+    edges.iter().all(|e| *e >= 0.0) || edges.iter().all(|e| *e <= 0.0)
+}
+
+fn rasterize_face(depth: &mut [f32], view_proj: Mat4, face: &[Vec3; 4]) {
+    // Faces crossing the camera plane are skipped, which only makes the culling less effective
+    let Some((quad, far, _)) = project(view_proj, face) else {
+        return;
+    };
+
+    let min = quad.iter().copied().reduce(Vec2::min).unwrap();
+    let max = quad.iter().copied().reduce(Vec2::max).unwrap();
+
+    let Some((min_x, max_x, min_y, max_y)) = pixel_range(min, max) else {
+        return;
+    };
+
+    for y in min_y..=max_y {
+        for x in min_x..=max_x {
+            // The quad is convex, so a pixel is covered when its four corners are
+            let covered = [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)]
+                .into_iter()
+                .all(|(corner_x, corner_y)| inside_quad(&quad, pixel_corner(corner_x, corner_y)));
+
+            if covered {
+                let pixel = &mut depth[y * DEPTH_WIDTH + x];
+                *pixel = pixel.min(far);
+            }
+        }
+    }
+}
+
+fn occluded(depth: &[f32], view_proj: Mat4, min: Vec3, max: Vec3) -> bool {
+    let corners = [
+        Vec3::new(min.x, min.y, min.z),
+        Vec3::new(max.x, min.y, min.z),
+        Vec3::new(min.x, max.y, min.z),
+        Vec3::new(max.x, max.y, min.z),
+        Vec3::new(min.x, min.y, max.z),
+        Vec3::new(max.x, min.y, max.z),
+        Vec3::new(min.x, max.y, max.z),
+        Vec3::new(max.x, max.y, max.z),
+    ];
+
+    let Some((projected, _, near)) = project(view_proj, &corners) else {
+        return false;
+    };
+
+    let min = projected.iter().copied().reduce(Vec2::min).unwrap();
+    let max = projected.iter().copied().reduce(Vec2::max).unwrap();
+
+    // Off screen chunks are left to the frustum culling
+    let Some((min_x, max_x, min_y, max_y)) = pixel_range(min, max) else {
+        return false;
+    };
+
+    (min_y..=max_y).all(|y| (min_x..=max_x).all(|x| depth[y * DEPTH_WIDTH + x] < near))
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::PI;
+
+    use glam::{IVec3, Mat4, Vec3};
+
+    use super::OcclusionCuller;
+    use crate::world::{HostVoxel, chunk::Chunks};
+
+    #[test]
+    fn occlusion_hides_chunks_behind_walls() {
+        let mut chunks = Chunks::default();
+
+        // A wall filling the chunk in front of the camera
+        for x in -64..128 {
+            for y in -64..128 {
+                for z in 64..72 {
+                    chunks.insert(IVec3::new(x, y, z), HostVoxel::default());
+                }
+            }
+        }
+
+        let hidden = IVec3::new(10, 10, 300);
+        let beside = IVec3::new(2000, 0, 300);
+
+        chunks.insert(hidden, HostVoxel::default());
+        chunks.insert(beside, HostVoxel::default());
+
+        let view = Mat4::look_at_rh(
+            Vec3::new(32.0, 32.0, 0.0),
+            Vec3::new(32.0, 32.0, -1.0),
+            Vec3::Y,
+        );
+        let proj = Mat4::perspective_lh(PI / 2.0, 1.0, 0.01, 10000.0);
+
+        let mut culler = OcclusionCuller::default();
+
+        assert!(culler.cull(&mut chunks, proj * view));
+
+        let visible = chunks.active_chunks().copied().collect::<Vec<_>>();
+
+        assert!(!visible.contains(&IVec3::new(0, 0, 4)));
+        assert!(visible.contains(&IVec3::new(31, 0, 4)));
+        assert!(visible.contains(&IVec3::new(0, 0, 1)));
+
+        // Removing the wall shows the chunk again
+        for x in -64..128 {
+            for y in -64..128 {
+                for z in 64..72 {
+                    chunks.remove_voxel(&IVec3::new(x, y, z));
+                }
+            }
+        }
+
+        culler.invalidate();
+
+        assert!(culler.cull(&mut chunks, proj * view));
+        assert!(chunks.active_chunks().any(|p| *p == IVec3::new(0, 0, 4)));
+    }
+}
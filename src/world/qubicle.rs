@@ -0,0 +1,227 @@
+use std::{collections::HashMap, io::Read};
+
+use glam::{IVec3, Vec4};
+
+use super::{
+    loader::{ModelInstance, VoxelSource},
+    voxel::{LoadError, Material},
+};
+
+// Run length encoding markers of compressed matrices
+const CODE_FLAG: u32 = 2;
+const NEXT_SLICE_FLAG: u32 = 6;
+
+// A Qubicle binary (.qb) file. Its colors are stored per voxel, so they are gathered into a
+// palette, colors past the 256th one use the closest entry
+#[derive(Debug, Default)]
+pub struct QubicleData {
+    // Relative to the origin of the file, with the Y axis up like .vox files without a scene
+    voxels: Vec<(IVec3, u8)>,
+    palette: Vec<[u8; 3]>,
+    palette_indices: HashMap<[u8; 3], u8>,
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, LoadError> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+
+    Ok(u32::from_le_bytes(bytes))
+}
+
+impl QubicleData {
+    pub fn read(reader: &mut impl Read) -> Result<Self, LoadError> {
+        let _version = read_u32(reader)?;
+        let bgra = match read_u32(reader)? {
+            0 => false,
+            1 => true,
+            format => return Err(LoadError::Parse(format!("unknown color format {format}"))),
+        };
+        let right_handed = read_u32(reader)? == 1;
+        let compressed = read_u32(reader)? != 0;
+        let _visibility_mask_encoded = read_u32(reader)?;
+        let matrix_count = read_u32(reader)?;
+
+        let mut data = QubicleData::default();
+
+        for _ in 0..matrix_count {
+            let mut name_length = [0];
+            reader.read_exact(&mut name_length)?;
+            reader.read_exact(&mut vec![0; name_length[0] as usize])?;
+
+            let size = [read_u32(reader)?, read_u32(reader)?, read_u32(reader)?];
+            let position = IVec3::new(
+                read_u32(reader)? as i32,
+                read_u32(reader)? as i32,
+                read_u32(reader)? as i32,
+            );
+
+            let mut insert = |x: u32, y: u32, z: u32, color: u32| {
+                let [r, g, b, visible] = color.to_le_bytes();
+
+                if visible == 0 {
+                    return;
+                }
+
+                let rgb = if bgra { [b, g, r] } else { [r, g, b] };
+                let mut position = position + IVec3::new(x as i32, y as i32, z as i32);
+
+                if right_handed {
+                    position.z = -position.z;
+                }
+
+                let index = data.palette_index(rgb);
+                data.voxels.push((position, index));
+            };
+
+            let [width, height, depth] = size;
+            let slice_size = width as u64 * height as u64;
+
+            for z in 0..depth {
+                if !compressed {
+                    for y in 0..height {
+                        for x in 0..width {
+                            insert(x, y, z, read_u32(reader)?);
+                        }
+                    }
+
+                    continue;
+                }
+
+                let mut index = 0;
+
+                loop {
+                    let (count, color) = match read_u32(reader)? {
+                        NEXT_SLICE_FLAG => break,
+                        CODE_FLAG => (read_u32(reader)? as u64, read_u32(reader)?),
+                        color => (1, color),
+                    };
+
+                    if index + count > slice_size {
+                        return Err(LoadError::Parse("run overflows its slice".to_string()));
+                    }
+
+                    for index in index..index + count {
+                        insert(
+                            (index % width as u64) as u32,
+                            (index / width as u64) as u32,
+                            z,
+                            color,
+                        );
+                    }
+
+                    index += count;
+                }
+            }
+        }
+
+        Ok(data)
+    }
+
+    fn palette_index(&mut self, rgb: [u8; 3]) -> u8 {
+        if let Some(&index) = self.palette_indices.get(&rgb) {
+            return index;
+        }
+
+        if self.palette.len() < 256 {
+            let index = self.palette.len() as u8;
+            self.palette.push(rgb);
+            self.palette_indices.insert(rgb, index);
+
+            return index;
+        }
+
+        let distance = |color: &[u8; 3]| {
+            color
+                .iter()
+                .zip(rgb)
+                .map(|(a, b)| (*a as i32 - b as i32).pow(2))
+                .sum::<i32>()
+        };
+
+        (0..=255)
+            .min_by_key(|index| distance(&self.palette[*index as usize]))
+            .unwrap()
+    }
+}
+
+impl VoxelSource for QubicleData {
+    fn voxels(&self) -> Vec<(IVec3, u8)> {
+        self.voxels.clone()
+    }
+
+    fn models(&self) -> Vec<ModelInstance> {
+        vec![]
+    }
+
+    fn palette(&self) -> [Vec4; 256] {
+        let mut array = [Vec4::ZERO; 256];
+
+        for (value, [r, g, b]) in array.iter_mut().zip(&self.palette) {
+            *value = Vec4::new(
+                f32::from(*r) / 255.0,
+                f32::from(*g) / 255.0,
+                f32::from(*b) / 255.0,
+                1.0,
+            );
+        }
+
+        array
+    }
+
+    fn materials(&self) -> [Material; 256] {
+        [Material::default(); 256]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use glam::{IVec3, Vec4};
+
+    use super::{CODE_FLAG, NEXT_SLICE_FLAG, QubicleData};
+    use crate::world::loader::VoxelSource;
+
+    // A file with a single unnamed matrix
+    fn read(compressed: bool, size: [u32; 3], position: [i32; 3], colors: &[u32]) -> QubicleData {
+        let header = [0x0101, 0, 0, compressed as u32, 0, 1];
+
+        let mut bytes = header.map(u32::to_le_bytes).concat();
+        bytes.push(0);
+        bytes.extend(size.map(u32::to_le_bytes).concat());
+        bytes.extend(position.map(i32::to_le_bytes).concat());
+        bytes.extend(colors.iter().flat_map(|color| color.to_le_bytes()));
+
+        QubicleData::read(&mut bytes.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn qubicle_uncompressed() {
+        let red = u32::from_le_bytes([255, 0, 0, 255]);
+        let data = read(false, [2, 2, 1], [10, 0, -5], &[red, 0, 0, red]);
+
+        assert!(data.voxels() == [(IVec3::new(10, 0, -5), 0), (IVec3::new(11, 1, -5), 0)]);
+        assert!(data.palette()[0] == Vec4::new(1.0, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn qubicle_compressed() {
+        let red = u32::from_le_bytes([255, 0, 0, 255]);
+        let blue = u32::from_le_bytes([0, 0, 255, 255]);
+        let colors = [
+            [CODE_FLAG, 2, red, blue, NEXT_SLICE_FLAG].as_slice(),
+            &[0, blue, NEXT_SLICE_FLAG],
+        ]
+        .concat();
+
+        let data = read(true, [3, 1, 2], [0, 0, 0], &colors);
+
+        assert!(
+            data.voxels()
+                == [
+                    (IVec3::new(0, 0, 0), 0),
+                    (IVec3::new(1, 0, 0), 0),
+                    (IVec3::new(2, 0, 0), 1),
+                    (IVec3::new(1, 0, 1), 1),
+                ]
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use std::{
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+};
+
+use glam::IVec3;
+
+use super::chunk::Chunk;
+
+struct ChunkRequest {
+    grid_position: IVec3,
+    id: u64,
+    voxels: Arc<[u8]>,
+}
+
+// Decodes evicted chunks on a background thread, so bringing them back doesn't stall a frame.
+// The thread stops once the loader is dropped
+pub struct ChunkLoader {
+    requests: Sender<ChunkRequest>,
+    results: Receiver<(IVec3, u64, Chunk)>,
+}
+
+impl ChunkLoader {
+    pub fn new(chunk_width: u32) -> Self {
+        let (requests, request_receiver) = mpsc::channel::<ChunkRequest>();
+        let (result_sender, results) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("chunk loader".to_string())
+            .spawn(move || {
+                for request in request_receiver {
+                    let chunk = Chunk::read_voxels(&mut &request.voxels[..], chunk_width)
+                        .expect("evicted chunks are encoded by write_voxels");
+
+                    if result_sender
+                        .send((request.grid_position, request.id, chunk))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            })
+            .unwrap();
+
+        Self { requests, results }
+    }
+
+    // `id` is handed back with the chunk, to tell apart the results of outdated requests
+    pub fn request(&self, grid_position: IVec3, id: u64, voxels: Arc<[u8]>) {
+        // The thread only stops once the results receiver is dropped
+        let _ = self.requests.send(ChunkRequest {
+            grid_position,
+            id,
+            voxels,
+        });
+    }
+
+    // The chunks decoded so far, without blocking
+    pub fn results(&self) -> impl Iterator<Item = (IVec3, u64, Chunk)> + '_ {
+        self.results.try_iter()
+    }
+}
@@ -1,139 +1,219 @@
-use crate::world::Vertex3D;
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::{self, File},
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+};
 
-pub fn open_file(path: &str) -> dot_vox::DotVoxData {
-    let vox_data = dot_vox::load(path).unwrap();
+use crate::world::{Vertex3D, loader::VoxelSource, qubicle::QubicleData};
 
-    #[cfg(debug_assertions)]
-    assert!(vox_data.palette.len() == 256);
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    // The file is read but its content is invalid
+    Parse(String),
+    UnsupportedExtension(PathBuf),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{e}"),
+            LoadError::Parse(e) => write!(f, "invalid file: {e}"),
+            LoadError::UnsupportedExtension(path) => write!(
+                f,
+                "unsupported file {}, expected a .vox or .qb file",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+fn lowercase_extension(path: &Path) -> Option<String> {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+}
+
+// The files of the directory `open_file` has an importer for, sorted by path
+pub fn list_assets(directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut assets = fs::read_dir(directory)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    assets.retain(|path| {
+        path.is_file() && matches!(lowercase_extension(path).as_deref(), Some("vox" | "qb"))
+    });
+    assets.sort();
 
-    vox_data
+    Ok(assets)
 }
 
-pub fn triangles_from_box(position: glam::Vec3) -> Vec<Vertex3D> {
+// Picks the importer from the extension of the file
+pub fn open_file(path: &Path) -> Result<Box<dyn VoxelSource>, LoadError> {
+    match lowercase_extension(path).as_deref() {
+        Some("vox") => {
+            let vox_data =
+                dot_vox::load(&path.to_string_lossy()).map_err(|e| LoadError::Parse(e.into()))?;
+
+            if vox_data.palette.len() != 256 {
+                eprintln!(
+                    "{} has {} palette entries instead of 256, missing entries are black",
+                    path.display(),
+                    vox_data.palette.len()
+                );
+            }
+
+            Ok(Box::new(vox_data))
+        }
+        Some("qb") => Ok(Box::new(QubicleData::read(&mut BufReader::new(
+            File::open(path)?,
+        ))?)),
+        _ => Err(LoadError::UnsupportedExtension(path.to_path_buf())),
+    }
+}
+
+pub fn triangles_from_box(position: glam::Vec3, size: f32) -> Vec<Vertex3D> {
     let glam::Vec3 { x, y, z } = position;
+    let half = size / 2.0;
 
     vec![
         // left face
         Vertex3D {
-            position: [x - 0.5, y - 0.5, z - 0.5],
+            position: [x - half, y - half, z - half],
         },
         Vertex3D {
-            position: [x - 0.5, y - 0.5, z + 0.5],
+            position: [x - half, y - half, z + half],
         },
         Vertex3D {
-            position: [x - 0.5, y + 0.5, z + 0.5],
+            position: [x - half, y + half, z + half],
         },
         Vertex3D {
-            position: [x - 0.5, y - 0.5, z - 0.5],
+            position: [x - half, y - half, z - half],
         },
         Vertex3D {
-            position: [x - 0.5, y + 0.5, z - 0.5],
+            position: [x - half, y + half, z - half],
         },
         Vertex3D {
-            position: [x - 0.5, y + 0.5, z + 0.5],
+            position: [x - half, y + half, z + half],
         },
         // right face
         Vertex3D {
-            position: [x + 0.5, y - 0.5, z - 0.5],
+            position: [x + half, y - half, z - half],
         },
         Vertex3D {
-            position: [x + 0.5, y - 0.5, z + 0.5],
+            position: [x + half, y - half, z + half],
         },
         Vertex3D {
-            position: [x + 0.5, y + 0.5, z + 0.5],
+            position: [x + half, y + half, z + half],
         },
         Vertex3D {
-            position: [x + 0.5, y - 0.5, z - 0.5],
+            position: [x + half, y - half, z - half],
         },
         Vertex3D {
-            position: [x + 0.5, y + 0.5, z - 0.5],
+            position: [x + half, y + half, z - half],
         },
         Vertex3D {
-            position: [x + 0.5, y + 0.5, z + 0.5],
+            position: [x + half, y + half, z + half],
         },
         // bottom face
         Vertex3D {
-            position: [x - 0.5, y - 0.5, z - 0.5],
+            position: [x - half, y - half, z - half],
         },
         Vertex3D {
-            position: [x + 0.5, y - 0.5, z - 0.5],
+            position: [x + half, y - half, z - half],
         },
         Vertex3D {
-            position: [x + 0.5, y - 0.5, z + 0.5],
+            position: [x + half, y - half, z + half],
         },
         Vertex3D {
-            position: [x - 0.5, y - 0.5, z - 0.5],
+            position: [x - half, y - half, z - half],
         },
         Vertex3D {
-            position: [x - 0.5, y - 0.5, z + 0.5],
+            position: [x - half, y - half, z + half],
         },
         Vertex3D {
-            position: [x + 0.5, y - 0.5, z + 0.5],
+            position: [x + half, y - half, z + half],
         },
         // top face
         Vertex3D {
-            position: [x - 0.5, y + 0.5, z - 0.5],
+            position: [x - half, y + half, z - half],
         },
         Vertex3D {
-            position: [x + 0.5, y + 0.5, z - 0.5],
+            position: [x + half, y + half, z - half],
         },
         Vertex3D {
-            position: [x + 0.5, y + 0.5, z + 0.5],
+            position: [x + half, y + half, z + half],
         },
         Vertex3D {
-            position: [x - 0.5, y + 0.5, z - 0.5],
+            position: [x - half, y + half, z - half],
         },
         Vertex3D {
-            position: [x - 0.5, y + 0.5, z + 0.5],
+            position: [x - half, y + half, z + half],
         },
         Vertex3D {
-            position: [x + 0.5, y + 0.5, z + 0.5],
+            position: [x + half, y + half, z + half],
         },
         // back face
         Vertex3D {
-            position: [x - 0.5, y - 0.5, z + 0.5],
+            position: [x - half, y - half, z + half],
         },
         Vertex3D {
-            position: [x + 0.5, y - 0.5, z + 0.5],
+            position: [x + half, y - half, z + half],
         },
         Vertex3D {
-            position: [x + 0.5, y + 0.5, z + 0.5],
+            position: [x + half, y + half, z + half],
         },
         Vertex3D {
-            position: [x - 0.5, y - 0.5, z + 0.5],
+            position: [x - half, y - half, z + half],
         },
         Vertex3D {
-            position: [x - 0.5, y + 0.5, z + 0.5],
+            position: [x - half, y + half, z + half],
         },
         Vertex3D {
-            position: [x + 0.5, y + 0.5, z + 0.5],
+            position: [x + half, y + half, z + half],
         },
         // front face
         Vertex3D {
-            position: [x - 0.5, y - 0.5, z - 0.5],
+            position: [x - half, y - half, z - half],
         },
         Vertex3D {
-            position: [x + 0.5, y - 0.5, z - 0.5],
+            position: [x + half, y - half, z - half],
         },
         Vertex3D {
-            position: [x + 0.5, y + 0.5, z - 0.5],
+            position: [x + half, y + half, z - half],
         },
         Vertex3D {
-            position: [x - 0.5, y - 0.5, z - 0.5],
+            position: [x - half, y - half, z - half],
         },
         Vertex3D {
-            position: [x - 0.5, y + 0.5, z - 0.5],
+            position: [x - half, y + half, z - half],
         },
         Vertex3D {
-            position: [x + 0.5, y + 0.5, z - 0.5],
+            position: [x + half, y + half, z - half],
         },
     ]
 }
 
+// Palette entries missing from the file are left opaque black
 pub fn get_palette(data: &dot_vox::DotVoxData) -> [glam::Vec4; 256] {
-    let mut array = [glam::Vec4::ZERO; 256];
-    for (i, value) in array.iter_mut().enumerate() {
-        let color = data.palette.get(i).unwrap();
+    let mut array = [glam::Vec4::W; 256];
+    for (value, color) in array.iter_mut().zip(&data.palette) {
         *value = glam::Vec4::new(
             f32::from(color.r) / 255.0,
             f32::from(color.g) / 255.0,
@@ -144,3 +224,152 @@ pub fn get_palette(data: &dot_vox::DotVoxData) -> [glam::Vec4; 256] {
 
     array
 }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Material {
+    pub metallic: f32,
+    pub roughness: f32,
+    // The color of the emitted light, scaled by `emission_strength`
+    pub emission: glam::Vec3,
+    pub emission_strength: f32,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            metallic: 0.0,
+            roughness: 1.0,
+            emission: glam::Vec3::ZERO,
+            emission_strength: 0.0,
+        }
+    }
+}
+
+// The material of each palette entry, non metal materials have no metallic term and non
+// emissive materials emit no light
+pub fn get_materials(data: &dot_vox::DotVoxData) -> [Material; 256] {
+    let palette = get_palette(data);
+    let mut array = [Material::default(); 256];
+
+    for material in &data.materials {
+        // Material ids start at 1 while the palette is indexed from 0
+        let Some(value) = (material.id as usize)
+            .checked_sub(1)
+            .and_then(|index| array.get_mut(index))
+        else {
+            continue;
+        };
+
+        let property = |key: &str| {
+            material
+                .properties
+                .get(key)
+                .and_then(|value| value.parse::<f32>().ok())
+        };
+
+        let material_type = material.properties.get("_type").map(String::as_str);
+
+        value.metallic = if material_type == Some("_metal") {
+            property("_metal").unwrap_or(0.0).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        value.roughness = property("_rough").unwrap_or(1.0).clamp(0.0, 1.0);
+
+        // Emissive voxels glow with their own color, `_flux` is the power set in MagicaVoxel
+        if material_type == Some("_emit") {
+            value.emission = palette[material.id as usize - 1].truncate();
+            value.emission_strength = property("_emit").unwrap_or(0.0).max(0.0)
+                * (1.0 + property("_flux").unwrap_or(0.0).max(0.0));
+        }
+    }
+
+    array
+}
+
+#[cfg(test)]
+mod test {
+    use dot_vox::{Color, Dict, DotVoxData, Material};
+    use glam::Vec3;
+
+    use super::{LoadError, get_materials, list_assets, open_file};
+
+    #[test]
+    fn materials_emission() {
+        let material = |id: u32, properties: &[(&str, &str)]| Material {
+            id,
+            properties: properties
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<Dict>(),
+        };
+
+        let color = |r: u8| Color {
+            r,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+
+        let mut palette = vec![color(0); 256];
+        palette[2] = color(255);
+
+        let data = DotVoxData {
+            version: 150,
+            index_map: vec![],
+            models: vec![],
+            palette,
+            materials: vec![
+                material(
+                    1,
+                    &[("_type", "_metal"), ("_metal", "0.5"), ("_rough", "0.2")],
+                ),
+                material(3, &[("_type", "_emit"), ("_emit", "0.5"), ("_flux", "1")]),
+            ],
+            scenes: vec![],
+            layers: vec![],
+        };
+
+        let materials = get_materials(&data);
+
+        assert!(materials[0].metallic == 0.5 && materials[0].roughness == 0.2);
+        assert!(materials[0].emission_strength == 0.0);
+
+        assert!(materials[2].metallic == 0.0);
+        assert!(materials[2].emission == Vec3::X);
+        assert!(materials[2].emission_strength == 1.0);
+
+        assert!(materials[1] == Default::default());
+    }
+
+    #[test]
+    fn open_file_unsupported_extension() {
+        let path = std::path::Path::new("model.txt");
+
+        assert!(matches!(
+            open_file(path),
+            Err(LoadError::UnsupportedExtension(p)) if p == path
+        ));
+        assert!(matches!(
+            open_file(std::path::Path::new("model")),
+            Err(LoadError::UnsupportedExtension(_))
+        ));
+    }
+
+    #[test]
+    fn list_assets_filters_extensions() {
+        let directory = std::env::temp_dir().join("a-tlas-list-assets");
+        std::fs::create_dir_all(directory.join("nested.vox")).unwrap();
+
+        for name in ["b.vox", "a.QB", "c.txt", "d"] {
+            std::fs::write(directory.join(name), []).unwrap();
+        }
+
+        let assets = list_assets(&directory).unwrap();
+
+        assert!(assets == [directory.join("a.QB"), directory.join("b.vox")]);
+        assert!(list_assets(&directory.join("missing")).is_err());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}